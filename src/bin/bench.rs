@@ -0,0 +1,347 @@
+use bracken::{est_abundance, kmer2read_distr, kmer_distrib, taxonomy};
+use clap::Parser;
+use log::{error, info, warn};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+#[derive(Parser, Clone, Debug)]
+#[clap(
+    version,
+    about = "Measures the throughput of every stage of the pipeline.",
+    long_about = "Times kmer2read-distr, kmer-distrib and est-abundance on a Kraken database and report, or on synthetic inputs of a configurable size, and reports the lines processed per second and the peak resident memory of every stage, to compare machines and thread counts (see the global --threads)."
+)]
+pub struct Args {
+    /// Kraken database folder (database.kraken, seqid2taxid.map and
+    /// taxonomy/).
+    #[clap(long = "db", required_unless_present = "synthetic_genomes")]
+    database_dir: Option<PathBuf>,
+
+    /// Kraken report for the est-abundance stage.
+    #[clap(short = 'r', long, requires = "database_dir")]
+    report: Option<PathBuf>,
+
+    /// Synthesize a database of this many genomes and a report instead of
+    /// using --db.
+    #[clap(long, conflicts_with = "database_dir")]
+    synthetic_genomes: Option<usize>,
+
+    /// Length of the synthetic genome sequences.
+    #[clap(long, default_value_t = 100_000)]
+    synthetic_seq_len: usize,
+
+    /// kmer length of the database.
+    #[clap(short = 'k', long, default_value_t = 35)]
+    kmer_len: usize,
+
+    /// Read length of the kmer distribution.
+    #[clap(short = 'l', long, default_value_t = 100)]
+    read_len: usize,
+
+    /// Output TSV file of the timings ("-" writes to stdout).
+    #[clap(short, long, default_value = "-")]
+    output: PathBuf,
+
+    /// Work folder for the synthetic inputs and the outputs [default: a
+    /// folder in the system temporary directory].
+    #[clap(long)]
+    workdir: Option<PathBuf>,
+
+    /// Keep the work folder after the benchmark.
+    #[clap(long)]
+    keep: bool,
+
+    /// Seed for the random number generator of the synthetic inputs.
+    #[clap(long, default_value_t = 0)]
+    seed: u64,
+}
+
+/// Timing of a stage.
+struct Timing {
+    stage: &'static str,
+    seconds: f64,
+    lines: usize,
+    peak_rss_kb: Option<u64>,
+}
+
+/// Peak resident memory of the process (VmHWM), on Linux.
+fn peak_rss_kb() -> Option<u64> {
+    let status = fs::read_to_string("/proc/self/status").ok()?;
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmHWM:"))?
+        .trim()
+        .trim_end_matches("kB")
+        .trim()
+        .parse()
+        .ok()
+}
+
+/// Resets the peak resident memory to the current one, so every stage
+/// reports its own peak; unsupported outside Linux.
+fn reset_peak_rss() {
+    fs::write("/proc/self/clear_refs", "5").ok();
+}
+
+fn count_lines(path: &Path) -> io::Result<usize> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let (mut lines, mut buf) = (0, Vec::new());
+    while reader.read_until(b'\n', &mut buf)? > 0 {
+        lines += 1;
+        buf.clear();
+    }
+    Ok(lines)
+}
+
+/// Runs and times a stage on an input.
+fn time_stage<F>(
+    stage: &'static str,
+    input: &Path,
+    run: F,
+) -> Result<Timing, Box<dyn std::error::Error>>
+where
+    F: FnOnce() -> Result<(), Box<dyn std::error::Error>>,
+{
+    let lines = count_lines(input)?;
+    info!(">> Stage {} ({} lines)", stage, lines);
+    reset_peak_rss();
+    let start = Instant::now();
+    run()?;
+    Ok(Timing {
+        stage,
+        seconds: start.elapsed().as_secs_f64(),
+        lines,
+        peak_rss_kb: peak_rss_kb(),
+    })
+}
+
+/// Writes a synthetic database of `n_genomes` species (five per genus) and
+/// a report of reads at their species and genera.
+fn synthesize(
+    dir: &Path,
+    n_genomes: usize,
+    seq_len: usize,
+    kmer_len: usize,
+    seed: u64,
+) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let genus = |genome: usize| 1_000 + (genome / 5) as u32;
+    let species = |genome: usize| 100_000 + genome as u32;
+    let n_genera = n_genomes.div_ceil(5);
+    fs::create_dir_all(dir.join("taxonomy"))?;
+
+    let mut nodes = BufWriter::new(File::create(dir.join("taxonomy/nodes.dmp"))?);
+    let mut names = BufWriter::new(File::create(dir.join("taxonomy/names.dmp"))?);
+    let mut node = |taxid: u32, parent: u32, rank: &str, name: String| -> io::Result<()> {
+        writeln!(
+            nodes,
+            "{}\t|\t{}\t|\t{}{}",
+            taxid,
+            parent,
+            rank,
+            "\t|\t".repeat(10) + "\t|"
+        )?;
+        writeln!(names, "{}\t|\t{}\t|\t\t|\tscientific name\t|", taxid, name)
+    };
+    node(1, 1, "no rank", "root".into())?;
+    node(2, 1, "superkingdom", "Bacteria".into())?;
+    for g in 0..n_genera {
+        node(genus(g * 5), 2, "genus", format!("Genus {}", g))?;
+    }
+    for i in 0..n_genomes {
+        node(species(i), genus(i), "species", format!("Species {}", i))?;
+    }
+    nodes.flush()?;
+    names.flush()?;
+
+    let mut seqid2taxid = BufWriter::new(File::create(dir.join("seqid2taxid.map"))?);
+    let mut kraken = BufWriter::new(File::create(dir.join("database.kraken"))?);
+    let n_kmers = seq_len.saturating_sub(kmer_len) + 1;
+    for i in 0..n_genomes {
+        writeln!(seqid2taxid, "seq{}\t{}", i, species(i))?;
+        // Runs of kmers unique to the species, shared with its genus or
+        // unclassified
+        let mut mappings = Vec::new();
+        let mut left = n_kmers;
+        while left > 0 {
+            let run = rng.gen_range(1..=left.min(200));
+            let taxid = match rng.gen_range(0..10) {
+                0..=6 => species(i),
+                7..=8 => genus(i),
+                _ => 0,
+            };
+            mappings.push(format!("{}:{}", taxid, run));
+            left -= run;
+        }
+        writeln!(
+            kraken,
+            "C\tseq{}\t{}\t{}\t{}",
+            i,
+            species(i),
+            seq_len,
+            mappings.join(" ")
+        )?;
+    }
+    seqid2taxid.flush()?;
+    kraken.flush()?;
+
+    let report_file = dir.join("report.kreport");
+    let species_reads: Vec<usize> = (0..n_genomes).map(|_| rng.gen_range(0..1_000)).collect();
+    let genus_reads: Vec<usize> = (0..n_genera).map(|_| rng.gen_range(0..100)).collect();
+    let unclassified = 1_000;
+    let classified: usize = species_reads.iter().sum::<usize>() + genus_reads.iter().sum::<usize>();
+    let total = (classified + unclassified) as f64;
+    let mut report = BufWriter::new(File::create(&report_file)?);
+    let mut line = |reads: usize, own: usize, rank: &str, taxid: u32, depth: usize, name: &str| {
+        writeln!(
+            report,
+            "{:6.2}\t{}\t{}\t{}\t{}\t{}{}",
+            100.0 * reads as f64 / total,
+            reads,
+            own,
+            rank,
+            taxid,
+            "  ".repeat(depth),
+            name
+        )
+    };
+    line(unclassified, unclassified, "U", 0, 0, "unclassified")?;
+    line(classified, 0, "R", 1, 0, "root")?;
+    line(classified, 0, "D", 2, 1, "Bacteria")?;
+    for (g, &own) in genus_reads.iter().enumerate() {
+        let members = g * 5..((g + 1) * 5).min(n_genomes);
+        let clade = own + species_reads[members.clone()].iter().sum::<usize>();
+        line(clade, own, "G", genus(g * 5), 2, &format!("Genus {}", g))?;
+        for i in members {
+            let name = format!("Species {}", i);
+            line(
+                species_reads[i],
+                species_reads[i],
+                "S",
+                species(i),
+                3,
+                &name,
+            )?;
+        }
+    }
+    report.flush()?;
+    Ok(report_file)
+}
+
+pub fn run(args: Args) -> Result<(), Box<dyn std::error::Error>> {
+    let workdir = args.workdir.clone().unwrap_or_else(|| {
+        std::env::temp_dir().join(format!("bracken-bench-{}", std::process::id()))
+    });
+    fs::create_dir_all(&workdir)?;
+    let (db, report) = match (&args.database_dir, args.synthetic_genomes) {
+        (Some(db), _) => (db.clone(), args.report.clone()),
+        (None, Some(n_genomes)) => {
+            let db = workdir.join("db");
+            info!(
+                ">> Synthesizing {} genomes of {} bp in {:?}",
+                n_genomes, args.synthetic_seq_len, db
+            );
+            let report = synthesize(
+                &db,
+                n_genomes,
+                args.synthetic_seq_len,
+                args.kmer_len,
+                args.seed,
+            )?;
+            (db, Some(report))
+        }
+        (None, None) => return Err("either --db or --synthetic-genomes is required".into()),
+    };
+
+    let kraken_file = db.join("database.kraken");
+    let kraken_cnts = workdir.join(format!("database{}mers.kraken_cnts", args.read_len));
+    let kmer_distr = workdir.join(format!("database{}mers.kmer_distrib", args.read_len));
+
+    let mut timings = Vec::new();
+    timings.push(time_stage("kmer2read-distr", &kraken_file, || {
+        let seqid2taxid = kmer2read_distr::get_seqid2taxid(db.join("seqid2taxid.map"))?;
+        let taxo =
+            taxonomy::load_taxonomy(db.join("taxonomy"))?.prune(seqid2taxid.values().copied());
+        kmer2read_distr::evaluate_kfile(
+            &kraken_file,
+            &kraken_cnts,
+            seqid2taxid,
+            args.read_len,
+            args.kmer_len,
+            &taxo,
+            kmer2read_distr::OutputOptions::default(),
+        )?;
+        Ok(())
+    })?);
+    timings.push(time_stage("kmer-distrib", &kraken_cnts, || {
+        kmer_distrib::generate_kmer_distribution(
+            std::slice::from_ref(&kraken_cnts),
+            &kmer_distr,
+            &kmer_distrib::DistribOptions::default(),
+        )?;
+        Ok(())
+    })?);
+    match &report {
+        Some(report) => {
+            let bracken_output = workdir.join("report.bracken");
+            timings.push(time_stage("est-abundance", report, || {
+                let est_args = est_abundance::Args::try_parse_from([
+                    "est-abundance".as_ref(),
+                    "-i".as_ref(),
+                    report.as_os_str(),
+                    "-k".as_ref(),
+                    kmer_distr.as_os_str(),
+                    "-o".as_ref(),
+                    bracken_output.as_os_str(),
+                ])?;
+                est_abundance::run(est_args)
+            })?);
+        }
+        None => warn!("No --report given; the est-abundance stage is skipped"),
+    }
+
+    let out: Box<dyn Write> = if args.output == Path::new("-") {
+        Box::new(io::stdout().lock())
+    } else {
+        Box::new(File::create(&args.output)?)
+    };
+    let mut out = BufWriter::new(out);
+    writeln!(
+        out,
+        "stage\tthreads\tseconds\tinput_lines\tlines_per_sec\tpeak_rss_mb"
+    )?;
+    for timing in timings.iter() {
+        writeln!(
+            out,
+            "{}\t{}\t{:.3}\t{}\t{:.0}\t{}",
+            timing.stage,
+            rayon::current_num_threads(),
+            timing.seconds,
+            timing.lines,
+            timing.lines as f64 / timing.seconds.max(1e-9),
+            timing
+                .peak_rss_kb
+                .map_or("NA".to_string(), |kb| format!("{:.1}", kb as f64 / 1024.0))
+        )?;
+    }
+    out.flush()?;
+
+    if args.keep {
+        info!(">> Kept the work folder {:?}", workdir);
+    } else if let Err(e) = fs::remove_dir_all(&workdir) {
+        warn!("Could not remove the work folder {:?}: {}", workdir, e);
+    }
+    Ok(())
+}
+
+#[allow(dead_code)]
+fn main() {
+    bracken::logging::init(0, false);
+    let args = Args::parse();
+    if let Err(e) = run(args) {
+        error!("Application error: {}", e);
+    }
+}
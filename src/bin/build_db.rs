@@ -0,0 +1,149 @@
+use bracken::database::{self, Compression};
+use bracken::kmer2read_distr::OutputOptions;
+use bracken::kraken2::{self, Kraken2Options};
+use clap::Parser;
+use log::{error, info, warn};
+use std::path::PathBuf;
+
+#[derive(Parser, Clone, Debug)]
+#[clap(
+    version,
+    about = "Builds the kmer distribution of a Kraken database.",
+    long_about = "Runs kmer2read-distr and kmer-distrib in sequence on a Kraken database (database.kraken or database.kraken.gz, seqid2taxid.map and taxonomy/), writing database{L}mers.kraken_cnts and database{L}mers.kmer_distrib into it, like bracken-build. With --kraken2, a missing database.kraken is first produced by classifying the library/ sequences with kraken2, in chunks so an interrupted build resumes after the last classified chunk."
+)]
+pub struct Args {
+    /// Kraken database folder.
+    #[clap(long = "db", required = true)]
+    database_dir: PathBuf,
+
+    /// kmer length used to build the Kraken database [default: the k of its
+    /// opts.k2d, or 35].
+    #[clap(short = 'k', long)]
+    kmer_len: Option<usize>,
+
+    /// Read lengths of the samples, comma-separated; database.kraken is
+    /// converted once for all of them.
+    #[clap(short = 'l', long, value_delimiter = ',', default_value = "100")]
+    read_len: Vec<usize>,
+
+    /// Number of threads (same as the global --threads).
+    #[clap(short = 't', value_name = "THREADS")]
+    build_threads: Option<usize>,
+
+    /// Regenerate the outputs even if they exist.
+    #[clap(long)]
+    force: bool,
+
+    /// Write the read distributions in the order of database.kraken, so
+    /// reruns produce identical files.
+    #[clap(long)]
+    ordered: bool,
+
+    /// Write the read distributions in the compact binary format instead of
+    /// text.
+    #[clap(long)]
+    binary_counts: bool,
+
+    /// Also write per-sequence classification statistics (shares of the
+    /// reads classified to the sequence's taxid, its ancestors, other taxa
+    /// and none) to this TSV file when the read distributions are generated.
+    #[clap(long, value_name = "FILE")]
+    stats: Option<PathBuf>,
+
+    /// Compress the read and kmer distributions with gzip
+    /// (database{L}mers.kraken_cnts.gz, database{L}mers.kmer_distrib.gz).
+    #[clap(long, conflicts_with = "zstd_output")]
+    gzip_output: bool,
+
+    /// Compress the read and kmer distributions with zstd
+    /// (database{L}mers.kraken_cnts.zst, database{L}mers.kmer_distrib.zst).
+    #[clap(long)]
+    zstd_output: bool,
+
+    /// kraken2 executable, to generate database.kraken from the library/
+    /// sequences when it is missing.
+    #[clap(long)]
+    kraken2: Option<PathBuf>,
+
+    /// Megabytes of library sequences classified by a kraken2 run.
+    #[clap(long, default_value_t = 1024, requires = "kraken2")]
+    chunk_size: u64,
+}
+
+pub fn run(mut args: Args) -> Result<(), Box<dyn std::error::Error>> {
+    args.read_len.sort_unstable();
+    args.read_len.dedup();
+    if let Some(threads) = args.build_threads {
+        if rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build_global()
+            .is_err()
+        {
+            warn!("The thread pool is already configured by --threads; -t is ignored");
+        }
+    }
+    let kmer_len = match args.kmer_len {
+        Some(kmer_len) => kmer_len,
+        None => kraken2::index_options(&args.database_dir)?.map_or(35, |options| options.kmer_len),
+    };
+    info!(
+        "BUILDING {}mers KMER DISTRIBUTION OF {:?} ({}mers database, {} threads)",
+        args.read_len
+            .iter()
+            .map(|read_len| read_len.to_string())
+            .collect::<Vec<_>>()
+            .join(", "),
+        args.database_dir,
+        kmer_len,
+        rayon::current_num_threads()
+    );
+    if let Some(binary) = &args.kraken2 {
+        let kraken_file = database::kraken_file_path(&args.database_dir);
+        if kraken_file.exists() {
+            info!(">> Reusing {:?}", kraken_file);
+        } else {
+            let options = Kraken2Options {
+                binary: binary.clone(),
+                threads: rayon::current_num_threads(),
+                chunk_size: args.chunk_size.max(1) << 20,
+            };
+            let kraken_file = kraken2::classify_library(&args.database_dir, &options)?;
+            info!("KRAKEN FILE PRODUCED: {:?}", kraken_file.display());
+        }
+    }
+    let kmer_distrs = database::build_kmer_distrs(
+        &args.database_dir,
+        kmer_len,
+        &args.read_len,
+        args.force,
+        OutputOptions {
+            ordered: args.ordered,
+            binary: args.binary_counts,
+            legacy: false,
+            stats: args.stats.clone(),
+            taxid_summary: None,
+            header: None,
+            split: None,
+            compression: if args.gzip_output {
+                Compression::Gzip
+            } else if args.zstd_output {
+                Compression::Zstd
+            } else {
+                Compression::None
+            },
+        },
+    )?;
+    for kmer_distr in kmer_distrs {
+        info!("KMER DISTRIBUTION PRODUCED: {:?}", kmer_distr.display());
+    }
+    Ok(())
+}
+
+#[allow(dead_code)]
+fn main() {
+    bracken::logging::init(0, false);
+    let args = Args::parse();
+    if let Err(e) = run(args) {
+        error!("Application error: {}", e);
+    }
+}
@@ -0,0 +1,77 @@
+use bracken::bracken_output::{self, suffixed_output, Table};
+use bracken::taxonomy;
+use clap::Parser;
+use log::{error, info};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+
+#[derive(Parser, Clone, Debug)]
+#[clap(
+    version,
+    about = "Sums a Bracken output up to higher ranks.",
+    long_about = "Sums the estimates of a Bracken output (e.g. species) up to their ancestors at higher ranks (e.g. genus, family or phylum) using the taxonomy, so tables at other ranks do not need another est-abundance run."
+)]
+pub struct Args {
+    /// Bracken output file.
+    #[clap(short, long, required = true)]
+    input: PathBuf,
+
+    /// Taxonomy folder with nodes.dmp (and names.dmp for the names of the
+    /// collapsed taxa).
+    #[clap(long = "taxonomy", required = true)]
+    taxonomy_dir: PathBuf,
+
+    /// Comma-separated ranks to collapse to (D, P, C, O, F, G or S).
+    #[clap(short, long, value_delimiter = ',', required = true)]
+    rank: Vec<String>,
+
+    /// Output file; with several ranks, one file per rank is written as
+    /// <output>.<RANK>.<ext>.
+    #[clap(short, long, required = true)]
+    output: PathBuf,
+}
+
+pub fn run(args: Args) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(rank) = args
+        .rank
+        .iter()
+        .find(|rank| taxonomy::ncbi_ranks(rank).is_none())
+    {
+        return Err(format!("unknown rank {:?}", rank).into());
+    }
+    let table = bracken_output::read_table(&args.input)?;
+    let taxo = taxonomy::load_taxonomy(args.taxonomy_dir.clone())?;
+    let names = taxonomy::load_taxonomy_names(&args.taxonomy_dir)?;
+
+    for rank in args.rank.iter() {
+        let collapsed = Table {
+            abundance_header: table.abundance_header.clone(),
+            rows: bracken_output::collapse(&table.rows, rank, &taxo, &names),
+        };
+        let path = if args.rank.len() > 1 {
+            suffixed_output(&args.output, rank)
+        } else {
+            args.output.clone()
+        };
+        let mut file = BufWriter::new(File::create(&path)?);
+        bracken_output::write_table(&collapsed, &mut file)?;
+        file.flush()?;
+        info!(
+            "BRACKEN {} OUTPUT PRODUCED: {:?} ({} taxa)",
+            rank,
+            path.display(),
+            collapsed.rows.len()
+        );
+    }
+    Ok(())
+}
+
+#[allow(dead_code)]
+fn main() {
+    bracken::logging::init(0, false);
+    let args = Args::parse();
+    if let Err(e) = run(args) {
+        error!("Application error: {}", e);
+    }
+}
@@ -0,0 +1,276 @@
+use bracken::bracken_output::{self, suffixed_output};
+use bracken::decontam::{self, Method};
+use bracken::exit_code::ClassifiedError;
+use clap::Parser;
+use log::{error, info, warn};
+use std::collections::{BTreeSet, HashMap};
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+#[derive(Parser, Clone, Debug)]
+#[clap(
+    version,
+    about = "Combines the Bracken outputs of several samples into abundance matrices.",
+    long_about = "Outer-joins the Bracken outputs of several samples on taxid and writes a counts matrix (<output>.counts) and a fractions matrix (<output>.fractions) with one column per sample; taxa missing from a sample are zero-filled. Contaminants, scored by their prevalence in negative controls or their frequency against the sample depth, can be flagged or removed."
+)]
+pub struct Args {
+    /// Bracken output files, one per sample.
+    #[clap(short, long, num_args = 1.., required_unless_present = "sample_sheet")]
+    input: Vec<PathBuf>,
+
+    /// Tab-separated sample sheet of `sample<TAB>bracken_output` lines;
+    /// relative paths are taken from the sheet's directory.
+    #[clap(long, conflicts_with_all = ["input", "sample_names"])]
+    sample_sheet: Option<PathBuf>,
+
+    /// Comma-separated sample names for --input [default: file names].
+    #[clap(long, value_delimiter = ',')]
+    sample_names: Vec<String>,
+
+    /// Output file; the matrices are written to <output>.counts and
+    /// <output>.fractions (before the extension).
+    #[clap(short, long, required = true)]
+    output: PathBuf,
+
+    /// Comma-separated names of the negative control samples.
+    #[clap(long, value_delimiter = ',', value_name = "SAMPLES")]
+    negative_controls: Vec<String>,
+
+    /// How taxa are scored as contaminants: prevalence compares the negative
+    /// controls with the other samples, frequency checks whether a taxon's
+    /// fraction falls with the sample's total reads (the sum of its counts
+    /// column), leaving the controls out.
+    #[clap(long, value_enum, default_value_t = Method::Prevalence)]
+    contaminant_method: Method,
+
+    /// Contaminant score above which a taxon is a contaminant; 0.5 flags taxa
+    /// more prevalent in the controls, or better fitted as contaminants.
+    #[clap(long, default_value_t = 0.5, value_parser = parse_score)]
+    contaminant_threshold: f64,
+
+    /// Add a `contaminant` column to the matrices.
+    #[clap(long, conflicts_with = "remove_contaminants")]
+    flag_contaminants: bool,
+
+    /// Drop the contaminants from the matrices.
+    #[clap(long)]
+    remove_contaminants: bool,
+}
+
+fn parse_score(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(score) if (0.0..=1.0).contains(&score) => Ok(score),
+        _ => Err(format!("{:?} is no score between 0 and 1", value)),
+    }
+}
+
+/// Reads the (sample, path) pairs of a sample sheet.
+fn read_sample_sheet(sheet: &Path) -> Result<Vec<(String, PathBuf)>, Box<dyn std::error::Error>> {
+    let reader = BufReader::new(File::open(sheet)?);
+    let base = sheet.parent().unwrap_or(Path::new(""));
+    let mut samples = Vec::new();
+    for (ix, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        match line.split_once('\t') {
+            Some((sample, path)) => {
+                samples.push((sample.trim().to_string(), base.join(path.trim())));
+            }
+            None => {
+                return Err(ClassifiedError::input_format(format!(
+                    "{:?} line {}: expected sample<TAB>bracken_output",
+                    sheet,
+                    ix + 1
+                ))
+                .into())
+            }
+        }
+    }
+    Ok(samples)
+}
+
+/// Samples to combine with their names, from the sample sheet or --input.
+fn samples(args: &Args) -> Result<Vec<(String, PathBuf)>, Box<dyn std::error::Error>> {
+    if let Some(sheet) = &args.sample_sheet {
+        return read_sample_sheet(sheet);
+    }
+    if !args.sample_names.is_empty() && args.sample_names.len() != args.input.len() {
+        return Err(format!(
+            "{} sample names given for {} inputs",
+            args.sample_names.len(),
+            args.input.len()
+        )
+        .into());
+    }
+    Ok(args
+        .input
+        .iter()
+        .enumerate()
+        .map(|(ix, path)| {
+            let name = args.sample_names.get(ix).cloned().unwrap_or_else(|| {
+                path.file_name()
+                    .map(|name| name.to_string_lossy().to_string())
+                    .unwrap_or_default()
+            });
+            (name, path.clone())
+        })
+        .collect())
+}
+
+/// Indices of the negative control samples, each control counted once.
+fn control_indices(
+    samples: &[(String, PathBuf)],
+    controls: &[String],
+) -> Result<BTreeSet<usize>, Box<dyn std::error::Error>> {
+    let indices = controls
+        .iter()
+        .map(|control| {
+            samples
+                .iter()
+                .position(|(sample, _)| sample == control)
+                .ok_or_else(|| format!("negative control {:?} is no sample", control))
+        })
+        .collect::<Result<BTreeSet<_>, _>>()?;
+    if !indices.is_empty() && indices.len() >= samples.len() {
+        return Err("all samples are negative controls".into());
+    }
+    Ok(indices)
+}
+
+pub fn run(args: Args) -> Result<(), Box<dyn std::error::Error>> {
+    let samples = samples(&args)?;
+    if samples.is_empty() {
+        return Err(ClassifiedError::input_format("no samples to combine").into());
+    }
+    info!(">> Combining {} samples", samples.len());
+    let controls = control_indices(&samples, &args.negative_controls)?;
+    let scoring = args.flag_contaminants || args.remove_contaminants || !controls.is_empty();
+    if scoring && controls.is_empty() && args.contaminant_method == Method::Prevalence {
+        return Err("--contaminant-method prevalence needs --negative-controls".into());
+    }
+
+    // taxid -> (name, level, counts and fractions per sample)
+    let mut taxa: HashMap<u32, (String, String, Vec<usize>, Vec<f64>)> = HashMap::new();
+    let mut levels = BTreeSet::new();
+    let mut abundance_headers = BTreeSet::new();
+    for (ix, (sample, path)) in samples.iter().enumerate() {
+        let table = bracken_output::read_table(path)?;
+        info!("\t{}: {} taxa from {:?}", sample, table.rows.len(), path);
+        abundance_headers.insert(table.abundance_header);
+        for row in table.rows {
+            levels.insert(row.level.clone());
+            let entry = taxa.entry(row.taxid).or_insert_with(|| {
+                (
+                    row.name.clone(),
+                    row.level.clone(),
+                    vec![0; samples.len()],
+                    vec![0.0; samples.len()],
+                )
+            });
+            entry.2[ix] += row.new_est_reads;
+            entry.3[ix] += row.abundance;
+        }
+    }
+    if levels.iter().filter(|level| *level != "U").count() > 1 {
+        warn!("Samples mix taxonomy levels: {:?}", levels);
+    }
+    if abundance_headers.len() > 1 {
+        warn!(
+            "Samples use different abundance units: {:?}",
+            abundance_headers
+        );
+    }
+
+    let mut rows: Vec<_> = taxa.into_iter().collect();
+    rows.sort_by(|a, b| {
+        let total = |counts: &[usize]| counts.iter().sum::<usize>();
+        total(&b.1 .2).cmp(&total(&a.1 .2)).then(a.0.cmp(&b.0))
+    });
+
+    let mut contaminants = BTreeSet::new();
+    if scoring {
+        let totals: Vec<usize> = (0..samples.len())
+            .map(|ix| rows.iter().map(|(_, (_, _, counts, _))| counts[ix]).sum())
+            .collect();
+        contaminants = rows
+            .iter()
+            .filter(|(_, (_, _, counts, _))| {
+                let score = decontam::score(args.contaminant_method, counts, &totals, &controls);
+                score > args.contaminant_threshold
+            })
+            .map(|(taxid, _)| *taxid)
+            .collect();
+        info!(
+            "\t{} of {} taxa are contaminants ({:?} method, {} negative controls)",
+            contaminants.len(),
+            rows.len(),
+            args.contaminant_method,
+            controls.len()
+        );
+    }
+    if args.remove_contaminants {
+        rows.retain(|(taxid, _)| !contaminants.contains(taxid));
+    }
+    let flag = |taxid: &u32| {
+        if contaminants.contains(taxid) {
+            "\tyes"
+        } else {
+            "\tno"
+        }
+    };
+
+    let mut header = samples
+        .iter()
+        .map(|(sample, _)| sample.as_str())
+        .collect::<Vec<_>>()
+        .join("\t");
+    if args.flag_contaminants {
+        header.push_str("\tcontaminant");
+    }
+
+    let counts_file = suffixed_output(&args.output, "counts");
+    let mut file = BufWriter::new(File::create(&counts_file)?);
+    writeln!(file, "name\ttaxonomy_id\ttaxonomy_lvl\t{}", header)?;
+    for (taxid, (name, level, counts, _)) in rows.iter() {
+        write!(file, "{}\t{}\t{}", name, taxid, level)?;
+        for count in counts {
+            write!(file, "\t{}", count)?;
+        }
+        if args.flag_contaminants {
+            write!(file, "{}", flag(taxid))?;
+        }
+        writeln!(file)?;
+    }
+    file.flush()?;
+    info!("COUNTS MATRIX PRODUCED: {:?}", counts_file.display());
+
+    let fractions_file = suffixed_output(&args.output, "fractions");
+    let mut file = BufWriter::new(File::create(&fractions_file)?);
+    writeln!(file, "name\ttaxonomy_id\ttaxonomy_lvl\t{}", header)?;
+    for (taxid, (name, level, _, fractions)) in rows.iter() {
+        write!(file, "{}\t{}\t{}", name, taxid, level)?;
+        for fraction in fractions {
+            write!(file, "\t{:.5}", fraction)?;
+        }
+        if args.flag_contaminants {
+            write!(file, "{}", flag(taxid))?;
+        }
+        writeln!(file)?;
+    }
+    file.flush()?;
+    info!("FRACTIONS MATRIX PRODUCED: {:?}", fractions_file.display());
+    Ok(())
+}
+
+#[allow(dead_code)]
+fn main() {
+    bracken::logging::init(0, false);
+    let args = Args::parse();
+    if let Err(e) = run(args) {
+        error!("Application error: {}", e);
+    }
+}
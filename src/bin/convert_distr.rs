@@ -0,0 +1,76 @@
+use bracken::kmer_distrib;
+use clap::{Parser, ValueEnum};
+use log::{error, info, warn};
+use std::path::PathBuf;
+
+/// Format of a kmer distribution file.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DistrFormat {
+    Text,
+    Binary,
+}
+
+#[derive(Parser, Clone, Debug)]
+#[clap(
+    version,
+    about = "Converts a kmer distribution file between the text and binary formats.",
+    long_about = "Converts a .kmer_distrib file between the text format written by kmer-distrib and a compact binary format that loads much faster. est-abundance and the other subcommands detect the binary format by its header, so the converted file can replace the text one."
+)]
+pub struct Args {
+    /// Kmer distribution file.
+    #[clap(short, long, required = true)]
+    input: PathBuf,
+
+    /// Output kmer distribution file.
+    #[clap(short, long, required = true)]
+    output: PathBuf,
+
+    /// Output format [default: the other format than the input's].
+    #[clap(long, value_enum)]
+    to: Option<DistrFormat>,
+}
+
+pub fn run(args: Args) -> Result<(), Box<dyn std::error::Error>> {
+    let from = if kmer_distrib::is_binary(&args.input)? {
+        DistrFormat::Binary
+    } else {
+        DistrFormat::Text
+    };
+    let to = args.to.unwrap_or(match from {
+        DistrFormat::Text => DistrFormat::Binary,
+        DistrFormat::Binary => DistrFormat::Text,
+    });
+    if from == to {
+        warn!("{:?} is already in the {:?} format", args.input, to);
+    }
+
+    let mut entries = Vec::new();
+    let malformed =
+        kmer_distrib::for_each_entry(&args.input, "Reading kmer distribution", |entry| {
+            entries.push(entry)
+        })?;
+    if malformed > 0 {
+        warn!("{} malformed lines of {:?} skipped", malformed, args.input);
+    }
+    info!(
+        ">> Converting {} mapped taxids from {:?} to {:?}",
+        entries.len(),
+        from,
+        to
+    );
+    match to {
+        DistrFormat::Text => kmer_distrib::write_text(&entries, &args.output)?,
+        DistrFormat::Binary => kmer_distrib::write_binary(&entries, &args.output)?,
+    }
+    info!("KMER DISTRIBUTION PRODUCED: {:?}", args.output.display());
+    Ok(())
+}
+
+#[allow(dead_code)]
+fn main() {
+    bracken::logging::init(0, false);
+    let args = Args::parse();
+    if let Err(e) = run(args) {
+        error!("Application error: {}", e);
+    }
+}
@@ -0,0 +1,138 @@
+use bracken::database;
+use bracken::exit_code::ClassifiedError;
+use bracken::kmer_distrib;
+use bracken::taxonomy::{self, NCBINames, NCBITaxonomy};
+use clap::Parser;
+use log::error;
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+
+#[derive(Parser, Clone, Debug)]
+#[clap(
+    version,
+    about = "Prints statistics of a kmer distribution file.",
+    long_about = "Prints the number of mapped taxids and genomes of a .kmer_distrib file, its kmer totals, the share of the genomes' kmers classified to the genomes themselves, the read length it was built for, and the ambiguous classifications (taxids above the genomes) holding the most kmers."
+)]
+pub struct Args {
+    /// Kmer distribution file.
+    input: PathBuf,
+
+    /// Number of ambiguous classifications to list.
+    #[clap(short = 'n', long, default_value_t = 10)]
+    top: usize,
+
+    /// Taxonomy folder with nodes.dmp and names.dmp, to print the rank and
+    /// name of the listed taxa.
+    #[clap(long = "taxonomy")]
+    taxonomy_dir: Option<PathBuf>,
+}
+
+pub fn run(args: Args) -> Result<(), Box<dyn std::error::Error>> {
+    let summary = database::summarize_kmer_distr(&args.input)?;
+    if summary.mapped.is_empty() {
+        return Err(
+            ClassifiedError::empty_result(format!("no mapped taxids in {:?}", args.input)).into(),
+        );
+    }
+    let taxonomy: Option<(NCBITaxonomy, NCBINames)> = match &args.taxonomy_dir {
+        Some(taxonomy_dir) => Some((
+            taxonomy::load_taxonomy(taxonomy_dir.clone())?,
+            taxonomy::load_taxonomy_names(taxonomy_dir)?,
+        )),
+        None => None,
+    };
+
+    let mapped_kmers = summary.mapped_kmers();
+    let genome_kmers = summary.total_genome_kmers();
+    let self_kmers: u64 = summary.self_kmers.values().sum();
+    let percent = |part: u64, total: u64| 100.0 * part as f64 / total.max(1) as f64;
+    let mut ambiguous: Vec<_> = summary
+        .mapped
+        .iter()
+        .filter(|taxon| !summary.genome_kmers.contains_key(&taxon.taxid))
+        .collect();
+    ambiguous.sort_by(|a, b| b.kmers.cmp(&a.kmers).then(a.taxid.cmp(&b.taxid)));
+    let ambiguous_kmers: u64 = ambiguous.iter().map(|taxon| taxon.kmers).sum();
+
+    let mut out = BufWriter::new(io::stdout().lock());
+    writeln!(out, "file\t{}", args.input.display())?;
+    let provenance = kmer_distrib::Provenance::read(&args.input)?.unwrap_or_default();
+    match (
+        provenance.length("read_len"),
+        database::kmer_distr_read_len(&args.input),
+    ) {
+        (Some(read_len), _) => writeln!(out, "read_length\t{}", read_len)?,
+        (None, Some(read_len)) => writeln!(out, "read_length\t{} (from the file name)", read_len)?,
+        (None, None) => writeln!(out, "read_length\tunknown")?,
+    }
+    match provenance.length("kmer_len") {
+        Some(kmer_len) => writeln!(out, "kmer_length\t{}", kmer_len)?,
+        None => writeln!(out, "kmer_length\tnot recorded in the file")?,
+    }
+    for key in ["kraken_db", "version", "date"] {
+        if let Some(value) = provenance.get(key) {
+            writeln!(out, "{}\t{}", key, value)?;
+        }
+    }
+    writeln!(out, "mapped_taxids\t{}", summary.mapped.len())?;
+    writeln!(out, "genomes\t{}", summary.genome_kmers.len())?;
+    writeln!(out, "ambiguous_taxids\t{}", ambiguous.len())?;
+    writeln!(out, "mapped_kmers\t{}", mapped_kmers)?;
+    writeln!(out, "genome_kmers\t{}", genome_kmers)?;
+    writeln!(
+        out,
+        "kmers_to_own_genome\t{} ({:.2}%)",
+        self_kmers,
+        percent(self_kmers, genome_kmers)
+    )?;
+    writeln!(
+        out,
+        "kmers_to_ambiguous_taxids\t{} ({:.2}%)",
+        ambiguous_kmers,
+        percent(ambiguous_kmers, mapped_kmers)
+    )?;
+    if summary.malformed_lines > 0 {
+        writeln!(out, "malformed_lines\t{}", summary.malformed_lines)?;
+    }
+
+    if !ambiguous.is_empty() && args.top > 0 {
+        writeln!(out)?;
+        writeln!(
+            out,
+            "{:>10}  {:>8}  {:>14}  {:>8}  {:<13}  name",
+            "taxid", "genomes", "kmers", "pct", "rank"
+        )?;
+        for taxon in ambiguous.iter().take(args.top) {
+            let (rank, name) = match &taxonomy {
+                Some((taxo, names)) => (
+                    taxo.get_node(&taxon.taxid)
+                        .map(|node| node.rank.as_str())
+                        .unwrap_or("-"),
+                    names.get(&taxon.taxid).unwrap_or_default(),
+                ),
+                None => ("-", ""),
+            };
+            writeln!(
+                out,
+                "{:>10}  {:>8}  {:>14}  {:>7.2}%  {:<13}  {}",
+                taxon.taxid,
+                taxon.n_genomes,
+                taxon.kmers,
+                percent(taxon.kmers, mapped_kmers),
+                rank,
+                name
+            )?;
+        }
+    }
+    out.flush()?;
+    Ok(())
+}
+
+#[allow(dead_code)]
+fn main() {
+    bracken::logging::init(0, false);
+    let args = Args::parse();
+    if let Err(e) = run(args) {
+        error!("Application error: {}", e);
+    }
+}
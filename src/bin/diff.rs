@@ -0,0 +1,212 @@
+use bracken::bracken_output;
+use clap::Parser;
+use log::{error, info, warn};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+#[derive(Parser, Clone, Debug)]
+#[clap(
+    version,
+    about = "Compares two Bracken outputs.",
+    long_about = "Reports the taxa gained and lost between two Bracken outputs (e.g. before/after a database upgrade or two timepoints of a patient) and the log2 fold change of the fraction of every taxon."
+)]
+pub struct Args {
+    /// First Bracken output (reference).
+    a: PathBuf,
+
+    /// Second Bracken output, compared with the first.
+    b: PathBuf,
+
+    /// Output TSV file ("-" writes to stdout).
+    #[clap(short, long, default_value = "-")]
+    output: PathBuf,
+
+    /// Minimum absolute log2 fold change of the fraction for a taxon present
+    /// in both outputs to be called up or down.
+    #[clap(long, default_value_t = 1.0)]
+    min_log2_fold: f64,
+
+    /// Minimum fraction for a taxon to count as present; taxa below it in
+    /// one output and above it in the other are called gained or lost.
+    #[clap(long, default_value_t = 0.0)]
+    min_fraction: f64,
+
+    /// Fraction added to both sides of the fold change, so taxa missing from
+    /// one output get a finite log2 fold change.
+    #[clap(long, default_value_t = 1e-6)]
+    pseudocount: f64,
+}
+
+/// Change of a taxon between the two outputs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Change {
+    Gained,
+    Lost,
+    Up,
+    Down,
+    Unchanged,
+}
+
+impl Change {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Change::Gained => "gained",
+            Change::Lost => "lost",
+            Change::Up => "up",
+            Change::Down => "down",
+            Change::Unchanged => "unchanged",
+        }
+    }
+}
+
+/// A taxon of one output.
+struct Taxon {
+    name: String,
+    level: String,
+    reads: usize,
+    fraction: f64,
+}
+
+/// A taxon compared between the two outputs.
+struct DiffRow {
+    change: Change,
+    taxid: u32,
+    name: String,
+    level: String,
+    reads: (usize, usize),
+    fractions: (f64, f64),
+    log2_fold: f64,
+}
+
+/// Taxa of an output keyed by taxid, with fractions relative to all its
+/// estimated reads whatever the unit of its abundance column.
+fn read_taxa(path: &Path) -> Result<HashMap<u32, Taxon>, Box<dyn std::error::Error>> {
+    let table = bracken_output::read_table(path)?;
+    let total: usize = table.rows.iter().map(|row| row.new_est_reads).sum();
+    Ok(table
+        .rows
+        .into_iter()
+        .map(|row| {
+            let taxon = Taxon {
+                fraction: row.new_est_reads as f64 / total.max(1) as f64,
+                reads: row.new_est_reads,
+                name: row.name,
+                level: row.level,
+            };
+            (row.taxid, taxon)
+        })
+        .collect())
+}
+
+pub fn run(args: Args) -> Result<(), Box<dyn std::error::Error>> {
+    let a = read_taxa(&args.a)?;
+    let b = read_taxa(&args.b)?;
+
+    let mut taxids: Vec<u32> = a.keys().chain(b.keys()).copied().collect();
+    taxids.sort_unstable();
+    taxids.dedup();
+
+    let mut rows = Vec::new();
+    for taxid in taxids {
+        let (taxon_a, taxon_b) = (a.get(&taxid), b.get(&taxid));
+        let Some(taxon) = taxon_a.or(taxon_b) else {
+            continue;
+        };
+        let reads = (
+            taxon_a.map_or(0, |t| t.reads),
+            taxon_b.map_or(0, |t| t.reads),
+        );
+        let fractions = (
+            taxon_a.map_or(0.0, |t| t.fraction),
+            taxon_b.map_or(0.0, |t| t.fraction),
+        );
+        let log2_fold =
+            ((fractions.1 + args.pseudocount) / (fractions.0 + args.pseudocount)).log2();
+
+        let present = |reads: usize, fraction: f64| reads > 0 && fraction >= args.min_fraction;
+        let change = match (present(reads.0, fractions.0), present(reads.1, fractions.1)) {
+            (false, false) => continue,
+            (false, true) => Change::Gained,
+            (true, false) => Change::Lost,
+            _ if log2_fold >= args.min_log2_fold => Change::Up,
+            _ if log2_fold <= -args.min_log2_fold => Change::Down,
+            _ => Change::Unchanged,
+        };
+        rows.push(DiffRow {
+            change,
+            taxid,
+            name: taxon.name.clone(),
+            level: taxon.level.clone(),
+            reads,
+            fractions,
+            log2_fold,
+        });
+    }
+    rows.sort_by(|x, y| {
+        x.change
+            .cmp(&y.change)
+            .then(y.log2_fold.abs().total_cmp(&x.log2_fold.abs()))
+            .then(x.taxid.cmp(&y.taxid))
+    });
+
+    let out: Box<dyn Write> = if args.output == Path::new("-") {
+        Box::new(io::stdout().lock())
+    } else {
+        Box::new(File::create(&args.output)?)
+    };
+    let mut out = BufWriter::new(out);
+    writeln!(
+        out,
+        "name\ttaxonomy_id\ttaxonomy_lvl\treads_a\treads_b\tfraction_a\tfraction_b\tlog2_fold_change\tchange"
+    )?;
+    let mut counts: BTreeMap<Change, usize> = BTreeMap::new();
+    for row in rows.iter() {
+        writeln!(
+            out,
+            "{}\t{}\t{}\t{}\t{}\t{:.5}\t{:.5}\t{:.3}\t{}",
+            row.name,
+            row.taxid,
+            row.level,
+            row.reads.0,
+            row.reads.1,
+            row.fractions.0,
+            row.fractions.1,
+            row.log2_fold,
+            row.change.as_str()
+        )?;
+        *counts.entry(row.change).or_default() += 1;
+    }
+    out.flush()?;
+
+    let levels: BTreeSet<&str> = a
+        .values()
+        .chain(b.values())
+        .map(|taxon| taxon.level.as_str())
+        .filter(|level| *level != "U")
+        .collect();
+    if levels.len() > 1 {
+        warn!("The outputs mix taxonomy levels: {:?}", levels);
+    }
+    info!(
+        "{:?} vs {:?}: {}",
+        args.a,
+        args.b,
+        counts
+            .iter()
+            .map(|(change, n)| format!("{} {}", n, change.as_str()))
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+    Ok(())
+}
+
+#[allow(dead_code)]
+fn main() {
+    bracken::logging::init(0, false);
+    let args = Args::parse();
+    if let Err(e) = run(args) {
+        error!("Application error: {}", e);
+    }
+}
@@ -0,0 +1,198 @@
+use bracken::kmer_distrib;
+use clap::Parser;
+use log::{error, info, warn};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+#[derive(Parser, Clone, Debug)]
+#[clap(
+    version,
+    about = "Compares two kmer distribution files.",
+    long_about = "Reports the mapped taxids of two .kmer_distrib files (e.g. before/after adding genomes to a database) whose genomes changed, or whose fraction of a genome's reads changed by more than a tolerance, to see how a database update will shift the abundance estimates."
+)]
+pub struct Args {
+    /// First kmer distribution file (reference).
+    a: PathBuf,
+
+    /// Second kmer distribution file, compared with the first.
+    b: PathBuf,
+
+    /// Output TSV file ("-" writes to stdout).
+    #[clap(short, long, default_value = "-")]
+    output: PathBuf,
+
+    /// Minimum absolute change of the fraction of a genome's reads mapped
+    /// to a taxid for the taxid to be reported as changed.
+    #[clap(long, default_value_t = 0.01)]
+    tolerance: f64,
+
+    /// Also list the unchanged mapped taxids.
+    #[clap(long)]
+    all: bool,
+}
+
+/// Change of a mapped taxid between the two files.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Change {
+    Added,
+    Removed,
+    Changed,
+    Unchanged,
+}
+
+impl Change {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Change::Added => "added",
+            Change::Removed => "removed",
+            Change::Changed => "changed",
+            Change::Unchanged => "unchanged",
+        }
+    }
+}
+
+/// Fraction of the reads of every genome mapped to a taxid.
+type Fractions = HashMap<u32, f64>;
+
+/// A mapped taxid compared between the two files.
+struct DiffRow {
+    change: Change,
+    taxid: u32,
+    genomes: (usize, usize),
+    genomes_added: usize,
+    genomes_removed: usize,
+    /// Genome whose fraction changed the most, and the change.
+    max_change: Option<(u32, f64)>,
+}
+
+/// Fractions of the genomes of every mapped taxid of a kmer distribution.
+fn read_fractions(path: &Path) -> Result<HashMap<u32, Fractions>, Box<dyn std::error::Error>> {
+    let mut taxa: HashMap<u32, Fractions> = HashMap::new();
+    let malformed = kmer_distrib::for_each_entry(path, "Reading kmer distribution", |entry| {
+        let fractions = taxa.entry(entry.mapped_taxid).or_default();
+        for genome in entry.genomes {
+            let fraction = genome.kmers_mapped as f64 / genome.total_kmers.max(1) as f64;
+            *fractions.entry(genome.taxid).or_default() += fraction;
+        }
+    })?;
+    if malformed > 0 {
+        warn!("{} malformed lines of {:?} skipped", malformed, path);
+    }
+    Ok(taxa)
+}
+
+fn compare(taxid: u32, a: Option<&Fractions>, b: Option<&Fractions>, tolerance: f64) -> DiffRow {
+    let empty = Fractions::new();
+    let (a, b, change) = match (a, b) {
+        (Some(a), Some(b)) => (a, b, Change::Unchanged),
+        (None, Some(b)) => (&empty, b, Change::Added),
+        (Some(a), None) => (a, &empty, Change::Removed),
+        (None, None) => (&empty, &empty, Change::Unchanged),
+    };
+    let genomes_added = b.keys().filter(|genome| !a.contains_key(genome)).count();
+    let genomes_removed = a.keys().filter(|genome| !b.contains_key(genome)).count();
+    let max_change = a
+        .keys()
+        .chain(b.keys())
+        .map(|genome| {
+            let fraction = |fractions: &Fractions| fractions.get(genome).copied().unwrap_or(0.0);
+            (*genome, fraction(b) - fraction(a))
+        })
+        .max_by(|x, y| x.1.abs().total_cmp(&y.1.abs()).then(y.0.cmp(&x.0)));
+    let change = match change {
+        Change::Unchanged
+            if genomes_added > 0
+                || genomes_removed > 0
+                || max_change.is_some_and(|(_, diff)| diff.abs() > tolerance) =>
+        {
+            Change::Changed
+        }
+        change => change,
+    };
+    DiffRow {
+        change,
+        taxid,
+        genomes: (a.len(), b.len()),
+        genomes_added,
+        genomes_removed,
+        max_change,
+    }
+}
+
+pub fn run(args: Args) -> Result<(), Box<dyn std::error::Error>> {
+    let a = read_fractions(&args.a)?;
+    let b = read_fractions(&args.b)?;
+
+    let mut taxids: Vec<u32> = a.keys().chain(b.keys()).copied().collect();
+    taxids.sort_unstable();
+    taxids.dedup();
+    let mut rows: Vec<DiffRow> = taxids
+        .into_iter()
+        .map(|taxid| compare(taxid, a.get(&taxid), b.get(&taxid), args.tolerance))
+        .collect();
+    let max_abs = |row: &DiffRow| row.max_change.map_or(0.0, |(_, diff)| diff.abs());
+    rows.sort_by(|x, y| {
+        x.change
+            .cmp(&y.change)
+            .then(max_abs(y).total_cmp(&max_abs(x)))
+            .then(x.taxid.cmp(&y.taxid))
+    });
+
+    let out: Box<dyn Write> = if args.output == Path::new("-") {
+        Box::new(io::stdout().lock())
+    } else {
+        Box::new(File::create(&args.output)?)
+    };
+    let mut out = BufWriter::new(out);
+    writeln!(
+        out,
+        "mapped_taxid\tgenomes_a\tgenomes_b\tgenomes_added\tgenomes_removed\tmax_change_genome\tmax_fraction_change\tchange"
+    )?;
+    let mut counts: BTreeMap<Change, usize> = BTreeMap::new();
+    for row in rows.iter() {
+        *counts.entry(row.change).or_default() += 1;
+        if row.change == Change::Unchanged && !args.all {
+            continue;
+        }
+        let (genome, diff) = match row.max_change {
+            Some((genome, diff)) => (genome.to_string(), diff),
+            None => ("-".to_string(), 0.0),
+        };
+        writeln!(
+            out,
+            "{}\t{}\t{}\t{}\t{}\t{}\t{:.5}\t{}",
+            row.taxid,
+            row.genomes.0,
+            row.genomes.1,
+            row.genomes_added,
+            row.genomes_removed,
+            genome,
+            diff,
+            row.change.as_str()
+        )?;
+    }
+    out.flush()?;
+
+    info!(
+        "{:?} vs {:?}: {}",
+        args.a,
+        args.b,
+        counts
+            .iter()
+            .map(|(change, n)| format!("{} {}", n, change.as_str()))
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+    Ok(())
+}
+
+#[allow(dead_code)]
+fn main() {
+    bracken::logging::init(0, false);
+    let args = Args::parse();
+    if let Err(e) = run(args) {
+        error!("Application error: {}", e);
+    }
+}
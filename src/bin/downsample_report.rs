@@ -0,0 +1,84 @@
+use bracken::kraken;
+use clap::Parser;
+use log::{error, info, warn};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+#[derive(Parser, Clone, Debug)]
+#[clap(
+    version,
+    about = "Rarefies a Kraken report to a read depth.",
+    long_about = "Subsamples the reads of a Kraken report without replacement to a target depth and writes a new Kraken report, so samples sequenced to different depths can be compared without touching the FASTQ files."
+)]
+pub struct Args {
+    /// Kraken report file.
+    #[clap(short, long, required = true)]
+    input: PathBuf,
+
+    /// Output Kraken report file ("-" writes to stdout).
+    #[clap(short, long, required = true)]
+    output: PathBuf,
+
+    /// Number of reads to keep, including unclassified reads.
+    #[clap(short, long, required = true)]
+    depth: usize,
+
+    /// Seed for the random number generator used by subsampling.
+    #[clap(long, default_value_t = 0)]
+    seed: u64,
+
+    /// Keep the taxa left without reads (like kraken2 --report-zero-counts).
+    #[clap(long)]
+    keep_zero: bool,
+}
+
+pub fn run(args: Args) -> Result<(), Box<dyn std::error::Error>> {
+    kraken::check_report_file(&args.input)?;
+    let report = kraken::read_kraken_report(&args.input)?;
+    let total_reads: usize = report.iter().map(|(_, node)| node.lvl_reads).sum();
+    if args.depth >= total_reads {
+        warn!(
+            "The report has {} reads, not more than the depth {}; it is copied unchanged",
+            total_reads, args.depth
+        );
+    }
+
+    let mut rng = StdRng::seed_from_u64(args.seed);
+    let mut subsample = kraken::subsample_report(&report, args.depth, &mut rng);
+    if !args.keep_zero {
+        // 分支读数为 0 的节点其子节点也为 0, 删除后层级不变
+        subsample.retain(|(_, node)| node.all_reads > 0);
+    }
+
+    let out: Box<dyn Write> = if args.output == Path::new("-") {
+        Box::new(io::stdout().lock())
+    } else {
+        Box::new(File::create(&args.output)?)
+    };
+    let mut out = BufWriter::new(out);
+    kraken::write_kraken_report(&subsample, &mut out)?;
+    out.flush()?;
+    info!(
+        "DOWNSAMPLED REPORT PRODUCED: {:?} ({} of {} reads, {} taxa)",
+        args.output.display(),
+        subsample
+            .iter()
+            .map(|(_, node)| node.lvl_reads)
+            .sum::<usize>(),
+        total_reads,
+        subsample.len()
+    );
+    Ok(())
+}
+
+#[allow(dead_code)]
+fn main() {
+    bracken::logging::init(0, false);
+    let args = Args::parse();
+    if let Err(e) = run(args) {
+        error!("Application error: {}", e);
+    }
+}
@@ -0,0 +1,237 @@
+use bracken::bracken_output::{self, Row};
+use bracken::exit_code::ClassifiedError;
+use bracken::taxonomy;
+use clap::Parser;
+use log::{error, info};
+use std::collections::{BTreeSet, HashMap};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+#[derive(Parser, Clone, Debug)]
+#[clap(
+    version,
+    about = "Compares a Bracken output with a ground-truth composition.",
+    long_about = "Computes the L1 and L2 errors and the Bray-Curtis distance between the estimated and true fractions, and the precision, recall and F1 score of the detected taxa, optionally after collapsing both to a rank. The truth table is a simulate truth table, a Bracken output, or any TSV with taxonomy_id and fraction columns."
+)]
+pub struct Args {
+    /// Bracken output file.
+    input: PathBuf,
+
+    /// Truth table.
+    truth: PathBuf,
+
+    /// Output file of the metrics ("-" writes to stdout).
+    #[clap(short, long, default_value = "-")]
+    output: PathBuf,
+
+    /// Write the true and estimated fraction and the error of every taxon to
+    /// this file.
+    #[clap(long)]
+    per_taxon: Option<PathBuf>,
+
+    /// Compare at this rank (D, P, C, O, F, G or S) by collapsing both tables
+    /// to it first.
+    #[clap(short, long, requires = "taxonomy_dir")]
+    rank: Option<String>,
+
+    /// Taxonomy folder with nodes.dmp, needed by --rank.
+    #[clap(long = "taxonomy")]
+    taxonomy_dir: Option<PathBuf>,
+
+    /// Minimum estimated fraction for a taxon to count as detected.
+    #[clap(long, default_value_t = 0.0)]
+    min_fraction: f64,
+}
+
+/// Reads a truth table as rows whose abundance is the true fraction (or
+/// read count) of the taxon.
+fn read_truth(path: &Path) -> Result<Vec<Row>, Box<dyn std::error::Error>> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut lines = reader.lines();
+    let header = lines.next().transpose()?.unwrap_or_default();
+    let columns: Vec<&str> = header.trim_end().split('\t').collect();
+    let column = |names: &[&str]| columns.iter().position(|c| names.contains(c));
+    let taxid_col = column(&["taxonomy_id", "taxid"]);
+    // Read counts, when present, give the fractions without rounding
+    let fraction_col = column(&["new_est_reads", "reads"])
+        .or_else(|| column(&["fraction_total_reads", "fraction", "abundance"]));
+    let (Some(taxid_col), Some(fraction_col)) = (taxid_col, fraction_col) else {
+        return Err(ClassifiedError::input_format(format!(
+            "{:?} is not a truth table (expected taxonomy_id and fraction columns)",
+            path
+        ))
+        .into());
+    };
+    let name_col = column(&["name"]);
+
+    let mut rows = Vec::new();
+    for (ix, line) in lines.enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let malformed = |message: &str| {
+            ClassifiedError::input_format(format!("{:?} line {}: {}", path, ix + 2, message))
+        };
+        let fields: Vec<&str> = line.trim_end_matches(['\r', '\n']).split('\t').collect();
+        let field = |col: usize| {
+            fields
+                .get(col)
+                .copied()
+                .ok_or_else(|| malformed("missing column"))
+        };
+        rows.push(Row {
+            name: name_col
+                .and_then(|col| fields.get(col))
+                .unwrap_or(&"")
+                .to_string(),
+            taxid: field(taxid_col)?
+                .parse()
+                .map_err(|_| malformed("invalid taxonomy_id"))?,
+            level: String::new(),
+            kraken_assigned_reads: 0,
+            added_reads: 0,
+            new_est_reads: 0,
+            abundance: field(fraction_col)?
+                .parse()
+                .map_err(|_| malformed("invalid fraction"))?,
+        });
+    }
+    Ok(rows)
+}
+
+/// Fractions of the classified taxa, normalized to sum to 1.
+fn fractions(rows: &[Row]) -> HashMap<u32, (String, f64)> {
+    let mut fractions: HashMap<u32, (String, f64)> = HashMap::new();
+    for row in rows.iter().filter(|row| row.taxid != 0) {
+        let entry = fractions
+            .entry(row.taxid)
+            .or_insert_with(|| (row.name.clone(), 0.0));
+        entry.1 += row.abundance;
+    }
+    let total: f64 = fractions.values().map(|(_, fraction)| fraction).sum();
+    if total > 0.0 {
+        fractions
+            .values_mut()
+            .for_each(|(_, fraction)| *fraction /= total);
+    }
+    fractions
+}
+
+pub fn run(args: Args) -> Result<(), Box<dyn std::error::Error>> {
+    let mut estimated = bracken_output::read_table(&args.input)?.rows;
+    let total_reads: usize = estimated.iter().map(|row| row.new_est_reads).sum();
+    for row in estimated.iter_mut() {
+        row.abundance = row.new_est_reads as f64 / total_reads.max(1) as f64;
+    }
+    let mut truth = read_truth(&args.truth)?;
+
+    if let (Some(rank), Some(taxonomy_dir)) = (&args.rank, &args.taxonomy_dir) {
+        if taxonomy::ncbi_ranks(rank).is_none() {
+            return Err(format!("unknown rank {:?}", rank).into());
+        }
+        let taxo = taxonomy::load_taxonomy(taxonomy_dir.clone())?;
+        let names = taxonomy::load_taxonomy_names(taxonomy_dir)?;
+        estimated = bracken_output::collapse(&estimated, rank, &taxo, &names);
+        truth = bracken_output::collapse(&truth, rank, &taxo, &names);
+    }
+    let estimated = fractions(&estimated);
+    let truth = fractions(&truth);
+    if truth.is_empty() {
+        return Err(ClassifiedError::empty_result(format!("no taxa in {:?}", args.truth)).into());
+    }
+
+    let taxids: BTreeSet<u32> = estimated.keys().chain(truth.keys()).copied().collect();
+    let (mut l1, mut l2, mut sum) = (0.0, 0.0, 0.0);
+    let (mut tp, mut fp, mut fn_) = (0usize, 0usize, 0usize);
+    let mut per_taxon = Vec::new();
+    for taxid in taxids {
+        let (name, true_fraction) = truth.get(&taxid).cloned().unwrap_or_default();
+        let (est_name, est_fraction) = estimated.get(&taxid).cloned().unwrap_or_default();
+        let error = est_fraction - true_fraction;
+        l1 += error.abs();
+        l2 += error * error;
+        sum += est_fraction + true_fraction;
+
+        let detected = est_fraction > 0.0 && est_fraction >= args.min_fraction;
+        let status = match (true_fraction > 0.0, detected) {
+            (true, true) => {
+                tp += 1;
+                "TP"
+            }
+            (false, true) => {
+                fp += 1;
+                "FP"
+            }
+            (true, false) => {
+                fn_ += 1;
+                "FN"
+            }
+            (false, false) => "TN",
+        };
+        let name = if name.is_empty() { est_name } else { name };
+        per_taxon.push((name, taxid, true_fraction, est_fraction, error, status));
+    }
+    let bray_curtis = if sum > 0.0 { l1 / sum } else { 0.0 };
+    let ratio = |a: usize, b: usize| {
+        if a + b > 0 {
+            a as f64 / (a + b) as f64
+        } else {
+            0.0
+        }
+    };
+    let precision = ratio(tp, fp);
+    let recall = ratio(tp, fn_);
+    let f1 = if precision + recall > 0.0 {
+        2.0 * precision * recall / (precision + recall)
+    } else {
+        0.0
+    };
+
+    let out: Box<dyn Write> = if args.output == Path::new("-") {
+        Box::new(io::stdout().lock())
+    } else {
+        Box::new(File::create(&args.output)?)
+    };
+    let mut out = BufWriter::new(out);
+    writeln!(out, "metric\tvalue")?;
+    writeln!(out, "l1_error\t{:.6}", l1)?;
+    writeln!(out, "l2_error\t{:.6}", l2.sqrt())?;
+    writeln!(out, "bray_curtis\t{:.6}", bray_curtis)?;
+    writeln!(out, "true_positives\t{}", tp)?;
+    writeln!(out, "false_positives\t{}", fp)?;
+    writeln!(out, "false_negatives\t{}", fn_)?;
+    writeln!(out, "precision\t{:.6}", precision)?;
+    writeln!(out, "recall\t{:.6}", recall)?;
+    writeln!(out, "f1\t{:.6}", f1)?;
+    out.flush()?;
+
+    if let Some(path) = &args.per_taxon {
+        per_taxon.sort_by(|a, b| b.4.abs().total_cmp(&a.4.abs()).then(a.1.cmp(&b.1)));
+        let mut file = BufWriter::new(File::create(path)?);
+        writeln!(
+            file,
+            "name\ttaxonomy_id\ttrue_fraction\testimated_fraction\terror\tstatus"
+        )?;
+        for (name, taxid, true_fraction, est_fraction, error, status) in per_taxon.iter() {
+            writeln!(
+                file,
+                "{}\t{}\t{:.6}\t{:.6}\t{:.6}\t{}",
+                name, taxid, true_fraction, est_fraction, error, status
+            )?;
+        }
+        file.flush()?;
+        info!("PER-TAXON ERRORS PRODUCED: {:?}", path.display());
+    }
+    Ok(())
+}
+
+#[allow(dead_code)]
+fn main() {
+    bracken::logging::init(0, false);
+    let args = Args::parse();
+    if let Err(e) = run(args) {
+        error!("Application error: {}", e);
+    }
+}
@@ -0,0 +1,108 @@
+use bracken::kraken::{self, Node};
+use clap::Parser;
+use log::{error, info, warn};
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+#[derive(Parser, Clone, Debug)]
+#[clap(
+    version,
+    about = "Extracts or removes subtrees of a Kraken report.",
+    long_about = "Keeps (or with --remove, drops) the subtrees rooted at the given taxids of a Kraken report and rewrites the clade counts and percentages, e.g. to make host-free or pathogen-focused reports before est-abundance."
+)]
+pub struct Args {
+    /// Kraken report file.
+    #[clap(short, long, required = true)]
+    input: PathBuf,
+
+    /// Output Kraken report file ("-" writes to stdout).
+    #[clap(short, long, required = true)]
+    output: PathBuf,
+
+    /// Root taxid of a subtree; may be given multiple times or
+    /// comma-separated.
+    #[clap(short, long, value_delimiter = ',', required = true)]
+    taxid: Vec<u32>,
+
+    /// Remove the subtrees instead of extracting them.
+    #[clap(long)]
+    remove: bool,
+
+    /// Write only the extracted subtrees, indented from their roots, without
+    /// their ancestors and the unclassified reads.
+    #[clap(long, conflicts_with = "remove")]
+    reroot: bool,
+}
+
+/// Nodes of the subtrees rooted at the given taxids, re-indented so every
+/// subtree root is at the top level.
+fn reroot(report: Vec<(usize, Node)>, taxids: &HashSet<u32>) -> Vec<(usize, Node)> {
+    let mut subtrees = Vec::new();
+    let mut base_level: Option<usize> = None;
+    for (indx, mut node) in report {
+        if base_level.is_some_and(|base| node.level_num <= base) {
+            base_level = None;
+        }
+        if base_level.is_none() && taxids.contains(&node.taxid) {
+            base_level = Some(node.level_num);
+        }
+        if let Some(base) = base_level {
+            node.level_num -= base;
+            subtrees.push((indx, node));
+        }
+    }
+    subtrees
+}
+
+pub fn run(args: Args) -> Result<(), Box<dyn std::error::Error>> {
+    kraken::check_report_file(&args.input)?;
+    let mut report = kraken::read_kraken_report(&args.input)?;
+    let taxids: HashSet<u32> = args.taxid.iter().copied().collect();
+    let total_reads: usize = report.iter().map(|(_, node)| node.lvl_reads).sum();
+
+    let missing: Vec<u32> = args
+        .taxid
+        .iter()
+        .copied()
+        .filter(|taxid| !report.iter().any(|(_, node)| node.taxid == *taxid))
+        .collect();
+    if !missing.is_empty() {
+        warn!("Taxids not found in the report: {:?}", missing);
+    }
+
+    if args.remove {
+        kraken::exclude_subtrees(&mut report, &taxids);
+    } else if args.reroot {
+        report = reroot(report, &taxids);
+    } else {
+        kraken::restrict_to_subtrees(&mut report, |node| taxids.contains(&node.taxid));
+    }
+
+    let out: Box<dyn Write> = if args.output == Path::new("-") {
+        Box::new(io::stdout().lock())
+    } else {
+        Box::new(File::create(&args.output)?)
+    };
+    let mut out = BufWriter::new(out);
+    kraken::write_kraken_report(&report, &mut out)?;
+    out.flush()?;
+    info!(
+        "REPORT PRODUCED: {:?} ({} of {} reads, {} taxa)",
+        args.output.display(),
+        report.iter().map(|(_, node)| node.lvl_reads).sum::<usize>(),
+        total_reads,
+        report.len()
+    );
+    Ok(())
+}
+
+#[allow(dead_code)]
+fn main() {
+    bracken::logging::init(0, false);
+    let args = Args::parse();
+    if let Err(e) = run(args) {
+        error!("Application error: {}", e);
+    }
+}
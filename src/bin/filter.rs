@@ -0,0 +1,102 @@
+use bracken::bracken_output;
+use bracken::kraken;
+use clap::Parser;
+use log::{error, info};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+#[derive(Parser, Clone, Debug)]
+#[clap(
+    version,
+    about = "Filters the taxa of a Bracken output.",
+    long_about = "Removes the taxa of a Bracken output below minimum reads or fraction, outside taxid lists or at other levels, and renormalizes the abundance column over the remaining taxa, so thresholds can be tightened without rerunning est-abundance."
+)]
+pub struct Args {
+    /// Bracken output file.
+    #[clap(short, long, required = true)]
+    input: PathBuf,
+
+    /// Output file ("-" writes to stdout).
+    #[clap(short, long, required = true)]
+    output: PathBuf,
+
+    /// Minimum estimated reads (new_est_reads) of a taxon.
+    #[clap(long, default_value_t = 0)]
+    min_reads: usize,
+
+    /// Minimum fraction of a taxon, relative to the estimated reads of the
+    /// input before filtering.
+    #[clap(long, default_value_t = 0.0)]
+    min_fraction: f64,
+
+    /// File of taxids (one per line) to keep.
+    #[clap(long, value_name = "FILE")]
+    include_taxids: Option<PathBuf>,
+
+    /// File of taxids (one per line) to remove.
+    #[clap(long, value_name = "FILE")]
+    exclude_taxids: Option<PathBuf>,
+
+    /// Comma-separated levels (taxonomy_lvl) to keep, e.g. S or S,U.
+    #[clap(short, long, value_delimiter = ',')]
+    level: Vec<String>,
+}
+
+pub fn run(args: Args) -> Result<(), Box<dyn std::error::Error>> {
+    let mut table = bracken_output::read_table(&args.input)?;
+    let include_taxids = args
+        .include_taxids
+        .as_ref()
+        .map(kraken::read_taxid_list)
+        .transpose()?;
+    let exclude_taxids = args
+        .exclude_taxids
+        .as_ref()
+        .map(kraken::read_taxid_list)
+        .transpose()?;
+
+    let n_taxa = table.rows.len();
+    let total_reads: usize = table.rows.iter().map(|row| row.new_est_reads).sum();
+    table.rows.retain(|row| {
+        row.new_est_reads >= args.min_reads
+            && row.new_est_reads as f64 / total_reads.max(1) as f64 >= args.min_fraction
+            && include_taxids
+                .as_ref()
+                .is_none_or(|include| include.contains(&row.taxid))
+            && exclude_taxids
+                .as_ref()
+                .is_none_or(|exclude| !exclude.contains(&row.taxid))
+            && (args.level.is_empty() || args.level.contains(&row.level))
+    });
+    table.renormalize();
+
+    let kept_reads: usize = table.rows.iter().map(|row| row.new_est_reads).sum();
+    info!(
+        "Kept {} of {} taxa ({} of {} estimated reads)",
+        table.rows.len(),
+        n_taxa,
+        kept_reads,
+        total_reads
+    );
+
+    let out: Box<dyn Write> = if args.output == Path::new("-") {
+        Box::new(io::stdout().lock())
+    } else {
+        Box::new(File::create(&args.output)?)
+    };
+    let mut out = BufWriter::new(out);
+    bracken_output::write_table(&table, &mut out)?;
+    out.flush()?;
+    info!("FILTERED OUTPUT PRODUCED: {:?}", args.output.display());
+    Ok(())
+}
+
+#[allow(dead_code)]
+fn main() {
+    bracken::logging::init(0, false);
+    let args = Args::parse();
+    if let Err(e) = run(args) {
+        error!("Application error: {}", e);
+    }
+}
@@ -1,74 +1,425 @@
 use bracken::ctime::timeval_subtract;
-use bracken::{kmer2read_distr, taxonomy};
+use bracken::database::{self, Compression};
+use bracken::kmer2read_distr::{
+    Chunk, MalformedLines, OnDuplicate, OutputOptions, PairedReads, ReadOptions, ShortSequences,
+    SplitOutput, TieBreak, ZeroKmers,
+};
+use bracken::{kmer2read_distr, kraken2, taxonomy};
 use clap::Parser;
-use std::path::PathBuf;
+use log::{error, info, warn};
+use std::path::{Path, PathBuf};
 use std::time::SystemTime;
 
 #[derive(Parser, Clone, Debug)]
 #[clap(version, about = "bracken kmer2read_distr", long_about)]
 pub struct Args {
+    /// Kraken database folder: its seqid2taxid.map, taxonomy/,
+    /// database.kraken and the kmer length of its opts.k2d are used
+    /// unless given, and recorded in the header of the output
+    #[clap(long = "db")]
+    database_dir: Option<PathBuf>,
+
     /// seqid2taxid file generated during
     /// Kraken database building process
-    #[clap(long)]
-    seqid2taxid: PathBuf,
+    #[clap(long, required_unless_present = "database_dir")]
+    seqid2taxid: Option<PathBuf>,
 
     /// taxonomy folder containing the nodes.dmp file
     /// (typically downloaded with the Kraken taxonomy)
-    #[clap(long = "taxonomy")]
-    taxonomy_dir: PathBuf,
+    #[clap(long = "taxonomy", required_unless_present = "database_dir")]
+    taxonomy_dir: Option<PathBuf>,
 
     /// kraken file of all classifications of all library
-    /// sequences (typically database.kraken)
-    #[clap(long)]
-    kraken: PathBuf,
+    /// sequences (typically database.kraken, optionally compressed);
+    /// several files or quoted glob patterns are read in turn as one
+    #[clap(long, num_args = 1.., required_unless_present = "database_dir")]
+    kraken: Vec<PathBuf>,
 
     /// name of an output file to print read distributions to
-    /// (suggested name: databaseXmers.kraken_cnts)
+    /// (suggested name: databaseXmers.kraken_cnts); with several read
+    /// lengths, {L} in the name is replaced by each of them
     #[clap(long)]
     output: PathBuf,
 
     /// kmer length used to build Kraken database
-    /// (default = 31)
-    #[clap(short = 'k', default_value_t = 31)]
-    kmer_len: usize,
+    /// (default = 31, or the k of the opts.k2d of --db)
+    #[clap(short = 'k')]
+    kmer_len: Option<usize>,
 
-    /// read length (evaluate every l-length read)
+    /// read lengths (evaluate every l-length read), comma-separated
+    /// to convert the kraken file once for several lengths
     /// (default = 100)
-    #[clap(short = 'l', default_value_t = 100)]
-    read_len: usize,
+    #[clap(short = 'l', value_delimiter = ',', default_value = "100")]
+    read_len: Vec<usize>,
+
+    /// model paired-end reads: fragments of this mean length in bp,
+    /// read as two mates of the read length and classified to the
+    /// lowest common ancestor of the mates
+    #[clap(long, value_name = "BP")]
+    insert_size: Option<usize>,
+
+    /// standard deviation of the fragment lengths in bp
+    /// (default = 0)
+    #[clap(long, value_name = "BP", default_value_t = 0, requires = "insert_size")]
+    insert_sd: usize,
+
+    /// reads whose kmers are mostly unclassified (taxid 0): skipped
+    /// (ignore), left unclassified (count), or classified by their
+    /// other kmers (penalize)
+    #[clap(long, value_enum, default_value_t = ZeroKmers::default())]
+    zero_kmers: ZeroKmers,
+
+    /// classification of the reads whose taxa tie for the highest
+    /// score: their lowest common ancestor, the deepest of them, or the
+    /// lowest taxid of them
+    #[clap(long, value_enum, default_value_t = TieBreak::default())]
+    tie_break: TieBreak,
+
+    /// reads of the sequences shorter than the read length: none (skip),
+    /// one of all their kmers (single-window), or one padded with
+    /// unclassified kmers to the read length (pad)
+    #[clap(long, value_enum, default_value_t = ShortSequences::default())]
+    short_seq: ShortSequences,
+
+    /// the kmer columns count the positions of minimizers of this
+    /// length (l-mers) rather than kmers, so a read spans
+    /// read length - L + 1 of them [default: the minimizer length of
+    /// the opts.k2d of --db]
+    #[clap(long, value_name = "L")]
+    minimizer_len: Option<usize>,
+
+    /// seqids listed more than once in the seqid2taxid file or the
+    /// kraken files: fail (error), keep the first taxid and line (first),
+    /// or classify them to the lowest common ancestor of their taxids and
+    /// keep all their lines (merge)
+    #[clap(long, value_enum, default_value_t = OnDuplicate::default())]
+    on_duplicate: OnDuplicate,
+
+    /// malformed lines of the kraken files (fewer than five fields,
+    /// kmer items other than taxid:count): fail with the line number
+    /// (strict), or skip them or read their kmers as unclassified and
+    /// report how many there were (lenient)
+    #[clap(long, value_enum, default_value_t = MalformedLines::default())]
+    malformed: MalformedLines,
+
+    /// skip the first N sequences (lines) of the kraken files
+    /// (default = 0)
+    #[clap(long, value_name = "N", default_value_t = 0)]
+    skip: usize,
+
+    /// convert at most N sequences, to try parameters on a slice of
+    /// a large kraken file
+    #[clap(long, value_name = "N")]
+    limit: Option<usize>,
+
+    /// convert only chunk K of N, the sequences K, K+N, K+2N, ...,
+    /// so N cluster jobs share a large library; merge-cnts puts their
+    /// outputs together
+    #[clap(long, value_name = "K/N")]
+    chunk: Option<Chunk>,
+
+    /// share of a read's kmers, classified or not, that the clade of its
+    /// classification must hold, as Kraken 2's --confidence; reads are
+    /// raised to the lowest ancestor that does (default = 0)
+    #[clap(long, value_name = "FRACTION", default_value_t = 0.0)]
+    confidence: f64,
+
+    /// approximate bound on the memory taken by the lines being
+    /// converted, e.g. 512M or 16G; smaller batches of lines are read
+    /// and fewer are in flight, at some cost in speed
+    #[clap(long, value_name = "SIZE", value_parser = parse_size)]
+    max_memory: Option<usize>,
+
+    /// write the sequences in the order of the kraken file, with sorted
+    /// taxids, so reruns produce identical files
+    #[clap(long)]
+    ordered: bool,
+
+    /// write the compact binary format read by kmer-distrib
+    /// instead of text
+    #[clap(long)]
+    binary: bool,
+
+    /// write text lines exactly as the C++ kmer2read_distr does
+    /// (sorted taxids, a space after every taxid:count, no header), for
+    /// scripts and regression tests diffing against its outputs
+    #[clap(long, conflicts_with = "binary")]
+    legacy_format: bool,
+
+    /// write the counts of every genome taxid to a file of its own,
+    /// <taxid>.<output file name>, in this directory instead of the outputs
+    #[clap(long, value_name = "DIR", conflicts_with = "binary")]
+    split_by_taxid: Option<PathBuf>,
+
+    /// with --split-by-taxid, write a file per clade at this rank
+    /// (D, P, C, O, F, G or S) instead; taxid 0 gets the sequences
+    /// outside any
+    #[clap(long, value_name = "RANK", requires = "split_by_taxid", value_parser = parse_rank)]
+    split_rank: Option<String>,
+
+    /// also write per-sequence classification statistics (shares
+    /// of the reads classified to the sequence's taxid, its ancestors,
+    /// other taxa and none) to this TSV file
+    #[clap(long, value_name = "FILE")]
+    stats: Option<PathBuf>,
+
+    /// also write, for every genome taxid, the shares of the reads of
+    /// its sequences classified uniquely to it, escalated to its
+    /// ancestors, to other taxa and none to this TSV file, telling
+    /// whether the database can resolve it at all
+    #[clap(long, value_name = "FILE")]
+    taxid_summary: Option<PathBuf>,
+
+    /// overwrite the outputs if they exist
+    #[clap(long)]
+    force: bool,
 
-    /// number of threads
-    /// (default = 1)
-    #[clap(short = 't', default_value_t = 1)]
-    threads: usize,
+    /// compress the output with gzip (suggested suffix: .gz)
+    #[clap(long, conflicts_with = "zstd_output")]
+    gzip_output: bool,
+
+    /// compress the output with zstd (suggested suffix: .zst)
+    #[clap(long)]
+    zstd_output: bool,
+}
+
+/// Bytes of a size such as 512M or 16G (powers of 1024, an optional B).
+fn parse_size(size: &str) -> Result<usize, String> {
+    let upper = size.trim().to_ascii_uppercase();
+    let digits = upper.strip_suffix('B').unwrap_or(&upper);
+    let (digits, shift) = match digits.char_indices().last() {
+        Some((i, 'K')) => (&digits[..i], 10),
+        Some((i, 'M')) => (&digits[..i], 20),
+        Some((i, 'G')) => (&digits[..i], 30),
+        Some((i, 'T')) => (&digits[..i], 40),
+        _ => (digits, 0),
+    };
+    digits
+        .trim()
+        .parse::<usize>()
+        .ok()
+        .and_then(|value| value.checked_mul(1 << shift))
+        .ok_or_else(|| format!("invalid size {:?}, expected e.g. 512M or 16G", size))
+}
+
+/// Rank code of --split-rank, upper-cased.
+fn parse_rank(rank: &str) -> Result<String, String> {
+    let rank = rank.trim().to_ascii_uppercase();
+    match taxonomy::ncbi_ranks(&rank) {
+        Some(_) => Ok(rank),
+        None => Err(format!(
+            "unknown rank {:?}, expected D, P, C, O, F, G or S",
+            rank
+        )),
+    }
 }
 
-pub fn run(args: Args) -> Result<(), Box<dyn std::error::Error>> {
+/// Kraken files of the --kraken arguments, with the glob patterns among
+/// them expanded in alphabetical order.
+fn kraken_files(args: &[PathBuf]) -> Result<Vec<PathBuf>, Box<dyn std::error::Error>> {
+    let mut files = Vec::new();
+    for arg in args {
+        let pattern = arg.to_string_lossy();
+        if arg.exists() || !pattern.contains(['*', '?', '[']) {
+            files.push(arg.clone());
+            continue;
+        }
+        let matches = glob::glob(&pattern)?.collect::<Result<Vec<_>, _>>()?;
+        if matches.is_empty() {
+            return Err(format!("no kraken file matches {:?}", pattern).into());
+        }
+        files.extend(matches);
+    }
+    Ok(files)
+}
+
+/// Output file of every read length: `{L}` in `output` is replaced by the
+/// read length, which is required to tell several read lengths apart.
+fn output_files(output: &Path, read_lens: &[usize]) -> Result<Vec<(usize, PathBuf)>, String> {
+    let name = output.to_string_lossy();
+    if read_lens.len() > 1 && !name.contains("{L}") {
+        return Err(format!(
+            "--output {:?} must contain {{L}} with several read lengths",
+            output
+        ));
+    }
+    Ok(read_lens
+        .iter()
+        .map(|&read_len| {
+            let file = if name.contains("{L}") {
+                PathBuf::from(name.replace("{L}", &read_len.to_string()))
+            } else {
+                output.to_path_buf()
+            };
+            (read_len, file)
+        })
+        .collect())
+}
+
+pub fn run(mut args: Args) -> Result<(), Box<dyn std::error::Error>> {
+    args.read_len.sort_unstable();
+    args.read_len.dedup();
+    let outputs = output_files(&args.output, &args.read_len)?;
+    if args.split_by_taxid.is_none() {
+        database::check_overwrite(outputs.iter().map(|(_, file)| file.as_path()), args.force)?;
+    }
+    database::check_overwrite(
+        args.stats
+            .iter()
+            .chain(&args.taxid_summary)
+            .map(PathBuf::as_path),
+        args.force,
+    )?;
     let ta = SystemTime::now();
-    println!("\t>>STEP 0: PARSING COMMAND LINE ARGUMENTS");
-    let taxonomy_dir = args.taxonomy_dir;
+    info!("\t>>STEP 0: PARSING COMMAND LINE ARGUMENTS");
+    let db = args.database_dir.as_deref();
+    let index_options = match db {
+        Some(db) => kraken2::index_options(db)?,
+        None => None,
+    };
+    let kmer_len = match (args.kmer_len, index_options) {
+        (Some(kmer_len), Some(options)) if kmer_len != options.kmer_len => {
+            warn!(
+                "-k {} differs from the kmer length {} of the Kraken2 database",
+                kmer_len, options.kmer_len
+            );
+            kmer_len
+        }
+        (Some(kmer_len), _) => kmer_len,
+        (None, Some(options)) => options.kmer_len,
+        (None, None) => 31,
+    };
+    match (args.minimizer_len, index_options) {
+        (Some(minimizer_len), _) if minimizer_len > kmer_len => {
+            return Err(format!(
+                "--minimizer-len {} is longer than the kmer length {}",
+                minimizer_len, kmer_len
+            )
+            .into());
+        }
+        (Some(minimizer_len), Some(options)) if minimizer_len != options.minimizer_len => {
+            warn!(
+                "--minimizer-len {} differs from the minimizer length {} of the Kraken2 database",
+                minimizer_len, options.minimizer_len
+            );
+        }
+        _ => {}
+    }
+    let minimizer_len = args
+        .minimizer_len
+        .or(index_options.map(|options| options.minimizer_len));
+    // Reads span the kmers (or minimizers) the kmer columns count
+    let window_len = minimizer_len.unwrap_or(kmer_len);
+    if let Some(&read_len) = args
+        .read_len
+        .first()
+        .filter(|&&read_len| read_len < window_len)
+    {
+        return Err(format!(
+            "-l {} is shorter than the {}-mers of the kmer columns",
+            read_len, window_len
+        )
+        .into());
+    }
+    // Required unless --db is given
+    let in_db = |arg: Option<PathBuf>, name: &str| {
+        arg.or_else(|| db.map(|db| db.join(name)))
+            .expect("clap requires the argument without --db")
+    };
+    let seqid2taxid = in_db(args.seqid2taxid, "seqid2taxid.map");
+    let taxonomy_dir = in_db(args.taxonomy_dir, "taxonomy");
+    let kraken_files = match db {
+        Some(db) if args.kraken.is_empty() => vec![database::kraken_file_path(db)],
+        _ => kraken_files(&args.kraken)?,
+    };
+    let header = db.map(|db| {
+        let mut header = format!(
+            "kraken_db={} kmer_len={} seqid2taxid={}",
+            db.display(),
+            kmer_len,
+            seqid2taxid.display()
+        );
+        if let Some(minimizer_len) = minimizer_len {
+            header += &format!(" minimizer_len={}", minimizer_len);
+        }
+        header
+    });
+    // merge-cnts checks that the chunks of a library are all there
+    let header = match (header, args.chunk) {
+        (Some(header), Some(chunk)) => Some(format!("{} chunk={}", header, chunk)),
+        (None, Some(chunk)) => Some(format!("chunk={}", chunk)),
+        (header, None) => header,
+    };
     let json_file = taxonomy_dir.join("nodes.json");
     let dmp_file = taxonomy_dir.join("nodes.dmp");
     if json_file.exists() {
-        println!("\t\tTaxonomy nodes file: {:}", json_file.display());
+        info!("\t\tTaxonomy nodes file: {:}", json_file.display());
     } else {
-        println!("\t\tTaxonomy nodes file: {:}", dmp_file.display());
+        info!("\t\tTaxonomy nodes file: {:}", dmp_file.display());
+    }
+    info!("\t\tSeqid file:          {:}", seqid2taxid.display());
+    info!("\t\tKraken files:        {:}", kraken_files.len());
+    info!(
+        "\t\tNum Threads:         {:?}",
+        rayon::current_num_threads()
+    );
+    info!("\t\tKmer Length:         {:?}", kmer_len);
+    info!("\t\tRead Length:         {:?}", args.read_len);
+
+    if !(0.0..=1.0).contains(&args.confidence) {
+        return Err(format!("--confidence {} is not between 0 and 1", args.confidence).into());
     }
-    println!("\t\tSeqid file:          {:}", args.seqid2taxid.display());
-    println!("\t\tNum Threads:         {:?}", args.threads);
-    println!("\t\tKmer Length:         {:?}", args.kmer_len);
-    println!("\t\tRead Length:         {:?}", args.read_len);
 
-    let seq_tax_map = kmer2read_distr::get_seqid2taxid(args.seqid2taxid)?;
+    // The taxonomy merges the taxids of duplicate seqids
     let taxo = taxonomy::load_taxonomy(taxonomy_dir)?;
+    let (seq_tax_map, left_out) =
+        kmer2read_distr::get_seqid2taxid_with(seqid2taxid, args.on_duplicate, Some(&taxo))?;
+    // The kmers of a library are classified to its taxa or their
+    // ancestors, all that classifying its reads takes
+    let taxo = taxo.prune(seq_tax_map.values().copied().chain(left_out));
 
-    kmer2read_distr::evaluate_kfile(
-        args.kraken,
-        args.output,
+    kmer2read_distr::evaluate_kfile_lengths(
+        &kraken_files,
+        &outputs,
         seq_tax_map,
-        args.read_len,
-        args.kmer_len,
+        kmer_len,
         &taxo,
+        ReadOptions {
+            paired: args.insert_size.map(|insert_size| PairedReads {
+                insert_size,
+                insert_sd: args.insert_sd,
+            }),
+            zero_kmers: args.zero_kmers,
+            tie_break: args.tie_break,
+            short_seq: args.short_seq,
+            minimizer_len,
+            on_duplicate: args.on_duplicate,
+            malformed: args.malformed,
+            skip: args.skip,
+            limit: args.limit,
+            chunk: args.chunk,
+            confidence: args.confidence,
+            max_memory: args.max_memory,
+        },
+        OutputOptions {
+            ordered: args.ordered,
+            binary: args.binary,
+            legacy: args.legacy_format,
+            split: args.split_by_taxid.map(|dir| SplitOutput {
+                dir,
+                rank: args.split_rank,
+            }),
+            stats: args.stats,
+            taxid_summary: args.taxid_summary,
+            header,
+            compression: if args.gzip_output {
+                Compression::Gzip
+            } else if args.zstd_output {
+                Compression::Zstd
+            } else {
+                Compression::None
+            },
+        },
     )?;
 
     let tb = SystemTime::now();
@@ -81,21 +432,22 @@ pub fn run(args: Args) -> Result<(), Box<dyn std::error::Error>> {
             let seconds = total_seconds % 60; // 得到剩余秒数
             let microseconds = duration.subsec_micros(); // 得到微秒数
 
-            println!(
+            info!(
                 "\tTime Elapsed: {} minutes, {} seconds, {:.5} microseconds",
                 minutes, seconds, microseconds as f64
             );
-            println!("\t=============================");
+            info!("\t=============================");
         }
-        Err(e) => println!("{}", e),
+        Err(e) => warn!("{}", e),
     }
     Ok(())
 }
 
 #[allow(dead_code)]
 fn main() {
+    bracken::logging::init(0, false);
     let args = Args::parse();
     if let Err(e) = run(args) {
-        eprintln!("Application error: {}", e);
+        error!("Application error: {}", e);
     }
 }
@@ -0,0 +1,128 @@
+use bracken::kraken;
+use clap::Parser;
+use log::{error, info};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+#[derive(Parser, Clone, Debug)]
+#[clap(
+    version,
+    about = "Converts a Kraken report to MetaPhlAn-style lineages.",
+    long_about = "Writes every taxon of a Kraken report at a main rank as a MetaPhlAn-style lineage (d__Bacteria|p__Proteobacteria|...) with its clade reads, like kreport2mpa.py of KrakenTools."
+)]
+pub struct Args {
+    /// Kraken report file.
+    #[clap(short, long, required = true)]
+    input: PathBuf,
+
+    /// Output file ("-" writes to stdout).
+    #[clap(short, long, required = true)]
+    output: PathBuf,
+
+    /// Write the percentage of all reads instead of the clade reads.
+    #[clap(long)]
+    percentages: bool,
+
+    /// Include the ranks between the main ranks below the root (e.g. S1, or
+    /// "-") in the lineages, with the x__ prefix.
+    #[clap(long)]
+    intermediate_ranks: bool,
+
+    /// Write a "#Classification<TAB>sample" header line.
+    #[clap(long)]
+    display_header: bool,
+
+    /// Prefix of a rank as RANK=PREFIX (e.g. D=k__); may be given multiple
+    /// times or comma-separated [default: the lowercase rank code and "__"].
+    #[clap(long, value_delimiter = ',', value_parser = parse_rank_prefix)]
+    rank_prefix: Vec<(String, String)>,
+}
+
+fn parse_rank_prefix(s: &str) -> Result<(String, String), String> {
+    s.split_once('=')
+        .map(|(rank, prefix)| (rank.to_string(), prefix.to_string()))
+        .ok_or_else(|| format!("invalid rank prefix {:?}, expected RANK=PREFIX", s))
+}
+
+pub fn run(args: Args) -> Result<(), Box<dyn std::error::Error>> {
+    kraken::check_report_file(&args.input)?;
+    let report = kraken::read_kraken_report(&args.input)?;
+    let total_reads: usize = report.iter().map(|(_, node)| node.lvl_reads).sum();
+
+    let mut prefixes: HashMap<String, String> = ["K", "D", "P", "C", "O", "F", "G", "S"]
+        .iter()
+        .map(|rank| (rank.to_string(), format!("{}__", rank.to_lowercase())))
+        .collect();
+    prefixes.extend(args.rank_prefix.iter().cloned());
+
+    let out: Box<dyn Write> = if args.output == Path::new("-") {
+        Box::new(io::stdout().lock())
+    } else {
+        Box::new(File::create(&args.output)?)
+    };
+    let mut out = BufWriter::new(out);
+    if args.display_header {
+        let sample = args
+            .input
+            .file_stem()
+            .map(|s| s.to_string_lossy().to_string())
+            .unwrap_or_default();
+        writeln!(out, "#Classification\t{}", sample)?;
+    }
+
+    // 祖先节点的 (层级, 谱系片段)
+    let mut lineage: Vec<(usize, Option<String>)> = Vec::new();
+    let mut n_lines = 0;
+    for (_, node) in report.iter() {
+        if node.level_id == "U" {
+            continue;
+        }
+        while lineage
+            .last()
+            .is_some_and(|&(level_num, _)| level_num >= node.level_num)
+        {
+            lineage.pop();
+        }
+        let prefix = match prefixes.get(&node.level_id) {
+            Some(prefix) => Some(prefix.clone()),
+            None if args.intermediate_ranks && !node.level_id.starts_with('R') => {
+                Some("x__".to_string())
+            }
+            None => None,
+        };
+        let segment = prefix.map(|prefix| format!("{}{}", prefix, node.name.replace(' ', "_")));
+        lineage.push((node.level_num, segment));
+
+        if lineage.last().is_some_and(|(_, segment)| segment.is_some()) {
+            let path: Vec<&str> = lineage
+                .iter()
+                .filter_map(|(_, segment)| segment.as_deref())
+                .collect();
+            if args.percentages {
+                let percent = 100.0 * node.all_reads as f64 / total_reads.max(1) as f64;
+                writeln!(out, "{}\t{:.5}", path.join("|"), percent)?;
+            } else {
+                writeln!(out, "{}\t{}", path.join("|"), node.all_reads)?;
+            }
+            n_lines += 1;
+        }
+    }
+    out.flush()?;
+    info!(
+        "MPA-STYLE REPORT PRODUCED: {:?} ({} lineages)",
+        args.output.display(),
+        n_lines
+    );
+    Ok(())
+}
+
+#[allow(dead_code)]
+fn main() {
+    bracken::logging::init(0, false);
+    let args = Args::parse();
+    if let Err(e) = run(args) {
+        error!("Application error: {}", e);
+    }
+}
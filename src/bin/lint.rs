@@ -0,0 +1,358 @@
+use bracken::exit_code::ClassifiedError;
+use bracken::kraken::{self, Node};
+use clap::{Parser, ValueEnum};
+use log::{error, info, warn};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+#[derive(Parser, Clone, Debug)]
+#[clap(
+    version,
+    about = "Checks the structure of a Kraken report.",
+    long_about = "Reports the structural problems of a Kraken report: malformed lines, negative or overflowing counts, duplicate taxids, odd or jumping indentation, clade counts that differ from the taxon's own reads plus its children's clade counts, and percentages that differ from the clade counts. Clade counts, percentages and odd indentation can be repaired with --fix. Exits with an error if any finding is an error."
+)]
+pub struct Args {
+    /// Kraken report file.
+    input: PathBuf,
+
+    /// Output file of the findings ("-" writes to stdout).
+    #[clap(short, long, default_value = "-")]
+    output: PathBuf,
+
+    /// Format of the findings.
+    #[clap(long, value_enum, default_value_t = FindingsFormat::Tsv)]
+    format: FindingsFormat,
+
+    /// Write the repaired report (six-column format) to this file when
+    /// every finding can be repaired.
+    #[clap(long, value_name = "FILE")]
+    fix: Option<PathBuf>,
+}
+
+/// Format of the findings output.
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum FindingsFormat {
+    Tsv,
+    Json,
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum Severity {
+    Error,
+    Warning,
+}
+
+/// A problem found in the report.
+#[derive(Serialize, Debug)]
+struct Finding {
+    /// Line of the report (1-based).
+    line: usize,
+    severity: Severity,
+    code: &'static str,
+    taxid: Option<u32>,
+    message: String,
+    /// Whether --fix repairs it.
+    fixable: bool,
+}
+
+/// A well-formed line of the report.
+struct Line {
+    line: usize,
+    node: Node,
+    percent: Option<f64>,
+}
+
+/// Count of a report field: negative and overflowing counts are findings.
+fn parse_count(field: &str) -> Result<usize, (&'static str, String)> {
+    let field = field.trim();
+    match field.parse::<usize>() {
+        Ok(count) => Ok(count),
+        Err(_) if field.starts_with('-') && field[1..].bytes().all(|b| b.is_ascii_digit()) => {
+            Err(("negative_count", format!("negative count {}", field)))
+        }
+        Err(_) if !field.is_empty() && field.bytes().all(|b| b.is_ascii_digit()) => Err((
+            "overflowing_count",
+            format!("count {} does not fit in {} bits", field, usize::BITS),
+        )),
+        Err(_) => Err((
+            "malformed_count",
+            format!("count {:?} is not a number", field),
+        )),
+    }
+}
+
+/// Parses the report, collecting the findings of single lines.
+fn parse_report(content: &str, findings: &mut Vec<Finding>) -> Vec<Line> {
+    let mut lines = Vec::new();
+    let mut error = |line: usize, code, taxid, message| {
+        findings.push(Finding {
+            line,
+            severity: Severity::Error,
+            code,
+            taxid,
+            message,
+            fixable: false,
+        })
+    };
+    for (indx, text) in content.lines().enumerate() {
+        let line = indx + 1;
+        if text.trim().is_empty() || text.starts_with('#') || text.starts_with('%') {
+            continue;
+        }
+        let fields: Vec<&str> = text.trim_end().split('\t').collect();
+        if fields.len() < 5 {
+            error(
+                line,
+                "malformed_line",
+                None,
+                format!("{} fields, expected at least 5", fields.len()),
+            );
+            continue;
+        }
+        let taxid = match fields[fields.len() - 2].trim().parse::<u32>() {
+            Ok(taxid) => taxid,
+            Err(_) => {
+                error(
+                    line,
+                    "invalid_taxid",
+                    None,
+                    format!("invalid taxid {:?}", fields[fields.len() - 2]),
+                );
+                continue;
+            }
+        };
+        let mut counts_ok = true;
+        for field in [fields[1], fields[2]] {
+            if let Err((code, message)) = parse_count(field) {
+                error(line, code, Some(taxid), message);
+                counts_ok = false;
+            }
+        }
+        if !counts_ok {
+            continue;
+        }
+        match text.parse::<Node>() {
+            Ok(node) => lines.push(Line {
+                line,
+                node,
+                percent: fields[0].trim().parse().ok(),
+            }),
+            Err(e) => error(line, "malformed_line", Some(taxid), e.to_string()),
+        }
+    }
+    lines
+}
+
+/// Findings of the tree of the report: duplicate taxids, indentation and
+/// clade counts.
+fn check_tree(content: &str, lines: &[Line], findings: &mut Vec<Finding>) {
+    let raw: Vec<&str> = content.lines().collect();
+    let mut seen: HashMap<u32, usize> = HashMap::new();
+    let mut ancestors: Vec<usize> = Vec::new();
+    let mut children_reads = vec![0usize; lines.len()];
+    for (pos, line) in lines.iter().enumerate() {
+        let node = &line.node;
+        let mut finding = |severity, code, message, fixable| {
+            findings.push(Finding {
+                line: line.line,
+                severity,
+                code,
+                taxid: Some(node.taxid),
+                message,
+                fixable,
+            })
+        };
+        if let Some(&first) = seen.get(&node.taxid) {
+            finding(
+                Severity::Error,
+                "duplicate_taxid",
+                format!("taxid {} already on line {}", node.taxid, first),
+                false,
+            );
+        } else {
+            seen.insert(node.taxid, line.line);
+        }
+        let name = raw[line.line - 1].rsplit('\t').next().unwrap_or_default();
+        let spaces = name.chars().take_while(|&c| c == ' ').count();
+        if spaces % 2 == 1 {
+            finding(
+                Severity::Warning,
+                "odd_indentation",
+                format!("{} spaces of indentation, expected a multiple of 2", spaces),
+                true,
+            );
+        }
+        while let Some(&a) = ancestors.last() {
+            if lines[a].node.level_num < node.level_num {
+                break;
+            }
+            ancestors.pop();
+        }
+        let parent_level = ancestors.last().map(|&a| lines[a].node.level_num);
+        let expected_max = parent_level.map_or(0, |level| level + 1);
+        if node.level_num > expected_max && pos > 0 {
+            finding(
+                Severity::Error,
+                "indentation_jump",
+                format!(
+                    "indented {} levels below its parent",
+                    node.level_num - parent_level.unwrap_or(0)
+                ),
+                false,
+            );
+        }
+        if let Some(&parent) = ancestors.last() {
+            children_reads[parent] = children_reads[parent].saturating_add(node.all_reads);
+        }
+        ancestors.push(pos);
+    }
+
+    for (pos, line) in lines.iter().enumerate() {
+        let node = &line.node;
+        let expected = node.lvl_reads.saturating_add(children_reads[pos]);
+        if node.all_reads != expected {
+            findings.push(Finding {
+                line: line.line,
+                severity: Severity::Error,
+                code: "clade_count_mismatch",
+                taxid: Some(node.taxid),
+                message: format!(
+                    "clade count {}, but {} own reads plus {} in the children",
+                    node.all_reads, node.lvl_reads, children_reads[pos]
+                ),
+                fixable: true,
+            });
+        }
+    }
+}
+
+/// Findings of percentages that do not match the clade counts.
+fn check_percentages(lines: &[Line], findings: &mut Vec<Finding>) {
+    let total: usize = lines.iter().map(|line| line.node.lvl_reads).sum();
+    for line in lines {
+        let Some(percent) = line.percent else {
+            continue;
+        };
+        let expected = 100.0 * line.node.all_reads as f64 / total.max(1) as f64;
+        // Kraken prints the percentages with two decimals
+        if (percent - expected).abs() > 0.0051 {
+            findings.push(Finding {
+                line: line.line,
+                severity: Severity::Warning,
+                code: "percentage_mismatch",
+                taxid: Some(line.node.taxid),
+                message: format!("{:.2}% but {:.2}% of the reads", percent, expected),
+                fixable: true,
+            });
+        }
+    }
+}
+
+fn write_findings<W: Write>(
+    out: &mut W,
+    findings: &[Finding],
+    format: FindingsFormat,
+) -> Result<(), Box<dyn std::error::Error>> {
+    match format {
+        FindingsFormat::Tsv => {
+            writeln!(out, "line\tseverity\tcode\ttaxid\tfixable\tmessage")?;
+            for finding in findings {
+                writeln!(
+                    out,
+                    "{}\t{}\t{}\t{}\t{}\t{}",
+                    finding.line,
+                    match finding.severity {
+                        Severity::Error => "error",
+                        Severity::Warning => "warning",
+                    },
+                    finding.code,
+                    finding
+                        .taxid
+                        .map_or("-".to_string(), |taxid| taxid.to_string()),
+                    finding.fixable,
+                    finding.message
+                )?;
+            }
+        }
+        FindingsFormat::Json => {
+            serde_json::to_writer_pretty(&mut *out, findings)?;
+            writeln!(out)?;
+        }
+    }
+    Ok(())
+}
+
+pub fn run(args: Args) -> Result<(), Box<dyn std::error::Error>> {
+    let content = fs::read_to_string(&args.input)?;
+    let mut findings = Vec::new();
+    let lines = parse_report(&content, &mut findings);
+    check_tree(&content, &lines, &mut findings);
+    check_percentages(&lines, &mut findings);
+    findings.sort_by_key(|finding| finding.line);
+
+    let out: Box<dyn Write> = if args.output == Path::new("-") {
+        Box::new(io::stdout().lock())
+    } else {
+        Box::new(File::create(&args.output)?)
+    };
+    let mut out = BufWriter::new(out);
+    write_findings(&mut out, &findings, args.format)?;
+    out.flush()?;
+
+    let count = |severity| {
+        findings
+            .iter()
+            .filter(|finding| finding.severity == severity)
+            .count()
+    };
+    let (n_errors, n_warnings) = (count(Severity::Error), count(Severity::Warning));
+    let n_unfixable = findings.iter().filter(|finding| !finding.fixable).count();
+    info!(
+        "{:?}: {} errors, {} warnings, {} repairable",
+        args.input,
+        n_errors,
+        n_warnings,
+        findings.len() - n_unfixable
+    );
+
+    if let Some(fixed) = &args.fix {
+        if n_unfixable > 0 {
+            warn!(
+                "{} findings cannot be repaired; no repaired report written",
+                n_unfixable
+            );
+        } else {
+            let mut report: Vec<(usize, Node)> = lines
+                .into_iter()
+                .map(|line| (line.line - 1, line.node))
+                .collect();
+            kraken::recompute_clade_reads(&mut report);
+            let mut file = BufWriter::new(File::create(fixed)?);
+            kraken::write_kraken_report(&report, &mut file)?;
+            file.flush()?;
+            info!("REPAIRED REPORT PRODUCED: {:?}", fixed.display());
+            return Ok(());
+        }
+    }
+    if n_errors > 0 {
+        return Err(ClassifiedError::input_format(format!(
+            "{} errors in {:?}",
+            n_errors, args.input
+        ))
+        .into());
+    }
+    Ok(())
+}
+
+#[allow(dead_code)]
+fn main() {
+    bracken::logging::init(0, false);
+    let args = Args::parse();
+    if let Err(e) = run(args) {
+        error!("Application error: {}", e);
+    }
+}
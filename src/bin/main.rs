@@ -1,36 +1,222 @@
-use clap::{Parser, Subcommand};
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand};
+use std::ffi::OsString;
 
+mod bench;
+mod build_db;
+mod collapse;
+mod combine;
+mod convert_distr;
+mod db_inspect;
+mod diff;
+mod distr_diff;
+mod downsample_report;
 mod est_abundance;
+mod evaluate;
+mod extract_subtree;
+mod filter;
 mod kmer2read_distr;
 mod kmer_distrib;
+mod kreport2mpa;
+mod lint;
+mod merge_cnts;
+mod merge_kreports;
+mod normalize;
+mod pipeline;
+mod prune_distr;
+mod rarefaction;
+mod report_stats;
+mod self_test;
+mod seqid2taxid;
+mod simulate;
+mod taxonomy;
+mod top;
+mod validate;
 
 #[derive(Subcommand, Debug)]
 enum Commands {
-    Kmer2readDistr(kmer2read_distr::Args),
+    Kmer2readDistr(Box<kmer2read_distr::Args>),
     KmerDistrib(kmer_distrib::Args),
-    EstAbundance(est_abundance::Args),
+    EstAbundance(Box<est_abundance::Args>),
+    Validate(validate::Args),
+    Combine(combine::Args),
+    Rarefaction(rarefaction::Args),
+    Diff(diff::Args),
+    Top(top::Args),
+    Collapse(collapse::Args),
+    Filter(filter::Args),
+    MergeKreports(merge_kreports::Args),
+    DownsampleReport(downsample_report::Args),
+    Kreport2mpa(kreport2mpa::Args),
+    ExtractSubtree(extract_subtree::Args),
+    Build(build_db::Args),
+    Pipeline(pipeline::Args),
+    Simulate(simulate::Args),
+    Evaluate(evaluate::Args),
+    Taxonomy(taxonomy::Args),
+    DbInspect(db_inspect::Args),
+    SelfTest(self_test::Args),
+    Bench(bench::Args),
+    ConvertDistr(convert_distr::Args),
+    MergeCnts(merge_cnts::Args),
+    Seqid2taxid(seqid2taxid::Args),
+    DistrDiff(distr_diff::Args),
+    Lint(lint::Args),
+    PruneDistr(prune_distr::Args),
+    ReportStats(report_stats::Args),
+    Normalize(normalize::Args),
 }
 
 #[derive(Parser, Debug)]
-#[clap(author, version, about, long_about = None)]
+#[clap(
+    author,
+    version,
+    about,
+    long_about = None,
+    after_help = "Exit codes: 0 success, 1 other failure, 2 usage error, 3 invalid input format, \
+4 missing taxonomy, 5 empty result, 6 I/O error."
+)]
 struct Args {
     #[clap(subcommand)]
     cmd: Commands,
+
+    /// Increase the log verbosity (-v debug, -vv trace).
+    #[clap(short, long, global = true, action = clap::ArgAction::Count)]
+    verbose: u8,
+
+    /// Only log warnings and errors.
+    #[clap(short, long, global = true, conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// Number of worker threads shared by all parallel steps [default: all
+    /// cores].
+    #[clap(long, global = true)]
+    threads: Option<usize>,
+
+    /// Do not show progress bars on stderr.
+    #[clap(long, global = true)]
+    no_progress: bool,
+
+    /// TOML file with default argument values, globally or per subcommand
+    /// (e.g. [est-abundance]). Also read from BRACKEN_CONFIG.
+    #[clap(long, global = true)]
+    config: Option<std::path::PathBuf>,
 }
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let args = Args::parse();
+fn run() -> Result<(), Box<dyn std::error::Error>> {
+    let command_line: Vec<OsString> = std::env::args_os().collect();
+    let matches = bracken::config::pre_parse(&Args::command(), &command_line);
+    let config = match bracken::config::config_path(&matches) {
+        Some(path) => bracken::config::load(&path)?,
+        None => Default::default(),
+    };
+    let cmd = bracken::config::apply(Args::command(), &config, &matches);
+    let args = Args::from_arg_matches(&cmd.get_matches_from(command_line))?;
+    bracken::logging::init(args.verbose, args.quiet);
+    bracken::progress::init(!args.no_progress && !args.quiet);
+    if let Some(threads) = args.threads {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build_global()?;
+    }
 
     match args.cmd {
         Commands::EstAbundance(cmd_args) => {
-            est_abundance::run(cmd_args)?;
+            est_abundance::run(*cmd_args)?;
         }
         Commands::Kmer2readDistr(cmd_args) => {
-            kmer2read_distr::run(cmd_args)?;
+            kmer2read_distr::run(*cmd_args)?;
         }
         Commands::KmerDistrib(cmd_args) => {
             kmer_distrib::run(cmd_args)?;
         }
+        Commands::Validate(cmd_args) => {
+            validate::run(cmd_args)?;
+        }
+        Commands::Combine(cmd_args) => {
+            combine::run(cmd_args)?;
+        }
+        Commands::Rarefaction(cmd_args) => {
+            rarefaction::run(cmd_args)?;
+        }
+        Commands::Diff(cmd_args) => {
+            diff::run(cmd_args)?;
+        }
+        Commands::Top(cmd_args) => {
+            top::run(cmd_args)?;
+        }
+        Commands::Collapse(cmd_args) => {
+            collapse::run(cmd_args)?;
+        }
+        Commands::Filter(cmd_args) => {
+            filter::run(cmd_args)?;
+        }
+        Commands::MergeKreports(cmd_args) => {
+            merge_kreports::run(cmd_args)?;
+        }
+        Commands::DownsampleReport(cmd_args) => {
+            downsample_report::run(cmd_args)?;
+        }
+        Commands::Kreport2mpa(cmd_args) => {
+            kreport2mpa::run(cmd_args)?;
+        }
+        Commands::ExtractSubtree(cmd_args) => {
+            extract_subtree::run(cmd_args)?;
+        }
+        Commands::Build(cmd_args) => {
+            build_db::run(cmd_args)?;
+        }
+        Commands::Pipeline(cmd_args) => {
+            pipeline::run(cmd_args)?;
+        }
+        Commands::Simulate(cmd_args) => {
+            simulate::run(cmd_args)?;
+        }
+        Commands::Evaluate(cmd_args) => {
+            evaluate::run(cmd_args)?;
+        }
+        Commands::Taxonomy(cmd_args) => {
+            taxonomy::run(cmd_args)?;
+        }
+        Commands::DbInspect(cmd_args) => {
+            db_inspect::run(cmd_args)?;
+        }
+        Commands::SelfTest(cmd_args) => {
+            self_test::run(cmd_args)?;
+        }
+        Commands::Bench(cmd_args) => {
+            bench::run(cmd_args)?;
+        }
+        Commands::ConvertDistr(cmd_args) => {
+            convert_distr::run(cmd_args)?;
+        }
+        Commands::MergeCnts(cmd_args) => {
+            merge_cnts::run(cmd_args)?;
+        }
+        Commands::Seqid2taxid(cmd_args) => {
+            seqid2taxid::run(cmd_args)?;
+        }
+        Commands::DistrDiff(cmd_args) => {
+            distr_diff::run(cmd_args)?;
+        }
+        Commands::Lint(cmd_args) => {
+            lint::run(cmd_args)?;
+        }
+        Commands::PruneDistr(cmd_args) => {
+            prune_distr::run(cmd_args)?;
+        }
+        Commands::ReportStats(cmd_args) => {
+            report_stats::run(cmd_args)?;
+        }
+        Commands::Normalize(cmd_args) => {
+            normalize::run(cmd_args)?;
+        }
     }
     Ok(())
 }
+
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("Error: {}", e);
+        std::process::exit(bracken::exit_code::of(e.as_ref()));
+    }
+}
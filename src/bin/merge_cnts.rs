@@ -0,0 +1,61 @@
+use bracken::database::{self, Compression};
+use bracken::kmer2read_distr;
+use clap::Parser;
+use log::{error, info};
+use std::path::PathBuf;
+
+#[derive(Parser, Clone, Debug)]
+#[clap(
+    version,
+    about = "Merges partial read distributions into one kraken_cnts file.",
+    long_about = "Puts the read distributions of several kmer2read-distr runs together, e.g. the chunks of a library converted by cluster jobs with --chunk K/N. The inputs must be all text or all binary; text inputs must have the same header, and chunked ones must include every chunk exactly once."
+)]
+pub struct Args {
+    /// Read distribution files (optionally gzip- or zstd-compressed).
+    #[clap(short, long, num_args = 1.., required = true)]
+    input: Vec<PathBuf>,
+
+    /// Output read distribution file.
+    #[clap(short, long, required = true)]
+    output: PathBuf,
+
+    /// Compress the output with gzip.
+    #[clap(long, conflicts_with = "zstd_output")]
+    gzip_output: bool,
+
+    /// Compress the output with zstd.
+    #[clap(long)]
+    zstd_output: bool,
+
+    /// Overwrite the output if it exists.
+    #[clap(long)]
+    force: bool,
+}
+
+pub fn run(args: Args) -> Result<(), Box<dyn std::error::Error>> {
+    database::check_overwrite([args.output.as_path()], args.force)?;
+    let compression = if args.gzip_output {
+        Compression::Gzip
+    } else if args.zstd_output {
+        Compression::Zstd
+    } else {
+        Compression::None
+    };
+    let sequences = kmer2read_distr::merge_counts(&args.input, &args.output, compression)?;
+    info!(
+        "MERGED READ DISTRIBUTIONS PRODUCED: {:?} ({} inputs, {} sequences)",
+        args.output.display(),
+        args.input.len(),
+        sequences
+    );
+    Ok(())
+}
+
+#[allow(dead_code)]
+fn main() {
+    bracken::logging::init(0, false);
+    let args = Args::parse();
+    if let Err(e) = run(args) {
+        error!("Application error: {}", e);
+    }
+}
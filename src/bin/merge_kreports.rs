@@ -0,0 +1,58 @@
+use bracken::kraken;
+use clap::Parser;
+use log::{error, info};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+#[derive(Parser, Clone, Debug)]
+#[clap(
+    version,
+    about = "Sums several Kraken reports into one report.",
+    long_about = "Sums the reads of several Kraken reports (e.g. technical replicates or lanes) built with the same taxonomy into one report tree, which can then be given to est-abundance."
+)]
+pub struct Args {
+    /// Kraken report files.
+    #[clap(short, long, num_args = 1.., required = true)]
+    input: Vec<PathBuf>,
+
+    /// Output Kraken report file ("-" writes to stdout).
+    #[clap(short, long, required = true)]
+    output: PathBuf,
+}
+
+pub fn run(args: Args) -> Result<(), Box<dyn std::error::Error>> {
+    let mut reports = Vec::with_capacity(args.input.len());
+    for input in args.input.iter() {
+        kraken::check_report_file(input)?;
+        let report = kraken::read_kraken_report(input)?;
+        info!("\t{} taxa read from {:?}", report.len(), input);
+        reports.push(report);
+    }
+    let merged = kraken::merge_reports(&reports)?;
+
+    let out: Box<dyn Write> = if args.output == Path::new("-") {
+        Box::new(io::stdout().lock())
+    } else {
+        Box::new(File::create(&args.output)?)
+    };
+    let mut out = BufWriter::new(out);
+    kraken::write_kraken_report(&merged, &mut out)?;
+    out.flush()?;
+    info!(
+        "MERGED REPORT PRODUCED: {:?} ({} taxa, {} reads)",
+        args.output.display(),
+        merged.len(),
+        merged.iter().map(|(_, node)| node.lvl_reads).sum::<usize>()
+    );
+    Ok(())
+}
+
+#[allow(dead_code)]
+fn main() {
+    bracken::logging::init(0, false);
+    let args = Args::parse();
+    if let Err(e) = run(args) {
+        error!("Application error: {}", e);
+    }
+}
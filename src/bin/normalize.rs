@@ -0,0 +1,307 @@
+use bracken::exit_code::ClassifiedError;
+use bracken::sampling;
+use clap::{Parser, ValueEnum};
+use log::{error, info, warn};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+#[derive(Parser, Clone, Debug)]
+#[clap(
+    version,
+    about = "Normalizes a combined abundance matrix.",
+    long_about = "Transforms the sample columns of a matrix written by combine (name, taxonomy_id, taxonomy_lvl, then one column per sample) by total sum scaling, counts per million, centered log-ratio or rarefaction, and writes the transformed matrix after '#' header lines recording the input and the parameters."
+)]
+pub struct Args {
+    /// Combined matrix (e.g. <output>.counts.tsv of combine).
+    #[clap(short, long, required = true)]
+    input: PathBuf,
+
+    /// Output matrix ("-" writes to stdout).
+    #[clap(short, long, required = true)]
+    output: PathBuf,
+
+    /// Normalization.
+    #[clap(short, long, value_enum)]
+    method: Method,
+
+    /// Value added to every entry before the log of --method clr.
+    #[clap(long, default_value_t = 0.5)]
+    pseudocount: f64,
+
+    /// Reads every sample is rarefied to with --method rarefy; samples with
+    /// fewer reads are dropped [default: the smallest non-zero sample total].
+    #[clap(long)]
+    depth: Option<usize>,
+
+    /// Seed for the random number generator of --method rarefy.
+    #[clap(long, default_value_t = 0)]
+    seed: u64,
+}
+
+/// Normalization of the sample columns.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Method {
+    /// Total sum scaling: fractions of the sample total.
+    Tss,
+    /// Counts per million of the sample total.
+    Cpm,
+    /// Centered log-ratio: log of the entries minus their mean log.
+    Clr,
+    /// Subsampling of the counts without replacement to the same depth.
+    Rarefy,
+}
+
+/// A matrix of taxa (rows) by samples (columns).
+struct Matrix {
+    samples: Vec<String>,
+    /// Name, taxid and level columns of every row.
+    taxa: Vec<[String; 3]>,
+    /// Values of every row, one per sample.
+    values: Vec<Vec<f64>>,
+}
+
+impl Matrix {
+    fn column(&self, sample: usize) -> Vec<f64> {
+        self.values.iter().map(|row| row[sample]).collect()
+    }
+
+    fn set_column(&mut self, sample: usize, column: &[f64]) {
+        for (row, value) in self.values.iter_mut().zip(column) {
+            row[sample] = *value;
+        }
+    }
+}
+
+fn read_matrix(path: &Path) -> Result<Matrix, Box<dyn std::error::Error>> {
+    let content = fs::read_to_string(path)?;
+    let mut lines = content
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.starts_with('#') && !line.trim().is_empty());
+    let invalid = |line: usize, message: &str| {
+        ClassifiedError::input_format(format!("{:?} line {}: {}", path, line + 1, message))
+    };
+    let (ix, header) = lines
+        .next()
+        .ok_or_else(|| ClassifiedError::input_format(format!("{:?} is empty", path)))?;
+    let header: Vec<&str> = header.split('\t').collect();
+    if header.len() < 4 || header[1] != "taxonomy_id" {
+        return Err(invalid(
+            ix,
+            "expected a name, taxonomy_id, taxonomy_lvl and sample columns header",
+        )
+        .into());
+    }
+    let samples: Vec<String> = header[3..].iter().map(|s| s.to_string()).collect();
+
+    let (mut taxa, mut values) = (Vec::new(), Vec::new());
+    for (ix, line) in lines {
+        let fields: Vec<&str> = line.split('\t').collect();
+        if fields.len() != header.len() {
+            return Err(invalid(
+                ix,
+                &format!("{} columns, expected {}", fields.len(), header.len()),
+            )
+            .into());
+        }
+        let row = fields[3..]
+            .iter()
+            .map(|value| value.trim().parse::<f64>())
+            .collect::<Result<Vec<f64>, _>>()
+            .map_err(|_| invalid(ix, "non-numeric value"))?;
+        if row.iter().any(|value| !value.is_finite() || *value < 0.0) {
+            return Err(invalid(ix, "negative or non-finite value").into());
+        }
+        taxa.push([
+            fields[0].to_string(),
+            fields[1].to_string(),
+            fields[2].to_string(),
+        ]);
+        values.push(row);
+    }
+    Ok(Matrix {
+        samples,
+        taxa,
+        values,
+    })
+}
+
+/// Scales every column to sum to `total`.
+fn scale(matrix: &mut Matrix, total: f64) {
+    for sample in 0..matrix.samples.len() {
+        let column = matrix.column(sample);
+        let sum: f64 = column.iter().sum();
+        if sum == 0.0 {
+            warn!("Sample {} has no reads", matrix.samples[sample]);
+        }
+        let scaled: Vec<f64> = column
+            .iter()
+            .map(|value| total * value / sum.max(f64::MIN_POSITIVE))
+            .collect();
+        matrix.set_column(sample, &scaled);
+    }
+}
+
+fn clr(matrix: &mut Matrix, pseudocount: f64) -> Result<(), ClassifiedError> {
+    for sample in 0..matrix.samples.len() {
+        let logs: Vec<f64> = matrix
+            .column(sample)
+            .iter()
+            .map(|value| (value + pseudocount).ln())
+            .collect();
+        if logs.iter().any(|log| !log.is_finite()) {
+            return Err(ClassifiedError::input_format(format!(
+                "sample {} has zeros; --pseudocount must be positive",
+                matrix.samples[sample]
+            )));
+        }
+        let mean = logs.iter().sum::<f64>() / logs.len().max(1) as f64;
+        let centered: Vec<f64> = logs.iter().map(|log| log - mean).collect();
+        matrix.set_column(sample, &centered);
+    }
+    Ok(())
+}
+
+/// Rarefies every sample to `depth` reads, dropping the samples with fewer
+/// reads. Returns the depth.
+fn rarefy(matrix: &mut Matrix, depth: Option<usize>, seed: u64) -> Result<usize, ClassifiedError> {
+    if matrix
+        .values
+        .iter()
+        .flatten()
+        .any(|value| value.fract() != 0.0)
+    {
+        return Err(ClassifiedError::input_format(
+            "rarefaction needs integer counts (the .counts matrix of combine)",
+        ));
+    }
+    let counts: Vec<Vec<usize>> = (0..matrix.samples.len())
+        .map(|sample| {
+            matrix
+                .column(sample)
+                .iter()
+                .map(|value| *value as usize)
+                .collect()
+        })
+        .collect();
+    let totals: Vec<usize> = counts.iter().map(|column| column.iter().sum()).collect();
+    let depth = match depth {
+        Some(0) => return Err(ClassifiedError::empty_result("cannot rarefy to 0 reads")),
+        Some(depth) => depth,
+        // Empty samples would rarefy all the others to nothing
+        None => totals
+            .iter()
+            .copied()
+            .filter(|&total| total > 0)
+            .min()
+            .ok_or_else(|| ClassifiedError::empty_result("no sample has reads"))?,
+    };
+
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut kept = Vec::new();
+    for (sample, column) in counts.iter().enumerate() {
+        if totals[sample] < depth {
+            warn!(
+                "Sample {} has {} reads, fewer than the depth {}; dropped",
+                matrix.samples[sample], totals[sample], depth
+            );
+            continue;
+        }
+        let rarefied: Vec<f64> = sampling::hypergeometric(column, depth, &mut rng)
+            .into_iter()
+            .map(|count| count as f64)
+            .collect();
+        matrix.set_column(sample, &rarefied);
+        kept.push(sample);
+    }
+    if kept.is_empty() {
+        return Err(ClassifiedError::empty_result(format!(
+            "no sample has {} reads",
+            depth
+        )));
+    }
+    matrix.samples = kept.iter().map(|&s| matrix.samples[s].clone()).collect();
+    for row in matrix.values.iter_mut() {
+        *row = kept.iter().map(|&s| row[s]).collect();
+    }
+    Ok(depth)
+}
+
+pub fn run(args: Args) -> Result<(), Box<dyn std::error::Error>> {
+    let mut matrix = read_matrix(&args.input)?;
+    info!(
+        ">> Normalizing {} taxa of {} samples ({:?})",
+        matrix.taxa.len(),
+        matrix.samples.len(),
+        args.method
+    );
+
+    let mut parameters = Vec::new();
+    let precision = match args.method {
+        Method::Tss => {
+            scale(&mut matrix, 1.0);
+            5
+        }
+        Method::Cpm => {
+            scale(&mut matrix, 1_000_000.0);
+            2
+        }
+        Method::Clr => {
+            clr(&mut matrix, args.pseudocount)?;
+            parameters.push(format!("pseudocount: {}", args.pseudocount));
+            5
+        }
+        Method::Rarefy => {
+            let depth = rarefy(&mut matrix, args.depth, args.seed)?;
+            parameters.push(format!("depth: {}", depth));
+            parameters.push(format!("seed: {}", args.seed));
+            0
+        }
+    };
+
+    let out: Box<dyn Write> = if args.output == Path::new("-") {
+        Box::new(io::stdout().lock())
+    } else {
+        Box::new(File::create(&args.output)?)
+    };
+    let mut out = BufWriter::new(out);
+    writeln!(out, "# bracken normalize {}", env!("CARGO_PKG_VERSION"))?;
+    writeln!(out, "# input: {}", args.input.display())?;
+    writeln!(
+        out,
+        "# method: {}",
+        args.method
+            .to_possible_value()
+            .map_or_else(String::new, |v| v.get_name().to_string())
+    )?;
+    for parameter in parameters {
+        writeln!(out, "# {}", parameter)?;
+    }
+    writeln!(
+        out,
+        "name\ttaxonomy_id\ttaxonomy_lvl\t{}",
+        matrix.samples.join("\t")
+    )?;
+    for (taxon, row) in matrix.taxa.iter().zip(matrix.values.iter()) {
+        write!(out, "{}", taxon.join("\t"))?;
+        for value in row {
+            write!(out, "\t{:.*}", precision, value)?;
+        }
+        writeln!(out)?;
+    }
+    out.flush()?;
+    info!("NORMALIZED MATRIX PRODUCED: {:?}", args.output.display());
+    Ok(())
+}
+
+#[allow(dead_code)]
+fn main() {
+    bracken::logging::init(0, false);
+    let args = Args::parse();
+    if let Err(e) = run(args) {
+        error!("Application error: {}", e);
+    }
+}
@@ -0,0 +1,261 @@
+use bracken::database;
+use bracken::est_abundance;
+use bracken::kmer2read_distr::OutputOptions;
+use chrono::Local;
+use clap::Parser;
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+#[derive(Parser, Clone, Debug)]
+#[clap(
+    version,
+    about = "Runs the whole Bracken pipeline on a Kraken database and reports.",
+    long_about = "Finds the kmer distribution of the read length in the Kraken database, building it (like the build subcommand) when it is missing, then runs est-abundance on every report. Completed stages are recorded in <outdir>/manifest.json with the files they produced and are skipped when the pipeline is run again."
+)]
+pub struct Args {
+    /// Kraken database folder.
+    #[clap(long = "db", required = true)]
+    database_dir: PathBuf,
+
+    /// Kraken report files, one per sample.
+    #[clap(short = 'r', long = "reports", num_args = 1.., required = true)]
+    reports: Vec<PathBuf>,
+
+    /// Output folder of the abundance tables (<outdir>/<report name>.bracken)
+    /// and the manifest.
+    #[clap(long, required = true)]
+    outdir: PathBuf,
+
+    /// kmer length used to build the Kraken database.
+    #[clap(short = 'k', long, default_value_t = 35)]
+    kmer_len: usize,
+
+    /// Read length of the samples.
+    #[clap(short = 'l', long, default_value_t = 100)]
+    read_len: usize,
+
+    /// Rerun every stage even if the manifest records it as completed.
+    #[clap(long)]
+    force: bool,
+
+    /// Options passed to est-abundance for every report (after `--`), e.g.
+    /// `-- -l G -t 5`.
+    #[clap(last = true)]
+    est_abundance_args: Vec<String>,
+}
+
+/// A completed stage of the pipeline.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct Stage {
+    name: String,
+    inputs: Vec<PathBuf>,
+    outputs: Vec<PathBuf>,
+    /// Options the stage was run with; a change of options reruns it.
+    options: Vec<String>,
+    completed: String,
+}
+
+/// Manifest of the stages completed in the output folder.
+#[derive(Serialize, Deserialize, Default, Debug)]
+struct Manifest {
+    stages: Vec<Stage>,
+}
+
+impl Manifest {
+    fn load(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        Ok(serde_json::from_reader(BufReader::new(File::open(path)?))?)
+    }
+
+    /// Writes the manifest through a temporary file, so an interrupted run
+    /// never leaves a truncated manifest behind.
+    fn save(&self, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        let tmp = path.with_extension("json.tmp");
+        let mut file = BufWriter::new(File::create(&tmp)?);
+        serde_json::to_writer_pretty(&mut file, self)?;
+        writeln!(file)?;
+        file.flush()?;
+        drop(file);
+        fs::rename(&tmp, path)?;
+        Ok(())
+    }
+
+    /// The stage if it was completed with the same inputs and options and
+    /// all of its outputs are still there.
+    fn completed(&self, name: &str, inputs: &[PathBuf], options: &[String]) -> Option<&Stage> {
+        self.stages.iter().find(|stage| {
+            stage.name == name
+                && stage.inputs == inputs
+                && stage.options == options
+                && stage.outputs.iter().all(|output| output.exists())
+        })
+    }
+
+    fn record(&mut self, stage: Stage) {
+        self.stages.retain(|s| s.name != stage.name);
+        self.stages.push(stage);
+    }
+}
+
+/// Modification times of the files of a folder.
+fn file_times(dir: &Path) -> Result<HashMap<PathBuf, SystemTime>, std::io::Error> {
+    let mut times = HashMap::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        if metadata.is_file() {
+            times.insert(entry.path(), metadata.modified()?);
+        }
+    }
+    Ok(times)
+}
+
+/// Files of a folder created or modified since `before` was taken.
+fn produced_files(
+    dir: &Path,
+    before: &HashMap<PathBuf, SystemTime>,
+) -> Result<Vec<PathBuf>, std::io::Error> {
+    let mut produced: Vec<PathBuf> = file_times(dir)?
+        .into_iter()
+        .filter(|(path, time)| before.get(path) != Some(time))
+        .map(|(path, _)| path)
+        .collect();
+    produced.sort();
+    Ok(produced)
+}
+
+/// Kmer distribution of the read length: the existing one, one built from
+/// the database, or else the closest one of the database.
+fn kmer_distr_stage(
+    args: &Args,
+    manifest: &mut Manifest,
+    manifest_file: &Path,
+) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let name = "kmer-distrib";
+    let inputs = vec![args.database_dir.clone()];
+    let options = vec![
+        format!("kmer_len={}", args.kmer_len),
+        format!("read_len={}", args.read_len),
+    ];
+    if !args.force {
+        if let Some(stage) = manifest.completed(name, &inputs, &options) {
+            info!(">> Stage {} already completed, skipping", name);
+            return Ok(stage.outputs[0].clone());
+        }
+    }
+
+    let kmer_distr = database::kmer_distr_path(&args.database_dir, args.read_len);
+    let kmer_distr = if kmer_distr.exists() && !args.force {
+        info!(">> Found kmer distribution {:?}", kmer_distr);
+        kmer_distr
+    } else if args.database_dir.join("database.kraken").exists() {
+        database::build_kmer_distr(
+            &args.database_dir,
+            args.kmer_len,
+            args.read_len,
+            args.force,
+            OutputOptions::default(),
+        )?
+    } else {
+        let (kmer_distr, db_len) = database::select_kmer_distr(&args.database_dir, args.read_len)?;
+        warn!(
+            "No database.kraken to build the {}mers kmer distribution; using {:?} built for {} bp reads",
+            args.read_len, kmer_distr, db_len
+        );
+        kmer_distr
+    };
+    manifest.record(Stage {
+        name: name.to_string(),
+        inputs,
+        outputs: vec![kmer_distr.clone()],
+        options,
+        completed: Local::now().to_rfc3339(),
+    });
+    manifest.save(manifest_file)?;
+    Ok(kmer_distr)
+}
+
+pub fn run(args: Args) -> Result<(), Box<dyn std::error::Error>> {
+    let mut samples = Vec::new();
+    let mut seen = HashSet::new();
+    for report in args.reports.iter() {
+        let sample = report
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().to_string())
+            .unwrap_or_default();
+        if !seen.insert(sample.clone()) {
+            return Err(format!(
+                "several reports are named {:?}; their outputs would collide",
+                sample
+            )
+            .into());
+        }
+        samples.push((sample, report.clone()));
+    }
+
+    fs::create_dir_all(&args.outdir)?;
+    let manifest_file = args.outdir.join("manifest.json");
+    let mut manifest = Manifest::load(&manifest_file)?;
+
+    let kmer_distr = kmer_distr_stage(&args, &mut manifest, &manifest_file)?;
+
+    for (sample, report) in samples.iter() {
+        let name = format!("est-abundance:{}", sample);
+        let inputs = vec![report.clone(), kmer_distr.clone()];
+        if !args.force
+            && manifest
+                .completed(&name, &inputs, &args.est_abundance_args)
+                .is_some()
+        {
+            info!(">> Stage {} already completed, skipping", name);
+            continue;
+        }
+
+        info!(">> Stage {}", name);
+        let output = args.outdir.join(format!("{}.bracken", sample));
+        let mut cmd_args = vec![
+            "est-abundance".into(),
+            "-i".into(),
+            report.to_string_lossy().to_string(),
+            "-k".into(),
+            kmer_distr.to_string_lossy().to_string(),
+            "-o".into(),
+            output.to_string_lossy().to_string(),
+        ];
+        cmd_args.extend(args.est_abundance_args.iter().cloned());
+        let est_args = est_abundance::Args::try_parse_from(cmd_args)?;
+
+        let before = file_times(&args.outdir)?;
+        est_abundance::run(est_args)?;
+        let mut outputs = produced_files(&args.outdir, &before)?;
+        outputs.retain(|path| path != &manifest_file);
+
+        manifest.record(Stage {
+            name,
+            inputs,
+            outputs,
+            options: args.est_abundance_args.clone(),
+            completed: Local::now().to_rfc3339(),
+        });
+        manifest.save(&manifest_file)?;
+    }
+
+    info!("PIPELINE MANIFEST PRODUCED: {:?}", manifest_file.display());
+    Ok(())
+}
+
+#[allow(dead_code)]
+fn main() {
+    bracken::logging::init(0, false);
+    let args = Args::parse();
+    if let Err(e) = run(args) {
+        error!("Application error: {}", e);
+    }
+}
@@ -0,0 +1,122 @@
+use bracken::exit_code::ClassifiedError;
+use bracken::kmer_distrib;
+use bracken::taxonomy::{self, NCBITaxonomy};
+use clap::Parser;
+use log::{error, info, warn};
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+#[derive(Parser, Clone, Debug)]
+#[clap(
+    version,
+    about = "Restricts a kmer distribution to the genomes of clades.",
+    long_about = "Writes a smaller .kmer_distrib (in the format of the input) keeping only the genomes under the given clades (e.g. 2 for Bacteria), for targeted analyses that load faster. The mapped taxids above the clades are kept by default, so the reads Kraken classified there are still redistributed to the kept genomes."
+)]
+pub struct Args {
+    /// Kmer distribution file.
+    #[clap(short, long, required = true)]
+    input: PathBuf,
+
+    /// Output kmer distribution file.
+    #[clap(short, long, required = true)]
+    output: PathBuf,
+
+    /// Taxids of the clades to keep.
+    #[clap(short, long, required = true, num_args = 1..)]
+    clades: Vec<u32>,
+
+    /// Taxonomy folder with nodes.dmp.
+    #[clap(long = "taxonomy", required = true)]
+    taxonomy_dir: PathBuf,
+
+    /// Also drop the mapped taxids outside the clades, such as their
+    /// ancestors.
+    #[clap(long)]
+    drop_ancestors: bool,
+}
+
+/// Whether taxids are under one of the clades, cached.
+struct CladeFilter<'a> {
+    taxo: &'a NCBITaxonomy,
+    clades: &'a [u32],
+    cache: HashMap<u32, bool>,
+}
+
+impl CladeFilter<'_> {
+    fn contains(&mut self, taxid: u32) -> bool {
+        let (taxo, clades) = (self.taxo, self.clades);
+        *self.cache.entry(taxid).or_insert_with(|| {
+            taxo.lineage(taxid)
+                .is_some_and(|lineage| lineage.iter().any(|t| clades.contains(t)))
+        })
+    }
+}
+
+pub fn run(args: Args) -> Result<(), Box<dyn std::error::Error>> {
+    let taxo = taxonomy::load_taxonomy(args.taxonomy_dir.clone())?;
+    for clade in args.clades.iter() {
+        if taxo.get_node(clade).is_none() {
+            return Err(ClassifiedError::input_format(format!(
+                "clade {} is not in the taxonomy",
+                clade
+            ))
+            .into());
+        }
+    }
+    let binary = kmer_distrib::is_binary(&args.input)?;
+    let mut filter = CladeFilter {
+        taxo: &taxo,
+        clades: &args.clades,
+        cache: HashMap::new(),
+    };
+
+    let (mut n_entries, mut n_genomes, mut kept_genomes) = (0, 0, 0);
+    let mut entries = Vec::new();
+    let malformed =
+        kmer_distrib::for_each_entry(&args.input, "Reading kmer distribution", |mut entry| {
+            n_entries += 1;
+            n_genomes += entry.genomes.len();
+            if args.drop_ancestors && !filter.contains(entry.mapped_taxid) {
+                return;
+            }
+            entry.genomes.retain(|genome| filter.contains(genome.taxid));
+            if !entry.genomes.is_empty() {
+                kept_genomes += entry.genomes.len();
+                entries.push(entry);
+            }
+        })?;
+    if malformed > 0 {
+        warn!("{} malformed lines of {:?} skipped", malformed, args.input);
+    }
+    if entries.is_empty() {
+        return Err(ClassifiedError::empty_result(format!(
+            "no genome of {:?} is under the clades {:?}",
+            args.input, args.clades
+        ))
+        .into());
+    }
+    info!(
+        ">> Kept {} of {} mapped taxids and {} of {} genome entries",
+        entries.len(),
+        n_entries,
+        kept_genomes,
+        n_genomes
+    );
+
+    if binary {
+        kmer_distrib::write_binary(&entries, &args.output)?;
+    } else {
+        kmer_distrib::write_text(&entries, &args.output)?;
+    }
+    info!("KMER DISTRIBUTION PRODUCED: {:?}", args.output.display());
+    Ok(())
+}
+
+#[allow(dead_code)]
+fn main() {
+    bracken::logging::init(0, false);
+    let args = Args::parse();
+    if let Err(e) = run(args) {
+        error!("Application error: {}", e);
+    }
+}
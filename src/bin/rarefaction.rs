@@ -0,0 +1,128 @@
+use bracken::exit_code::ClassifiedError;
+use bracken::kraken;
+use clap::Parser;
+use log::{error, info, warn};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+#[derive(Parser, Clone, Debug)]
+#[clap(
+    version,
+    about = "Computes a rarefaction curve of the taxa richness of a Kraken report.",
+    long_about = "Subsamples a Kraken report (without replacement) at a series of read depths and writes the number of taxa observed at the level for every depth and replicate as a tidy TSV, to check whether the sequencing depth saturates the richness."
+)]
+pub struct Args {
+    /// Kraken report file.
+    #[clap(short, long, required = true)]
+    input: PathBuf,
+
+    /// Output TSV file ("-" writes to stdout).
+    #[clap(short, long, required = true)]
+    output: PathBuf,
+
+    /// Level at which taxa are counted [default: S].
+    #[clap(short, long, default_value = "S")]
+    level: String,
+
+    /// Comma-separated read depths (reads of the report, including
+    /// unclassified reads) [default: --steps evenly spaced depths].
+    #[clap(short, long, value_delimiter = ',')]
+    depths: Vec<usize>,
+
+    /// Number of evenly spaced depths up to the report's total reads when
+    /// --depths is not given.
+    #[clap(long, default_value_t = 10)]
+    steps: usize,
+
+    /// Number of subsamples at each depth.
+    #[clap(short, long, default_value_t = 1)]
+    replicates: usize,
+
+    /// Minimum number of reads for a taxon to count as observed.
+    #[clap(short, long, default_value_t = 1)]
+    threshold: usize,
+
+    /// Seed for the random number generator used by subsampling.
+    #[clap(long, default_value_t = 0)]
+    seed: u64,
+}
+
+/// Number of taxa at the level with at least `threshold` clade reads.
+fn richness(report: &[(usize, kraken::Node)], level: &str, threshold: usize) -> usize {
+    report
+        .iter()
+        .filter(|(_, node)| {
+            node.level_id == level && node.all_reads >= threshold && node.all_reads > 0
+        })
+        .count()
+}
+
+pub fn run(args: Args) -> Result<(), Box<dyn std::error::Error>> {
+    kraken::level_branch(&args.level)?;
+    kraken::check_report_file(&args.input)?;
+    let report = kraken::read_kraken_report(&args.input)?;
+    let total_reads: usize = report.iter().map(|(_, node)| node.lvl_reads).sum();
+    if total_reads == 0 {
+        return Err(ClassifiedError::empty_result(format!("no reads in {:?}", args.input)).into());
+    }
+
+    let mut depths = if args.depths.is_empty() {
+        let steps = args.steps.max(1);
+        (1..=steps).map(|step| total_reads * step / steps).collect()
+    } else {
+        args.depths.clone()
+    };
+    depths.sort_unstable();
+    depths.dedup();
+    if let Some(&max_depth) = depths.last().filter(|&&depth| depth > total_reads) {
+        warn!(
+            "Depths above the {} reads of the report are skipped (up to {})",
+            total_reads, max_depth
+        );
+        depths.retain(|&depth| depth <= total_reads);
+    }
+
+    let out: Box<dyn Write> = if args.output == Path::new("-") {
+        Box::new(io::stdout().lock())
+    } else {
+        Box::new(File::create(&args.output)?)
+    };
+    let mut out = BufWriter::new(out);
+    writeln!(out, "depth\treplicate\tlevel\tobserved_taxa")?;
+
+    let mut rng = StdRng::seed_from_u64(args.seed);
+    for &depth in depths.iter() {
+        for replicate in 1..=args.replicates {
+            let subsample = kraken::subsample_report(&report, depth, &mut rng);
+            let observed = richness(&subsample, &args.level, args.threshold);
+            writeln!(
+                out,
+                "{}\t{}\t{}\t{}",
+                depth, replicate, args.level, observed
+            )?;
+        }
+        info!("\tDepth {}: {} replicates", depth, args.replicates);
+    }
+    out.flush()?;
+
+    info!(
+        "Full report: {} {} taxa observed in {} reads",
+        richness(&report, &args.level, args.threshold),
+        args.level,
+        total_reads
+    );
+    info!("RAREFACTION CURVE PRODUCED: {:?}", args.output.display());
+    Ok(())
+}
+
+#[allow(dead_code)]
+fn main() {
+    bracken::logging::init(0, false);
+    let args = Args::parse();
+    if let Err(e) = run(args) {
+        error!("Application error: {}", e);
+    }
+}
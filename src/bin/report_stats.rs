@@ -0,0 +1,181 @@
+use bracken::exit_code::ClassifiedError;
+use bracken::kraken;
+use clap::{Parser, ValueEnum};
+use log::error;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+#[derive(Parser, Clone, Debug)]
+#[clap(
+    version,
+    about = "Summarizes a Kraken report.",
+    long_about = "Prints the total, classified and unclassified reads of a Kraken report, and the taxa and reads of every rank and of every depth of its tree, as a quick check of a sample before estimating abundances."
+)]
+pub struct Args {
+    /// Kraken report file.
+    input: PathBuf,
+
+    /// Output file ("-" writes to stdout).
+    #[clap(short, long, default_value = "-")]
+    output: PathBuf,
+
+    /// Output format.
+    #[clap(long, value_enum, default_value_t = StatsFormat::Tsv)]
+    format: StatsFormat,
+}
+
+/// Format of the statistics output.
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum StatsFormat {
+    /// Rows of section, key, taxa, reads, clade reads and fraction of the
+    /// reads.
+    Tsv,
+    Json,
+}
+
+/// Taxa and reads of a rank or a depth.
+#[derive(Serialize, Default, Debug)]
+struct Group {
+    key: String,
+    taxa: usize,
+    /// Taxa with reads assigned directly.
+    taxa_with_reads: usize,
+    /// Reads assigned directly to the taxa.
+    reads: usize,
+    /// Reads assigned to the taxa and their descendants.
+    clade_reads: usize,
+}
+
+#[derive(Serialize, Debug)]
+struct ReportStats {
+    total_reads: usize,
+    classified_reads: usize,
+    unclassified_reads: usize,
+    unclassified_fraction: f64,
+    taxa: usize,
+    max_depth: usize,
+    ranks: Vec<Group>,
+    depths: Vec<Group>,
+}
+
+/// Sort key of a rank code: the main ranks from the root down, each
+/// followed by its numbered sub-ranks (S, S1, S2...).
+fn rank_order(code: &str) -> (usize, usize, String) {
+    let mut chars = code.chars();
+    let letter = chars.next().unwrap_or('-');
+    let position = "URDKPCOFGS".find(letter).unwrap_or(usize::MAX);
+    let number = chars.as_str().parse().unwrap_or(0);
+    (position, number, code.to_string())
+}
+
+fn add(group: &mut Group, node: &kraken::Node) {
+    group.taxa += 1;
+    group.taxa_with_reads += usize::from(node.lvl_reads > 0);
+    group.reads += node.lvl_reads;
+    group.clade_reads += node.all_reads;
+}
+
+fn write_tsv<W: Write>(out: &mut W, stats: &ReportStats) -> io::Result<()> {
+    let fraction = |reads: usize| reads as f64 / stats.total_reads.max(1) as f64;
+    writeln!(
+        out,
+        "section\tkey\ttaxa\ttaxa_with_reads\treads\tclade_reads\tfraction_total_reads"
+    )?;
+    for (key, reads) in [
+        ("total", stats.total_reads),
+        ("classified", stats.classified_reads),
+        ("unclassified", stats.unclassified_reads),
+    ] {
+        writeln!(
+            out,
+            "reads\t{}\t-\t-\t{}\t{}\t{:.5}",
+            key,
+            reads,
+            reads,
+            fraction(reads)
+        )?;
+    }
+    for (section, groups) in [("rank", &stats.ranks), ("depth", &stats.depths)] {
+        for group in groups.iter() {
+            writeln!(
+                out,
+                "{}\t{}\t{}\t{}\t{}\t{}\t{:.5}",
+                section,
+                group.key,
+                group.taxa,
+                group.taxa_with_reads,
+                group.reads,
+                group.clade_reads,
+                fraction(group.reads)
+            )?;
+        }
+    }
+    Ok(())
+}
+
+pub fn run(args: Args) -> Result<(), Box<dyn std::error::Error>> {
+    kraken::check_report_file(&args.input)?;
+    let report = kraken::read_kraken_report(&args.input)?;
+    if report.is_empty() {
+        return Err(ClassifiedError::empty_result(format!("no taxa in {:?}", args.input)).into());
+    }
+
+    let mut ranks: BTreeMap<(usize, usize, String), Group> = BTreeMap::new();
+    let mut depths: BTreeMap<usize, Group> = BTreeMap::new();
+    let (mut total_reads, mut unclassified_reads) = (0, 0);
+    for (_, node) in report.iter() {
+        total_reads += node.lvl_reads;
+        if node.taxid == 0 {
+            unclassified_reads += node.lvl_reads;
+        }
+        add(ranks.entry(rank_order(&node.level_id)).or_default(), node);
+        add(depths.entry(node.level_num).or_default(), node);
+    }
+    let stats = ReportStats {
+        total_reads,
+        classified_reads: total_reads - unclassified_reads,
+        unclassified_reads,
+        unclassified_fraction: unclassified_reads as f64 / total_reads.max(1) as f64,
+        taxa: report.len(),
+        max_depth: depths.keys().last().copied().unwrap_or(0),
+        ranks: ranks
+            .into_iter()
+            .map(|((_, _, key), group)| Group { key, ..group })
+            .collect(),
+        depths: depths
+            .into_iter()
+            .map(|(depth, group)| Group {
+                key: depth.to_string(),
+                ..group
+            })
+            .collect(),
+    };
+
+    let out: Box<dyn Write> = if args.output == Path::new("-") {
+        Box::new(io::stdout().lock())
+    } else {
+        Box::new(File::create(&args.output)?)
+    };
+    let mut out = BufWriter::new(out);
+    match args.format {
+        StatsFormat::Tsv => write_tsv(&mut out, &stats)?,
+        StatsFormat::Json => {
+            serde_json::to_writer_pretty(&mut out, &stats)?;
+            writeln!(out)?;
+        }
+    }
+    out.flush()?;
+    Ok(())
+}
+
+#[allow(dead_code)]
+fn main() {
+    bracken::logging::init(0, false);
+    let args = Args::parse();
+    if let Err(e) = run(args) {
+        error!("Application error: {}", e);
+    }
+}
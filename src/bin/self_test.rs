@@ -0,0 +1,209 @@
+use bracken::{est_abundance, kmer2read_distr, kmer_distrib, taxonomy};
+use clap::Parser;
+use log::{error, info, warn};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+#[derive(Parser, Clone, Debug)]
+#[clap(
+    version,
+    about = "Checks the installation on an embedded miniature dataset.",
+    long_about = "Writes a miniature Kraken database (three genomes) and report embedded in the binary to a work folder, runs kmer2read-distr, kmer-distrib and est-abundance on them, and compares every output with the expected one. Exits with an error if any stage fails or differs."
+)]
+pub struct Args {
+    /// Work folder for the dataset and the outputs [default: a folder in
+    /// the system temporary directory].
+    #[clap(long)]
+    workdir: Option<PathBuf>,
+
+    /// Keep the work folder after the test.
+    #[clap(long)]
+    keep: bool,
+}
+
+const KMER_LEN: usize = 35;
+const READ_LEN: usize = 50;
+
+/// Inputs of the miniature dataset, as (path in the work folder, content).
+const DATASET: &[(&str, &str)] = &[
+    (
+        "db/database.kraken",
+        include_str!("../../tests/data/self_test/database.kraken"),
+    ),
+    (
+        "db/seqid2taxid.map",
+        include_str!("../../tests/data/self_test/seqid2taxid.map"),
+    ),
+    (
+        "db/taxonomy/nodes.dmp",
+        include_str!("../../tests/data/self_test/nodes.dmp"),
+    ),
+    (
+        "db/taxonomy/names.dmp",
+        include_str!("../../tests/data/self_test/names.dmp"),
+    ),
+    (
+        "report.kreport",
+        include_str!("../../tests/data/self_test/report.kreport"),
+    ),
+];
+
+const EXPECTED_KRAKEN_CNTS: &str = include_str!("../../tests/data/self_test/expected.kraken_cnts");
+const EXPECTED_KMER_DISTRIB: &str =
+    include_str!("../../tests/data/self_test/expected.kmer_distrib");
+const EXPECTED_BRACKEN: &str = include_str!("../../tests/data/self_test/expected.bracken");
+
+/// Lines of an output in a canonical order: the stages write lines, and the
+/// `taxid:count` items of a line, in no particular order. The `#` provenance
+/// lines, which hold the date, are left out.
+fn canonical(content: &str) -> Vec<String> {
+    let mut lines: Vec<String> = content
+        .lines()
+        .filter(|line| !line.trim().is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            line.split('\t')
+                .map(|field| {
+                    let mut items: Vec<&str> = field.split(' ').collect();
+                    if items.len() > 1 && items.iter().all(|item| item.contains(':')) {
+                        items.sort_unstable();
+                    }
+                    items.join(" ")
+                })
+                .collect::<Vec<_>>()
+                .join("\t")
+        })
+        .collect();
+    lines.sort();
+    lines
+}
+
+/// Compares an output with the expected content; returns the first
+/// difference.
+fn compare(output: &Path, expected: &str) -> Result<(), String> {
+    let actual = fs::read_to_string(output).map_err(|e| format!("{:?}: {}", output, e))?;
+    let (actual, expected) = (canonical(&actual), canonical(expected));
+    for (actual_line, expected_line) in actual.iter().zip(expected.iter()) {
+        if actual_line != expected_line {
+            return Err(format!(
+                "expected {:?}, got {:?}",
+                expected_line, actual_line
+            ));
+        }
+    }
+    if actual.len() != expected.len() {
+        return Err(format!(
+            "expected {} lines, got {}",
+            expected.len(),
+            actual.len()
+        ));
+    }
+    Ok(())
+}
+
+/// Runs a stage and checks its output, logging the outcome.
+fn stage<F>(name: &str, output: &Path, expected: &str, run: F) -> bool
+where
+    F: FnOnce() -> Result<(), Box<dyn std::error::Error>>,
+{
+    let start = Instant::now();
+    let outcome = run()
+        .map_err(|e| e.to_string())
+        .and_then(|_| compare(output, expected));
+    match outcome {
+        Ok(()) => {
+            info!(
+                "PASS\t{} ({:.1} ms)",
+                name,
+                start.elapsed().as_secs_f64() * 1000.0
+            );
+            true
+        }
+        Err(e) => {
+            error!("FAIL\t{}: {}", name, e);
+            false
+        }
+    }
+}
+
+pub fn run(args: Args) -> Result<(), Box<dyn std::error::Error>> {
+    let workdir = args.workdir.clone().unwrap_or_else(|| {
+        std::env::temp_dir().join(format!("bracken-self-test-{}", std::process::id()))
+    });
+    for (path, content) in DATASET {
+        let path = workdir.join(path);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&path, content)?;
+    }
+    info!(">> Running the self-test in {:?}", workdir);
+
+    let db = workdir.join("db");
+    let kraken_cnts = db.join(format!("database{}mers.kraken_cnts", READ_LEN));
+    let kmer_distr = db.join(format!("database{}mers.kmer_distrib", READ_LEN));
+    let bracken_output = workdir.join("report.bracken");
+
+    let mut passed = stage(
+        "kmer2read-distr",
+        &kraken_cnts,
+        EXPECTED_KRAKEN_CNTS,
+        || {
+            let seqid2taxid = kmer2read_distr::get_seqid2taxid(db.join("seqid2taxid.map"))?;
+            let taxo = taxonomy::load_taxonomy(db.join("taxonomy"))?;
+            kmer2read_distr::evaluate_kfile(
+                &db.join("database.kraken"),
+                &kraken_cnts,
+                seqid2taxid,
+                READ_LEN,
+                KMER_LEN,
+                &taxo,
+                kmer2read_distr::OutputOptions {
+                    ordered: true,
+                    ..Default::default()
+                },
+            )?;
+            Ok(())
+        },
+    );
+    passed &= stage("kmer-distrib", &kmer_distr, EXPECTED_KMER_DISTRIB, || {
+        kmer_distrib::generate_kmer_distribution(
+            std::slice::from_ref(&kraken_cnts),
+            &kmer_distr,
+            &kmer_distrib::DistribOptions::default(),
+        )?;
+        Ok(())
+    });
+    passed &= stage("est-abundance", &bracken_output, EXPECTED_BRACKEN, || {
+        let est_args = est_abundance::Args::try_parse_from([
+            "est-abundance".as_ref(),
+            "-i".as_ref(),
+            workdir.join("report.kreport").as_os_str(),
+            "-k".as_ref(),
+            kmer_distr.as_os_str(),
+            "-o".as_ref(),
+            bracken_output.as_os_str(),
+        ])?;
+        est_abundance::run(est_args)
+    });
+
+    if args.keep {
+        info!(">> Kept the work folder {:?}", workdir);
+    } else if let Err(e) = fs::remove_dir_all(&workdir) {
+        warn!("Could not remove the work folder {:?}: {}", workdir, e);
+    }
+    if !passed {
+        return Err("self-test failed".into());
+    }
+    info!("SELF-TEST PASSED");
+    Ok(())
+}
+
+#[allow(dead_code)]
+fn main() {
+    bracken::logging::init(0, false);
+    let args = Args::parse();
+    if let Err(e) = run(args) {
+        error!("Application error: {}", e);
+    }
+}
@@ -0,0 +1,193 @@
+use bracken::exit_code::ClassifiedError;
+use bracken::seqid2taxid::{self, AccessionIndex};
+use clap::Parser;
+use log::{debug, error, info, warn};
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+#[derive(Parser, Clone, Debug)]
+#[clap(
+    version,
+    about = "Writes a seqid2taxid.map from library FASTA headers.",
+    long_about = "Reads the sequence ids of library FASTA files (plain or .gz, folders are searched recursively), looks their accessions up in NCBI accession2taxid files and writes a seqid2taxid.map, so a database can be built without the Kraken build artifacts. The accession2taxid files are converted once to a sorted binary index that is memory-mapped by later runs. With --assembly-summary, every sequence of a per-assembly FASTA file (named after its GCF_/GCA_ accession, or in a folder named so) gets the taxid of the assembly instead. Sequence ids of the form kraken:taxid|<taxid>|... are mapped to their taxid directly."
+)]
+pub struct Args {
+    /// Library FASTA files or folders.
+    #[clap(required = true, num_args = 1..)]
+    library: Vec<PathBuf>,
+
+    /// NCBI accession2taxid files (e.g. nucl_gb.accession2taxid.gz) to
+    /// build the index from.
+    #[clap(short = 'a', long, num_args = 1..)]
+    accession2taxid: Vec<PathBuf>,
+
+    /// Accession index, built from --accession2taxid when it is missing or
+    /// older than them [default: the first accession2taxid file with an
+    /// .idx suffix].
+    #[clap(long, required_unless_present_any = ["accession2taxid", "assembly_summary"])]
+    index: Option<PathBuf>,
+
+    /// RefSeq/GenBank assembly_summary.txt files, to map the sequences of
+    /// per-assembly FASTA files to the taxid of their assembly.
+    #[clap(long, num_args = 1.., conflicts_with_all = ["accession2taxid", "index"])]
+    assembly_summary: Vec<PathBuf>,
+
+    /// Map the assemblies to their species taxid instead of their strain
+    /// taxid.
+    #[clap(
+        long,
+        requires = "assembly_summary",
+        conflicts_with_all = ["accession2taxid", "index"]
+    )]
+    species_taxid: bool,
+
+    /// Rebuild the accession index even if it is up to date.
+    #[clap(long)]
+    force: bool,
+
+    /// Output seqid2taxid.map file ("-" writes to stdout).
+    #[clap(short, long, required = true)]
+    output: PathBuf,
+
+    /// Write the sequence ids that could not be mapped to a taxid to this
+    /// file.
+    #[clap(long)]
+    unmapped: Option<PathBuf>,
+}
+
+fn default_index(accession2taxid: &Path) -> PathBuf {
+    let mut name = OsString::from(accession2taxid.as_os_str());
+    name.push(".idx");
+    PathBuf::from(name)
+}
+
+/// Source of the taxids of the sequences.
+enum Lookup {
+    Accessions(AccessionIndex),
+    Assemblies(HashMap<String, u32>),
+}
+
+fn open_index(args: &Args) -> Result<AccessionIndex, Box<dyn std::error::Error>> {
+    let index_path = match (&args.index, args.accession2taxid.first()) {
+        (Some(index), _) => index.clone(),
+        (None, Some(first)) => default_index(first),
+        (None, None) => return Err("--index or --accession2taxid is required".into()),
+    };
+    if !args.accession2taxid.is_empty()
+        && (args.force || seqid2taxid::index_is_stale(&index_path, &args.accession2taxid))
+    {
+        info!(">> Building the accession index {:?}", index_path);
+        let n_accessions = seqid2taxid::build_accession_index(&args.accession2taxid, &index_path)?;
+        info!("\t{} accessions indexed", n_accessions);
+    }
+    let index = AccessionIndex::open(&index_path)?;
+    info!(
+        ">> Using the accession index {:?} ({} accessions)",
+        index_path,
+        index.len()
+    );
+    Ok(index)
+}
+
+pub fn run(args: Args) -> Result<(), Box<dyn std::error::Error>> {
+    let lookup = if args.assembly_summary.is_empty() {
+        Lookup::Accessions(open_index(&args)?)
+    } else {
+        let assemblies =
+            seqid2taxid::read_assembly_summary(&args.assembly_summary, args.species_taxid)?;
+        info!(
+            ">> {} assemblies in the assembly summaries",
+            assemblies.len()
+        );
+        Lookup::Assemblies(assemblies)
+    };
+
+    let files = seqid2taxid::fasta_files(&args.library)?;
+    if files.is_empty() {
+        return Err(ClassifiedError::input_format(format!(
+            "no FASTA files found in {:?}",
+            args.library
+        ))
+        .into());
+    }
+
+    let out: Box<dyn Write> = if args.output == Path::new("-") {
+        Box::new(io::stdout().lock())
+    } else {
+        Box::new(File::create(&args.output)?)
+    };
+    let mut out = BufWriter::new(out);
+    let mut unmapped_out = match &args.unmapped {
+        Some(path) => Some(BufWriter::new(File::create(path)?)),
+        None => None,
+    };
+    let (mut n_seqs, mut n_mapped) = (0, 0);
+    for file in files.iter() {
+        info!(">> Reading the headers of {:?}", file);
+        let file_taxid = match &lookup {
+            Lookup::Accessions(_) => None,
+            Lookup::Assemblies(assemblies) => {
+                let taxid = seqid2taxid::assembly_accession(file)
+                    .and_then(|accession| assemblies.get(&accession).copied());
+                if taxid.is_none() {
+                    warn!("{:?} does not belong to an assembly of the summaries", file);
+                }
+                taxid
+            }
+        };
+        let mut result = Ok(());
+        seqid2taxid::for_each_seqid(file, |seqid| {
+            if result.is_err() {
+                return;
+            }
+            n_seqs += 1;
+            let taxid = seqid2taxid::kraken_taxid(seqid)
+                .or(file_taxid)
+                .or_else(|| match &lookup {
+                    Lookup::Accessions(index) => index.get(seqid2taxid::accession(seqid)),
+                    Lookup::Assemblies(_) => None,
+                });
+            result = match (taxid, unmapped_out.as_mut()) {
+                (Some(taxid), _) => {
+                    n_mapped += 1;
+                    writeln!(out, "{}\t{}", seqid, taxid)
+                }
+                (None, Some(unmapped_out)) => writeln!(unmapped_out, "{}", seqid),
+                (None, None) => {
+                    debug!("\tNo taxid for {}", seqid);
+                    Ok(())
+                }
+            };
+        })?;
+        result?;
+    }
+    out.flush()?;
+    if let Some(mut unmapped_out) = unmapped_out {
+        unmapped_out.flush()?;
+    }
+
+    info!("\t{} of {} sequences mapped to a taxid", n_mapped, n_seqs);
+    if n_mapped < n_seqs {
+        warn!(
+            "{} sequences have no taxid and are left out of the map",
+            n_seqs - n_mapped
+        );
+    }
+    if n_mapped == 0 {
+        return Err(ClassifiedError::empty_result("no sequence mapped to a taxid").into());
+    }
+    info!("SEQID2TAXID MAP PRODUCED: {:?}", args.output.display());
+    Ok(())
+}
+
+#[allow(dead_code)]
+fn main() {
+    bracken::logging::init(0, false);
+    let args = Args::parse();
+    if let Err(e) = run(args) {
+        error!("Application error: {}", e);
+    }
+}
@@ -0,0 +1,284 @@
+use bracken::exit_code::ClassifiedError;
+use bracken::sampling;
+use clap::Parser;
+use log::{error, info};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+#[derive(Parser, Clone, Debug)]
+#[clap(
+    version,
+    about = "Simulates reads of a known composition from reference genomes.",
+    long_about = "Samples single-end reads from reference FASTA files in the proportions of a composition table, with optional substitution errors, and writes them as FASTQ together with a truth table of the reads drawn from every taxon, to validate a database and Bracken end to end."
+)]
+pub struct Args {
+    /// Tab-separated composition table of `fasta<TAB>taxid<TAB>abundance`
+    /// lines, with an optional fourth name column; relative paths are taken
+    /// from the table's directory.
+    #[clap(short, long, required = true)]
+    composition: PathBuf,
+
+    /// Output FASTQ file.
+    #[clap(short, long, required = true)]
+    output: PathBuf,
+
+    /// Output truth table of the simulated reads per taxon.
+    #[clap(long, required = true)]
+    truth: PathBuf,
+
+    /// Number of reads to simulate.
+    #[clap(short = 'n', long, default_value_t = 100_000)]
+    num_reads: usize,
+
+    /// Length of the simulated reads.
+    #[clap(short = 'l', long, default_value_t = 150)]
+    read_len: usize,
+
+    /// Probability of a substitution error at every base.
+    #[clap(long, default_value_t = 0.0)]
+    error_rate: f64,
+
+    /// Read the abundances as cell (genome copy) abundances, so larger
+    /// genomes receive proportionally more reads.
+    #[clap(long)]
+    cell_abundance: bool,
+
+    /// Seed for the random number generator.
+    #[clap(long, default_value_t = 0)]
+    seed: u64,
+}
+
+/// A FASTA record: seqid and sequence.
+type Record = (String, Vec<u8>);
+
+/// A genome of the composition table.
+struct Genome {
+    taxid: u32,
+    name: String,
+    abundance: f64,
+    /// Sequences long enough for a read.
+    sequences: Vec<Record>,
+}
+
+impl Genome {
+    fn len(&self) -> usize {
+        self.sequences.iter().map(|(_, seq)| seq.len()).sum()
+    }
+}
+
+/// Reads the records of a FASTA file, upper-cased.
+fn read_fasta(path: &Path) -> Result<Vec<Record>, Box<dyn std::error::Error>> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut records: Vec<Record> = Vec::new();
+    for line in reader.lines() {
+        let line = line?;
+        let line = line.trim_end();
+        if let Some(header) = line.strip_prefix('>') {
+            let seqid = header.split_whitespace().next().unwrap_or_default();
+            records.push((seqid.to_string(), Vec::new()));
+        } else if let Some((_, seq)) = records.last_mut() {
+            seq.extend(line.bytes().map(|base| base.to_ascii_uppercase()));
+        } else if !line.is_empty() {
+            return Err(ClassifiedError::input_format(format!(
+                "{:?} is not a FASTA file (no header before the first sequence)",
+                path
+            ))
+            .into());
+        }
+    }
+    Ok(records)
+}
+
+/// Reads the genomes of a composition table and their sequences.
+fn read_composition(
+    table: &Path,
+    read_len: usize,
+) -> Result<Vec<Genome>, Box<dyn std::error::Error>> {
+    let reader = BufReader::new(File::open(table)?);
+    let base = table.parent().unwrap_or(Path::new(""));
+    let mut genomes = Vec::new();
+    for (ix, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let malformed = |message: &str| {
+            ClassifiedError::input_format(format!("{:?} line {}: {}", table, ix + 1, message))
+        };
+        let fields: Vec<&str> = line.split('\t').map(str::trim).collect();
+        if fields.len() < 3 {
+            return Err(malformed("expected fasta<TAB>taxid<TAB>abundance").into());
+        }
+        let fasta = base.join(fields[0]);
+        let taxid = fields[1].parse().map_err(|_| malformed("invalid taxid"))?;
+        let abundance: f64 = fields[2]
+            .parse()
+            .map_err(|_| malformed("invalid abundance"))?;
+        if abundance.is_nan() || abundance < 0.0 {
+            return Err(malformed("abundance must not be negative").into());
+        }
+        let name = match fields.get(3) {
+            Some(name) => name.to_string(),
+            None => fasta
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().to_string())
+                .unwrap_or_default(),
+        };
+
+        let mut sequences = read_fasta(&fasta)?;
+        sequences.retain(|(_, seq)| seq.len() >= read_len);
+        if sequences.is_empty() {
+            return Err(ClassifiedError::input_format(format!(
+                "{:?} has no sequence of at least {} bp",
+                fasta, read_len
+            ))
+            .into());
+        }
+        genomes.push(Genome {
+            taxid,
+            name,
+            abundance,
+            sequences,
+        });
+    }
+    Ok(genomes)
+}
+
+fn complement(base: u8) -> u8 {
+    match base {
+        b'A' => b'T',
+        b'T' => b'A',
+        b'C' => b'G',
+        b'G' => b'C',
+        other => other,
+    }
+}
+
+/// Draws a read from a random position and strand of the genome, with
+/// substitution errors at `error_rate`. Returns the read and its origin.
+fn draw_read<R: Rng>(
+    genome: &Genome,
+    read_len: usize,
+    error_rate: f64,
+    rng: &mut R,
+) -> (Vec<u8>, String) {
+    // Sequences are chosen by the number of read start positions they hold
+    let starts: Vec<usize> = genome
+        .sequences
+        .iter()
+        .map(|(_, seq)| seq.len() - read_len + 1)
+        .collect();
+    let mut start = rng.gen_range(0..starts.iter().sum::<usize>());
+    let mut ix = 0;
+    while start >= starts[ix] {
+        start -= starts[ix];
+        ix += 1;
+    }
+    let (seqid, seq) = &genome.sequences[ix];
+    let mut read = seq[start..start + read_len].to_vec();
+    let reverse = rng.gen_bool(0.5);
+    if reverse {
+        read.reverse();
+        read.iter_mut().for_each(|base| *base = complement(*base));
+    }
+    if error_rate > 0.0 {
+        for base in read.iter_mut() {
+            if rng.gen_bool(error_rate) {
+                let others: Vec<u8> = b"ACGT".iter().copied().filter(|b| b != base).collect();
+                *base = others[rng.gen_range(0..others.len())];
+            }
+        }
+    }
+    let origin = format!("{}:{}{}", seqid, start + 1, if reverse { '-' } else { '+' });
+    (read, origin)
+}
+
+pub fn run(args: Args) -> Result<(), Box<dyn std::error::Error>> {
+    if !(0.0..=1.0).contains(&args.error_rate) {
+        return Err(format!("--error-rate {} is not a probability", args.error_rate).into());
+    }
+    let genomes = read_composition(&args.composition, args.read_len)?;
+    let weights: Vec<f64> = genomes
+        .iter()
+        .map(|genome| {
+            if args.cell_abundance {
+                genome.abundance * genome.len() as f64
+            } else {
+                genome.abundance
+            }
+        })
+        .collect();
+    if weights.iter().sum::<f64>() <= 0.0 {
+        return Err(ClassifiedError::empty_result(format!(
+            "no genome with a positive abundance in {:?}",
+            args.composition
+        ))
+        .into());
+    }
+    info!(
+        ">> Simulating {} {} bp reads from {} genomes",
+        args.num_reads,
+        args.read_len,
+        genomes.len()
+    );
+
+    let mut rng = StdRng::seed_from_u64(args.seed);
+    let counts = sampling::multinomial(&weights, args.num_reads, &mut rng);
+
+    let quality = vec![b'I'; args.read_len];
+    let mut out = BufWriter::new(File::create(&args.output)?);
+    let mut n_read = 0;
+    for (genome, &count) in genomes.iter().zip(counts.iter()) {
+        for _ in 0..count {
+            n_read += 1;
+            let (read, origin) = draw_read(genome, args.read_len, args.error_rate, &mut rng);
+            writeln!(out, "@read{} taxid={} {}", n_read, genome.taxid, origin)?;
+            out.write_all(&read)?;
+            out.write_all(b"\n+\n")?;
+            out.write_all(&quality)?;
+            writeln!(out)?;
+        }
+    }
+    out.flush()?;
+    info!("SIMULATED READS PRODUCED: {:?}", args.output.display());
+
+    // Genomes of the same taxon are reported together
+    let mut truth: Vec<(u32, &str, usize)> = Vec::new();
+    for (genome, &count) in genomes.iter().zip(counts.iter()) {
+        match truth
+            .iter_mut()
+            .find(|(taxid, _, _)| *taxid == genome.taxid)
+        {
+            Some(entry) => entry.2 += count,
+            None => truth.push((genome.taxid, &genome.name, count)),
+        }
+    }
+    let mut file = BufWriter::new(File::create(&args.truth)?);
+    writeln!(file, "name\ttaxonomy_id\treads\tfraction_total_reads")?;
+    for (taxid, name, count) in truth.iter() {
+        writeln!(
+            file,
+            "{}\t{}\t{}\t{:.5}",
+            name,
+            taxid,
+            count,
+            *count as f64 / args.num_reads.max(1) as f64
+        )?;
+    }
+    file.flush()?;
+    info!("TRUTH TABLE PRODUCED: {:?}", args.truth.display());
+    Ok(())
+}
+
+#[allow(dead_code)]
+fn main() {
+    bracken::logging::init(0, false);
+    let args = Args::parse();
+    if let Err(e) = run(args) {
+        error!("Application error: {}", e);
+    }
+}
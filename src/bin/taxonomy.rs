@@ -0,0 +1,132 @@
+use bracken::exit_code::ClassifiedError;
+use bracken::taxonomy::{self, NCBINames, NCBITaxonomy};
+use clap::{Parser, Subcommand};
+use log::error;
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+
+#[derive(Parser, Clone, Debug)]
+#[clap(
+    version,
+    about = "Queries the taxonomy (lca, lineage, children).",
+    long_about = "Looks taxa up in the taxonomy of a Kraken database (nodes.dmp, or the cached nodes.json, and names.dmp) and prints them as taxid, rank and name lines."
+)]
+pub struct Args {
+    #[clap(subcommand)]
+    query: Query,
+
+    /// Taxonomy folder with nodes.dmp and names.dmp.
+    #[clap(long = "taxonomy", global = true)]
+    taxonomy_dir: Option<PathBuf>,
+}
+
+#[derive(Subcommand, Clone, Debug)]
+enum Query {
+    /// Lowest common ancestor of the taxa.
+    Lca {
+        #[clap(required = true, num_args = 2..)]
+        taxids: Vec<u32>,
+    },
+    /// Ancestors of a taxon, from the root down to the taxon.
+    Lineage { taxid: u32 },
+    /// Direct children of a taxon, or its descendants at a rank.
+    Children {
+        taxid: u32,
+
+        /// List the descendants at this rank (D, P, C, O, F, G or S) instead
+        /// of the direct children.
+        #[clap(short, long)]
+        rank: Option<String>,
+    },
+}
+
+/// Lineage of a taxon, which must be in the taxonomy.
+fn lineage(taxo: &NCBITaxonomy, taxid: u32) -> Result<Vec<u32>, ClassifiedError> {
+    taxo.lineage(taxid).ok_or_else(|| {
+        ClassifiedError::input_format(format!("taxid {} is not in the taxonomy", taxid))
+    })
+}
+
+fn write_taxon<W: Write>(
+    out: &mut W,
+    taxo: &NCBITaxonomy,
+    names: &NCBINames,
+    taxid: u32,
+) -> io::Result<()> {
+    let rank = taxo
+        .get_node(&taxid)
+        .map(|node| node.rank.as_str())
+        .unwrap_or_default();
+    writeln!(
+        out,
+        "{}\t{}\t{}",
+        taxid,
+        rank,
+        names.get(&taxid).unwrap_or_default()
+    )
+}
+
+pub fn run(args: Args) -> Result<(), Box<dyn std::error::Error>> {
+    let taxonomy_dir = args
+        .taxonomy_dir
+        .ok_or("--taxonomy (or BRACKEN_TAXONOMY_DIR) is required")?;
+    let taxo = taxonomy::load_taxonomy(taxonomy_dir.clone())?;
+    let names = taxonomy::load_taxonomy_names(&taxonomy_dir)?;
+
+    let taxids = match &args.query {
+        Query::Lca { taxids } => {
+            let mut common = lineage(&taxo, taxids[0])?;
+            for &taxid in &taxids[1..] {
+                let other = lineage(&taxo, taxid)?;
+                let shared = common
+                    .iter()
+                    .zip(other.iter())
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                common.truncate(shared);
+            }
+            common.last().copied().into_iter().collect()
+        }
+        Query::Lineage { taxid } => lineage(&taxo, *taxid)?,
+        Query::Children { taxid, rank } => {
+            lineage(&taxo, *taxid)?;
+            match rank {
+                Some(rank) => {
+                    let ranks = taxonomy::ncbi_ranks(rank)
+                        .ok_or_else(|| format!("unknown rank {:?}", rank))?;
+                    let mut descendants: Vec<u32> = taxo
+                        .descendants(*taxid)
+                        .into_iter()
+                        .filter(|descendant| {
+                            taxo.get_node(descendant)
+                                .is_some_and(|node| ranks.contains(&node.rank.as_str()))
+                        })
+                        .collect();
+                    descendants.sort_unstable();
+                    descendants
+                }
+                None => {
+                    let mut children = taxo.children(*taxid);
+                    children.sort_unstable();
+                    children
+                }
+            }
+        }
+    };
+
+    let mut out = BufWriter::new(io::stdout().lock());
+    for taxid in taxids {
+        write_taxon(&mut out, &taxo, &names, taxid)?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+#[allow(dead_code)]
+fn main() {
+    bracken::logging::init(0, false);
+    let args = Args::parse();
+    if let Err(e) = run(args) {
+        error!("Application error: {}", e);
+    }
+}
@@ -0,0 +1,100 @@
+use bracken::bracken_output;
+use bracken::taxonomy;
+use clap::Parser;
+use log::error;
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+
+#[derive(Parser, Clone, Debug)]
+#[clap(
+    version,
+    about = "Prints the most abundant taxa of a Bracken output.",
+    long_about = "Prints the top taxa of a Bracken output by estimated reads, with their fraction and the cumulative percentage of all estimated reads, optionally collapsed to a higher rank."
+)]
+pub struct Args {
+    /// Bracken output file.
+    input: PathBuf,
+
+    /// Number of taxa to print.
+    #[clap(short = 'n', long, default_value_t = 10)]
+    top: usize,
+
+    /// Collapse the taxa to this higher rank (D, P, C, O, F or G) first.
+    #[clap(short, long, requires = "taxonomy_dir")]
+    rank: Option<String>,
+
+    /// Taxonomy folder with nodes.dmp (and names.dmp for the names of the
+    /// collapsed taxa), needed by --rank.
+    #[clap(long = "taxonomy")]
+    taxonomy_dir: Option<PathBuf>,
+}
+
+pub fn run(args: Args) -> Result<(), Box<dyn std::error::Error>> {
+    let table = bracken_output::read_table(&args.input)?;
+    let mut rows = match (&args.rank, &args.taxonomy_dir) {
+        (Some(rank), Some(taxonomy_dir)) => {
+            if taxonomy::ncbi_ranks(rank).is_none() {
+                return Err(format!("unknown rank {:?}", rank).into());
+            }
+            let taxo = taxonomy::load_taxonomy(taxonomy_dir.clone())?;
+            let names = taxonomy::load_taxonomy_names(taxonomy_dir)?;
+            bracken_output::collapse(&table.rows, rank, &taxo, &names)
+        }
+        _ => table.rows,
+    };
+    rows.sort_by(|a, b| {
+        b.new_est_reads
+            .cmp(&a.new_est_reads)
+            .then(a.taxid.cmp(&b.taxid))
+    });
+
+    let total_reads: usize = rows.iter().map(|row| row.new_est_reads).sum();
+    let percent = |reads: usize| 100.0 * reads as f64 / total_reads.max(1) as f64;
+
+    let mut out = BufWriter::new(io::stdout().lock());
+    writeln!(
+        out,
+        "{:>4}  {:>12}  {:>10}  {:>9}  {:>10}  {:<5}  name",
+        "#", "est_reads", "fraction", "cum_pct", "taxid", "level"
+    )?;
+    let mut cumulative = 0;
+    for (ix, row) in rows.iter().take(args.top).enumerate() {
+        cumulative += row.new_est_reads;
+        writeln!(
+            out,
+            "{:>4}  {:>12}  {:>10.5}  {:>8.2}%  {:>10}  {:<5}  {}",
+            ix + 1,
+            row.new_est_reads,
+            row.new_est_reads as f64 / total_reads.max(1) as f64,
+            percent(cumulative),
+            row.taxid,
+            row.level,
+            row.name
+        )?;
+    }
+    if rows.len() > args.top {
+        let other = total_reads - cumulative;
+        writeln!(
+            out,
+            "{:>4}  {:>12}  {:>10.5}  {:>8.2}%  {:>10}  {:<5}  ({} other taxa)",
+            "",
+            other,
+            other as f64 / total_reads.max(1) as f64,
+            100.0,
+            "-",
+            "-",
+            rows.len() - args.top
+        )?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+#[allow(dead_code)]
+fn main() {
+    bracken::logging::init(0, false);
+    let args = Args::parse();
+    if let Err(e) = run(args) {
+        error!("Application error: {}", e);
+    }
+}
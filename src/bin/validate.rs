@@ -0,0 +1,289 @@
+use bracken::exit_code::ClassifiedError;
+use bracken::taxonomy::MergedTaxids;
+use bracken::{database, kraken};
+use clap::Parser;
+use log::{error, info, warn};
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::PathBuf;
+
+#[derive(Parser, Clone, Debug)]
+#[clap(
+    version,
+    about = "Checks a Kraken report and a kmer distribution before running est-abundance.",
+    long_about = "Verifies the formats of a Kraken report and a kmer distribution, the read length the distribution was built for, and the overlap of their taxids, then prints a pass/fail report."
+)]
+pub struct Args {
+    /// Kraken report file.
+    #[clap(short, long, required = true)]
+    input: PathBuf,
+
+    /// Kmer distribution file.
+    #[clap(short, long, required = true)]
+    kmer_distr: PathBuf,
+
+    /// Level to push all reads to [default: S].
+    #[clap(short, long, default_value = "S")]
+    level: String,
+
+    /// Threshold for the minimum number of reads kraken must assign to a
+    /// level taxon for it to be kept.
+    #[clap(short, long, default_value_t = 10)]
+    threshold: usize,
+
+    /// Sample FASTQ used to detect the mean read length.
+    #[clap(long, conflicts_with = "read_len")]
+    reads: Option<PathBuf>,
+
+    /// Read length of the sample.
+    #[clap(long)]
+    read_len: Option<usize>,
+
+    /// Allowed difference in bases between the sample's read length and the
+    /// kmer distribution's.
+    #[clap(long, default_value_t = 10)]
+    read_len_tolerance: usize,
+
+    /// NCBI merged.dmp file used to remap merged taxids before comparing the
+    /// report with the kmer distribution.
+    #[clap(long, value_name = "FILE")]
+    merged: Option<PathBuf>,
+}
+
+/// Outcome of the checks; any failure makes the validation fail.
+#[derive(Default)]
+struct Checks {
+    failures: usize,
+    warnings: usize,
+}
+
+impl Checks {
+    fn pass(&self, message: String) {
+        info!("\t[PASS] {}", message);
+    }
+
+    fn warn(&mut self, message: String) {
+        self.warnings += 1;
+        warn!("\t[WARN] {}", message);
+    }
+
+    fn fail(&mut self, message: String) {
+        self.failures += 1;
+        error!("\t[FAIL] {}", message);
+    }
+}
+
+/// Counts the lines of a kmer distribution file and those that do not
+/// follow the `mapped_taxid\tgenome:kmers:total ...` format.
+fn check_kmer_distr_format(
+    kmer_distr: &PathBuf,
+) -> Result<(bool, usize, usize), Box<dyn std::error::Error>> {
+    let reader = BufReader::new(File::open(kmer_distr)?);
+    let mut lines = reader
+        .lines()
+        .skip_while(|line| line.as_ref().is_ok_and(|line| line.starts_with('#')));
+    let header_ok = match lines.next() {
+        Some(header) => header?.starts_with("mapped_taxid"),
+        None => false,
+    };
+
+    let (mut n_lines, mut n_malformed) = (0, 0);
+    for line in lines {
+        let line = line?;
+        n_lines += 1;
+        let mut fields = line.trim().split('\t');
+        let mapped_ok = fields
+            .next()
+            .is_some_and(|taxid| taxid.parse::<u32>().is_ok());
+        let genomes_ok = fields.next().is_some_and(|genomes| {
+            genomes.split_whitespace().all(|genome| {
+                let parts: Vec<&str> = genome.split(':').collect();
+                parts.len() == 3 && parts.iter().all(|part| part.parse::<u64>().is_ok())
+            })
+        });
+        if !mapped_ok || !genomes_ok {
+            n_malformed += 1;
+        }
+    }
+    Ok((header_ok, n_lines, n_malformed))
+}
+
+pub fn run(args: Args) -> Result<(), Box<dyn std::error::Error>> {
+    let mut checks = Checks::default();
+    info!("BRACKEN VALIDATION");
+
+    let merged = match &args.merged {
+        Some(merged_file) => MergedTaxids::load_ncbi_dmp(merged_file)?,
+        None => MergedTaxids::default(),
+    };
+
+    // Report format
+    let report = match kraken::check_report_file(&args.input)
+        .and_then(|_| kraken::read_kraken_report(&args.input))
+    {
+        Ok(mut report) if !report.is_empty() => {
+            kraken::remap_merged_taxids(&mut report, &merged);
+            checks.pass(format!("Kraken report: {} taxa", report.len()));
+            Some(report)
+        }
+        Ok(_) => {
+            checks.fail(format!("Kraken report {:?} has no taxa", args.input));
+            None
+        }
+        Err(e) => {
+            checks.fail(format!("Kraken report {:?}: {}", args.input, e));
+            None
+        }
+    };
+
+    // Kmer distribution format
+    let kmer_distr_ok = match check_kmer_distr_format(&args.kmer_distr) {
+        Ok((false, _, _)) => {
+            checks.fail(format!(
+                "kmer distribution {:?}: missing the mapped_taxid header (was it produced by kmer-distrib?)",
+                args.kmer_distr
+            ));
+            false
+        }
+        Ok((true, 0, _)) => {
+            checks.fail(format!(
+                "kmer distribution {:?} has no entries",
+                args.kmer_distr
+            ));
+            false
+        }
+        Ok((true, n_lines, 0)) => {
+            checks.pass(format!("kmer distribution: {} entries", n_lines));
+            true
+        }
+        Ok((true, n_lines, n_malformed)) => {
+            checks.warn(format!(
+                "kmer distribution: {} of {} entries are malformed and will be skipped",
+                n_malformed, n_lines
+            ));
+            true
+        }
+        Err(e) => {
+            checks.fail(format!("kmer distribution {:?}: {}", args.kmer_distr, e));
+            false
+        }
+    };
+
+    // Read length
+    let sample_read_len = match (&args.reads, args.read_len) {
+        (_, Some(read_len)) => Some(read_len),
+        (Some(reads), None) => match database::mean_read_length(reads, 10_000) {
+            Ok(read_len) => Some(read_len),
+            Err(e) => {
+                checks.fail(format!("reads {:?}: {}", reads, e));
+                None
+            }
+        },
+        (None, None) => None,
+    };
+    match (database::kmer_distr_read_len(&args.kmer_distr), sample_read_len) {
+        (Some(db_len), Some(read_len)) if db_len.abs_diff(read_len) > args.read_len_tolerance => {
+            checks.warn(format!(
+                "kmer distribution is for {} bp reads but the sample has {} bp reads; build one with kmer2read-distr -l {}",
+                db_len, read_len, read_len
+            ));
+        }
+        (Some(db_len), Some(read_len)) => checks.pass(format!(
+            "read length: sample {} bp, kmer distribution {} bp",
+            read_len, db_len
+        )),
+        (Some(db_len), None) => checks.warn(format!(
+            "kmer distribution is for {} bp reads; give --reads or --read-len to check the sample",
+            db_len
+        )),
+        (None, _) => checks.warn(
+            "read length of the kmer distribution unknown (expected a database{N}mers.kmer_distrib file name)"
+                .to_string(),
+        ),
+    }
+
+    // Taxid overlap
+    if let (Some(report), true) = (report, kmer_distr_ok) {
+        let (branch, branch_lvl) = kraken::level_branch(&args.level)?;
+        let mut stats = kraken::Stats::default();
+        kraken::parse_kraken_report(
+            report,
+            &args.level,
+            args.threshold,
+            branch,
+            branch_lvl,
+            &mut stats,
+        )?;
+        let taxids = kraken::read_kmer_distribution_taxids(&args.kmer_distr, &merged)?;
+        let mismatches = kraken::kmer_distribution_mismatches(&stats, &args.level, &taxids);
+        let count = |issue: &str| -> (usize, usize) {
+            mismatches
+                .iter()
+                .filter(|m| m.issue == issue)
+                .fold((0, 0), |(n, reads), m| (n + 1, reads + m.reads))
+        };
+
+        let (n_uncovered, uncovered_reads) = count("level_taxid_without_genome");
+        let n_level = stats.lvl_taxids.len();
+        if n_level == 0 {
+            checks.fail(format!(
+                "no {} taxa with at least {} reads in the report",
+                args.level, args.threshold
+            ));
+        } else if n_uncovered == n_level {
+            checks.fail(format!(
+                "none of the {} {} taxa has a genome in the kmer distribution; was it built from the same database?",
+                n_level, args.level
+            ));
+        } else if n_uncovered > 0 {
+            checks.warn(format!(
+                "{} of {} {} taxa ({} reads) have no genome in the kmer distribution and will receive no reads",
+                n_uncovered, n_level, args.level, uncovered_reads
+            ));
+        } else {
+            checks.pass(format!(
+                "all {} {} taxa have genomes in the kmer distribution",
+                n_level, args.level
+            ));
+        }
+
+        let (n_missing, missing_reads) = count("report_taxid_not_in_kmer_distr");
+        if n_missing > 0 {
+            checks.warn(format!(
+                "{} report taxa above {} ({} reads) are not in the kmer distribution; their reads will not be distributed",
+                n_missing, args.level, missing_reads
+            ));
+        } else {
+            checks.pass(format!(
+                "all report taxa above {} are in the kmer distribution",
+                args.level
+            ));
+        }
+
+        let (n_absent, _) = count("genome_not_in_report");
+        info!(
+            "\t[INFO] {} of {} genomes of the kmer distribution are absent from the report",
+            n_absent,
+            taxids.genomes.len()
+        );
+    }
+
+    if checks.failures > 0 {
+        error!(
+            "VALIDATION FAILED: {} failures, {} warnings",
+            checks.failures, checks.warnings
+        );
+        return Err(ClassifiedError::input_format("validation failed").into());
+    }
+    info!("VALIDATION PASSED: {} warnings", checks.warnings);
+    Ok(())
+}
+
+#[allow(dead_code)]
+fn main() {
+    bracken::logging::init(0, false);
+    let args = Args::parse();
+    if let Err(e) = run(args) {
+        error!("Application error: {}", e);
+    }
+}
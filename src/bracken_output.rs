@@ -0,0 +1,219 @@
+//! Abundance tables written by est-abundance, read back by the subcommands
+//! that compare or combine samples.
+
+use crate::taxonomy::{NCBINames, NCBITaxonomy};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+/// Errors reading a Bracken output table.
+#[derive(Debug, thiserror::Error)]
+pub enum OutputError {
+    #[error("{path:?}: {source}")]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    #[error("{0:?} is not a Bracken output (expected a name\\ttaxonomy_id\\t... header)")]
+    MissingHeader(PathBuf),
+    #[error("{path:?} line {line}: {message}")]
+    Malformed {
+        path: PathBuf,
+        line: usize,
+        message: &'static str,
+    },
+}
+
+impl OutputError {
+    fn io(path: &Path) -> impl FnOnce(io::Error) -> Self + '_ {
+        move |source| Self::Io {
+            path: path.to_path_buf(),
+            source,
+        }
+    }
+}
+
+/// A row of a Bracken output table.
+#[derive(Debug, Clone)]
+pub struct Row {
+    pub name: String,
+    pub taxid: u32,
+    pub level: String,
+    pub kraken_assigned_reads: usize,
+    pub added_reads: usize,
+    pub new_est_reads: usize,
+    /// Abundance column, in the unit named by [`Table::abundance_header`].
+    pub abundance: f64,
+}
+
+/// A Bracken output table.
+#[derive(Debug, Clone)]
+pub struct Table {
+    /// Header of the abundance column (fraction_total_reads, percent_total_reads, ...).
+    pub abundance_header: String,
+    pub rows: Vec<Row>,
+}
+
+impl Table {
+    /// Scale of the abundance column relative to a fraction, and the
+    /// precision est-abundance writes it with.
+    pub fn abundance_unit(&self) -> (f64, usize) {
+        match self.abundance_header.as_str() {
+            "percent_total_reads" => (100.0, 3),
+            "rpm_total_reads" => (1_000_000.0, 2),
+            _ => (1.0, 5),
+        }
+    }
+
+    /// Recomputes the abundance column from the estimated reads of the rows.
+    pub fn renormalize(&mut self) {
+        let (scale, _) = self.abundance_unit();
+        let total: usize = self.rows.iter().map(|row| row.new_est_reads).sum();
+        for row in self.rows.iter_mut() {
+            row.abundance = row.new_est_reads as f64 / total.max(1) as f64 * scale;
+        }
+    }
+}
+
+/// Reads a Bracken output table; columns after the abundance column (spike-in,
+/// confidence intervals) are ignored.
+pub fn read_table(path: &Path) -> Result<Table, OutputError> {
+    let file = File::open(path).map_err(OutputError::io(path))?;
+    let mut lines = BufReader::new(file).lines();
+
+    let header = match lines.next() {
+        Some(header) => header.map_err(OutputError::io(path))?,
+        None => return Err(OutputError::MissingHeader(path.to_path_buf())),
+    };
+    let columns: Vec<&str> = header.trim_end().split('\t').collect();
+    if columns.len() < 7 || columns[0] != "name" || columns[1] != "taxonomy_id" {
+        return Err(OutputError::MissingHeader(path.to_path_buf()));
+    }
+    let abundance_header = columns[6].to_string();
+
+    let mut rows = Vec::new();
+    for (ix, line) in lines.enumerate() {
+        let line = line.map_err(OutputError::io(path))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let malformed = |message| OutputError::Malformed {
+            path: path.to_path_buf(),
+            line: ix + 2,
+            message,
+        };
+        let fields: Vec<&str> = line.trim_end_matches(['\r', '\n']).split('\t').collect();
+        if fields.len() < 7 {
+            return Err(malformed("expected at least 7 columns"));
+        }
+        let count = |field: &str| {
+            field
+                .parse::<usize>()
+                .map_err(|_| malformed("invalid read count"))
+        };
+        rows.push(Row {
+            name: fields[0].to_string(),
+            taxid: fields[1]
+                .parse()
+                .map_err(|_| malformed("invalid taxonomy_id"))?,
+            level: fields[2].to_string(),
+            kraken_assigned_reads: count(fields[3])?,
+            added_reads: count(fields[4])?,
+            new_est_reads: count(fields[5])?,
+            abundance: fields[6]
+                .parse()
+                .map_err(|_| malformed("invalid abundance"))?,
+        });
+    }
+    Ok(Table {
+        abundance_header,
+        rows,
+    })
+}
+
+/// Writes a table in the est-abundance format, with the abundance column at
+/// the precision est-abundance uses for its unit.
+pub fn write_table<W: Write>(table: &Table, out: &mut W) -> io::Result<()> {
+    let (_, prec) = table.abundance_unit();
+    writeln!(
+        out,
+        "name\ttaxonomy_id\ttaxonomy_lvl\tkraken_assigned_reads\tadded_reads\tnew_est_reads\t{}",
+        table.abundance_header
+    )?;
+    for row in table.rows.iter() {
+        writeln!(
+            out,
+            "{}\t{}\t{}\t{}\t{}\t{}\t{:.prec$}",
+            row.name,
+            row.taxid,
+            row.level,
+            row.kraken_assigned_reads,
+            row.added_reads,
+            row.new_est_reads,
+            row.abundance
+        )?;
+    }
+    Ok(())
+}
+
+/// Sums the rows up to their ancestors at `rank` (a report rank code such
+/// as G). Rows without an ancestor at that rank, such as the unclassified
+/// row, are kept as they are. Names of the ancestors come from `names`, or
+/// are left empty.
+pub fn collapse(rows: &[Row], rank: &str, taxonomy: &NCBITaxonomy, names: &NCBINames) -> Vec<Row> {
+    let mut collapsed: HashMap<u32, Row> = HashMap::new();
+    let mut kept = Vec::new();
+    for row in rows {
+        match taxonomy.ancestor_at_rank(row.taxid, rank) {
+            Some(taxid) => {
+                let entry = collapsed.entry(taxid).or_insert_with(|| Row {
+                    name: names.get(&taxid).unwrap_or_default().to_string(),
+                    taxid,
+                    level: rank.to_string(),
+                    kraken_assigned_reads: 0,
+                    added_reads: 0,
+                    new_est_reads: 0,
+                    abundance: 0.0,
+                });
+                if entry.name.is_empty() && taxid == row.taxid {
+                    entry.name = row.name.clone();
+                }
+                entry.kraken_assigned_reads += row.kraken_assigned_reads;
+                entry.added_reads += row.added_reads;
+                entry.new_est_reads += row.new_est_reads;
+                entry.abundance += row.abundance;
+            }
+            None => kept.push(row.clone()),
+        }
+    }
+    if kept.iter().any(|row| row.level != "U") {
+        log::warn!(
+            "{} taxa have no ancestor at rank {} and are kept as they are",
+            kept.iter().filter(|row| row.level != "U").count(),
+            rank
+        );
+    }
+    let mut rows: Vec<Row> = collapsed.into_values().chain(kept).collect();
+    rows.sort_by(|a, b| {
+        b.new_est_reads
+            .cmp(&a.new_est_reads)
+            .then(a.taxid.cmp(&b.taxid))
+    });
+    rows
+}
+
+/// Adds a suffix before the extension of an output file (sample.bracken ->
+/// sample.<suffix>.bracken).
+pub fn suffixed_output(output: &Path, suffix: &str) -> PathBuf {
+    let stem = output
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let file_name = match output.extension() {
+        Some(ext) => format!("{}.{}.{}", stem, suffix, ext.to_string_lossy()),
+        None => format!("{}.{}", stem, suffix),
+    };
+    output.with_file_name(file_name)
+}
@@ -0,0 +1,153 @@
+//! Project configuration file (TOML) supplying default argument values.
+//!
+//! Top-level keys apply to every subcommand that has such an argument, and a
+//! table named after a subcommand (e.g. `[est-abundance]`) applies to that
+//! subcommand only. Keys are the long option names (`kmer-distr` or
+//! `kmer_distr`). Values given on the command line take precedence over the
+//! file, also over values of arguments they conflict with.
+
+use clap::builder::Resettable;
+use clap::parser::ValueSource;
+use clap::{Arg, ArgMatches, Command};
+use std::ffi::OsString;
+use std::io;
+use std::path::{Path, PathBuf};
+use toml::{Table, Value};
+
+/// Errors reading a configuration file.
+#[derive(Debug, thiserror::Error)]
+pub enum ConfigError {
+    #[error("cannot read config file {path:?}: {source}")]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    #[error("invalid config file {path:?}: {source}")]
+    Toml {
+        path: PathBuf,
+        #[source]
+        source: toml::de::Error,
+    },
+}
+
+/// Reads a configuration file.
+pub fn load(path: &Path) -> Result<Table, ConfigError> {
+    let content = std::fs::read_to_string(path).map_err(|source| ConfigError::Io {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    content
+        .parse::<Table>()
+        .map_err(|source| ConfigError::Toml {
+            path: path.to_path_buf(),
+            source,
+        })
+}
+
+/// Parses the command line before the configuration is known, ignoring
+/// errors such as missing required arguments, to find `--config` and the
+/// arguments given on the command line.
+pub fn pre_parse(cmd: &Command, args: &[OsString]) -> ArgMatches {
+    cmd.clone()
+        .ignore_errors(true)
+        .try_get_matches_from(args)
+        .unwrap_or_default()
+}
+
+/// The `--config` path of the command line (or `BRACKEN_CONFIG`), so the
+/// file can supply the defaults of the parser.
+pub fn config_path(matches: &ArgMatches) -> Option<PathBuf> {
+    let config = |matches: &ArgMatches| {
+        matches
+            .try_get_one::<PathBuf>("config")
+            .ok()
+            .flatten()
+            .cloned()
+    };
+    config(matches)
+        .or_else(|| matches.subcommand().and_then(|(_, sub)| config(sub)))
+        .or_else(|| std::env::var_os("BRACKEN_CONFIG").map(PathBuf::from))
+}
+
+/// Converts a TOML value into the argument values clap expects.
+fn arg_values(value: &Value) -> Vec<String> {
+    match value {
+        Value::String(s) => vec![s.clone()],
+        Value::Array(values) => values.iter().flat_map(arg_values).collect(),
+        other => vec![other.to_string()],
+    }
+}
+
+/// Makes every subcommand argument take its default from the configuration,
+/// except the arguments that conflict with one given on the command line
+/// (`matches` of [`pre_parse`]).
+pub fn apply(mut cmd: Command, config: &Table, matches: &ArgMatches) -> Command {
+    let (invoked, given) = match matches.subcommand() {
+        Some((name, sub)) => (
+            name,
+            sub.ids()
+                .filter(|id| sub.value_source(id.as_str()) == Some(ValueSource::CommandLine))
+                .map(|id| id.to_string())
+                .collect(),
+        ),
+        None => ("", Vec::new()),
+    };
+    let subcommands: Vec<String> = cmd
+        .get_subcommands()
+        .map(|sc| sc.get_name().to_string())
+        .collect();
+
+    for name in subcommands {
+        let section = config.get(&name).and_then(Value::as_table);
+        cmd = cmd.mut_subcommand(&name, |mut sc| {
+            let ids: Vec<String> = sc
+                .get_arguments()
+                .filter(|arg| !arg.is_positional())
+                .map(|arg| arg.get_id().to_string())
+                .filter(|id| id != "help" && id != "version")
+                .collect();
+            for id in ids {
+                let value = section
+                    .and_then(|section| lookup(section, &id))
+                    .or_else(|| lookup(config, &id).filter(|value| !value.is_table()));
+                let given = if name == invoked { &given[..] } else { &[] };
+                if let Some(value) = value.filter(|_| !conflicts(&sc, &id, given)) {
+                    sc = sc.mut_arg(&id, |arg| {
+                        arg.default_values(arg_values(value))
+                            .required(false)
+                            .required_unless_present(Resettable::Reset)
+                    });
+                }
+            }
+            sc
+        });
+    }
+    cmd
+}
+
+/// Whether the argument `id` conflicts with one of the arguments `given`,
+/// declared on either of them.
+fn conflicts(sc: &Command, id: &str, given: &[String]) -> bool {
+    let find = |id: &str| {
+        sc.get_arguments()
+            .find(|arg| arg.get_id() == id && !arg.is_global_set())
+    };
+    let conflicting = |a: &Arg, b: &Arg| {
+        sc.get_arg_conflicts_with(a)
+            .iter()
+            .any(|arg| arg.get_id() == b.get_id())
+    };
+    let Some(arg) = find(id) else {
+        return false;
+    };
+    given
+        .iter()
+        .filter_map(|id| find(id))
+        .any(|other| conflicting(arg, other) || conflicting(other, arg))
+}
+
+/// Looks up an argument by id, accepting kebab-case keys.
+fn lookup<'a>(table: &'a Table, id: &str) -> Option<&'a Value> {
+    table.get(id).or_else(|| table.get(&id.replace('_', "-")))
+}
@@ -0,0 +1,465 @@
+//! Kmer distribution files of a Bracken database and the read lengths
+//! they were built for.
+
+use crate::taxonomy::{self, TaxonomyError};
+use crate::{kmer2read_distr, kmer_distrib, kraken2, progress};
+use flate2::read::MultiGzDecoder;
+use flate2::write::GzEncoder;
+use indicatif::ProgressFinish;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+
+/// Errors reading kmer distributions and the files used to build them.
+#[derive(Debug, thiserror::Error)]
+pub enum DistribError {
+    #[error("{path:?}: {source}")]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    #[error("no database{{N}}mers.kmer_distrib file found in {0:?}")]
+    NoKmerDistr(PathBuf),
+    #[error("no reads found in {0:?}")]
+    NoReads(PathBuf),
+    #[error("{0:?} not found in the Kraken database")]
+    MissingInput(PathBuf),
+    #[error(transparent)]
+    Taxonomy(#[from] TaxonomyError),
+    #[error("{path:?}: invalid binary kmer distribution: {source}")]
+    Binary {
+        path: PathBuf,
+        #[source]
+        source: bincode::Error,
+    },
+    #[error("{0:?}: invalid accession index ({1})")]
+    AccessionIndex(PathBuf, &'static str),
+    #[error("{0:?}: invalid binary read distributions ({1})")]
+    BinaryCounts(PathBuf, &'static str),
+    #[error("{0:?}: invalid Kraken2 index options")]
+    IndexOptions(PathBuf),
+    #[error("{0:?}: seqid {1} is listed more than once (see --on-duplicate)")]
+    DuplicateSeqid(PathBuf, String),
+    #[error("{0:?}, line {1}: malformed kraken line ({2}), see --malformed")]
+    MalformedLine(PathBuf, usize, &'static str),
+    #[error("{0:?}: cannot merge the read distributions ({1})")]
+    MergeCounts(PathBuf, String),
+    #[error("{0} genome or mapped taxids are not in the taxonomy (see --taxid-issues)")]
+    InvalidTaxids(usize),
+    #[error("the kmer counts of genome {0} overflow 64 bits")]
+    KmerOverflow(String),
+    #[error("{0:?} already exists (use --force to overwrite it)")]
+    OutputExists(PathBuf),
+    #[error("kraken2 failed on chunk {chunk} ({status}), see {log:?}")]
+    Kraken2 {
+        chunk: usize,
+        status: String,
+        log: PathBuf,
+    },
+}
+
+impl DistribError {
+    pub(crate) fn io(path: &Path) -> impl FnOnce(io::Error) -> Self + '_ {
+        move |source| Self::Io {
+            path: path.to_path_buf(),
+            source,
+        }
+    }
+}
+
+/// Temporary file an output is written to, next to it, before
+/// [`rename_tmp`] moves it into place, so an interrupted run never leaves a
+/// truncated output behind.
+pub fn tmp_path(path: &Path) -> PathBuf {
+    let mut tmp = path.as_os_str().to_os_string();
+    tmp.push(".tmp");
+    PathBuf::from(tmp)
+}
+
+/// Moves the complete temporary file of `path` into place.
+pub fn rename_tmp(path: &Path) -> Result<(), DistribError> {
+    std::fs::rename(tmp_path(path), path).map_err(DistribError::io(path))
+}
+
+/// Fails on the first of `outputs` that exists, unless `force` is set.
+pub fn check_overwrite<'a>(
+    outputs: impl IntoIterator<Item = &'a Path>,
+    force: bool,
+) -> Result<(), DistribError> {
+    match outputs.into_iter().find(|output| output.exists()) {
+        Some(output) if !force => Err(DistribError::OutputExists(output.to_path_buf())),
+        _ => Ok(()),
+    }
+}
+
+/// Reader of a plain, gzip- or zstd-compressed file, detected by its first
+/// bytes, showing `message` on the progress bar.
+pub fn open_maybe_compressed(
+    path: &Path,
+    message: &'static str,
+) -> Result<Box<dyn BufRead + Send>, DistribError> {
+    let file = File::open(path).map_err(DistribError::io(path))?;
+    let len = file.metadata().map(|m| m.len()).unwrap_or(0);
+    let bar = progress::bytes(len, message).with_finish(ProgressFinish::AndClear);
+    decompress(path, bar.wrap_read(file))
+}
+
+/// Reader of a plain or compressed file without a progress bar, for callers
+/// reporting their own progress.
+pub fn open_maybe_compressed_quiet(path: &Path) -> Result<Box<dyn BufRead + Send>, DistribError> {
+    decompress(path, File::open(path).map_err(DistribError::io(path))?)
+}
+
+fn decompress<R: Read + Send + 'static>(
+    path: &Path,
+    file: R,
+) -> Result<Box<dyn BufRead + Send>, DistribError> {
+    let mut reader = BufReader::new(file);
+    let magic = reader.fill_buf().map_err(DistribError::io(path))?;
+    if magic.starts_with(&[0x1f, 0x8b]) {
+        Ok(Box::new(BufReader::new(MultiGzDecoder::new(reader))))
+    } else if magic.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+        let decoder = zstd::Decoder::with_buffer(reader).map_err(DistribError::io(path))?;
+        Ok(Box::new(BufReader::new(decoder)))
+    } else {
+        Ok(Box::new(reader))
+    }
+}
+
+/// Compression of an output file.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Compression {
+    #[default]
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    /// Suffix of the files written with the compression.
+    pub fn extension(self) -> &'static str {
+        match self {
+            Compression::None => "",
+            Compression::Gzip => ".gz",
+            Compression::Zstd => ".zst",
+        }
+    }
+
+    /// `path` with the suffix of the compression.
+    pub fn path(self, path: &Path) -> PathBuf {
+        let mut path = path.as_os_str().to_os_string();
+        path.push(self.extension());
+        PathBuf::from(path)
+    }
+}
+
+/// Output file compressed on the fly. [`CompressedWriter::finish`] must be
+/// called to write the end of the compressed stream and report its errors.
+pub enum CompressedWriter {
+    Plain(File),
+    Gzip(GzEncoder<File>),
+    Zstd(zstd::Encoder<'static, File>),
+}
+
+impl CompressedWriter {
+    pub fn create(path: &Path, compression: Compression) -> io::Result<Self> {
+        let file = File::create(path)?;
+        Ok(match compression {
+            Compression::None => CompressedWriter::Plain(file),
+            Compression::Gzip => {
+                CompressedWriter::Gzip(GzEncoder::new(file, flate2::Compression::fast()))
+            }
+            Compression::Zstd => CompressedWriter::Zstd(zstd::Encoder::new(file, 3)?),
+        })
+    }
+
+    /// Like [`CompressedWriter::create`], appending to `path`; compressed
+    /// data goes to a new member (frame) of the file.
+    pub fn append(path: &Path, compression: Compression) -> io::Result<Self> {
+        let file = File::options().append(true).open(path)?;
+        Ok(match compression {
+            Compression::None => CompressedWriter::Plain(file),
+            Compression::Gzip => {
+                CompressedWriter::Gzip(GzEncoder::new(file, flate2::Compression::fast()))
+            }
+            Compression::Zstd => CompressedWriter::Zstd(zstd::Encoder::new(file, 3)?),
+        })
+    }
+
+    pub fn finish(self) -> io::Result<()> {
+        match self {
+            CompressedWriter::Plain(mut file) => file.flush(),
+            CompressedWriter::Gzip(encoder) => encoder.finish()?.flush(),
+            CompressedWriter::Zstd(encoder) => encoder.finish()?.flush(),
+        }
+    }
+}
+
+impl Write for CompressedWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            CompressedWriter::Plain(file) => file.write(buf),
+            CompressedWriter::Gzip(encoder) => encoder.write(buf),
+            CompressedWriter::Zstd(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            CompressedWriter::Plain(file) => file.flush(),
+            CompressedWriter::Gzip(encoder) => encoder.flush(),
+            CompressedWriter::Zstd(encoder) => encoder.flush(),
+        }
+    }
+}
+
+/// Read length of a database{N}mers.kmer_distrib file, taken from its name.
+pub fn kmer_distr_read_len(path: &Path) -> Option<usize> {
+    let name = path.file_name()?.to_str()?;
+    [Compression::Gzip, Compression::Zstd]
+        .iter()
+        .find_map(|compression| name.strip_suffix(compression.extension()))
+        .unwrap_or(name)
+        .strip_prefix("database")?
+        .strip_suffix("mers.kmer_distrib")?
+        .parse()
+        .ok()
+}
+
+/// Mean length of the first reads of a FASTQ file, possibly compressed.
+pub fn mean_read_length(reads: &Path, max_reads: usize) -> Result<usize, DistribError> {
+    let reader = open_maybe_compressed_quiet(reads)?;
+    let (mut n_reads, mut total_len) = (0, 0);
+    // 每条 FASTQ 记录占 4 行, 第 2 行为序列; 跳过空行 (如文件末尾的空行)
+    let lines = reader
+        .lines()
+        .filter(|line| line.as_ref().map_or(true, |line| !line.trim().is_empty()));
+    for line in lines.skip(1).step_by(4).take(max_reads) {
+        total_len += line.map_err(DistribError::io(reads))?.trim_end().len();
+        n_reads += 1;
+    }
+    if n_reads == 0 {
+        return Err(DistribError::NoReads(reads.to_path_buf()));
+    }
+    Ok(total_len / n_reads)
+}
+
+/// Picks the database{N}mers.kmer_distrib file of `database_dir` whose read
+/// length is closest to `read_len`. Returns the file and its read length.
+pub fn select_kmer_distr(
+    database_dir: &Path,
+    read_len: usize,
+) -> Result<(PathBuf, usize), DistribError> {
+    let mut best: Option<(PathBuf, usize)> = None;
+    let entries = std::fs::read_dir(database_dir).map_err(DistribError::io(database_dir))?;
+    for entry in entries {
+        let path = entry.map_err(DistribError::io(database_dir))?.path();
+        let Some(len) = kmer_distr_read_len(&path) else {
+            continue;
+        };
+        let closer = best.as_ref().is_none_or(|(_, best_len)| {
+            (len.abs_diff(read_len), len) < (best_len.abs_diff(read_len), *best_len)
+        });
+        if closer {
+            best = Some((path, len));
+        }
+    }
+    best.ok_or_else(|| DistribError::NoKmerDistr(database_dir.to_path_buf()))
+}
+
+/// Read distribution file of a read length in a database directory.
+pub fn kraken_cnts_path(database_dir: &Path, read_len: usize) -> PathBuf {
+    database_dir.join(format!("database{}mers.kraken_cnts", read_len))
+}
+
+/// Kmer distribution file of a read length in a database directory:
+/// database{L}mers.kmer_distrib, or its gzip or zstd compressed version if
+/// only that exists.
+pub fn kmer_distr_path(database_dir: &Path, read_len: usize) -> PathBuf {
+    let kmer_distr = database_dir.join(format!("database{}mers.kmer_distrib", read_len));
+    if kmer_distr.exists() {
+        return kmer_distr;
+    }
+    [Compression::Gzip, Compression::Zstd]
+        .into_iter()
+        .map(|compression| compression.path(&kmer_distr))
+        .find(|compressed| compressed.exists())
+        .unwrap_or(kmer_distr)
+}
+
+/// Kraken output of the library sequences of a database: database.kraken,
+/// or database.kraken.gz if only the compressed file exists.
+pub fn kraken_file_path(database_dir: &Path) -> PathBuf {
+    let kraken_file = database_dir.join("database.kraken");
+    let compressed = database_dir.join("database.kraken.gz");
+    if !kraken_file.exists() && compressed.exists() {
+        compressed
+    } else {
+        kraken_file
+    }
+}
+
+/// Builds the kmer distribution of a Kraken database for a read length like
+/// bracken-build: kmer2read-distr on database.kraken, then kmer-distrib.
+/// Existing outputs are reused unless `force` is set; `output` sets how the
+/// read distributions are written, compressed ones and their kmer
+/// distribution getting the extension of their compression. Returns the
+/// kmer distribution file.
+pub fn build_kmer_distr(
+    database_dir: &Path,
+    kmer_len: usize,
+    read_len: usize,
+    force: bool,
+    output: kmer2read_distr::OutputOptions,
+) -> Result<PathBuf, DistribError> {
+    let mut kmer_distrs = build_kmer_distrs(database_dir, kmer_len, &[read_len], force, output)?;
+    Ok(kmer_distrs.remove(0))
+}
+
+/// Like [`build_kmer_distr`] for several read lengths, converting
+/// database.kraken in a single pass for all the read lengths whose read
+/// distributions are missing. Returns the kmer distribution files, in the
+/// order of `read_lens`.
+pub fn build_kmer_distrs(
+    database_dir: &Path,
+    kmer_len: usize,
+    read_lens: &[usize],
+    force: bool,
+    output: kmer2read_distr::OutputOptions,
+) -> Result<Vec<PathBuf>, DistribError> {
+    let kraken_file = kraken_file_path(database_dir);
+    let seqid2taxid_file = database_dir.join("seqid2taxid.map");
+    let taxonomy_dir = database_dir.join("taxonomy");
+    let compression = output.compression;
+    let kraken_cnts: Vec<PathBuf> = read_lens
+        .iter()
+        .map(|&read_len| compression.path(&kraken_cnts_path(database_dir, read_len)))
+        .collect();
+
+    let rebuilt: Vec<(usize, PathBuf)> = read_lens
+        .iter()
+        .copied()
+        .zip(kraken_cnts.iter().cloned())
+        .filter(|(_, kraken_cnts)| force || !kraken_cnts.exists())
+        .collect();
+    if !rebuilt.is_empty() {
+        for input in [&kraken_file, &seqid2taxid_file, &taxonomy_dir] {
+            if !input.exists() {
+                return Err(DistribError::MissingInput(input.clone()));
+            }
+        }
+        for (_, kraken_cnts) in &rebuilt {
+            log::info!(">> Generating {:?}", kraken_cnts);
+        }
+        // The taxonomy merges the taxids of duplicate seqids
+        let taxo = taxonomy::load_taxonomy(taxonomy_dir)?;
+        let (seqid2taxid, left_out) = kmer2read_distr::get_seqid2taxid_with(
+            &seqid2taxid_file,
+            kmer2read_distr::OnDuplicate::Merge,
+            Some(&taxo),
+        )?;
+        // The kmers of a library are classified to its taxa or their
+        // ancestors, all that classifying its reads takes
+        let taxo = taxo.prune(seqid2taxid.values().copied().chain(left_out));
+        // Reads of a Kraken2 database span its minimizers
+        let index_options = kraken2::index_options(database_dir)?;
+        kmer2read_distr::evaluate_kfile_lengths(
+            std::slice::from_ref(&kraken_file),
+            &rebuilt,
+            seqid2taxid,
+            kmer_len,
+            &taxo,
+            kmer2read_distr::ReadOptions {
+                minimizer_len: index_options.map(|options| options.minimizer_len),
+                ..Default::default()
+            },
+            output,
+        )?;
+    }
+
+    let mut kmer_distrs = Vec::new();
+    for (&read_len, kraken_cnts) in read_lens.iter().zip(&kraken_cnts) {
+        let mut kmer_distr = kmer_distr_path(database_dir, read_len);
+        let rebuild_cnts = rebuilt.iter().any(|(len, _)| *len == read_len);
+        if !rebuild_cnts {
+            log::info!(">> Reusing {:?}", kraken_cnts);
+        }
+        if rebuild_cnts || !kmer_distr.exists() {
+            // Compressed like the read distributions
+            kmer_distr = compression
+                .path(&database_dir.join(format!("database{}mers.kmer_distrib", read_len)));
+            log::info!(">> Generating {:?}", kmer_distr);
+            kmer_distrib::generate_kmer_distribution(
+                std::slice::from_ref(kraken_cnts),
+                &kmer_distr,
+                &kmer_distrib::DistribOptions {
+                    compression,
+                    ..Default::default()
+                },
+            )?;
+        } else {
+            log::info!(">> Reusing {:?}", kmer_distr);
+        }
+        kmer_distrs.push(kmer_distr);
+    }
+    Ok(kmer_distrs)
+}
+
+/// A mapped taxid of a kmer distribution file.
+#[derive(Debug, Clone)]
+pub struct MappedTaxon {
+    pub taxid: u32,
+    /// Number of genomes whose reads are classified to the taxid.
+    pub n_genomes: usize,
+    /// Kmers of all genomes mapped to the taxid.
+    pub kmers: u64,
+}
+
+/// Statistics of a kmer distribution file.
+#[derive(Debug, Default)]
+pub struct KmerDistrSummary {
+    pub mapped: Vec<MappedTaxon>,
+    /// Total kmers of each genome.
+    pub genome_kmers: HashMap<u32, u64>,
+    /// Kmers of each genome mapped to the genome itself.
+    pub self_kmers: HashMap<u32, u64>,
+    /// Lines that could not be parsed.
+    pub malformed_lines: usize,
+}
+
+impl KmerDistrSummary {
+    pub fn mapped_kmers(&self) -> u64 {
+        self.mapped.iter().map(|taxon| taxon.kmers).sum()
+    }
+
+    pub fn total_genome_kmers(&self) -> u64 {
+        self.genome_kmers.values().sum()
+    }
+}
+
+/// Reads the statistics of a kmer distribution file, text or binary.
+pub fn summarize_kmer_distr(path: &Path) -> Result<KmerDistrSummary, DistribError> {
+    let mut summary = KmerDistrSummary::default();
+    summary.malformed_lines =
+        kmer_distrib::for_each_entry(path, "Reading kmer distribution", |entry| {
+            let mut taxon = MappedTaxon {
+                taxid: entry.mapped_taxid,
+                n_genomes: 0,
+                kmers: 0,
+            };
+            for genome in entry.genomes {
+                taxon.n_genomes += 1;
+                taxon.kmers += genome.kmers_mapped;
+                summary
+                    .genome_kmers
+                    .insert(genome.taxid, genome.total_kmers);
+                if genome.taxid == entry.mapped_taxid {
+                    summary.self_kmers.insert(genome.taxid, genome.kmers_mapped);
+                }
+            }
+            summary.mapped.push(taxon);
+        })?;
+    Ok(summary)
+}
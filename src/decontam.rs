@@ -0,0 +1,100 @@
+use std::collections::BTreeSet;
+
+/// How a taxon is scored as a contaminant, after the methods of decontam.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Method {
+    /// Compare the taxon's prevalence in the negative controls with its
+    /// prevalence in the other samples; needs negative controls.
+    #[default]
+    Prevalence,
+    /// Compare how the taxon's fraction of a sample follows the sample's
+    /// total reads: contaminants are diluted by the reads of the sample, so
+    /// their fraction falls with the total.
+    Frequency,
+}
+
+/// Contaminant score of a taxon between 0 and 1; the higher, the more
+/// likely the taxon is a contaminant.
+///
+/// `counts` and `totals` hold the reads of the taxon and all reads of every
+/// sample, `controls` the indices of the negative control samples.
+pub fn score(
+    method: Method,
+    counts: &[usize],
+    totals: &[usize],
+    controls: &BTreeSet<usize>,
+) -> f64 {
+    match method {
+        Method::Prevalence => prevalence_score(counts, controls),
+        Method::Frequency => frequency_score(counts, totals, controls),
+    }
+}
+
+/// Prevalence in the controls over the sum of the prevalences in the
+/// controls and the other samples, 0 for taxa found nowhere.
+pub fn prevalence_score(counts: &[usize], controls: &BTreeSet<usize>) -> f64 {
+    let (mut in_controls, mut in_samples) = (0, 0);
+    for (ix, &count) in counts.iter().enumerate() {
+        if count == 0 {
+            continue;
+        }
+        if controls.contains(&ix) {
+            in_controls += 1;
+        } else {
+            in_samples += 1;
+        }
+    }
+    let samples = counts.len() - controls.len();
+    let control_prevalence = if controls.is_empty() {
+        0.0
+    } else {
+        in_controls as f64 / controls.len() as f64
+    };
+    let sample_prevalence = if samples == 0 {
+        0.0
+    } else {
+        in_samples as f64 / samples as f64
+    };
+    let total = control_prevalence + sample_prevalence;
+    if total > 0.0 {
+        control_prevalence / total
+    } else {
+        0.0
+    }
+}
+
+/// Fits the log fraction of the taxon against the log total reads of the
+/// samples (controls excluded) once with the slope -1 of a contaminant,
+/// whose reads do not grow with the sample, and once with the slope 0 of a
+/// genuine taxon. The score is the share of the contaminant model in the
+/// summed squared errors of both: the better the contaminant model fits,
+/// the closer to 1. Taxa found in fewer than two samples or only in
+/// samples of one size cannot be told apart and score 0.
+pub fn frequency_score(counts: &[usize], totals: &[usize], controls: &BTreeSet<usize>) -> f64 {
+    let points: Vec<(f64, f64)> = counts
+        .iter()
+        .zip(totals)
+        .enumerate()
+        .filter(|(ix, (&count, &total))| !controls.contains(ix) && count > 0 && total > 0)
+        .map(|(_, (&count, &total))| {
+            let log_total = (total as f64).ln();
+            (log_total, (count as f64).ln() - log_total)
+        })
+        .collect();
+    if points.len() < 2 || points.iter().all(|&(x, _)| x == points[0].0) {
+        return 0.0;
+    }
+
+    let n = points.len() as f64;
+    let mean_fraction = points.iter().map(|&(_, y)| y).sum::<f64>() / n;
+    let mean_count = points.iter().map(|&(x, y)| x + y).sum::<f64>() / n;
+    let genuine_error: f64 = points
+        .iter()
+        .map(|&(_, y)| (y - mean_fraction).powi(2))
+        .sum();
+    let contaminant_error: f64 = points
+        .iter()
+        .map(|&(x, y)| (x + y - mean_count).powi(2))
+        .sum();
+    genuine_error / (genuine_error + contaminant_error)
+}
@@ -0,0 +1,1314 @@
+use crate::bracken_output::suffixed_output;
+use crate::exit_code::ClassifiedError;
+use crate::kraken::{self, DistributionMode, DistributionOptions, KrakenError, Rounding};
+use crate::taxonomy::{self, MergedTaxids, NCBINames};
+use crate::{database, kmer_distrib, kraken2, sampling};
+use chrono::{DateTime, Local};
+use clap::{Parser, ValueEnum};
+use log::{info, warn};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use rayon::prelude::*;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Unit of the abundance column in the output table.
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum Normalize {
+    /// Fraction of total reads (0-1).
+    Fraction,
+    /// Percentage of total reads (0-100).
+    Percent,
+    /// Reads per million.
+    Rpm,
+}
+
+impl Normalize {
+    fn header(self) -> &'static str {
+        match self {
+            Normalize::Fraction => "fraction_total_reads",
+            Normalize::Percent => "percent_total_reads",
+            Normalize::Rpm => "rpm_total_reads",
+        }
+    }
+
+    fn scale(self) -> f64 {
+        match self {
+            Normalize::Fraction => 1.0,
+            Normalize::Percent => 100.0,
+            Normalize::Rpm => 1_000_000.0,
+        }
+    }
+
+    fn precision(self) -> usize {
+        match self {
+            Normalize::Fraction => 5,
+            Normalize::Percent => 3,
+            Normalize::Rpm => 2,
+        }
+    }
+}
+
+/// Order of the rows in the output table.
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum SortBy {
+    /// Descending estimated reads.
+    Reads,
+    /// Ascending name.
+    Name,
+    /// Ascending taxid.
+    Taxid,
+}
+
+/// How taxon names are written in the output tables.
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum NameStyle {
+    /// Names as they are.
+    Raw,
+    /// Names with spaces, tabs, brackets or quotes enclosed in double quotes.
+    Quoted,
+    /// Brackets and quotes removed and runs of whitespace replaced by "_".
+    Underscored,
+}
+
+impl NameStyle {
+    fn apply(self, name: &str) -> Cow<'_, str> {
+        let special = |c: char| c.is_whitespace() || "[]()\"'".contains(c);
+        match self {
+            NameStyle::Raw => Cow::Borrowed(name),
+            NameStyle::Quoted if name.contains(special) => {
+                Cow::Owned(format!("\"{}\"", name.replace('"', "\"\"")))
+            }
+            NameStyle::Quoted => Cow::Borrowed(name),
+            NameStyle::Underscored => Cow::Owned(
+                name.split(|c: char| c.is_whitespace())
+                    .map(|word| word.replace(|c: char| "[]()\"'".contains(c), ""))
+                    .filter(|word| !word.is_empty())
+                    .collect::<Vec<_>>()
+                    .join("_"),
+            ),
+        }
+    }
+}
+
+/// Command-line name of an enum value, as in the summary parameters.
+fn value_name<T: ValueEnum>(value: T) -> Option<String> {
+    value
+        .to_possible_value()
+        .map(|value| value.get_name().to_string())
+}
+
+/// Sorts the level taxa for output. Ties are broken by taxid so the order
+/// never depends on the internal map ordering.
+fn sorted_lvl_taxids<'a>(
+    lvl_taxids: impl IntoIterator<Item = (&'a u32, &'a kraken::LvlValue)>,
+    sort: SortBy,
+) -> Vec<(&'a u32, &'a kraken::LvlValue)> {
+    let mut rows: Vec<_> = lvl_taxids.into_iter().collect();
+    match sort {
+        SortBy::Reads => rows.sort_by(|a, b| {
+            b.1.new_est_reads()
+                .total_cmp(&a.1.new_est_reads())
+                .then(a.0.cmp(b.0))
+        }),
+        SortBy::Name => rows.sort_by(|a, b| a.1 .0.cmp(&b.1 .0).then(a.0.cmp(b.0))),
+        SortBy::Taxid => rows.sort_by_key(|row| *row.0),
+    }
+    rows
+}
+
+/// Spike-in organism with a known amount added to the sample (`taxid:cells`).
+#[derive(Clone, Copy, Debug)]
+pub struct SpikeIn {
+    taxid: u32,
+    cells: f64,
+}
+
+impl FromStr for SpikeIn {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (taxid, cells) = s
+            .split_once(':')
+            .ok_or_else(|| format!("expected TAXID:CELLS, got {:?}", s))?;
+        let taxid = taxid
+            .parse::<u32>()
+            .map_err(|_| format!("invalid spike-in taxid {:?}", taxid))?;
+        let cells = cells
+            .parse::<f64>()
+            .ok()
+            .filter(|cells| *cells > 0.0)
+            .ok_or_else(|| format!("invalid spike-in amount {:?}", cells))?;
+        Ok(Self { taxid, cells })
+    }
+}
+
+#[derive(Parser, Clone, Debug)]
+#[clap(
+    version,
+    about = "Estimates species or genus level abundance from Kraken outputs using Bayesian methods.",
+    long_about = "Estimates species or genus level abundance based on assigned reads and expected kmer distributions from Kraken report outputs."
+)]
+pub struct Args {
+    /// Kraken counts file for each genome mapped to the overall database.
+    #[clap(short, long, required = true)]
+    input: PathBuf,
+
+    /// Kmer distribution file.
+    #[clap(short, long, required_unless_present = "database_dir")]
+    kmer_distr: Option<PathBuf>,
+
+    /// Database directory holding several database{N}mers.kmer_distrib
+    /// files; the one closest to the sample's read length is used.
+    #[clap(long, conflicts_with = "kmer_distr")]
+    database_dir: Option<PathBuf>,
+
+    /// Sample FASTQ used to detect the mean read length for --database-dir
+    /// (and to check the read length recorded in the kmer distribution).
+    #[clap(long, conflicts_with = "read_len")]
+    reads: Option<PathBuf>,
+
+    /// Read length of the sample for --database-dir (and to check the read
+    /// length recorded in the kmer distribution).
+    #[clap(long)]
+    read_len: Option<usize>,
+
+    /// Warn when the kmer distribution's read length differs from the
+    /// sample's by more than this many bases.
+    #[clap(long, default_value_t = 10)]
+    read_len_tolerance: usize,
+
+    /// NCBI names.dmp file; its scientific names replace the names of the
+    /// report in the outputs (taxa missing from it keep their report name).
+    #[clap(long, value_name = "FILE")]
+    names: Option<PathBuf>,
+
+    /// NCBI merged.dmp file; taxids of the report and the kmer distribution
+    /// that were merged into other taxids are remapped to the current ones.
+    #[clap(long, value_name = "FILE")]
+    merged: Option<PathBuf>,
+
+    /// Output modified kraken report file with abundance estimates
+    /// ("-" writes the table to stdout).
+    #[clap(short, long, required = true)]
+    output: PathBuf,
+
+    /// Level to push all reads to [default: S].
+    #[clap(short, long, default_value = "S")]
+    level: String,
+
+    /// Threshold for the minimum number of reads kraken must assign
+    /// to a classification for that classification to be considered in the
+    /// final abundance estimation. Several comma-separated thresholds
+    /// write one output per threshold plus a sweep summary.
+    #[clap(short, long, value_delimiter = ',', default_value = "10")]
+    threshold: Vec<usize>,
+
+    /// Compute fractions relative to all reads in the sample, including
+    /// unclassified reads, and add an explicit unclassified row to the output.
+    #[clap(long)]
+    include_unclassified: bool,
+
+    /// Remove the given taxid and all of its descendants (e.g. 9606 for host
+    /// reads) before estimation. May be given multiple times.
+    #[clap(long = "exclude-subtree", value_name = "TAXID")]
+    exclude_subtree: Vec<u32>,
+
+    /// Restrict the estimation to a domain (Bacteria, Archaea, Eukaryota,
+    /// Viruses or its taxid), resolved through --taxonomy. May be given
+    /// multiple times.
+    #[clap(long = "domain", value_name = "DOMAIN", requires = "taxonomy_dir")]
+    domain: Vec<String>,
+
+    /// Taxonomy folder (nodes.dmp and names.dmp) placing the report taxa in
+    /// the --domain domains.
+    #[clap(long = "taxonomy", value_name = "DIR")]
+    taxonomy_dir: Option<PathBuf>,
+
+    /// File of taxids (one per line) to restrict the estimation to.
+    #[clap(long, value_name = "FILE")]
+    include_taxids: Option<PathBuf>,
+
+    /// File of taxids (one per line) to remove from the estimation.
+    #[clap(long, value_name = "FILE")]
+    exclude_taxids: Option<PathBuf>,
+
+    /// Unit of the abundance column: fraction, percent or rpm (reads per million).
+    #[clap(long, value_enum, default_value_t = Normalize::Fraction)]
+    normalize: Normalize,
+
+    /// Spike-in organism and the number of cells (or genome copies) added,
+    /// as TAXID:CELLS. Adds an absolute_abundance column scaled by the
+    /// spike-in's estimated reads.
+    #[clap(long, value_name = "TAXID:CELLS")]
+    spike_in: Option<SpikeIn>,
+
+    /// Maximum number of EM rounds re-estimating genome priors from the
+    /// previous round's abundances (1 = single Bayesian pass).
+    #[clap(long, default_value_t = 1)]
+    em_iterations: usize,
+
+    /// Stop the EM rounds once the largest change of a genome estimate,
+    /// relative to all estimated reads, is below this value.
+    #[clap(long, default_value_t = 1e-4)]
+    em_tolerance: f64,
+
+    /// Number of bootstrap replicates (multinomial resampling of the report's
+    /// read counts) used to add 95% confidence interval columns.
+    #[clap(long, default_value_t = 0)]
+    bootstrap: usize,
+
+    /// Rarefy the report to this many reads (subsampling without
+    /// replacement) before estimation, so samples of different depth can be
+    /// compared.
+    #[clap(long)]
+    subsample_depth: Option<usize>,
+
+    /// Write the taxa whose kraken reads lie near the threshold, whose
+    /// presence in the output would flip with a small change of threshold.
+    #[clap(long)]
+    sensitivity_report: Option<PathBuf>,
+
+    /// Write the taxids on which the report and the kmer distribution
+    /// disagree, with the reads affected, to this file.
+    #[clap(long)]
+    diagnostics: Option<PathBuf>,
+
+    /// Write the nodes whose reads could not be redistributed, with the
+    /// reason, to this file.
+    #[clap(long)]
+    out_nondistributed: Option<PathBuf>,
+
+    /// Write the summary counters, parameters and timing as JSON to this
+    /// file (e.g. for MultiQC).
+    #[clap(long)]
+    summary_json: Option<PathBuf>,
+
+    /// Write the level taxa discarded by the threshold, with their kraken
+    /// reads, to this file.
+    #[clap(long)]
+    out_discarded: Option<PathBuf>,
+
+    /// Also list the level taxa of the kmer distribution's genomes that
+    /// received no reads, so tables share the database's feature space.
+    #[clap(long)]
+    include_zero: bool,
+
+    /// Also write cumulative tables at every major rank above the level
+    /// (e.g. <output>.G.<ext>), summed from the level estimates.
+    #[clap(long)]
+    all_ranks: bool,
+
+    /// Relative window around the threshold used by --sensitivity-report
+    /// (0.5 lists taxa with reads between 0.5x and 1.5x the threshold).
+    #[clap(long, default_value_t = 0.5)]
+    sensitivity_window: f64,
+
+    /// Absolute window around the threshold used by --sensitivity-report, in
+    /// the unit of the abundance column (--normalize) of the total reads;
+    /// the wider of both windows is used. A threshold of 0 has only this one.
+    #[clap(long)]
+    sensitivity_fraction_window: Option<f64>,
+
+    /// Seed for the random number generator used by resampling, subsampling
+    /// and stochastic rounding.
+    #[clap(long, default_value_t = 0)]
+    seed: u64,
+
+    /// How each node's reads are rounded to whole reads per genome.
+    /// largest-remainder conserves the reads exactly; truncate keeps the
+    /// legacy per-genome truncation; none keeps fractional reads like the
+    /// original Bracken; stochastic assigns reads by seeded sampling.
+    #[clap(long, value_enum, default_value_t = Rounding::LargestRemainder)]
+    rounding: Rounding,
+
+    /// Read distribution formula: bracken-compat matches the original
+    /// Bracken, legacy reproduces earlier bracken-rust releases.
+    #[clap(long, value_enum, default_value_t = DistributionMode::BrackenCompat)]
+    distribution_mode: DistributionMode,
+
+    /// Order of the output rows: reads (descending), name or taxid.
+    #[clap(long, value_enum, default_value_t = SortBy::Reads)]
+    sort: SortBy,
+
+    /// How names with spaces, brackets or tabs are written in the output
+    /// tables: left as-is, quoted, or underscored.
+    #[clap(long, value_enum, default_value_t = NameStyle::Raw)]
+    name_style: NameStyle,
+}
+
+impl Args {
+    fn distribution_options(&self) -> DistributionOptions {
+        DistributionOptions {
+            mode: self.distribution_mode,
+            rounding: self.rounding,
+            em_iterations: self.em_iterations,
+            em_tolerance: self.em_tolerance,
+            seed: self.seed,
+        }
+    }
+}
+
+/// Keeps the report taxa of the --domain domains, placed through the
+/// taxonomy rather than the domain rows of the report (which may be renamed
+/// or missing). Returns the number of reads excluded.
+fn restrict_to_domains(
+    report: &mut Vec<(usize, kraken::Node)>,
+    domains: &[String],
+    taxonomy_dir: &Path,
+) -> Result<usize, Box<dyn std::error::Error>> {
+    let taxo = taxonomy::load_taxonomy(taxonomy_dir.to_path_buf())?;
+    let names = taxonomy::load_taxonomy_names(taxonomy_dir)?;
+    let domain_ranks = taxonomy::ncbi_ranks("D").unwrap_or_default();
+    let mut domain_taxids = HashMap::new();
+    for domain in domains {
+        let taxid = match domain.parse::<u32>() {
+            Ok(taxid) => taxo.get_node(&taxid).map(|node| node.taxid),
+            Err(_) => names.taxids_named(domain).into_iter().find(|taxid| {
+                taxo.get_node(taxid)
+                    .is_some_and(|node| domain_ranks.contains(&node.rank.as_str()))
+            }),
+        };
+        let taxid = taxid.ok_or_else(|| {
+            format!(
+                "--domain {:?} is no domain of the taxonomy in {:?}",
+                domain, taxonomy_dir
+            )
+        })?;
+        domain_taxids.insert(taxid, domain);
+    }
+
+    let matched = std::cell::RefCell::new(HashSet::new());
+    let excluded_reads = kraken::restrict_to_subtrees(report, |node| {
+        let Some(lineage) = taxo.lineage(node.taxid) else {
+            return false;
+        };
+        match lineage
+            .iter()
+            .find(|taxid| domain_taxids.contains_key(taxid))
+        {
+            Some(&domain) => {
+                matched.borrow_mut().insert(domain);
+                true
+            }
+            None => false,
+        }
+    });
+    let matched = matched.into_inner();
+    if let Some((_, domain)) = domain_taxids
+        .iter()
+        .find(|(taxid, _)| !matched.contains(*taxid))
+    {
+        return Err(format!("--domain {:?} has no taxa in the report", domain).into());
+    }
+    Ok(excluded_reads)
+}
+
+/// Kmer distribution file given by -k, or selected from --database-dir by
+/// the sample's read length.
+fn resolve_kmer_distr(
+    args: &Args,
+    read_len: Option<usize>,
+) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    if let Some(kmer_distr) = &args.kmer_distr {
+        return Ok(kmer_distr.clone());
+    }
+    let database_dir = args
+        .database_dir
+        .as_ref()
+        .ok_or("either --kmer-distr or --database-dir is required")?;
+    let read_len = read_len.ok_or("--database-dir requires --reads or --read-len")?;
+    let (kmer_distr, db_len) = database::select_kmer_distr(database_dir, read_len)?;
+    info!(
+        ">> Using kmer distribution {:?} for read length {}",
+        kmer_distr, read_len
+    );
+    if db_len.abs_diff(read_len) > args.read_len_tolerance {
+        warn!(
+            "\tWARNING: closest kmer distribution is for {} bp reads, the sample has {} bp reads",
+            db_len, read_len
+        );
+    }
+    Ok(kmer_distr)
+}
+
+/// Checks the kmer and read lengths recorded in the provenance line of a
+/// kmer distribution against its file name, the Kraken database it sits in
+/// and the sample's read length.
+fn check_provenance(
+    kmer_distr: &Path,
+    read_len: Option<usize>,
+    tolerance: usize,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let Some(provenance) = kmer_distrib::Provenance::read(kmer_distr)? else {
+        return Ok(());
+    };
+    if let Some(recorded) = provenance.length("read_len") {
+        if let Some(named) = database::kmer_distr_read_len(kmer_distr) {
+            if named != recorded {
+                return Err(format!(
+                    "{:?} was built for {} bp reads, not {} as its name says",
+                    kmer_distr, recorded, named
+                )
+                .into());
+            }
+        }
+        if let Some(read_len) = read_len.filter(|len| len.abs_diff(recorded) > tolerance) {
+            warn!(
+                "\tWARNING: the kmer distribution was built for {} bp reads, the sample has {} bp reads",
+                recorded, read_len
+            );
+        }
+    }
+    let database_dir = kmer_distr.parent().unwrap_or(Path::new("."));
+    if let (Some(recorded), Some(index)) = (
+        provenance.length("kmer_len"),
+        kraken2::index_options(database_dir)?,
+    ) {
+        if index.kmer_len != recorded {
+            return Err(format!(
+                "{:?} was built with {}-mers, the Kraken database in {:?} has {}-mers",
+                kmer_distr, recorded, database_dir, index.kmer_len
+            )
+            .into());
+        }
+    }
+    Ok(())
+}
+
+/// Total of the estimated reads at the level, used as the denominator of the
+/// abundance column.
+fn sum_all_reads(stats: &kraken::Stats, include_unclassified: bool) -> f64 {
+    let mut sum_all_reads: f64 = stats.lvl_taxids.values().map(|v| v.new_est_reads()).sum();
+    if include_unclassified && sum_all_reads > 0.0 {
+        sum_all_reads += stats.u_reads as f64;
+    }
+    sum_all_reads
+}
+
+/// 95% confidence intervals (low, high) of a taxon's estimated reads and fraction.
+#[derive(Clone, Copy, Debug, Default)]
+struct ConfidenceInterval {
+    reads: (f64, f64),
+    fraction: (f64, f64),
+}
+
+impl ConfidenceInterval {
+    fn write_columns<W: Write>(&self, out: &mut W, scale: f64, prec: usize) -> io::Result<()> {
+        write!(
+            out,
+            "\t{:.0}\t{:.0}\t{:.prec$}\t{:.prec$}",
+            self.reads.0,
+            self.reads.1,
+            self.fraction.0 * scale,
+            self.fraction.1 * scale
+        )
+    }
+}
+
+/// Output file of one threshold; with several thresholds the threshold is
+/// added before the extension (e.g. sample.t10.bracken).
+fn threshold_output(output: &Path, threshold: usize, n_thresholds: usize) -> PathBuf {
+    if n_thresholds <= 1 {
+        return output.to_path_buf();
+    }
+    suffixed_output(output, &format!("t{}", threshold))
+}
+
+/// Report nodes and inputs shared by the estimations at every threshold.
+struct Estimator<'a> {
+    args: &'a Args,
+    report: Vec<(usize, kraken::Node)>,
+    abundance_lvl: String,
+    branch: usize,
+    branch_lvl: usize,
+    include_taxids: Option<HashSet<u32>>,
+    exclude_taxids: Option<HashSet<u32>>,
+    excluded_reads: usize,
+    kmer_distr_taxids: Option<kraken::KmerDistrTaxids>,
+    kmer_distr_file: PathBuf,
+    start_time: DateTime<Local>,
+    kmer_distr: HashMap<u32, HashMap<u32, Vec<f32>>>,
+}
+
+impl Estimator<'_> {
+    /// Whether a taxid passes the --include-taxids/--exclude-taxids lists.
+    fn taxid_allowed(&self, taxid: u32) -> bool {
+        self.include_taxids
+            .as_ref()
+            .is_none_or(|include| include.contains(&taxid))
+            && self
+                .exclude_taxids
+                .as_ref()
+                .is_none_or(|exclude| !exclude.contains(&taxid))
+    }
+
+    /// Warns about the taxids of the --include-taxids/--exclude-taxids lists
+    /// that are no level taxon of the report (e.g. genus taxids at -l S, or
+    /// typos), which filter nothing.
+    fn warn_unmatched_taxids(&self) -> Result<(), KrakenError> {
+        if self.include_taxids.is_none() && self.exclude_taxids.is_none() {
+            return Ok(());
+        }
+        let mut stats = kraken::Stats::default();
+        kraken::parse_kraken_report(
+            self.report.clone(),
+            &self.args.level,
+            0,
+            self.branch,
+            self.branch_lvl,
+            &mut stats,
+        )?;
+        let lists = [
+            ("--include-taxids", &self.include_taxids),
+            ("--exclude-taxids", &self.exclude_taxids),
+        ];
+        for (option, taxids) in lists {
+            let Some(taxids) = taxids else { continue };
+            let unmatched = taxids
+                .iter()
+                .filter(|taxid| !stats.lvl_taxids.contains_key(taxid))
+                .count();
+            if unmatched > 0 {
+                warn!(
+                    "\tWARNING: {} of the {} taxids of {} are no {} taxon of the report",
+                    unmatched,
+                    taxids.len(),
+                    option,
+                    self.abundance_lvl
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Parses the report nodes into level taxa and applies the taxid filters.
+    fn build_stats(
+        &self,
+        report: Vec<(usize, kraken::Node)>,
+        threshold: usize,
+    ) -> Result<kraken::Stats, KrakenError> {
+        let mut stats = kraken::Stats::default();
+        kraken::parse_kraken_report(
+            report,
+            &self.args.level,
+            threshold,
+            self.branch,
+            self.branch_lvl,
+            &mut stats,
+        )?;
+        stats.excluded_reads = self.excluded_reads;
+
+        if self.include_taxids.is_some() || self.exclude_taxids.is_some() {
+            let no_taxids = HashSet::new();
+            kraken::filter_lvl_taxids(
+                &mut stats,
+                self.include_taxids.as_ref(),
+                self.exclude_taxids.as_ref().unwrap_or(&no_taxids),
+            );
+        }
+        Ok(stats)
+    }
+
+    /// Redistributes the reads of a report and maps them up to the level.
+    /// Returns the stats and the number of EM rounds.
+    fn estimate(
+        &self,
+        report: Vec<(usize, kraken::Node)>,
+        threshold: usize,
+        options: &DistributionOptions,
+    ) -> Result<(kraken::Stats, usize), KrakenError> {
+        let mut stats = self.build_stats(report, threshold)?;
+        let em_rounds =
+            kraken::em_redistribute(1, &mut stats, &self.args.level, &self.kmer_distr, options);
+        kraken::map_reads_to_level(&mut stats);
+        Ok((stats, em_rounds))
+    }
+
+    /// Repeats the estimation on multinomial resamplings of the report and
+    /// returns the 95% confidence interval of every level taxon.
+    fn bootstrap(
+        &self,
+        threshold: usize,
+    ) -> Result<HashMap<u32, ConfidenceInterval>, Box<dyn std::error::Error>> {
+        let args = self.args;
+        let mut rng = StdRng::seed_from_u64(args.seed);
+        let mut samples: HashMap<u32, (Vec<f64>, Vec<f64>)> = HashMap::new();
+
+        // 重采样按顺序进行, 估计并行进行, 结果与线程数无关
+        let resampled: Vec<_> = (0..args.bootstrap)
+            .map(|_| kraken::resample_report(&self.report, &mut rng))
+            .collect();
+        let replicates = resampled
+            .into_par_iter()
+            .enumerate()
+            .map(|(replicate, resampled)| {
+                // 每个重复使用不同的种子进行随机分配
+                let options = DistributionOptions {
+                    seed: args.seed.wrapping_add(replicate as u64 + 1),
+                    ..args.distribution_options()
+                };
+                self.estimate(resampled, threshold, &options)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        for (replicate, (stats, _)) in replicates.into_iter().enumerate() {
+            let sum_all_reads = sum_all_reads(&stats, args.include_unclassified);
+            let unclassified = args
+                .include_unclassified
+                .then_some((0, stats.u_reads as f64));
+            let estimates = stats
+                .lvl_taxids
+                .iter()
+                .map(|(taxid, value)| (*taxid, value.new_est_reads()));
+            for (taxid, new_all_reads) in estimates.chain(unclassified) {
+                let entry = samples.entry(taxid).or_default();
+                // 之前的重复中未出现的物种记为 0
+                entry.0.resize(replicate, 0.0);
+                entry.1.resize(replicate, 0.0);
+                entry.0.push(new_all_reads);
+                entry.1.push(new_all_reads / sum_all_reads.max(1.0));
+            }
+        }
+
+        let ci = samples
+            .into_iter()
+            .map(|(taxid, (mut reads, mut fractions))| {
+                reads.resize(args.bootstrap, 0.0);
+                fractions.resize(args.bootstrap, 0.0);
+                reads.sort_by(f64::total_cmp);
+                fractions.sort_by(f64::total_cmp);
+                let ci = ConfidenceInterval {
+                    reads: (
+                        sampling::percentile(&reads, 0.025),
+                        sampling::percentile(&reads, 0.975),
+                    ),
+                    fraction: (
+                        sampling::percentile(&fractions, 0.025),
+                        sampling::percentile(&fractions, 0.975),
+                    ),
+                };
+                (taxid, ci)
+            })
+            .collect();
+        Ok(ci)
+    }
+
+    /// Estimates the abundances at one threshold, writes the output table and
+    /// prints the summary. Returns the stats of the estimation.
+    fn run_threshold(
+        &self,
+        threshold: usize,
+        output: &Path,
+    ) -> Result<kraken::Stats, Box<dyn std::error::Error>> {
+        let args = self.args;
+        let abundance_lvl = &self.abundance_lvl;
+        let (stats, em_rounds) =
+            self.estimate(self.report.clone(), threshold, &args.distribution_options())?;
+
+        // Sum all of the reads for the desired level -- use for fraction of reads
+        let sum_all_reads = sum_all_reads(&stats, args.include_unclassified);
+        if sum_all_reads <= 0.0 {
+            return Err(ClassifiedError::empty_result(
+                "no reads found. Please check your Kraken report",
+            )
+            .into());
+        }
+
+        let bootstrap_ci = if args.bootstrap > 0 {
+            Some(self.bootstrap(threshold)?)
+        } else {
+            None
+        };
+
+        // Cells per estimated read, derived from the spike-in organism
+        let spike_in_factor = match args.spike_in {
+            Some(spike_in) => {
+                let spike_reads = stats
+                    .lvl_taxids
+                    .get(&spike_in.taxid)
+                    .map(|value| value.new_est_reads())
+                    .unwrap_or(0.0);
+                if spike_reads <= 0.0 {
+                    return Err(format!(
+                        "Spike-in taxid {} has no estimated reads at level {}",
+                        spike_in.taxid, args.level
+                    )
+                    .into());
+                }
+                Some(spike_in.cells / spike_reads)
+            }
+            None => None,
+        };
+
+        let mut file: Box<dyn Write> = if output == Path::new("-") {
+            Box::new(BufWriter::new(io::stdout().lock()))
+        } else {
+            Box::new(BufWriter::new(File::create(output)?))
+        };
+
+        write!(
+            file,
+            "name\ttaxonomy_id\ttaxonomy_lvl\tkraken_assigned_reads\tadded_reads\tnew_est_reads\t{}",
+            args.normalize.header()
+        )?;
+        if spike_in_factor.is_some() {
+            write!(file, "\tabsolute_abundance")?;
+        }
+        if bootstrap_ci.is_some() {
+            write!(
+                file,
+                "\tnew_est_reads_ci95_low\tnew_est_reads_ci95_high\t{h}_ci95_low\t{h}_ci95_high",
+                h = args.normalize.header()
+            )?;
+        }
+        writeln!(file)?;
+
+        let scale = args.normalize.scale();
+        let prec = args.normalize.precision();
+        let zero_taxa = match &self.kmer_distr_taxids {
+            Some(taxids) if args.include_zero => {
+                let mut zero_taxa = kraken::zero_count_taxa(&stats, &taxids.genomes, &args.level);
+                zero_taxa.retain(|taxid, _| self.taxid_allowed(*taxid));
+                zero_taxa
+            }
+            _ => HashMap::new(),
+        };
+        let rows = sorted_lvl_taxids(stats.lvl_taxids.iter().chain(zero_taxa.iter()), args.sort);
+        for (taxid, value) in rows {
+            let new_all_reads = value.new_est_reads();
+            let abundance = new_all_reads / sum_all_reads * scale;
+            write!(file,
+                "{name}\t{taxid}\t{level}\t{kraken_assigned_reads}\t{added_reads}\t{tnew_est_reads}\t{abundance:.prec$}",
+                name=args.name_style.apply(&value.0),
+                taxid=taxid,
+                level=args.level,
+                kraken_assigned_reads=value.1,
+                added_reads=value.3 as usize,
+                tnew_est_reads=new_all_reads as usize,
+            )?;
+            if let Some(factor) = spike_in_factor {
+                write!(file, "\t{:.2}", new_all_reads * factor)?;
+            }
+            if let Some(ci) = &bootstrap_ci {
+                let ci = ci.get(taxid).copied().unwrap_or_default();
+                ci.write_columns(&mut file, scale, prec)?;
+            }
+            writeln!(file)?;
+        }
+
+        if args.include_unclassified {
+            let abundance = stats.u_reads as f64 / sum_all_reads * scale;
+            write!(
+                file,
+                "unclassified\t0\tU\t{u_reads}\t0\t{u_reads}\t{abundance:.prec$}",
+                u_reads = stats.u_reads,
+            )?;
+            if let Some(factor) = spike_in_factor {
+                write!(file, "\t{:.2}", stats.u_reads as f64 * factor)?;
+            }
+            if let Some(ci) = &bootstrap_ci {
+                let ci = ci.get(&0).copied().unwrap_or_default();
+                ci.write_columns(&mut file, scale, prec)?;
+            }
+            writeln!(file)?;
+        }
+
+        file.flush()?;
+        drop(file);
+
+        info!("BRACKEN SUMMARY (Kraken report: {:?})", args.input);
+        info!("    >>> Threshold: {} ", threshold);
+        info!(
+            "    >>> Number of {:?} in sample: {:?} ",
+            abundance_lvl, stats.n_lvl_total
+        );
+        info!(
+            "\t  >> Number of {:} with reads > threshold: {:} ",
+            abundance_lvl, stats.n_lvl_est
+        );
+        info!(
+            "\t  >> Number of {} with reads < threshold: {} ",
+            abundance_lvl, stats.n_lvl_del
+        );
+        if args.include_taxids.is_some() || args.exclude_taxids.is_some() {
+            info!(
+                "\t  >> Number of {} removed by taxid filters: {} ",
+                abundance_lvl, stats.n_lvl_filtered
+            );
+        }
+        info!("    >>> Total reads in sample: {}", stats.total_reads);
+        info!(
+            "\t  >> Total reads kept at {} level (reads > threshold): {}",
+            abundance_lvl, stats.kept_reads
+        );
+        info!(
+            "\t  >> Total reads discarded ({} reads < threshold): {}",
+            abundance_lvl, stats.ignored_reads
+        );
+        if args.include_taxids.is_some() || args.exclude_taxids.is_some() {
+            info!(
+                "\t  >> Total reads removed by taxid filters: {}",
+                stats.filtered_reads
+            );
+        }
+        if args.em_iterations > 1 {
+            info!("\t  >> EM rounds: {}", em_rounds);
+        }
+        info!("\t  >> Reads distributed: {}", stats.distributed_reads);
+        info!(
+            "\t  >> Reads not distributed (eg. no {} above threshold): {}",
+            abundance_lvl, stats.nondistributed_reads
+        );
+        info!("\t  >> Unclassified reads: {:}", stats.u_reads);
+        if !args.exclude_subtree.is_empty() || !args.domain.is_empty() {
+            info!(
+                "\t  >> Reads excluded (subtrees {:?}, outside domains {:?}): {}",
+                args.exclude_subtree, args.domain, stats.excluded_reads
+            );
+        }
+        if let Some(depth) = args.subsample_depth {
+            info!("\t  >> Subsampled to depth: {}", depth);
+        }
+        if let (Some(spike_in), Some(factor)) = (args.spike_in, spike_in_factor) {
+            info!(
+                "\t  >> Spike-in {} ({} cells): {:.5} cells per read",
+                spike_in.taxid, spike_in.cells, factor
+            );
+        }
+        info!("BRACKEN OUTPUT PRODUCED: {:?}", output.display());
+
+        if let Some(path) = &args.out_discarded {
+            let path = threshold_output(path, threshold, args.threshold.len());
+            let mut file = BufWriter::new(File::create(&path)?);
+            writeln!(
+                file,
+                "name\ttaxonomy_id\ttaxonomy_lvl\tkraken_assigned_reads\tthreshold"
+            )?;
+            for (taxid, value) in sorted_lvl_taxids(&stats.discarded_taxids, args.sort) {
+                writeln!(
+                    file,
+                    "{}\t{}\t{}\t{}\t{}",
+                    args.name_style.apply(&value.0),
+                    taxid,
+                    args.level,
+                    value.1,
+                    threshold
+                )?;
+            }
+            info!("DISCARDED TAXA PRODUCED: {:?}", path.display());
+        }
+
+        if args.all_ranks {
+            self.write_rank_tables(&stats, sum_all_reads, output)?;
+        }
+
+        if let Some(path) = &args.sensitivity_report {
+            let path = threshold_output(path, threshold, args.threshold.len());
+            self.write_sensitivity_report(&stats, threshold, sum_all_reads, &path)?;
+            info!("SENSITIVITY REPORT PRODUCED: {:?}", path.display());
+        }
+
+        if let (Some(path), Some(taxids)) = (&args.diagnostics, &self.kmer_distr_taxids) {
+            let path = threshold_output(path, threshold, args.threshold.len());
+            let mut file = BufWriter::new(File::create(&path)?);
+            writeln!(file, "taxonomy_id\tname\tissue\treads")?;
+            for mismatch in kraken::kmer_distribution_mismatches(&stats, &args.level, taxids) {
+                writeln!(
+                    file,
+                    "{}\t{}\t{}\t{}",
+                    mismatch.taxid,
+                    args.name_style.apply(&mismatch.name),
+                    mismatch.issue,
+                    mismatch.reads
+                )?;
+            }
+            info!("DIAGNOSTICS PRODUCED: {:?}", path.display());
+        }
+
+        if let Some(path) = &args.out_nondistributed {
+            let path = threshold_output(path, threshold, args.threshold.len());
+            let mut nodes = stats.nondistributed_nodes.clone();
+            nodes.sort_by(|a, b| b.reads.cmp(&a.reads).then(a.taxid.cmp(&b.taxid)));
+            let mut file = BufWriter::new(File::create(&path)?);
+            writeln!(file, "name\ttaxonomy_id\ttaxonomy_lvl\treads\treason")?;
+            for node in nodes {
+                writeln!(
+                    file,
+                    "{}\t{}\t{}\t{}\t{}",
+                    args.name_style.apply(&node.name),
+                    node.taxid,
+                    node.level_id,
+                    node.reads,
+                    node.reason.as_str()
+                )?;
+            }
+            info!("NONDISTRIBUTED READS PRODUCED: {:?}", path.display());
+        }
+
+        if let Some(path) = &args.summary_json {
+            let path = threshold_output(path, threshold, args.threshold.len());
+            self.write_summary_json(&stats, threshold, em_rounds, output, &path)?;
+            info!("SUMMARY JSON PRODUCED: {:?}", path.display());
+        }
+        Ok(stats)
+    }
+
+    /// Serializes the summary counters, the parameters and the timing of the
+    /// run at one threshold.
+    fn write_summary_json(
+        &self,
+        stats: &kraken::Stats,
+        threshold: usize,
+        em_rounds: usize,
+        output: &Path,
+        path: &Path,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let args = self.args;
+        let end_time: DateTime<Local> = Local::now();
+        let summary = serde_json::json!({
+            "input": args.input,
+            "kmer_distr": self.kmer_distr_file,
+            "output": output,
+            "parameters": {
+                "level": args.level,
+                "threshold": threshold,
+                "include_unclassified": args.include_unclassified,
+                "distribution_mode": value_name(args.distribution_mode),
+                "rounding": value_name(args.rounding),
+                "em_iterations": args.em_iterations,
+                "em_tolerance": args.em_tolerance,
+                "bootstrap": args.bootstrap,
+                "seed": args.seed,
+                "subsample_depth": args.subsample_depth,
+                "exclude_subtree": args.exclude_subtree,
+                "domain": args.domain,
+                "taxonomy": args.taxonomy_dir,
+                "include_taxids": args.include_taxids,
+                "exclude_taxids": args.exclude_taxids,
+                "normalize": value_name(args.normalize),
+                "spike_in": args.spike_in.map(|spike_in| serde_json::json!({
+                    "taxid": spike_in.taxid,
+                    "cells": spike_in.cells,
+                })),
+                "read_len": args.read_len,
+                "reads": args.reads,
+                "names": args.names,
+                "merged": args.merged,
+                "include_zero": args.include_zero,
+                "all_ranks": args.all_ranks,
+                "sort": value_name(args.sort),
+                "name_style": value_name(args.name_style),
+                "sensitivity_window": args.sensitivity_window,
+                "sensitivity_fraction_window": args.sensitivity_fraction_window,
+            },
+            "stats": {
+                "n_lvl_total": stats.n_lvl_total,
+                "n_lvl_est": stats.n_lvl_est,
+                "n_lvl_del": stats.n_lvl_del,
+                "n_lvl_filtered": stats.n_lvl_filtered,
+                "total_reads": stats.total_reads,
+                "kept_reads": stats.kept_reads,
+                "ignored_reads": stats.ignored_reads,
+                "filtered_reads": stats.filtered_reads,
+                "excluded_reads": stats.excluded_reads,
+                "distributed_reads": stats.distributed_reads,
+                "nondistributed_reads": stats.nondistributed_reads,
+                "unclassified_reads": stats.u_reads,
+                "em_rounds": em_rounds,
+            },
+            "timing": {
+                "start_time": self.start_time.to_rfc3339(),
+                "end_time": end_time.to_rfc3339(),
+                "elapsed_seconds": (end_time - self.start_time).num_milliseconds() as f64 / 1000.0,
+            },
+        });
+        let mut file = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(&mut file, &summary)?;
+        writeln!(file)?;
+        Ok(())
+    }
+
+    /// Writes one cumulative table per major rank above the level, summing the
+    /// level estimates into their ancestors so all tables agree.
+    fn write_rank_tables(
+        &self,
+        stats: &kraken::Stats,
+        sum_all_reads: f64,
+        output: &Path,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let args = self.args;
+        let ranks = ["D", "P", "C", "O", "F", "G", "S"];
+        let scale = args.normalize.scale();
+        let prec = args.normalize.precision();
+        // Only the ranks above the level (its main rank too for S1, G2...)
+        let above_level = |rank: &&str| {
+            let rank_lvl = kraken::MAIN_LVLS
+                .iter()
+                .position(|lvl| rank.starts_with(*lvl))
+                .unwrap_or(usize::MAX);
+            rank_lvl < self.branch_lvl || (rank_lvl == self.branch_lvl && self.branch > 0)
+        };
+        for rank in ranks.into_iter().filter(above_level) {
+            let rank_taxids = kraken::roll_up_to_rank(stats, rank);
+            let path = suffixed_output(output, rank);
+            let mut file = BufWriter::new(File::create(&path)?);
+            writeln!(
+                file,
+                "name\ttaxonomy_id\ttaxonomy_lvl\tkraken_assigned_reads\tadded_reads\tnew_est_reads\t{}",
+                args.normalize.header()
+            )?;
+            for (taxid, value) in sorted_lvl_taxids(&rank_taxids, args.sort) {
+                let new_all_reads = value.new_est_reads();
+                writeln!(
+                    file,
+                    "{}\t{}\t{}\t{}\t{}\t{}\t{:.prec$}",
+                    args.name_style.apply(&value.0),
+                    taxid,
+                    rank,
+                    value.1,
+                    value.3 as usize,
+                    new_all_reads as usize,
+                    new_all_reads / sum_all_reads * scale
+                )?;
+            }
+            info!("BRACKEN {} OUTPUT PRODUCED: {:?}", rank, path.display());
+        }
+        Ok(())
+    }
+
+    /// Lists the level taxa whose kraken reads lie within the sensitivity
+    /// window around the threshold, with their status and margin.
+    fn write_sensitivity_report(
+        &self,
+        stats: &kraken::Stats,
+        threshold: usize,
+        sum_all_reads: f64,
+        path: &Path,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let args = self.args;
+        let scale = args.normalize.scale();
+        let prec = args.normalize.precision();
+        // The relative window collapses to the threshold itself at 0
+        let relative = threshold as f64 * args.sensitivity_window.max(0.0);
+        let absolute = args
+            .sensitivity_fraction_window
+            .map_or(0.0, |window| window.max(0.0) / scale * sum_all_reads);
+        let margin = relative.max(absolute);
+        if margin == 0.0 {
+            warn!(
+                "The sensitivity window around the threshold of {} is empty; \
+                 give --sensitivity-fraction-window to list the taxa near it",
+                threshold
+            );
+        }
+        let low = threshold as f64 - margin;
+        let high = threshold as f64 + margin;
+
+        let mut borderline: Vec<&kraken::Node> = stats
+            .nodes
+            .values()
+            .filter(|node| node.level_id == args.level)
+            .filter(|node| (low..=high).contains(&(node.all_reads as f64)))
+            .collect();
+        borderline.sort_by(|a, b| a.all_reads.cmp(&b.all_reads).then(a.taxid.cmp(&b.taxid)));
+
+        let mut file = BufWriter::new(File::create(path)?);
+        writeln!(
+            file,
+            "name\ttaxonomy_id\tkraken_assigned_reads\tthreshold\tmargin\tstatus\tnew_est_reads\t{}",
+            args.normalize.header()
+        )?;
+        for node in borderline {
+            let (status, new_est_reads) = match stats.lvl_taxids.get(&node.taxid) {
+                Some(value) => ("kept", value.new_est_reads()),
+                None if node.all_reads < threshold => ("below_threshold", 0.0),
+                None => ("filtered", 0.0),
+            };
+            writeln!(
+                file,
+                "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{:.prec$}",
+                args.name_style.apply(&node.name),
+                node.taxid,
+                node.all_reads,
+                threshold,
+                node.all_reads as i64 - threshold as i64,
+                status,
+                new_est_reads as usize,
+                new_est_reads / sum_all_reads * scale
+            )?;
+        }
+        Ok(())
+    }
+}
+
+pub fn run(args: Args) -> Result<(), Box<dyn std::error::Error>> {
+    let mut lvl_dict: HashMap<String, &str> = HashMap::new();
+
+    let now: DateTime<Local> = Local::now();
+    let time = now.format("%m-%d-%Y %H:%M:%S").to_string();
+    info!("PROGRAM START TIME: {}", time);
+
+    lvl_dict.insert("D".into(), "domains");
+    lvl_dict.insert("P".into(), "phylums");
+    lvl_dict.insert("O".into(), "orders");
+    lvl_dict.insert("C".into(), "classes");
+    lvl_dict.insert("F".into(), "families");
+    lvl_dict.insert("G".into(), "genuses");
+    lvl_dict.insert("S".into(), "species");
+
+    let abundance_lvl = lvl_dict
+        .get(&args.level)
+        .unwrap_or(&args.level.as_str())
+        .to_string();
+
+    let (branch, branch_lvl) = kraken::level_branch(&args.level)?;
+
+    let input_file = args.input.clone();
+    kraken::check_report_file(&input_file)?;
+
+    let mut report = kraken::read_kraken_report(&input_file)?;
+    let merged = match &args.merged {
+        Some(merged_file) => {
+            let merged = MergedTaxids::load_ncbi_dmp(merged_file)?;
+            let remapped = kraken::remap_merged_taxids(&mut report, &merged);
+            info!(
+                ">> Read {} merged taxids from {:?}; remapped {} report taxa",
+                merged.len(),
+                merged_file,
+                remapped
+            );
+            merged
+        }
+        None => MergedTaxids::default(),
+    };
+    if let Some(names_file) = &args.names {
+        let names = NCBINames::load_ncbi_dmp(names_file)?;
+        let renamed = kraken::apply_names(&mut report, &names);
+        info!(
+            ">> Read {} scientific names from {:?}; renamed {} taxa",
+            names.len(),
+            names_file,
+            renamed
+        );
+    }
+    let excluded_taxids: HashSet<u32> = args.exclude_subtree.iter().copied().collect();
+    let mut excluded_reads = kraken::exclude_subtrees(&mut report, &excluded_taxids);
+    if let Some(taxonomy_dir) = args
+        .taxonomy_dir
+        .as_ref()
+        .filter(|_| !args.domain.is_empty())
+    {
+        excluded_reads += restrict_to_domains(&mut report, &args.domain, taxonomy_dir)?;
+    }
+    if let Some(depth) = args.subsample_depth {
+        let mut rng = StdRng::seed_from_u64(args.seed);
+        report = kraken::subsample_report(&report, depth, &mut rng);
+    }
+
+    let read_len = match (&args.reads, args.read_len) {
+        (_, Some(read_len)) => Some(read_len),
+        (Some(reads), None) => Some(database::mean_read_length(reads, 10_000)?),
+        (None, None) => None,
+    };
+    let kmer_distr_file = resolve_kmer_distr(&args, read_len)?;
+    // A distribution selected from --database-dir was checked against the
+    // read length already
+    check_provenance(
+        &kmer_distr_file,
+        read_len.filter(|_| args.kmer_distr.is_some()),
+        args.read_len_tolerance,
+    )?;
+    let mut estimator = Estimator {
+        args: &args,
+        report,
+        abundance_lvl,
+        branch,
+        branch_lvl,
+        include_taxids: args
+            .include_taxids
+            .as_ref()
+            .map(kraken::read_taxid_list)
+            .transpose()?,
+        exclude_taxids: args
+            .exclude_taxids
+            .as_ref()
+            .map(kraken::read_taxid_list)
+            .transpose()?,
+        excluded_reads,
+        kmer_distr_taxids: if args.include_zero || args.diagnostics.is_some() {
+            Some(kraken::read_kmer_distribution_taxids(
+                &kmer_distr_file,
+                &merged,
+            )?)
+        } else {
+            None
+        },
+        kmer_distr_file,
+        start_time: now,
+        kmer_distr: HashMap::new(),
+    };
+
+    estimator.warn_unmatched_taxids()?;
+
+    // The lowest threshold keeps the most genomes, so its distribution covers all
+    // thresholds; bootstrap replicates can lift any taxon over the threshold
+    let min_threshold = match args.bootstrap {
+        0 => args.threshold.iter().copied().min().unwrap_or(0),
+        _ => 0,
+    };
+    let stats = estimator.build_stats(estimator.report.clone(), min_threshold)?;
+    estimator.kmer_distr =
+        kraken::read_kmer_distribution(&estimator.kmer_distr_file, &stats, &merged)?;
+
+    if args.output == Path::new("-") && (args.threshold.len() > 1 || args.all_ranks) {
+        return Err("writing to stdout (-o -) needs a single threshold and no --all-ranks".into());
+    }
+
+    let mut sweep = Vec::new();
+    for &threshold in args.threshold.iter() {
+        let output = threshold_output(&args.output, threshold, args.threshold.len());
+        let stats = estimator.run_threshold(threshold, &output)?;
+        sweep.push((threshold, stats, output));
+    }
+
+    if sweep.len() > 1 {
+        let summary_file = args.output.with_extension("thresholds.tsv");
+        let mut file = BufWriter::new(File::create(&summary_file)?);
+        writeln!(
+            file,
+            "threshold\tn_taxa_kept\tn_taxa_discarded\tkept_reads\tdiscarded_reads\toutput"
+        )?;
+        info!("THRESHOLD SWEEP SUMMARY");
+        for (threshold, stats, output) in sweep.iter() {
+            writeln!(
+                file,
+                "{}\t{}\t{}\t{}\t{}\t{}",
+                threshold,
+                stats.n_lvl_est,
+                stats.n_lvl_del,
+                stats.kept_reads,
+                stats.ignored_reads,
+                output.display()
+            )?;
+            info!(
+                "    >>> Threshold {}: {} {} kept ({} reads)",
+                threshold, stats.n_lvl_est, estimator.abundance_lvl, stats.kept_reads
+            );
+        }
+        info!(
+            "THRESHOLD SWEEP SUMMARY PRODUCED: {:?}",
+            summary_file.display()
+        );
+    }
+
+    let now: DateTime<Local> = Local::now();
+    let time = now.format("%m-%d-%Y %H:%M:%S").to_string();
+    info!("PROGRAM END TIME: {}", time);
+
+    Ok(())
+}
@@ -0,0 +1,101 @@
+//! Process exit codes by failure class, so schedulers and workflow engines
+//! can branch on the kind of failure.
+
+use crate::bracken_output::OutputError;
+use crate::config::ConfigError;
+use crate::database::DistribError;
+use crate::kraken::KrakenError;
+use crate::taxonomy::TaxonomyError;
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+/// Failure not covered by a more specific class.
+pub const FAILURE: i32 = 1;
+/// Invalid command line (reported by clap).
+pub const USAGE: i32 = 2;
+/// An input file is not in the expected format.
+pub const INPUT_FORMAT: i32 = 3;
+/// The taxonomy files are missing or unusable.
+pub const MISSING_TAXONOMY: i32 = 4;
+/// The inputs are valid but yield no result (e.g. no reads at the level).
+pub const EMPTY_RESULT: i32 = 5;
+/// Reading or writing a file failed.
+pub const IO_ERROR: i32 = 6;
+
+/// Error carrying the exit code of its failure class.
+#[derive(Debug)]
+pub struct ClassifiedError {
+    pub code: i32,
+    pub message: String,
+}
+
+impl ClassifiedError {
+    pub fn input_format(message: impl Into<String>) -> Self {
+        Self {
+            code: INPUT_FORMAT,
+            message: message.into(),
+        }
+    }
+
+    pub fn empty_result(message: impl Into<String>) -> Self {
+        Self {
+            code: EMPTY_RESULT,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for ClassifiedError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl Error for ClassifiedError {}
+
+/// Exit code of an error returned by a subcommand.
+pub fn of(error: &(dyn Error + 'static)) -> i32 {
+    if let Some(error) = error.downcast_ref::<ClassifiedError>() {
+        error.code
+    } else if let Some(error) = error.downcast_ref::<KrakenError>() {
+        match error {
+            KrakenError::Io { .. } => IO_ERROR,
+            KrakenError::InvalidLevel { .. } => USAGE,
+            _ => INPUT_FORMAT,
+        }
+    } else if let Some(error) = error.downcast_ref::<DistribError>() {
+        match error {
+            DistribError::Io { .. } => IO_ERROR,
+            DistribError::Taxonomy(error) => of(error),
+            DistribError::Kraken2 { .. } => FAILURE,
+            DistribError::OutputExists(_) => USAGE,
+            _ => INPUT_FORMAT,
+        }
+    } else if let Some(error) = error.downcast_ref::<TaxonomyError>() {
+        match error {
+            TaxonomyError::MissingNodes(_) => MISSING_TAXONOMY,
+            TaxonomyError::Io { .. } => IO_ERROR,
+            _ => INPUT_FORMAT,
+        }
+    } else if let Some(error) = error.downcast_ref::<OutputError>() {
+        match error {
+            OutputError::Io { .. } => IO_ERROR,
+            _ => INPUT_FORMAT,
+        }
+    } else if let Some(error) = error.downcast_ref::<ConfigError>() {
+        match error {
+            ConfigError::Io { .. } => IO_ERROR,
+            ConfigError::Toml { .. } => USAGE,
+        }
+    } else if let Some(error) = error.downcast_ref::<io::Error>() {
+        match error.kind() {
+            io::ErrorKind::InvalidData | io::ErrorKind::InvalidInput => INPUT_FORMAT,
+            _ => IO_ERROR,
+        }
+    } else if let Some(error) = error.downcast_ref::<clap::Error>() {
+        error.exit_code()
+    } else {
+        FAILURE
+    }
+}
@@ -1,163 +1,1442 @@
+use crate::database::{self, CompressedWriter, Compression, DistribError};
+use crate::progress;
 use crate::taxonomy::NCBITaxonomy;
 use dashmap::DashMap;
-use memmap2::MmapOptions;
+use log::{debug, info, warn};
+use memchr::{memchr, memmem};
 use rayon::prelude::*;
-use std::collections::{HashMap, VecDeque};
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use std::fs::File;
-use std::io::{BufRead, BufReader, BufWriter, Result, Write};
-use std::path::Path;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicUsize, Ordering};
-use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{self, Receiver, SyncSender};
 
 /// 读取 seqid2taxid.map 文件。为了裁剪 ncbi 的 taxonomy 树
-pub fn get_seqid2taxid<P: AsRef<Path>>(filename: P) -> Result<HashMap<String, u32>> {
-    let file = File::open(filename)?;
+pub fn get_seqid2taxid<P: AsRef<Path>>(filename: P) -> Result<HashMap<String, u32>, DistribError> {
+    get_seqid2taxid_with(filename, OnDuplicate::Merge, None).map(|(id_map, _)| id_map)
+}
+
+/// Like [`get_seqid2taxid`], with the seqids listed more than once handled
+/// as set by `on_duplicate`; merged seqids get the lowest common ancestor of
+/// their taxids in `taxo`, or their last taxid without a taxonomy. Also
+/// returns the taxids of the duplicates left out of the map, to which the
+/// kmers of the database may still be classified.
+pub fn get_seqid2taxid_with<P: AsRef<Path>>(
+    filename: P,
+    on_duplicate: OnDuplicate,
+    taxo: Option<&NCBITaxonomy>,
+) -> Result<(HashMap<String, u32>, Vec<u32>), DistribError> {
+    let path = filename.as_ref();
+    let file = File::open(path).map_err(DistribError::io(path))?;
     let reader = BufReader::new(file);
     let mut id_map = HashMap::new();
     let mut s_count = 0;
-    print!("\t>>STEP 1: READING SEQID2TAXID MAP\n");
-    print!("\t\t0 sequences read");
+    let mut duplicates = 0;
+    let mut left_out = Vec::new();
+    info!("\t>>STEP 1: READING SEQID2TAXID MAP");
     for line in reader.lines() {
-        let line = line?;
+        let line = line.map_err(DistribError::io(path))?;
         s_count += 1;
         if s_count % 1000 == 0 {
-            print!("\r\t\t{:?} sequences read", s_count);
+            debug!("\t\t{:?} sequences read", s_count);
         }
 
-        let parts: Vec<&str> = line.trim().split_whitespace().collect();
+        let parts: Vec<&str> = line.split_whitespace().collect();
         if parts.len() < 2 {
             continue;
         }
         let seq_id = parts[0].to_string();
-        if let Ok(taxid) = parts[1].parse::<u32>() {
-            id_map.insert(seq_id, taxid);
+        let Ok(taxid) = parts[1].parse::<u32>() else {
+            continue;
+        };
+        match id_map.entry(seq_id) {
+            Entry::Vacant(entry) => {
+                entry.insert(taxid);
+            }
+            Entry::Occupied(mut entry) => {
+                duplicates += 1;
+                match on_duplicate {
+                    OnDuplicate::Error => {
+                        return Err(DistribError::DuplicateSeqid(
+                            path.to_path_buf(),
+                            entry.key().clone(),
+                        ))
+                    }
+                    OnDuplicate::First => left_out.push(taxid),
+                    OnDuplicate::Merge => {
+                        let merged =
+                            taxo.map_or(taxid, |taxo| mates_lca(*entry.get(), taxid, taxo));
+                        left_out.extend([taxid, entry.insert(merged)]);
+                    }
+                }
+            }
         }
     }
-    print!("\r\t\t{:?} total sequences read\n", s_count);
+    info!("\t\t{:?} total sequences read", s_count);
+    if duplicates > 0 {
+        warn!(
+            "\t\t{} duplicate seqids in {:?}: {}",
+            duplicates,
+            path,
+            on_duplicate.describe()
+        );
+    }
+
+    Ok((id_map, left_out))
+}
+
+/// Reads of a length into which the sequences are classified.
+struct ReadShape {
+    read_len: usize,
+    /// Kmers of a read (of a mate for paired-end reads).
+    n_kmers: usize,
+    /// Distances in bp between the starts of the two mates, with their
+    /// weights; empty for single-end reads.
+    mates: Vec<(usize, usize)>,
+    /// Sequences with fewer kmers than a read, for the summary.
+    short: AtomicUsize,
+    /// Read classifications of the sequences of every genome taxid, for
+    /// the taxid summary.
+    taxids: DashMap<u32, Shares>,
+}
 
-    Ok(id_map)
+/// Reads of sequences of a taxid classified to the taxid itself, to its
+/// ancestors, to other taxa, and left unclassified.
+#[derive(Debug, Clone, Copy, Default)]
+struct Shares {
+    sequences: usize,
+    own: usize,
+    ancestor: usize,
+    other: usize,
+    unclassified: usize,
+}
+
+impl Shares {
+    /// Shares of the read classifications `counts` of a sequence of `taxid`.
+    fn new(taxid: u32, counts: &[(u32, usize)], taxo: &NCBITaxonomy) -> Self {
+        let ancestors = taxo
+            .get_node(&taxid)
+            .map_or(&[][..], |node| node.path_to_root.as_slice());
+        let mut shares = Shares {
+            sequences: 1,
+            ..Shares::default()
+        };
+        for &(mapped, count) in counts {
+            if mapped == 0 {
+                shares.unclassified += count;
+            } else if mapped == taxid {
+                shares.own += count;
+            } else if ancestors.contains(&mapped) {
+                shares.ancestor += count;
+            } else {
+                shares.other += count;
+            }
+        }
+        shares
+    }
+
+    fn add(&mut self, shares: &Shares) {
+        self.sequences += shares.sequences;
+        self.own += shares.own;
+        self.ancestor += shares.ancestor;
+        self.other += shares.other;
+        self.unclassified += shares.unclassified;
+    }
+
+    fn reads(&self) -> usize {
+        self.own + self.ancestor + self.other + self.unclassified
+    }
+
+    /// Reads, then the four shares as fractions, tab-separated.
+    fn format(&self) -> String {
+        let reads = self.reads();
+        let fraction = |count: usize| count as f64 / reads.max(1) as f64;
+        format!(
+            "{}\t{:.6}\t{:.6}\t{:.6}\t{:.6}",
+            reads,
+            fraction(self.own),
+            fraction(self.ancestor),
+            fraction(self.other),
+            fraction(self.unclassified)
+        )
+    }
 }
 
+/// A line of database.kraken converted for every read shape.
+struct ConvertedLine {
+    seqid: String,
+    outputs: Vec<Vec<u8>>,
+    /// Statistics lines, if asked for.
+    stats: String,
+    /// Why some kmer items of the line could not be read, if any; they are
+    /// read as unclassified.
+    malformed: Option<&'static str>,
+}
+
+/// A line of the kraken files, with its location for the errors.
+struct KrakenLine {
+    /// Index of the kraken file.
+    file: usize,
+    /// Line number in the kraken file, from 1.
+    number: usize,
+    text: String,
+}
+
+/// Converts a line of database.kraken into the read classifications of the
+/// sequence for every read shape of `shapes`, formatted as set by `output`,
+/// or tells why the line is malformed.
 fn convert_line(
     line: &str,
     seqid2taxid: &HashMap<String, u32>,
-    n_kmers: usize,
+    shapes: &[ReadShape],
     taxo: &NCBITaxonomy,
-) -> Option<(String, String)> {
-    // 处理行的逻辑，替换为适合你需求的处理过程
-    // let mut taxids_mapped: HashMap<u32, usize> = HashMap::new();
-    let taxid_map: DashMap<u32, usize> = DashMap::new();
-
-    let fields: Vec<_> = line.split("\t").collect();
-    if fields.len() < 5 {
-        return None;
-    }
-    let seqid = fields[1].trim();
+    reads: &ReadOptions,
+    output: &OutputOptions,
+) -> Result<ConvertedLine, &'static str> {
+    let mut fields = fields(line);
+    let (Some(_), Some(seqid), Some(_), Some(_), Some(kmers)) = (
+        fields.next(),
+        fields.next(),
+        fields.next(),
+        fields.next(),
+        fields.next(),
+    ) else {
+        return Err("fewer than 5 fields");
+    };
+    let seqid = seqid.trim();
     let taxid = seqid2taxid.get(seqid).unwrap_or(&0);
-    let mut output = String::new();
-    output += &format!("{}\t{}\t\t", seqid, taxid);
-
-    let curr_ks: Vec<u32> = fields[4]
-        .trim()
-        .split(" ")
-        .flat_map(|item| {
-            let pair: Vec<_> = item.trim().split(":").collect();
-            let taxid = pair[0].parse::<u32>().unwrap_or(0);
-            let count = pair[1].parse::<usize>().unwrap_or(0);
-            if count >= n_kmers {
-                *taxid_map.entry(taxid).or_insert(0) += count - n_kmers + 1;
-                std::iter::repeat(taxid)
-                    .take(n_kmers - 1)
-                    .collect::<Vec<_>>()
-                    .into_iter()
+    // Runs of kmers classified to the same taxid, shared by the read
+    // lengths, for each mate of a paired classification
+    let mut malformed = None;
+    let mut mates: Vec<Vec<(u32, usize)>> = Vec::with_capacity(1);
+    let mut kmers = kmers.as_bytes().trim_ascii();
+    loop {
+        let (mate, rest) = match memmem::find(kmers, MATE_SEPARATOR) {
+            Some(i) => (&kmers[..i], Some(&kmers[i + MATE_SEPARATOR.len()..])),
+            None => (kmers, None),
+        };
+        let runs = mate
+            .split(u8::is_ascii_whitespace)
+            .filter(|item| !item.is_empty())
+            .map(|item| {
+                parse_run(item).unwrap_or_else(|reason| {
+                    malformed.get_or_insert(reason);
+                    // Kmers of an unreadable taxid are unclassified
+                    let count = memchr(b':', item).and_then(|i| parse_decimal(&item[i + 1..]));
+                    (0, count.unwrap_or(0))
+                })
+            });
+        mates.push(runs.collect());
+        match rest {
+            Some(rest) => kmers = rest,
+            None => break,
+        }
+    }
+
+    let mut stats = String::new();
+    let outputs = shapes
+        .iter()
+        .map(|shape| {
+            let kmers = |runs: &[(u32, usize)]| runs.iter().map(|&(_, count)| count).sum::<usize>();
+            if mates.iter().any(|runs| kmers(runs) < shape.n_kmers) {
+                shape.short.fetch_add(1, Ordering::Relaxed);
+            }
+            let classify = |runs: &[(u32, usize)]| {
+                if kmers(runs) < shape.n_kmers {
+                    classify_short(runs, shape, taxo, reads)
+                } else if shape.mates.is_empty() {
+                    classify_reads(runs, shape.n_kmers, taxo, reads)
+                } else {
+                    classify_pairs(runs, shape, taxo, reads)
+                }
+            };
+            let mut counts = match mates.as_slice() {
+                [runs] => classify(runs),
+                // Reads do not span the mates
+                _ => {
+                    let mut counts: HashMap<u32, usize> = HashMap::new();
+                    for (taxid, count) in mates.iter().flat_map(|runs| classify(runs)) {
+                        *counts.entry(taxid).or_insert(0) += count;
+                    }
+                    counts.into_iter().collect()
+                }
+            };
+            if output.ordered || output.legacy {
+                // Ascending taxids, like the map of the C++ tool
+                counts.sort_unstable();
+            }
+            if output.stats.is_some() || output.taxid_summary.is_some() {
+                let shares = Shares::new(*taxid, &counts, taxo);
+                if output.stats.is_some() {
+                    stats += &format!(
+                        "{}\t{}\t{}\t{}\n",
+                        seqid,
+                        taxid,
+                        shape.read_len,
+                        shares.format()
+                    );
+                }
+                if output.taxid_summary.is_some() {
+                    shape.taxids.entry(*taxid).or_default().add(&shares);
+                }
+            }
+            if output.binary {
+                encode_counts(*taxid, &counts)
+            } else if output.legacy {
+                format!(
+                    "{}\t{}\t\t{}\n",
+                    seqid,
+                    taxid,
+                    format_legacy_counts(&counts)
+                )
+                .into_bytes()
             } else {
-                std::iter::repeat(taxid)
-                    .take(count)
-                    .collect::<Vec<_>>()
-                    .into_iter()
+                format!("{}\t{}\t\t{}\n", seqid, taxid, format_counts(&counts)).into_bytes()
             }
         })
         .collect();
+    Ok(ConvertedLine {
+        seqid: seqid.to_string(),
+        outputs,
+        stats,
+        malformed,
+    })
+}
 
-    let mut curr_kmers = VecDeque::new();
-    let mut taxid2kmers = HashMap::new();
-    let mut pre_mer: Option<u32> = None;
-    let mut pre_taxid: u32 = 0;
-    for kmer in curr_ks.iter() {
-        curr_kmers.push_back(kmer);
-        *taxid2kmers.entry(*kmer).or_insert_with(|| 0) += 1;
-        if curr_kmers.len() == n_kmers {
-            if pre_mer == Some(*kmer) {
-                *taxid_map.entry(pre_taxid).or_insert(0) += 1;
-            } else {
-                let mapped_taxid = get_classification(&taxid2kmers, taxo);
-                pre_taxid = mapped_taxid;
-                *taxid_map.entry(mapped_taxid).or_insert(0) += 1;
+/// Header of the per-sequence statistics.
+const STATS_HEADER: &str = "seqid\ttaxid\tread_len\treads\town_fraction\tancestor_fraction\tother_fraction\tunclassified_fraction";
+
+/// Header of the per-taxid summary.
+const TAXID_SUMMARY_HEADER: &str = "taxid\trank\tread_len\tsequences\treads\tunique_fraction\tancestor_fraction\tother_fraction\tunclassified_fraction";
+
+/// Writes the summary of the read classifications of the sequences of
+/// every genome taxid: the share of the reads classified uniquely to the
+/// taxid rather than escalated to its ancestors (or lost to other taxa)
+/// tells whether the database resolves it at all.
+fn write_taxid_summary(summary: File, shapes: &[ReadShape], taxo: &NCBITaxonomy) -> io::Result<()> {
+    let mut summary = BufWriter::new(summary);
+    writeln!(summary, "{}", TAXID_SUMMARY_HEADER)?;
+    let mut rows: Vec<_> = shapes
+        .iter()
+        .flat_map(|shape| {
+            shape
+                .taxids
+                .iter()
+                .map(move |entry| (*entry.key(), shape.read_len, *entry.value()))
+        })
+        .collect();
+    rows.sort_unstable_by_key(|&(taxid, read_len, _)| (taxid, read_len));
+    for (taxid, read_len, shares) in rows {
+        let rank = taxo.get_node(&taxid).map_or("-", |node| node.rank.as_str());
+        writeln!(
+            summary,
+            "{}\t{}\t{}\t{}\t{}",
+            taxid,
+            rank,
+            read_len,
+            shares.sequences,
+            shares.format()
+        )?;
+    }
+    summary.flush()
+}
+
+/// Taxid of the runs of ambiguous kmers (`A:<n>`, kmers with ambiguous
+/// nucleotides), which do not take part in the classification of a read.
+const AMBIGUOUS: u32 = u32::MAX;
+
+/// Separator of the kmers of the two mates of a paired classification.
+const MATE_SEPARATOR: &[u8] = b"|:|";
+
+/// Tab-separated fields of a line, split without allocating.
+fn fields(line: &str) -> impl Iterator<Item = &str> {
+    let mut rest = Some(line);
+    std::iter::from_fn(move || {
+        let line = rest?;
+        match memchr(b'\t', line.as_bytes()) {
+            // Tabs are ASCII, so both sides are whole characters
+            Some(i) => {
+                rest = Some(&line[i + 1..]);
+                Some(&line[..i])
             }
-            if let Some(cur) = curr_kmers.pop_front() {
-                pre_mer = Some(*cur);
-                let count = taxid2kmers.entry(*cur).or_default();
-                *count -= 1;
-                if *count == 0 {
-                    taxid2kmers.remove(&cur);
-                }
+            None => rest.take(),
+        }
+    })
+}
+
+/// Value of a decimal number of ASCII digits, without sign or spaces;
+/// `None` for other bytes and numbers beyond `u32` taxids and `usize`
+/// counts.
+#[cfg(feature = "simd")]
+fn parse_decimal<T: atoi_simd::Parse>(digits: &[u8]) -> Option<T> {
+    // atoi_simd counts leading zeros towards the digits a type holds
+    let start = digits
+        .iter()
+        .position(|&digit| digit != b'0')
+        .unwrap_or(digits.len().saturating_sub(1));
+    atoi_simd::parse_pos(&digits[start..]).ok()
+}
+
+/// Value of a decimal number of ASCII digits, without sign or spaces;
+/// `None` for other bytes and numbers beyond `u32` taxids and `usize`
+/// counts.
+#[cfg(not(feature = "simd"))]
+fn parse_decimal<T: TryFrom<u64>>(digits: &[u8]) -> Option<T> {
+    if digits.is_empty() {
+        return None;
+    }
+    let value = digits.iter().try_fold(0u64, |value, &digit| {
+        let digit = digit.wrapping_sub(b'0');
+        if digit >= 10 {
+            return None;
+        }
+        value.checked_mul(10)?.checked_add(u64::from(digit))
+    })?;
+    T::try_from(value).ok()
+}
+
+/// Run of kmers of a `taxid:count` item of the kmer column, or why the
+/// item is malformed.
+fn parse_run(item: &[u8]) -> Result<(u32, usize), &'static str> {
+    let colon = memchr(b':', item).ok_or("kmer item without a colon")?;
+    let (taxid, count) = (&item[..colon], &item[colon + 1..]);
+    let taxid = match taxid {
+        b"A" => AMBIGUOUS,
+        taxid => parse_decimal(taxid).ok_or("invalid taxid in a kmer item")?,
+    };
+    let count = parse_decimal(count).ok_or("invalid count in a kmer item")?;
+    Ok((taxid, count))
+}
+
+/// Classifications of the reads of `n_kmers` kmers of a sequence whose kmers
+/// are classified as `runs`, counted by taxid; reads skipped as set by
+/// `reads` are not counted.
+fn classify_reads(
+    runs: &[(u32, usize)],
+    n_kmers: usize,
+    taxo: &NCBITaxonomy,
+    reads: &ReadOptions,
+) -> Vec<(u32, usize)> {
+    let taxid_map: DashMap<u32, usize> = DashMap::new();
+
+    // Kmers are streamed rather than collected, so a chromosome-scale
+    // sequence takes no more memory than a read
+    let curr_ks = runs.iter().flat_map(|&(taxid, count)| {
+        if count >= n_kmers {
+            let mut window = WindowScores::new(taxo);
+            window.add(taxid, n_kmers);
+            if let Some(class) = window.classify(reads) {
+                *taxid_map.entry(class).or_insert(0) += count - n_kmers + 1;
             }
+            std::iter::repeat_n(taxid, n_kmers - 1)
+        } else {
+            std::iter::repeat_n(taxid, count)
         }
+    });
+
+    classify_windows(curr_ks, n_kmers, taxo, reads, |class| {
+        if let Some(mapped_taxid) = class {
+            *taxid_map.entry(mapped_taxid).or_insert(0) += 1;
+        }
+    });
+
+    taxid_map.into_iter().collect()
+}
+
+/// Passes the classification of every read of `n_kmers` kmers of a sequence
+/// to `class`, in the order of their start positions; `None` for the reads
+/// skipped as set by `reads`.
+fn classify_windows(
+    kmers: impl Iterator<Item = u32>,
+    n_kmers: usize,
+    taxo: &NCBITaxonomy,
+    reads: &ReadOptions,
+    mut class: impl FnMut(Option<u32>),
+) {
+    let mut window = VecDeque::with_capacity(n_kmers + 1);
+    let mut scores = WindowScores::new(taxo);
+    let mut last = None;
+    for kmer in kmers {
+        window.push_back(kmer);
+        // The kmer leaving the read; the read is unchanged if it is the
+        // taxid of the kmer entering it
+        let left = if window.len() > n_kmers {
+            window.pop_front()
+        } else {
+            None
+        };
+        if let Some(previous) = last.filter(|_| left == Some(kmer)) {
+            class(previous);
+            continue;
+        }
+        scores.add(kmer, 1);
+        if let Some(left) = left {
+            scores.remove(left);
+        }
+        if window.len() < n_kmers {
+            continue;
+        }
+        let current = scores.classify(reads);
+        last = Some(current);
+        class(current);
     }
-    // let k_mers = Kmers::new(curr_ks, n_kmers);
-    // k_mers.par_bridge().for_each(|taxid2kmers| {
-    //     let mapped_taxid = get_classification(&taxid2kmers, taxo);
-    //     *taxid_map.entry(mapped_taxid).or_insert(0) += 1;
-    // });
+}
 
-    output += &taxid_map
+/// Lowest common ancestor of the classifications of two mates (or of two
+/// taxids of a seqid); a mate left unclassified does not change the
+/// classification of the other.
+fn mates_lca(a: u32, b: u32, taxo: &NCBITaxonomy) -> u32 {
+    if a == b || b == 0 {
+        return a;
+    }
+    if a == 0 {
+        return b;
+    }
+    match (taxo.lineage(a), taxo.lineage(b)) {
+        (Some(a), Some(b)) => a
+            .iter()
+            .zip(b.iter())
+            .take_while(|(a, b)| a == b)
+            .last()
+            .map_or(0, |(taxid, _)| *taxid),
+        _ => 0,
+    }
+}
+
+/// Classifications of the fragments of a sequence whose kmers are classified
+/// as `runs`, read as pairs of mates of `shape`, counted by taxid with the
+/// weights of their insert sizes. A fragment is classified to the lowest
+/// common ancestor of its mates.
+fn classify_pairs(
+    runs: &[(u32, usize)],
+    shape: &ReadShape,
+    taxo: &NCBITaxonomy,
+    reads: &ReadOptions,
+) -> Vec<(u32, usize)> {
+    let kmers = runs
         .iter()
-        .map(|item| format!("{}:{}", item.key(), item.value()))
+        .flat_map(|&(taxid, count)| std::iter::repeat_n(taxid, count));
+    // Only the reads within the longest distance of the current one are
+    // kept, the first mates of the fragments it ends
+    let longest = shape.mates.iter().map(|&(distance, _)| distance).max();
+    let mut windows: VecDeque<Option<u32>> = VecDeque::new();
+    let mut lcas: HashMap<(u32, u32), u32> = HashMap::new();
+    let mut counts: HashMap<u32, usize> = HashMap::new();
+    classify_windows(kmers, shape.n_kmers, taxo, reads, |second| {
+        windows.push_back(second);
+        if windows.len() > longest.unwrap_or(0) + 1 {
+            windows.pop_front();
+        }
+        for &(distance, weight) in &shape.mates {
+            let Some(&first) = windows.len().checked_sub(distance + 1).map(|i| &windows[i]) else {
+                continue;
+            };
+            // A skipped mate leaves the pair to the other one
+            let taxid = match (first, second) {
+                (Some(first), Some(second)) => *lcas
+                    .entry((first, second))
+                    .or_insert_with(|| mates_lca(first, second, taxo)),
+                (Some(taxid), None) | (None, Some(taxid)) => taxid,
+                (None, None) => continue,
+            };
+            *counts.entry(taxid).or_insert(0) += weight;
+        }
+    });
+    counts.into_iter().collect()
+}
+
+/// Classification of a sequence whose kmers, classified as `runs`, are
+/// fewer than those of a read of `shape`, as set by `reads.short_seq`: a
+/// single read (a fragment for paired-end reads) or none.
+fn classify_short(
+    runs: &[(u32, usize)],
+    shape: &ReadShape,
+    taxo: &NCBITaxonomy,
+    reads: &ReadOptions,
+) -> Vec<(u32, usize)> {
+    if reads.short_seq == ShortSequences::Skip {
+        return Vec::new();
+    }
+    let mut window = WindowScores::new(taxo);
+    for &(taxid, count) in runs.iter().filter(|&&(_, count)| count > 0) {
+        window.add(taxid, count);
+    }
+    if reads.short_seq == ShortSequences::Pad {
+        let kmers: usize = runs.iter().map(|&(_, count)| count).sum();
+        window.add(0, shape.n_kmers - kmers);
+    }
+    let weight = shape.mates.iter().map(|&(_, weight)| weight).sum::<usize>();
+    window
+        .classify(reads)
+        .map_or_else(Vec::new, |taxid| vec![(taxid, weight.max(1))])
+}
+
+/// Read counts of a sequence as "taxid:count" items.
+fn format_counts(counts: &[(u32, usize)]) -> String {
+    counts
+        .iter()
+        .map(|(taxid, count)| format!("{}:{}", taxid, count))
         .collect::<Vec<_>>()
-        .join(" ");
+        .join(" ")
+}
 
-    output += "\n";
-    Some((seqid.to_string(), output))
+/// Read counts of a sequence as the C++ tool writes them: every
+/// "taxid:count" item followed by a space.
+fn format_legacy_counts(counts: &[(u32, usize)]) -> String {
+    counts
+        .iter()
+        .map(|(taxid, count)| format!("{}:{} ", taxid, count))
+        .collect()
 }
 
-fn get_classification(taxid2kmers: &HashMap<u32, usize>, taxo: &NCBITaxonomy) -> u32 {
-    if taxid2kmers.len() == 1 {
-        if let Some((&taxid, _)) = taxid2kmers.iter().next() {
-            return taxid;
+/// First bytes of a binary read distribution file. The sequences follow as
+/// records of varints: the number of mapped taxids plus one, the taxid of
+/// the sequence and its `taxid, count` pairs. A 0 ends the records, followed
+/// by the number of seqids and the seqids of the records, as a varint length
+/// and UTF-8 bytes.
+pub const COUNTS_MAGIC: &[u8; 8] = b"BRKCNTS1";
+
+/// Appends `value` to `out` as a LEB128 varint.
+fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+/// Reads a LEB128 varint, or `None` at the end of `reader`.
+fn read_varint<R: BufRead>(reader: &mut R) -> io::Result<Option<u64>> {
+    let mut value = 0u64;
+    let mut shift = 0;
+    loop {
+        let mut byte = [0u8];
+        if reader.read(&mut byte)? == 0 {
+            if shift == 0 {
+                return Ok(None);
+            }
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        if shift > 63 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "varint overflow",
+            ));
+        }
+        value |= u64::from(byte[0] & 0x7f) << shift;
+        if byte[0] & 0x80 == 0 {
+            return Ok(Some(value));
         }
+        shift += 7;
     }
+}
 
-    let mut max_score = 0;
-    let mut max_taxid = 0;
+/// Record of a sequence in a binary read distribution file.
+fn encode_counts(taxid: u32, counts: &[(u32, usize)]) -> Vec<u8> {
+    let mut record = Vec::with_capacity(4 + 6 * counts.len());
+    write_varint(&mut record, counts.len() as u64 + 1);
+    write_varint(&mut record, taxid.into());
+    for &(taxid, count) in counts {
+        write_varint(&mut record, taxid.into());
+        write_varint(&mut record, count as u64);
+    }
+    record
+}
 
-    for (&taxid, &count) in taxid2kmers.iter() {
+/// Table of the seqids ending a binary read distribution file, after the
+/// zero marking the end of the records.
+fn encode_seqid_table(seqids: &[String]) -> Vec<u8> {
+    let mut table = vec![0];
+    write_varint(&mut table, seqids.len() as u64);
+    for seqid in seqids {
+        write_varint(&mut table, seqid.len() as u64);
+        table.extend_from_slice(seqid.as_bytes());
+    }
+    table
+}
+
+/// Calls `f` with the taxid and the read counts of every sequence of a
+/// binary read distribution file, read from `reader` past
+/// [`COUNTS_MAGIC`]. Returns the seqids of the sequences.
+pub fn read_binary_counts<R, F>(
+    mut reader: R,
+    path: &Path,
+    mut f: F,
+) -> Result<Vec<String>, DistribError>
+where
+    R: BufRead,
+    F: FnMut(u32, &[(u32, u64)]),
+{
+    let invalid = |e: io::Error| match e.kind() {
+        io::ErrorKind::UnexpectedEof => {
+            DistribError::BinaryCounts(path.to_path_buf(), "truncated file")
+        }
+        io::ErrorKind::InvalidData => {
+            DistribError::BinaryCounts(path.to_path_buf(), "invalid varint")
+        }
+        _ => DistribError::io(path)(e),
+    };
+    let next = |reader: &mut R| {
+        read_varint(reader)
+            .map_err(invalid)?
+            .ok_or(DistribError::BinaryCounts(
+                path.to_path_buf(),
+                "truncated file",
+            ))
+    };
+    let next_taxid = |reader: &mut R| {
+        u32::try_from(next(reader)?)
+            .map_err(|_| DistribError::BinaryCounts(path.to_path_buf(), "taxid out of range"))
+    };
+    let mut counts = Vec::new();
+    loop {
+        let n_counts = next(&mut reader)?;
+        if n_counts == 0 {
+            break;
+        }
+        let taxid = next_taxid(&mut reader)?;
+        counts.clear();
+        for _ in 1..n_counts {
+            counts.push((next_taxid(&mut reader)?, next(&mut reader)?));
+        }
+        f(taxid, &counts);
+    }
+    let n_seqids = next(&mut reader)?;
+    let mut seqids = Vec::new();
+    for _ in 0..n_seqids {
+        // The length is not trusted with the allocation, a corrupt one
+        // reads to the end of the file
+        let len = next(&mut reader)?;
+        let mut seqid = Vec::new();
+        (&mut reader)
+            .take(len)
+            .read_to_end(&mut seqid)
+            .map_err(invalid)?;
+        if (seqid.len() as u64) < len {
+            return Err(DistribError::BinaryCounts(
+                path.to_path_buf(),
+                "truncated file",
+            ));
+        }
+        seqids.push(String::from_utf8_lossy(&seqid).into_owned());
+    }
+    Ok(seqids)
+}
+
+/// Classifications a [`WindowScores`] keeps before starting over.
+const CLASSIFICATION_CACHE_SIZE: usize = 1 << 12;
+
+/// Taxa a read needs for its classification to be worth caching; scoring
+/// fewer is cheaper than a lookup.
+const CACHED_MIN_TAXA: usize = 8;
+
+/// Contribution of `count` kmers of `taxid` to the fingerprint of a read.
+fn fingerprint(taxid: u32, count: usize) -> u64 {
+    // splitmix64 finalizer
+    let mut x = ((taxid as u64) << 32 ^ count as u64).wrapping_add(0x9e3779b97f4a7c15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+    x ^ (x >> 31)
+}
+
+/// Kmers of a read counted by taxid, with the score of every classified
+/// taxon among them (its kmers and those of its ancestors) kept up to date
+/// as kmers enter and leave the read, in time proportional to the depth of
+/// the taxonomy rather than to the length of the read. Classifications are
+/// cached by a fingerprint of the counts, as reads of repetitive sequences
+/// often have the same ones.
+struct WindowScores<'a> {
+    taxo: &'a NCBITaxonomy,
+    taxid2kmers: HashMap<u32, usize>,
+    /// Sum of the [`fingerprint`]s of the counts of `taxid2kmers`.
+    fingerprint: u64,
+    cache: HashMap<u64, Option<u32>>,
+    /// Kmers of the read, ambiguous ones excluded.
+    kmers: usize,
+    scores: HashMap<u32, usize>,
+    /// Taxa of the read below every ancestor of one of them, once per
+    /// occurrence of the ancestor in their path to the root.
+    descendants: HashMap<u32, Vec<u32>>,
+}
+
+impl<'a> WindowScores<'a> {
+    fn new(taxo: &'a NCBITaxonomy) -> Self {
+        WindowScores {
+            taxo,
+            taxid2kmers: HashMap::new(),
+            fingerprint: 0,
+            cache: HashMap::new(),
+            kmers: 0,
+            scores: HashMap::new(),
+            descendants: HashMap::new(),
+        }
+    }
+
+    fn path_to_root(&self, taxid: u32) -> &'a [u32] {
+        self.taxo
+            .get_node(&taxid)
+            .map_or(&[][..], |node| node.path_to_root.as_slice())
+    }
+
+    /// Adds `n` kmers of `taxid` to the read.
+    fn add(&mut self, taxid: u32, n: usize) {
+        let count = self.taxid2kmers.entry(taxid).or_insert(0);
+        let new = *count == 0;
+        if !new {
+            self.fingerprint = self.fingerprint.wrapping_sub(fingerprint(taxid, *count));
+        }
+        *count += n;
+        self.fingerprint = self.fingerprint.wrapping_add(fingerprint(taxid, *count));
+        if taxid == AMBIGUOUS {
+            return;
+        }
+        self.kmers += n;
+        for descendant in self.descendants.get(&taxid).into_iter().flatten() {
+            *self.scores.entry(*descendant).or_insert(0) += n;
+        }
         if taxid == 0 {
-            continue;
+            return;
+        }
+        if !new {
+            *self.scores.entry(taxid).or_insert(0) += n;
+            return;
+        }
+        let path = self.path_to_root(taxid);
+        let score = path
+            .iter()
+            .filter_map(|ancestor| self.taxid2kmers.get(ancestor))
+            .sum::<usize>()
+            + n;
+        self.scores.insert(taxid, score);
+        for &ancestor in path {
+            self.descendants.entry(ancestor).or_default().push(taxid);
         }
+    }
 
-        let score = if let Some(node) = taxo.get_node(&taxid) {
-            node.path_to_root
-                .iter()
-                .filter_map(|&ancestor| taxid2kmers.get(&ancestor))
-                .sum::<usize>()
-                + count
-        } else {
-            count
+    /// Removes a kmer of `taxid` from the read.
+    fn remove(&mut self, taxid: u32) {
+        let Some(count) = self.taxid2kmers.get_mut(&taxid) else {
+            return;
         };
+        self.fingerprint = self.fingerprint.wrapping_sub(fingerprint(taxid, *count));
+        *count -= 1;
+        let gone = *count == 0;
+        if gone {
+            self.taxid2kmers.remove(&taxid);
+        } else {
+            self.fingerprint = self.fingerprint.wrapping_add(fingerprint(taxid, *count));
+        }
+        if taxid == AMBIGUOUS {
+            return;
+        }
+        self.kmers -= 1;
+        if taxid != 0 {
+            if gone {
+                self.scores.remove(&taxid);
+                for &ancestor in self.path_to_root(taxid) {
+                    if let Some(descendants) = self.descendants.get_mut(&ancestor) {
+                        if let Some(i) = descendants.iter().position(|&d| d == taxid) {
+                            descendants.swap_remove(i);
+                        }
+                        if descendants.is_empty() {
+                            self.descendants.remove(&ancestor);
+                        }
+                    }
+                }
+            } else if let Some(score) = self.scores.get_mut(&taxid) {
+                *score -= 1;
+            }
+        }
+        for descendant in self.descendants.get(&taxid).into_iter().flatten() {
+            if let Some(score) = self.scores.get_mut(descendant) {
+                *score -= 1;
+            }
+        }
+    }
+
+    /// Classification of the read: the taxon with the highest score, or the
+    /// lowest common ancestor of the tied ones, raised to the lowest ancestor
+    /// whose clade holds `reads.confidence` of the classified and
+    /// unclassified kmers. Ambiguous kmers take no part; unclassified ones
+    /// are handled as set by `reads.zero_kmers`, and `None` is a skipped
+    /// read.
+    fn classify(&mut self, reads: &ReadOptions) -> Option<u32> {
+        if self.scores.len() < CACHED_MIN_TAXA {
+            return self.score(reads);
+        }
+        if let Some(&class) = self.cache.get(&self.fingerprint) {
+            return class;
+        }
+        let class = self.score(reads);
+        if self.cache.len() >= CLASSIFICATION_CACHE_SIZE {
+            self.cache.clear();
+        }
+        self.cache.insert(self.fingerprint, class);
+        class
+    }
+
+    /// One of two taxa with the highest score, or their ancestor, as set by
+    /// `tie_break`.
+    fn break_tie(&self, a: u32, b: u32, tie_break: TieBreak) -> u32 {
+        let depth = |taxid| self.taxo.get_node(&taxid).map_or(0, |node| node.depth);
+        match tie_break {
+            TieBreak::Lca => self.taxo.lca(a, b),
+            TieBreak::Deepest => match depth(a).cmp(&depth(b)) {
+                std::cmp::Ordering::Greater => a,
+                std::cmp::Ordering::Less => b,
+                std::cmp::Ordering::Equal => a.min(b),
+            },
+            TieBreak::LowestTaxid => a.min(b),
+        }
+    }
+
+    /// [`Self::classify`], uncached.
+    fn score(&self, reads: &ReadOptions) -> Option<u32> {
+        // Reads of ambiguous kmers only are unclassified
+        if self.kmers == 0 {
+            return Some(0);
+        }
+        let zero = self.taxid2kmers.get(&0).copied().unwrap_or(0);
+        if 2 * zero > self.kmers {
+            match reads.zero_kmers {
+                ZeroKmers::Ignore => return None,
+                ZeroKmers::Count => return Some(0),
+                ZeroKmers::Penalize => {}
+            }
+        }
+
+        let mut max_score = 0;
+        let mut max_taxid = 0;
+        for (&taxid, &score) in self.scores.iter() {
+            if score > max_score {
+                max_score = score;
+                max_taxid = taxid;
+            } else if score == max_score && max_taxid != 0 {
+                max_taxid = self.break_tie(max_taxid, taxid, reads.tie_break);
+            }
+        }
+
+        if reads.confidence > 0.0 {
+            max_taxid = confident_ancestor(
+                max_taxid,
+                &self.taxid2kmers,
+                self.kmers,
+                self.taxo,
+                reads.confidence,
+            );
+        }
+        Some(max_taxid)
+    }
+}
+
+/// Lowest ancestor of `taxid` (or `taxid` itself) whose clade holds at least
+/// `confidence` of the `total` kmers of the read, 0 if none does.
+fn confident_ancestor(
+    taxid: u32,
+    taxid2kmers: &HashMap<u32, usize>,
+    total: usize,
+    taxo: &NCBITaxonomy,
+    confidence: f64,
+) -> u32 {
+    let Some(lineage) = taxo.lineage(taxid) else {
+        return 0;
+    };
+    let required = confidence * total as f64;
+    for &ancestor in lineage.iter().rev() {
+        let clade: usize = taxid2kmers
+            .iter()
+            .filter(|&(&kmer_taxid, _)| {
+                kmer_taxid == ancestor
+                    || taxo
+                        .get_node(&kmer_taxid)
+                        .is_some_and(|node| node.path_to_root.contains(&ancestor))
+            })
+            .map(|(_, &count)| count)
+            .sum();
+        if clade as f64 >= required {
+            return ancestor;
+        }
+    }
+    0
+}
+
+/// Bytes of database.kraken lines read into a batch before it is handed to
+/// the workers; a batch holds at least one line.
+const READ_BATCH_BYTES: usize = 16 << 20;
+
+/// Smallest batch a memory bound shrinks the batches to.
+const MIN_BATCH_BYTES: usize = 64 << 10;
+
+/// Capacity of the output buffer of the writer thread.
+const WRITE_BUFFER_BYTES: usize = 8 << 20;
+
+/// Error reading the kraken files.
+enum ReadError {
+    Io(io::Error),
+    Duplicate(String),
+}
+
+/// Reads the lines of `readers` in turn, as a single input, in batches of
+/// about `batch_bytes` and sends them to `sender` until the readers are
+/// exhausted or the receiver is gone. Every batch takes one of the `credits`
+/// the writer hands back once the batch is written, which bounds the batches
+/// in flight. Only the lines of indices within `lines` are sent. Lines of a
+/// seqid already read are handled as set by `on_duplicate`, and counted in
+/// the result. Invalid UTF-8 is replaced rather than rejected. Errors come
+/// with the index of the reader they occurred on.
+fn read_batches<R: BufRead>(
+    readers: Vec<R>,
+    batch_bytes: usize,
+    credits: Receiver<()>,
+    sender: SyncSender<Vec<KrakenLine>>,
+    lines: Range<usize>,
+    chunk: Option<Chunk>,
+    on_duplicate: OnDuplicate,
+) -> Result<usize, (usize, ReadError)> {
+    let send = |batch| credits.recv().is_ok() && sender.send(batch).is_ok();
+    let mut batch = Vec::new();
+    let mut read_bytes = 0;
+    let mut buf = Vec::new();
+    let mut seqids = HashSet::new();
+    let mut duplicates = 0;
+    let mut index = 0;
+    'read: for (i, mut reader) in readers.into_iter().enumerate() {
+        let mut number = 0;
+        loop {
+            buf.clear();
+            let read = reader
+                .read_until(b'\n', &mut buf)
+                .map_err(|e| (i, ReadError::Io(e)))?;
+            if read == 0 {
+                break;
+            }
+            index += 1;
+            number += 1;
+            if index <= lines.start {
+                continue;
+            }
+            if index > lines.end {
+                break 'read;
+            }
+            if chunk.is_some_and(|chunk| !chunk.contains(index - lines.start - 1)) {
+                continue;
+            }
+            let line = String::from_utf8_lossy(&buf);
+            let line = line.trim_end_matches(['\n', '\r']);
+            if let Some(seqid) = fields(line).nth(1).map(str::trim) {
+                if !seqids.insert(seqid.to_string()) {
+                    duplicates += 1;
+                    match on_duplicate {
+                        OnDuplicate::Error => {
+                            return Err((i, ReadError::Duplicate(seqid.to_string())))
+                        }
+                        OnDuplicate::First => continue,
+                        OnDuplicate::Merge => {}
+                    }
+                }
+            }
+            read_bytes += buf.len();
+            batch.push(KrakenLine {
+                file: i,
+                number,
+                text: line.to_string(),
+            });
+            if read_bytes >= batch_bytes {
+                if !send(std::mem::take(&mut batch)) {
+                    return Ok(duplicates);
+                }
+                read_bytes = 0;
+            }
+        }
+    }
+    if !batch.is_empty() {
+        send(batch);
+    }
+    Ok(duplicates)
+}
+
+/// Sequences of a batch converted for every read shape.
+struct ConvertedBatch {
+    outputs: Vec<Vec<u8>>,
+    /// Outputs of the sequences of every group of a split output.
+    groups: BTreeMap<u32, Vec<Vec<u8>>>,
+    seqids: Vec<String>,
+    stats: String,
+}
+
+/// Files of a split output: the output of every group of sequences goes to
+/// `<group>.<output file name>` in the directory.
+struct SplitFiles<'a> {
+    dir: &'a Path,
+    /// Read length and file of every output.
+    outputs: Vec<(usize, &'a Path)>,
+    /// Groups whose files are created; later batches are appended.
+    created: HashSet<u32>,
+}
+
+impl SplitFiles<'_> {
+    fn path(&self, group: u32, output: &Path) -> PathBuf {
+        let name = output.file_name().unwrap_or(output.as_os_str());
+        self.dir
+            .join(format!("{}.{}", group, name.to_string_lossy()))
+    }
+
+    /// Files of the groups written so far.
+    fn files(&self) -> impl Iterator<Item = PathBuf> + '_ {
+        self.created.iter().flat_map(move |&group| {
+            self.outputs
+                .iter()
+                .map(move |(_, output)| self.path(group, output))
+        })
+    }
+
+    /// Writes the outputs of a batch of the sequences of `group` to the
+    /// temporary files of the group.
+    fn write(
+        &mut self,
+        group: u32,
+        outputs: &[Vec<u8>],
+        options: &OutputOptions,
+    ) -> Result<(), DistribError> {
+        let new = self.created.insert(group);
+        for (&(read_len, output), bytes) in self.outputs.iter().zip(outputs) {
+            let path = database::tmp_path(&self.path(group, output));
+            let write = || {
+                // Compressed batches are appended as members (frames) of
+                // their own, which the readers go through in turn
+                let mut writer = if new {
+                    CompressedWriter::create(&path, options.compression)?
+                } else {
+                    CompressedWriter::append(&path, options.compression)?
+                };
+                if let Some(header) = header_line(options, read_len).filter(|_| new) {
+                    writeln!(writer, "{}", header)?;
+                }
+                writer.write_all(bytes)?;
+                writer.finish()
+            };
+            write().map_err(DistribError::io(&path))?;
+        }
+        Ok(())
+    }
+}
+
+/// `#` line starting the text output of a read length: the provenance of
+/// `options` and the read length, which kmer-distrib checks its inputs
+/// agree on. kmer-distrib skips the line, which has less than four fields.
+fn header_line(options: &OutputOptions, read_len: usize) -> Option<String> {
+    let header = options
+        .header
+        .as_ref()
+        .filter(|_| !options.binary && !options.legacy)?;
+    Some(format!("# {} read_len={}", header, read_len))
+}
+
+/// Writes the converted batches, one output per read length, to `outputs`
+/// (or, split, to the files of `split`) and their statistics to `stats`, as
+/// they arrive or in the order of their indices if `ordered`, handing a
+/// credit back to the reader for every batch written; binary outputs get the
+/// magic and the seqid table of the format.
+fn write_batches(
+    outputs: Vec<(usize, &Path, CompressedWriter)>,
+    stats: Option<(&Path, File)>,
+    mut split: Option<&mut SplitFiles>,
+    receiver: Receiver<(usize, ConvertedBatch)>,
+    credits: SyncSender<()>,
+    options: &OutputOptions,
+) -> Result<(), DistribError> {
+    let mut stats =
+        stats.map(|(path, stats)| (path, BufWriter::with_capacity(WRITE_BUFFER_BYTES, stats)));
+    if let Some((path, stats)) = &mut stats {
+        writeln!(stats, "{}", STATS_HEADER).map_err(DistribError::io(path))?;
+    }
+    let mut writers = Vec::with_capacity(outputs.len());
+    for (read_len, path, output) in outputs {
+        let mut writer = BufWriter::with_capacity(WRITE_BUFFER_BYTES, output);
+        match header_line(options, read_len) {
+            _ if options.binary => writer.write_all(COUNTS_MAGIC),
+            Some(header) => writeln!(writer, "{}", header),
+            None => Ok(()),
+        }
+        .map_err(DistribError::io(path))?;
+        writers.push((path, writer));
+    }
+    let mut seqids = Vec::new();
+    let mut write = |batch: ConvertedBatch| -> Result<(), DistribError> {
+        for ((path, writer), bytes) in writers.iter_mut().zip(batch.outputs) {
+            writer.write_all(&bytes).map_err(DistribError::io(path))?;
+        }
+        if let Some(split) = &mut split {
+            for (group, outputs) in &batch.groups {
+                split.write(*group, outputs, options)?;
+            }
+        }
+        seqids.extend(batch.seqids);
+        if let Some((path, stats)) = &mut stats {
+            stats
+                .write_all(batch.stats.as_bytes())
+                .map_err(DistribError::io(path))?;
+        }
+        // The reader is gone once it has read everything
+        credits.send(()).ok();
+        Ok(())
+    };
+    let ordered = options.ordered;
+    // Batches converted ahead of the next one to write, by index
+    let mut pending = BTreeMap::new();
+    let mut next = 0;
+    for (index, batch) in receiver {
+        if !ordered {
+            write(batch)?;
+            continue;
+        }
+        pending.insert(index, batch);
+        while let Some(batch) = pending.remove(&next) {
+            write(batch)?;
+            next += 1;
+        }
+    }
+    if options.binary {
+        let table = encode_seqid_table(&seqids);
+        for (path, writer) in writers.iter_mut() {
+            writer.write_all(&table).map_err(DistribError::io(path))?;
+        }
+    }
+    for (path, writer) in writers {
+        writer
+            .into_inner()
+            .map_err(io::IntoInnerError::into_error)
+            .and_then(CompressedWriter::finish)
+            .map_err(DistribError::io(path))?;
+    }
+    if let Some((path, stats)) = stats {
+        stats
+            .into_inner()
+            .map_err(io::IntoInnerError::into_error)
+            .and_then(|mut stats| stats.flush())
+            .map_err(DistribError::io(path))?;
+    }
+    Ok(())
+}
+
+/// How [`evaluate_kfile`] writes the read distributions.
+#[derive(Debug, Clone, Default)]
+pub struct OutputOptions {
+    /// Write the lines in the order of database.kraken, with sorted taxids,
+    /// so reruns produce identical files; otherwise batches of lines are
+    /// written as soon as they are converted.
+    pub ordered: bool,
+    /// Write the compact binary format (see [`COUNTS_MAGIC`]) instead of
+    /// text lines.
+    pub binary: bool,
+    /// Write the text lines byte for byte as the C++ kmer2read_distr does,
+    /// with sorted taxids, a space after every item and no header.
+    pub legacy: bool,
+    pub compression: Compression,
+    /// Also write per-sequence classification statistics to this file.
+    pub stats: Option<PathBuf>,
+    /// Write a summary of the read classifications of every genome taxid
+    /// (the statistics of its sequences combined) to this TSV file.
+    pub taxid_summary: Option<PathBuf>,
+    /// Provenance written as a `#` line at the start of text outputs.
+    pub header: Option<String>,
+    /// Write the text outputs as one file per genome taxid (or clade)
+    /// instead.
+    pub split: Option<SplitOutput>,
+}
+
+/// Text outputs split by the taxid of the sequences: the sequences of every
+/// genome taxid, or of every clade at `rank`, go to `<taxid>.<output file
+/// name>` in `dir`. Sequences without an ancestor at `rank` go to taxid 0.
+#[derive(Debug, Clone)]
+pub struct SplitOutput {
+    pub dir: PathBuf,
+    /// Rank code (S, G, F, ...) of the clades.
+    pub rank: Option<String>,
+}
+
+/// Paired-end reads: two mates of the read length at the ends of fragments
+/// of `insert_size` bp on average, with a standard deviation of `insert_sd`
+/// bp.
+#[derive(Debug, Clone, Copy)]
+pub struct PairedReads {
+    pub insert_size: usize,
+    pub insert_sd: usize,
+}
+
+impl PairedReads {
+    /// Distances between the starts of the mates of `read_len` bp, with
+    /// their weights: the insert sizes follow a binomial distribution over
+    /// the mean and one and two standard deviations around it, which has the
+    /// mean and standard deviation of the inserts and integer weights.
+    fn mates(&self, read_len: usize) -> Vec<(usize, usize)> {
+        if self.insert_sd == 0 {
+            return vec![(self.insert_size.saturating_sub(read_len), 1)];
+        }
+        [1, 4, 6, 4, 1]
+            .into_iter()
+            .enumerate()
+            .map(|(i, weight)| {
+                let insert_size =
+                    (self.insert_size + i * self.insert_sd).saturating_sub(2 * self.insert_sd);
+                (insert_size.saturating_sub(read_len), weight)
+            })
+            .collect()
+    }
+}
+
+/// Handling of the unclassified (taxid 0) kmers of a read, which never
+/// make a classification of their own.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ZeroKmers {
+    /// Skip the reads whose kmers are mostly unclassified.
+    Ignore,
+    /// Leave the reads whose kmers are mostly unclassified unclassified.
+    Count,
+    /// Classify every read by its classified kmers; the unclassified ones
+    /// only count against the confidence.
+    #[default]
+    Penalize,
+}
+
+/// Classification of a read whose taxa tie for the highest score.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TieBreak {
+    /// Their lowest common ancestor.
+    #[default]
+    Lca,
+    /// The deepest of them, the lowest taxid among equally deep ones.
+    Deepest,
+    /// The lowest taxid of them.
+    LowestTaxid,
+}
+
+/// Reads of the sequences with fewer kmers than a read.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ShortSequences {
+    /// None.
+    #[default]
+    Skip,
+    /// A single read of all the kmers of the sequence.
+    SingleWindow,
+    /// A single read of all the kmers of the sequence and unclassified
+    /// kmers up to the read length.
+    Pad,
+}
+
+/// Handling of the seqids listed more than once in seqid2taxid.map or the
+/// kraken files.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OnDuplicate {
+    /// Fail.
+    Error,
+    /// Keep the first taxid or kraken line of the seqid.
+    First,
+    /// Classify the seqid to the lowest common ancestor of its taxids, and
+    /// keep all its kraken lines, whose reads add up.
+    #[default]
+    Merge,
+}
+
+impl OnDuplicate {
+    fn describe(self) -> &'static str {
+        match self {
+            OnDuplicate::Error => "rejected",
+            OnDuplicate::First => "only the first kept",
+            OnDuplicate::Merge => "merged",
+        }
+    }
+}
+
+/// Handling of the malformed lines of the kraken files: lines of fewer than
+/// five fields, and kmer items that are not `taxid:count`.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MalformedLines {
+    /// Fail at the first one, with its line number.
+    Strict,
+    /// Skip the short lines, read the kmers of malformed items as
+    /// unclassified, and report how many lines were malformed.
+    #[default]
+    Lenient,
+}
+
+/// Interleaved share `k` of `n` of the sequences, `K/N` on the command
+/// line: sequences `k`, `k + n`, `k + 2n`, ... counting from 1. The partial
+/// outputs of the `n` chunks are put together by [`merge_counts`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Chunk {
+    pub k: usize,
+    pub n: usize,
+}
 
-        if score > max_score {
-            max_score = score;
-            max_taxid = taxid;
-        } else if score == max_score && max_taxid != 0 {
-            max_taxid = taxo.lca(max_taxid, taxid);
+impl Chunk {
+    /// Whether the sequence at `position`, from 0, belongs to the chunk.
+    fn contains(self, position: usize) -> bool {
+        position % self.n == self.k - 1
+    }
+}
+
+impl std::str::FromStr for Chunk {
+    type Err = String;
+
+    fn from_str(chunk: &str) -> Result<Self, Self::Err> {
+        let parsed = chunk
+            .split_once('/')
+            .and_then(|(k, n)| Some((k.trim().parse().ok()?, n.trim().parse().ok()?)));
+        match parsed {
+            Some((k, n)) if (1..=n).contains(&k) => Ok(Chunk { k, n }),
+            _ => Err(format!(
+                "invalid chunk {:?}, expected K/N with 1 <= K <= N",
+                chunk
+            )),
         }
     }
+}
 
-    max_taxid
+impl std::fmt::Display for Chunk {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}/{}", self.k, self.n)
+    }
 }
 
-const BATCH_SIZE: usize = 100;
+/// How the reads are modelled and classified, and the memory this takes.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ReadOptions {
+    /// Classify paired-end fragments instead of single reads.
+    pub paired: Option<PairedReads>,
+    pub zero_kmers: ZeroKmers,
+    pub tie_break: TieBreak,
+    pub short_seq: ShortSequences,
+    /// Length of the minimizers whose positions the kmer columns count,
+    /// instead of kmers: a read then spans `read_len - minimizer_len + 1`
+    /// of them. Kraken 2 itself counts kmers.
+    pub minimizer_len: Option<usize>,
+    /// Handling of the seqids with several lines in the kraken files.
+    pub on_duplicate: OnDuplicate,
+    pub malformed: MalformedLines,
+    /// Sequences (lines of the kraken files) skipped before the converted
+    /// ones.
+    pub skip: usize,
+    /// Sequences converted, all the remaining ones if `None`.
+    pub limit: Option<usize>,
+    /// Share of the sequences (after `skip`) converted by this job of a
+    /// distributed conversion.
+    pub chunk: Option<Chunk>,
+    /// Share of the classified and unclassified kmers of a read that the
+    /// clade of its classification must hold, as Kraken 2's --confidence;
+    /// 0 keeps the classification of the kmers alone.
+    pub confidence: f64,
+    /// Approximate bound in bytes on the lines of database.kraken being
+    /// converted or written at once, which smaller batches of lines and
+    /// fewer of them in flight keep to; a single longer line still fits.
+    pub max_memory: Option<usize>,
+}
 
+/// Converts the kmer mappings of database.kraken (`k_file`) into the read
+/// classifications of every genome, written to `o_file` as set by `output`.
 pub fn evaluate_kfile<P: AsRef<Path>>(
     k_file: P,
     o_file: P,
@@ -165,51 +1444,953 @@ pub fn evaluate_kfile<P: AsRef<Path>>(
     read_len: usize,
     kmer_len: usize,
     taxo: &NCBITaxonomy,
-) -> Result<()> {
-    print!("\t>>STEP 3: CONVERTING KMER MAPPINGS INTO READ CLASSIFICATIONS:\n");
-    print!(
-        "\t\t{}mers, with a database built using {}mers\n",
-        read_len, kmer_len,
-    );
+    output: OutputOptions,
+) -> Result<(), DistribError> {
+    evaluate_kfile_lengths(
+        &[k_file.as_ref().to_path_buf()],
+        &[(read_len, o_file.as_ref().to_path_buf())],
+        seqid2taxid,
+        kmer_len,
+        taxo,
+        ReadOptions::default(),
+        output,
+    )
+}
 
-    let file = File::open(k_file)?;
-    let mmap = unsafe { MmapOptions::new().map(&file)? };
-    let data = unsafe { std::str::from_utf8_unchecked(&mmap) };
+/// Like [`evaluate_kfile`] for several read lengths in a single pass over
+/// the Kraken files `k_files`, read in turn as one database.kraken, writing
+/// the read classifications of every `(read length, output file)` of
+/// `outputs`, modelled and classified as set by `reads`.
+pub fn evaluate_kfile_lengths(
+    k_files: &[PathBuf],
+    outputs: &[(usize, PathBuf)],
+    seqid2taxid: HashMap<String, u32>,
+    kmer_len: usize,
+    taxo: &NCBITaxonomy,
+    reads: ReadOptions,
+    output: OutputOptions,
+) -> Result<(), DistribError> {
+    let read_lens: Vec<usize> = outputs.iter().map(|(read_len, _)| *read_len).collect();
+    info!("\t>>STEP 3: CONVERTING KMER MAPPINGS INTO READ CLASSIFICATIONS:");
+    info!(
+        "\t\t{}mers, with a database built using {}mers",
+        read_lens
+            .iter()
+            .map(|read_len| read_len.to_string())
+            .collect::<Vec<_>>()
+            .join(", "),
+        kmer_len,
+    );
+    if let Some(minimizer_len) = reads.minimizer_len {
+        info!(
+            "\t\tkmer columns counting the positions of {}-mer minimizers",
+            minimizer_len
+        );
+    }
+    if let Some(paired) = &reads.paired {
+        info!(
+            "\t\tpaired-end reads, {} bp inserts (standard deviation {} bp)",
+            paired.insert_size, paired.insert_sd
+        );
+    }
+    info!(
+        "\t\tunclassified kmers: {:?}, ties: {:?}, confidence {}",
+        reads.zero_kmers, reads.tie_break, reads.confidence
+    );
 
-    let outfile = File::create(o_file)?;
-    let writer = Arc::new(Mutex::new(BufWriter::new(outfile)));
+    // Every file is written aside and renamed into place once complete
+    let tmp_outputs: Vec<PathBuf> = outputs
+        .iter()
+        .map(|(_, o_file)| database::tmp_path(o_file))
+        .collect();
+    let (outfiles, mut split) = match &output.split {
+        Some(split) => {
+            std::fs::create_dir_all(&split.dir).map_err(DistribError::io(&split.dir))?;
+            let files = SplitFiles {
+                dir: &split.dir,
+                outputs: outputs
+                    .iter()
+                    .map(|(read_len, o_file)| (*read_len, o_file.as_path()))
+                    .collect(),
+                created: HashSet::new(),
+            };
+            (Vec::new(), Some(files))
+        }
+        None => {
+            let outfiles = tmp_outputs
+                .iter()
+                .zip(&read_lens)
+                .map(|(tmp, &read_len)| {
+                    CompressedWriter::create(tmp, output.compression)
+                        .map(|writer| (read_len, tmp.as_path(), writer))
+                        .map_err(DistribError::io(tmp))
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            (outfiles, None)
+        }
+    };
+    let tmp_stats = output.stats.as_deref().map(database::tmp_path);
+    let stats_file = match &tmp_stats {
+        Some(stats) => Some((
+            stats.as_path(),
+            File::create(stats).map_err(DistribError::io(stats))?,
+        )),
+        None => None,
+    };
+    let tmp_summary = output.taxid_summary.as_deref().map(database::tmp_path);
+    let summary_file = match &tmp_summary {
+        Some(summary) => Some(File::create(summary).map_err(DistribError::io(summary))?),
+        None => None,
+    };
 
     /*Initialize variables for getting read mappings instead of kmer mappings */
-    let n_kmers = read_len - kmer_len + 1;
-    let counter = AtomicUsize::new(1);
+    let shapes: Vec<ReadShape> = read_lens
+        .iter()
+        .map(|&read_len| ReadShape {
+            read_len,
+            n_kmers: read_len - reads.minimizer_len.unwrap_or(kmer_len) + 1,
+            mates: reads
+                .paired
+                .map_or_else(Vec::new, |paired| paired.mates(read_len)),
+            short: AtomicUsize::new(0),
+            taxids: DashMap::new(),
+        })
+        .collect();
+    // Every sequence of the library has a line in database.kraken
+    let lines = reads.skip
+        ..reads
+            .limit
+            .map_or(usize::MAX, |limit| reads.skip.saturating_add(limit));
+    let sequences = seqid2taxid.len().saturating_sub(reads.skip);
+    let sequences = reads.limit.map_or(sequences, |limit| limit.min(sequences));
+    let bar = progress::counter(
+        reads
+            .chunk
+            .map_or(sequences, |chunk| sequences.div_ceil(chunk.n)) as u64,
+        "Converting sequences",
+    );
+    if let Some(chunk) = reads.chunk {
+        info!(
+            "\t\tchunk {}: sequences {}, {}, {}, ...",
+            chunk,
+            chunk.k,
+            chunk.k + chunk.n,
+            chunk.k + 2 * chunk.n
+        );
+    }
 
-    print!("\t\t0 sequences converted...");
+    let readers = k_files
+        .iter()
+        .map(|k_file| database::open_maybe_compressed_quiet(k_file))
+        .collect::<Result<Vec<_>, _>>()?;
+    // database.kraken, possibly compressed, is streamed in batches so memory
+    // stays bounded whatever its size: the credits keep the reader at most
+    // `window` batches ahead of the writer thread, the only one touching the
+    // outputs, whether the batches wait for a worker, are being converted,
+    // or wait to be written in order.
+    let threads = rayon::current_num_threads();
+    let window = 2 * threads + 2;
+    let batch_bytes = match reads.max_memory {
+        // A batch takes about its size again while converted, and as much
+        // for the output of every read length
+        Some(max_memory) => {
+            let batch_bytes = max_memory / (window * (2 + shapes.len()));
+            if batch_bytes < MIN_BATCH_BYTES {
+                warn!(
+                    "\t\t{} bytes are too few for {} threads; batches of {} bytes are used",
+                    max_memory, threads, MIN_BATCH_BYTES
+                );
+            }
+            batch_bytes.clamp(MIN_BATCH_BYTES, READ_BATCH_BYTES)
+        }
+        None => READ_BATCH_BYTES,
+    };
+    debug!(
+        "\t\tat most {} batches of about {} bytes in flight",
+        window, batch_bytes
+    );
+    let (credit_sender, credit_receiver) = mpsc::sync_channel(window);
+    for _ in 0..window {
+        credit_sender.send(()).ok();
+    }
+    let (sender, receiver) = mpsc::sync_channel(threads);
+    let (output_sender, output_receiver) = mpsc::sync_channel(2 * threads);
+    let malformed = AtomicUsize::new(0);
+    let convert = |line: &KrakenLine| -> Result<Option<ConvertedLine>, DistribError> {
+        if line.text.trim().is_empty() {
+            return Ok(None);
+        }
+        let converted = convert_line(&line.text, &seqid2taxid, &shapes, taxo, &reads, &output);
+        let reason = match &converted {
+            Ok(converted) => converted.malformed,
+            Err(reason) => Some(*reason),
+        };
+        if let Some(reason) = reason {
+            let path = &k_files[line.file];
+            if reads.malformed == MalformedLines::Strict {
+                return Err(DistribError::MalformedLine(
+                    path.clone(),
+                    line.number,
+                    reason,
+                ));
+            }
+            malformed.fetch_add(1, Ordering::Relaxed);
+            debug!(
+                "\t\t{:?}:{}: malformed line ({}), {}",
+                path,
+                line.number,
+                reason,
+                if converted.is_ok() {
+                    "read in part"
+                } else {
+                    "skipped"
+                }
+            );
+        }
+        let Ok(converted) = converted else {
+            return Ok(None);
+        };
+        bar.inc(1);
+        debug!("\t\tfinished: {}", converted.seqid);
+        Ok(Some(converted))
+    };
+    let options = &output;
+    let split_files = split.as_mut();
+    let (read, converted, written) = std::thread::scope(|scope| {
+        let on_duplicate = reads.on_duplicate;
+        let reader = scope.spawn(move || {
+            read_batches(
+                readers,
+                batch_bytes,
+                credit_receiver,
+                sender,
+                lines,
+                reads.chunk,
+                on_duplicate,
+            )
+        });
+        let writer = scope.spawn(move || {
+            write_batches(
+                outfiles,
+                stats_file,
+                split_files,
+                output_receiver,
+                credit_sender,
+                options,
+            )
+        });
+        let converted: Result<(), DistribError> = receiver
+            .into_iter()
+            .enumerate()
+            .par_bridge()
+            .try_for_each_with(output_sender, |output_sender, (index, batch)| {
+                let lines: Vec<_> = batch
+                    .par_iter()
+                    .map(convert)
+                    .filter_map(Result::transpose)
+                    .collect::<Result<_, _>>()?;
+                let mut converted = ConvertedBatch {
+                    outputs: vec![Vec::new(); shapes.len()],
+                    groups: BTreeMap::new(),
+                    seqids: Vec::new(),
+                    stats: String::new(),
+                };
+                for ConvertedLine {
+                    seqid,
+                    outputs,
+                    stats,
+                    ..
+                } in lines
+                {
+                    let bytes = match &output.split {
+                        Some(split) => {
+                            let taxid = seqid2taxid.get(&seqid).copied().unwrap_or(0);
+                            let group = match &split.rank {
+                                Some(rank) => taxo.ancestor_at_rank(taxid, rank).unwrap_or(0),
+                                None => taxid,
+                            };
+                            converted
+                                .groups
+                                .entry(group)
+                                .or_insert_with(|| vec![Vec::new(); shapes.len()])
+                        }
+                        None => &mut converted.outputs,
+                    };
+                    for (bytes, line_output) in bytes.iter_mut().zip(outputs) {
+                        bytes.extend_from_slice(&line_output);
+                    }
+                    if output.binary {
+                        converted.seqids.push(seqid);
+                    }
+                    converted.stats += &stats;
+                }
+                // The writer only hangs up on an error, reported below
+                output_sender.send((index, converted)).ok();
+                Ok(())
+            });
+        (
+            reader.join().expect("database.kraken reader panicked"),
+            converted,
+            writer.join().expect("read distribution writer panicked"),
+        )
+    });
+    converted?;
+    let duplicates = read.map_err(|(i, e)| match e {
+        ReadError::Io(e) => DistribError::io(&k_files[i])(e),
+        ReadError::Duplicate(seqid) => DistribError::DuplicateSeqid(k_files[i].clone(), seqid),
+    })?;
+    if duplicates > 0 {
+        warn!(
+            "\t\t{} duplicate seqids in the kraken files: {}",
+            duplicates,
+            reads.on_duplicate.describe()
+        );
+    }
+    written?;
+    let malformed = malformed.into_inner();
+    if malformed > 0 {
+        warn!(
+            "\t\t{} malformed lines in the kraken files skipped or read in part \
+             (--malformed strict reports them)",
+            malformed
+        );
+    }
+    match &split {
+        Some(split) => {
+            for file in split.files() {
+                database::rename_tmp(&file)?;
+            }
+            info!("\t\toutputs split into {:?}", split.dir);
+        }
+        None => {
+            for (_, o_file) in outputs {
+                database::rename_tmp(o_file)?;
+            }
+        }
+    }
+    if let Some(stats) = &output.stats {
+        database::rename_tmp(stats)?;
+    }
+    bar.finish_and_clear();
+    info!("\t\t{} sequences converted", bar.position());
+    for shape in &shapes {
+        let short = shape.short.load(Ordering::Relaxed);
+        if short > 0 {
+            info!(
+                "\t\t{} sequences shorter than {} bp reads: {}",
+                short,
+                shape.read_len,
+                match reads.short_seq {
+                    ShortSequences::Skip => "skipped",
+                    ShortSequences::SingleWindow => "read as a single read",
+                    ShortSequences::Pad => "padded to a read with unclassified kmers",
+                }
+            );
+        }
+    }
+    if let (Some(summary), Some(tmp), Some(path)) =
+        (summary_file, &tmp_summary, &output.taxid_summary)
+    {
+        write_taxid_summary(summary, &shapes, taxo).map_err(DistribError::io(tmp))?;
+        database::rename_tmp(path)?;
+        info!("\t\ttaxid summary written to {:?}", path);
+    }
+    Ok(())
+}
 
-    let buffer = Arc::new(Mutex::new(Vec::new()));
-    data.par_lines().for_each(|line| {
-        if let Some((seqid, output)) = convert_line(line, &seqid2taxid, n_kmers, taxo) {
-            let count = counter.fetch_add(1, Ordering::SeqCst);
-            print!("\r\t\t{} sequences converted (finished: {})", count, seqid);
-            let mut buffer = buffer.lock().unwrap();
-            buffer.extend_from_slice(output.as_bytes());
+/// Key of the chunk of a partial output in its `#` header line.
+const CHUNK_KEY: &str = "chunk=";
 
-            if buffer.len() >= BATCH_SIZE {
-                // Acquire the lock and write the buffer contents
-                let mut write = writer.lock().unwrap();
-                write.write_all(&buffer).expect("write data error");
-                write.flush().expect("flush writer error");
+/// Puts the read distributions of `inputs`, e.g. the partial outputs of the
+/// chunks of a distributed conversion, together in `output`. The inputs must
+/// all be text or all binary; text inputs must have the same header but for
+/// their chunk, and chunked ones must cover every chunk exactly once.
+/// Returns the number of sequences.
+pub fn merge_counts(
+    inputs: &[PathBuf],
+    output: &Path,
+    compression: Compression,
+) -> Result<usize, DistribError> {
+    let invalid =
+        |path: &Path, reason: String| DistribError::MergeCounts(path.to_path_buf(), reason);
+    let mut readers = Vec::with_capacity(inputs.len());
+    let mut binary = None;
+    for input in inputs {
+        let mut reader = database::open_maybe_compressed_quiet(input)?;
+        let is_binary = reader
+            .fill_buf()
+            .map_err(DistribError::io(input))?
+            .starts_with(COUNTS_MAGIC);
+        if *binary.get_or_insert(is_binary) != is_binary {
+            return Err(invalid(input, "binary and text inputs are mixed".into()));
+        }
+        readers.push(reader);
+    }
 
-                // Clear the buffer
-                buffer.clear();
+    // Written aside and renamed once complete
+    let tmp = database::tmp_path(output);
+    let mut writer = BufWriter::with_capacity(
+        WRITE_BUFFER_BYTES,
+        CompressedWriter::create(&tmp, compression).map_err(DistribError::io(&tmp))?,
+    );
+    let mut seqids = HashSet::new();
+    let mut duplicates = 0;
+    let mut sequences = 0;
+    if binary == Some(true) {
+        writer
+            .write_all(COUNTS_MAGIC)
+            .map_err(DistribError::io(&tmp))?;
+        let mut table = Vec::new();
+        for (input, mut reader) in inputs.iter().zip(readers) {
+            reader.consume(COUNTS_MAGIC.len());
+            let mut written = Ok(());
+            let input_seqids = read_binary_counts(reader, input, |taxid, counts| {
+                let counts: Vec<_> = counts
+                    .iter()
+                    .map(|&(taxid, count)| (taxid, count as usize))
+                    .collect();
+                if written.is_ok() {
+                    written = writer.write_all(&encode_counts(taxid, &counts));
+                }
+            })?;
+            written.map_err(DistribError::io(&tmp))?;
+            for seqid in input_seqids {
+                if !seqids.insert(seqid.clone()) {
+                    duplicates += 1;
+                }
+                table.push(seqid);
             }
         }
-    });
+        sequences = table.len();
+        writer
+            .write_all(&encode_seqid_table(&table))
+            .map_err(DistribError::io(&tmp))?;
+    } else {
+        // Header of the first input without its chunk, and the chunk of
+        // every input
+        let mut header: Option<(&Path, String)> = None;
+        let mut chunks = Vec::new();
+        let mut lines = Vec::with_capacity(readers.len());
+        for (input, reader) in inputs.iter().zip(readers) {
+            let mut reader_lines = reader.lines().peekable();
+            let first = match reader_lines.peek() {
+                Some(Ok(line)) => line.strip_prefix("# ").map(str::to_string),
+                _ => None,
+            };
+            let (provenance, chunk) = match &first {
+                Some(first) => {
+                    reader_lines.next();
+                    let mut chunk = None;
+                    let mut provenance = Vec::new();
+                    for field in first.split_whitespace() {
+                        match field.strip_prefix(CHUNK_KEY) {
+                            Some(value) => {
+                                chunk = Some(value.parse::<Chunk>().map_err(|e| invalid(input, e))?)
+                            }
+                            None => provenance.push(field),
+                        }
+                    }
+                    (provenance.join(" "), chunk)
+                }
+                None => (String::new(), None),
+            };
+            match &header {
+                Some((first_input, first)) if *first != provenance => {
+                    return Err(invalid(
+                        input,
+                        format!("its header differs from the one of {:?}", first_input),
+                    ));
+                }
+                Some(_) => {}
+                None => header = Some((input, provenance)),
+            }
+            chunks.push(chunk);
+            lines.push(reader_lines);
+        }
+        check_chunks(inputs, &chunks)?;
+        if let Some((_, header)) = header.filter(|(_, header)| !header.is_empty()) {
+            writeln!(writer, "# {}", header).map_err(DistribError::io(&tmp))?;
+        }
+        for (input, input_lines) in inputs.iter().zip(lines) {
+            for (number, line) in input_lines.enumerate() {
+                let line = line.map_err(DistribError::io(input))?;
+                if line.is_empty() {
+                    continue;
+                }
+                let mut line_fields = fields(&line);
+                let (Some(seqid), Some(_), Some(_), Some(_)) = (
+                    line_fields.next(),
+                    line_fields.next(),
+                    line_fields.next(),
+                    line_fields.next(),
+                ) else {
+                    return Err(invalid(input, format!("malformed line {}", number + 2)));
+                };
+                if !seqids.insert(seqid.to_string()) {
+                    duplicates += 1;
+                }
+                sequences += 1;
+                writeln!(writer, "{}", line).map_err(DistribError::io(&tmp))?;
+            }
+        }
+    }
+    writer
+        .into_inner()
+        .map_err(io::IntoInnerError::into_error)
+        .and_then(CompressedWriter::finish)
+        .map_err(DistribError::io(&tmp))?;
+    database::rename_tmp(output)?;
+    if duplicates > 0 {
+        warn!(
+            "{} seqids are in several inputs; kmer-distrib adds their reads up",
+            duplicates
+        );
+    }
+    Ok(sequences)
+}
 
-    let buffer = buffer.lock().unwrap();
-    if !buffer.is_empty() {
-        let mut write = writer.lock().unwrap();
-        write.write_all(&buffer).expect("write data error");
-        write.flush().expect("flush writer error");
+/// Checks that chunked inputs are all chunked alike and cover every chunk
+/// exactly once.
+fn check_chunks(inputs: &[PathBuf], chunks: &[Option<Chunk>]) -> Result<(), DistribError> {
+    let Some(first) = chunks.iter().flatten().next() else {
+        return Ok(());
+    };
+    let mut seen = vec![false; first.n];
+    for (input, chunk) in inputs.iter().zip(chunks) {
+        let reason = match chunk {
+            None => "it is not a chunk, unlike the others".to_string(),
+            Some(chunk) if chunk.n != first.n => {
+                format!("chunk {} is not one of {} chunks", chunk, first.n)
+            }
+            Some(chunk) if seen[chunk.k - 1] => format!("chunk {} is given twice", chunk),
+            Some(chunk) => {
+                seen[chunk.k - 1] = true;
+                continue;
+            }
+        };
+        return Err(DistribError::MergeCounts(input.clone(), reason));
+    }
+    let missing: Vec<_> = (1..=first.n)
+        .filter(|&k| !seen[k - 1])
+        .map(|k| Chunk { k, n: first.n }.to_string())
+        .collect();
+    if !missing.is_empty() {
+        return Err(DistribError::MergeCounts(
+            inputs[0].clone(),
+            format!("missing chunks {}", missing.join(", ")),
+        ));
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    fn taxonomy() -> NCBITaxonomy {
+        NCBITaxonomy::load("tests/data/self_test/nodes.dmp").unwrap()
+    }
+
+    /// Lowest common ancestor of `taxa` from their lineages.
+    fn naive_lca(taxa: &[u32], taxo: &NCBITaxonomy) -> u32 {
+        let lineages: Vec<Vec<u32>> = taxa.iter().map(|&t| taxo.lineage(t).unwrap()).collect();
+        let mut lca = 0;
+        for (i, &taxid) in lineages[0].iter().enumerate() {
+            if lineages
+                .iter()
+                .all(|lineage| lineage.get(i) == Some(&taxid))
+            {
+                lca = taxid;
+            }
+        }
+        lca
+    }
+
+    /// Classification of the read of `kmers`, computed from scratch.
+    fn naive_classify(kmers: &[u32], taxo: &NCBITaxonomy, reads: &ReadOptions) -> Option<u32> {
+        let mut counts: HashMap<u32, usize> = HashMap::new();
+        for &kmer in kmers.iter().filter(|&&kmer| kmer != AMBIGUOUS) {
+            *counts.entry(kmer).or_insert(0) += 1;
+        }
+        let total: usize = counts.values().sum();
+        if total == 0 {
+            return Some(0);
+        }
+        let zero = counts.get(&0).copied().unwrap_or(0);
+        if 2 * zero > total {
+            match reads.zero_kmers {
+                ZeroKmers::Ignore => return None,
+                ZeroKmers::Count => return Some(0),
+                ZeroKmers::Penalize => {}
+            }
+        }
+        let lineage = |taxid: u32| taxo.lineage(taxid).unwrap();
+        let scores: Vec<(u32, usize)> = counts
+            .keys()
+            .filter(|&&taxid| taxid != 0)
+            .map(|&taxid| {
+                let score = lineage(taxid)
+                    .iter()
+                    .filter_map(|ancestor| counts.get(ancestor))
+                    .sum();
+                (taxid, score)
+            })
+            .collect();
+        let Some(max_score) = scores.iter().map(|&(_, score)| score).max() else {
+            return Some(0);
+        };
+        let tied: Vec<u32> = scores
+            .iter()
+            .filter(|&&(_, score)| score == max_score)
+            .map(|&(taxid, _)| taxid)
+            .collect();
+        let depth = |taxid: u32| taxo.get_node(&taxid).unwrap().depth;
+        let class = match reads.tie_break {
+            TieBreak::Lca => naive_lca(&tied, taxo),
+            TieBreak::Deepest => *tied
+                .iter()
+                .max_by_key(|&&taxid| (depth(taxid), std::cmp::Reverse(taxid)))
+                .unwrap(),
+            TieBreak::LowestTaxid => *tied.iter().min().unwrap(),
+        };
+        if reads.confidence == 0.0 {
+            return Some(class);
+        }
+        for &ancestor in lineage(class).iter().rev() {
+            let clade: usize = counts
+                .iter()
+                .filter(|&(&taxid, _)| taxid != 0 && lineage(taxid).contains(&ancestor))
+                .map(|(_, &count)| count)
+                .sum();
+            if clade as f64 >= reads.confidence * total as f64 {
+                return Some(ancestor);
+            }
+        }
+        Some(0)
+    }
+
+    /// Kmer runs of a random sequence, some of them repeated so reads have
+    /// the same counts and the classification cache is used.
+    fn random_runs(rng: &mut StdRng, taxo: &NCBITaxonomy, n_kmers: usize) -> Vec<(u32, usize)> {
+        let mut pool: Vec<u32> = taxo.nodes.iter().map(|node| node.taxid).collect();
+        pool.extend([0, 0, AMBIGUOUS]);
+        let mut runs = Vec::new();
+        for _ in 0..rng.gen_range(1..20) {
+            let taxid = pool[rng.gen_range(0..pool.len())];
+            let count = match rng.gen_range(0..10) {
+                0 => rng.gen_range(n_kmers..3 * n_kmers),
+                _ => rng.gen_range(1..4),
+            };
+            runs.push((taxid, count));
+        }
+        let motif = runs.clone();
+        for _ in 0..rng.gen_range(0..4) {
+            runs.extend_from_slice(&motif);
+        }
+        runs
+    }
+
+    fn kmers(runs: &[(u32, usize)]) -> Vec<u32> {
+        runs.iter()
+            .flat_map(|&(taxid, count)| std::iter::repeat_n(taxid, count))
+            .collect()
+    }
+
+    fn sorted(counts: impl IntoIterator<Item = (u32, usize)>) -> Vec<(u32, usize)> {
+        let mut counts: Vec<_> = counts.into_iter().collect();
+        counts.sort_unstable();
+        counts
+    }
+
+    fn shape(n_kmers: usize, paired: Option<PairedReads>) -> ReadShape {
+        // Kmers are 1 bp long, so reads span their kmers
+        ReadShape {
+            read_len: n_kmers,
+            n_kmers,
+            mates: paired.map_or_else(Vec::new, |paired| paired.mates(n_kmers)),
+            short: AtomicUsize::new(0),
+            taxids: DashMap::new(),
+        }
+    }
+
+    /// Every combination of the classification options, with and without
+    /// paired-end reads.
+    fn all_read_options() -> Vec<ReadOptions> {
+        let mut options = Vec::new();
+        for tie_break in [TieBreak::Lca, TieBreak::Deepest, TieBreak::LowestTaxid] {
+            for zero_kmers in [ZeroKmers::Ignore, ZeroKmers::Count, ZeroKmers::Penalize] {
+                for confidence in [0.0, 0.5, 0.8] {
+                    for short_seq in [
+                        ShortSequences::Skip,
+                        ShortSequences::SingleWindow,
+                        ShortSequences::Pad,
+                    ] {
+                        for paired in [
+                            None,
+                            Some(PairedReads {
+                                insert_size: 20,
+                                insert_sd: 0,
+                            }),
+                            Some(PairedReads {
+                                insert_size: 25,
+                                insert_sd: 3,
+                            }),
+                        ] {
+                            options.push(ReadOptions {
+                                paired,
+                                zero_kmers,
+                                tie_break,
+                                short_seq,
+                                confidence,
+                                ..Default::default()
+                            });
+                        }
+                    }
+                }
+            }
+        }
+        options
+    }
+
+    #[test]
+    fn incremental_classification_matches_recomputed_windows() {
+        let taxo = taxonomy();
+        let mut rng = StdRng::seed_from_u64(619);
+        for reads in all_read_options() {
+            for _ in 0..4 {
+                let n_kmers = rng.gen_range(4..16);
+                let runs = random_runs(&mut rng, &taxo, n_kmers);
+                let kmers = kmers(&runs);
+                if kmers.len() < n_kmers {
+                    continue;
+                }
+                let expected: Vec<Option<u32>> = kmers
+                    .windows(n_kmers)
+                    .map(|window| naive_classify(window, &taxo, &reads))
+                    .collect();
+
+                let mut windows = Vec::new();
+                classify_windows(kmers.iter().copied(), n_kmers, &taxo, &reads, |class| {
+                    windows.push(class)
+                });
+                assert_eq!(windows, expected, "{:?} of {:?}", reads, runs);
+
+                let mut counts: HashMap<u32, usize> = HashMap::new();
+                for class in expected.iter().flatten() {
+                    *counts.entry(*class).or_insert(0) += 1;
+                }
+                let classified = classify_reads(&runs, n_kmers, &taxo, &reads);
+                assert_eq!(
+                    sorted(classified),
+                    sorted(counts),
+                    "{:?} of {:?}",
+                    reads,
+                    runs
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn paired_classification_matches_recomputed_fragments() {
+        let taxo = taxonomy();
+        let mut rng = StdRng::seed_from_u64(619);
+        for reads in all_read_options()
+            .into_iter()
+            .filter(|r| r.paired.is_some())
+        {
+            for _ in 0..4 {
+                let n_kmers = rng.gen_range(4..12);
+                let shape = shape(n_kmers, reads.paired);
+                let runs = random_runs(&mut rng, &taxo, n_kmers);
+                let kmers = kmers(&runs);
+                if kmers.len() < n_kmers {
+                    continue;
+                }
+                let windows: Vec<Option<u32>> = kmers
+                    .windows(n_kmers)
+                    .map(|window| naive_classify(window, &taxo, &reads))
+                    .collect();
+                let mut counts: HashMap<u32, usize> = HashMap::new();
+                for (second_ix, &second) in windows.iter().enumerate() {
+                    for &(distance, weight) in &shape.mates {
+                        let Some(&first) = second_ix.checked_sub(distance).map(|i| &windows[i])
+                        else {
+                            continue;
+                        };
+                        let taxid = match (first, second) {
+                            (Some(a), Some(b)) if a == b || b == 0 => a,
+                            (Some(0), Some(b)) => b,
+                            (Some(a), Some(b)) => naive_lca(&[a, b], &taxo),
+                            (Some(taxid), None) | (None, Some(taxid)) => taxid,
+                            (None, None) => continue,
+                        };
+                        *counts.entry(taxid).or_insert(0) += weight;
+                    }
+                }
+                let classified = classify_pairs(&runs, &shape, &taxo, &reads);
+                assert_eq!(
+                    sorted(classified),
+                    sorted(counts),
+                    "{:?} of {:?}",
+                    reads,
+                    runs
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn short_sequences_match_recomputed_reads() {
+        let taxo = taxonomy();
+        let mut rng = StdRng::seed_from_u64(619);
+        for reads in all_read_options() {
+            for _ in 0..4 {
+                let shape = shape(rng.gen_range(30..60), reads.paired);
+                let runs = random_runs(&mut rng, &taxo, 1);
+                let mut kmers = kmers(&runs);
+                if kmers.len() >= shape.n_kmers {
+                    continue;
+                }
+                let weight = shape.mates.iter().map(|&(_, weight)| weight).sum::<usize>();
+                if reads.short_seq == ShortSequences::Pad {
+                    kmers.resize(shape.n_kmers, 0);
+                }
+                let expected = match reads.short_seq {
+                    ShortSequences::Skip => Vec::new(),
+                    _ => naive_classify(&kmers, &taxo, &reads)
+                        .map_or_else(Vec::new, |taxid| vec![(taxid, weight.max(1))]),
+                };
+                let classified = classify_short(&runs, &shape, &taxo, &reads);
+                assert_eq!(classified, expected, "{:?} of {:?}", reads, runs);
+            }
+        }
+    }
+
+    #[test]
+    fn batches_in_flight_are_bounded_by_the_credits() {
+        // 22-byte lines, the second one with invalid UTF-8
+        let mut lines = Vec::new();
+        for i in 0..100 {
+            let length: &[u8] = if i == 1 { b"4\xff" } else { b"40" };
+            lines.extend_from_slice(format!("C\tseq{:02}\t562\t", i).as_bytes());
+            lines.extend_from_slice(length);
+            lines.extend_from_slice(format!("\t562:{}\n", 10 + i % 10).as_bytes());
+        }
+        let (credit_sender, credits) = mpsc::sync_channel(2);
+        for _ in 0..2 {
+            credit_sender.send(()).unwrap();
+        }
+        // the writer hands no credit back
+        drop(credit_sender);
+        let (sender, receiver) = mpsc::sync_channel(10);
+        let duplicates = read_batches(
+            vec![&lines[..]],
+            100,
+            credits,
+            sender,
+            0..usize::MAX,
+            None,
+            OnDuplicate::Merge,
+        );
+        let batches: Vec<Vec<KrakenLine>> = receiver.into_iter().collect();
+
+        // the reader stops after the two batches it has credits for, each
+        // closed by the line reaching 100 bytes
+        assert_eq!(duplicates.ok(), Some(0));
+        assert_eq!(batches.len(), 2);
+        let numbers: Vec<Vec<usize>> = batches
+            .iter()
+            .map(|batch| batch.iter().map(|line| line.number).collect())
+            .collect();
+        assert_eq!(numbers, [vec![1, 2, 3, 4, 5], vec![6, 7, 8, 9, 10]]);
+        assert_eq!(batches[0][1].text, "C\tseq01\t562\t4\u{FFFD}\t562:11");
+    }
+
+    #[test]
+    fn kraken_fields_and_kmer_items_are_parsed_in_place() {
+        let fields = |line| fields(line).collect::<Vec<_>>();
+        assert_eq!(fields("C\tseq1\t\t562:3"), ["C", "seq1", "", "562:3"]);
+        assert_eq!(fields("C\tseq1\t"), ["C", "seq1", ""]);
+        assert_eq!(fields("C"), ["C"]);
+        // Multi-byte characters next to the tabs stay whole
+        assert_eq!(fields("é\tséq\tà"), ["é", "séq", "à"]);
+
+        assert_eq!(parse_run(b"562:10"), Ok((562, 10)));
+        assert_eq!(parse_run(b"0:0"), Ok((0, 0)));
+        assert_eq!(parse_run(b"A:31"), Ok((AMBIGUOUS, 31)));
+        assert_eq!(parse_run(b"4294967295:1"), Ok((u32::MAX, 1)));
+        assert_eq!(parse_run(b"562"), Err("kmer item without a colon"));
+        assert_eq!(parse_run(b":3"), Err("invalid taxid in a kmer item"));
+        assert_eq!(parse_run(b"x:3"), Err("invalid taxid in a kmer item"));
+        assert_eq!(parse_run(b"-1:3"), Err("invalid taxid in a kmer item"));
+        assert_eq!(
+            parse_run(b"4294967296:1"),
+            Err("invalid taxid in a kmer item")
+        );
+        assert_eq!(parse_run(b"562:"), Err("invalid count in a kmer item"));
+        assert_eq!(parse_run(b"562:1:2"), Err("invalid count in a kmer item"));
+        assert_eq!(parse_run(b"562: 3"), Err("invalid count in a kmer item"));
+    }
+
+    #[test]
+    fn decimal_parsing_matches_the_standard_library() {
+        // Digits only, as both the simd and the portable parsing read them
+        fn reference<T: std::str::FromStr>(digits: &[u8]) -> Option<T> {
+            if digits.is_empty() || !digits.iter().all(u8::is_ascii_digit) {
+                return None;
+            }
+            std::str::from_utf8(digits).ok()?.parse().ok()
+        }
+        let mut inputs: Vec<Vec<u8>> = [
+            "",
+            "0",
+            "7",
+            "007",
+            "+7",
+            "-7",
+            " 7",
+            "7 ",
+            "1a",
+            "a1",
+            "4294967295",
+            "4294967296",
+            "18446744073709551615",
+            "18446744073709551616",
+            "99999999999999999999",
+            "00000000000000000000000000001",
+        ]
+        .iter()
+        .map(|input| input.as_bytes().to_vec())
+        .collect();
+        let mut rng = StdRng::seed_from_u64(634);
+        for _ in 0..10_000 {
+            let len = rng.gen_range(1..=22);
+            inputs.push(
+                (0..len)
+                    .map(|_| match rng.gen_range(0..20) {
+                        0 => b":/ A"[rng.gen_range(0..4)],
+                        _ => b'0' + rng.gen_range(0..10),
+                    })
+                    .collect(),
+            );
+        }
+
+        for input in &inputs {
+            assert_eq!(
+                parse_decimal::<u32>(input),
+                reference::<u32>(input),
+                "{:?}",
+                input
+            );
+            assert_eq!(
+                parse_decimal::<usize>(input),
+                reference::<usize>(input),
+                "{:?}",
+                input
+            );
+        }
+    }
+}
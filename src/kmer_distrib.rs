@@ -0,0 +1,682 @@
+//! Kmer distribution file generated from the read distributions of the
+//! genomes of a Kraken database.
+
+use crate::database::{self, CompressedWriter, Compression, DistribError};
+use crate::kmer2read_distr;
+use crate::taxonomy::{self, MergedTaxids};
+use chrono::Local;
+use log::{info, warn};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::File;
+use std::io::{self, BufRead, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+/// First bytes of a binary kmer distribution file, followed by the number
+/// of mapped taxids (u64) and the bincode-encoded [`MappedGenomes`].
+pub const BINARY_MAGIC: &[u8; 8] = b"BRKDIST2";
+
+/// First bytes of the binary kmer distribution files of 32-bit kmer
+/// counts, still read.
+const BINARY_MAGIC_U32: &[u8; 8] = b"BRKDIST1";
+
+/// Header line of a text kmer distribution file.
+const TEXT_HEADER: &str = "mapped_taxid\tgenome_taxids:kmers_mapped:total_genome_kmers";
+
+/// Kmers of a genome mapped to a taxid.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct GenomeKmers {
+    pub taxid: u32,
+    pub kmers_mapped: u64,
+    pub total_kmers: u64,
+}
+
+/// An entry (line) of a kmer distribution: a mapped taxid and the genomes
+/// whose reads are classified to it.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct MappedGenomes {
+    pub mapped_taxid: u32,
+    pub genomes: Vec<GenomeKmers>,
+}
+
+/// `# key=value ...` line starting a text kmer distribution, recording the
+/// kmer and read lengths and the Kraken database of its read distributions,
+/// and the version and date of kmer-distrib. Readers skip the `#` lines.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Provenance {
+    pub fields: Vec<(String, String)>,
+}
+
+impl Provenance {
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(field, _)| field == key)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// A recorded length, e.g. `kmer_len` or `read_len`.
+    pub fn length(&self, key: &str) -> Option<usize> {
+        self.get(key)?.parse().ok()
+    }
+
+    /// Adds a field unless it is already recorded.
+    fn insert(&mut self, key: &str, value: &str) {
+        if self.get(key).is_none() {
+            self.fields.push((key.to_string(), value.to_string()));
+        }
+    }
+
+    fn line(&self) -> String {
+        let fields: Vec<String> = self
+            .fields
+            .iter()
+            .map(|(key, value)| format!("{}={}", key, value))
+            .collect();
+        format!("# {}", fields.join(" "))
+    }
+
+    /// Provenance of a kmer distribution file, possibly compressed, or `None`
+    /// for binary files and files without one.
+    pub fn read(path: &Path) -> Result<Option<Provenance>, DistribError> {
+        let mut reader = database::open_maybe_compressed_quiet(path)?;
+        if !reader
+            .fill_buf()
+            .map_err(DistribError::io(path))?
+            .starts_with(b"# ")
+        {
+            return Ok(None);
+        }
+        let mut line = String::new();
+        reader
+            .read_line(&mut line)
+            .map_err(DistribError::io(path))?;
+        let mut provenance = Provenance::default();
+        for (key, value) in line[2..]
+            .split_whitespace()
+            .filter_map(|field| field.split_once('='))
+        {
+            provenance.insert(key, value);
+        }
+        Ok(Some(provenance))
+    }
+}
+
+/// [`MappedGenomes`] of the binary files of 32-bit kmer counts.
+#[derive(Deserialize)]
+struct MappedGenomesU32 {
+    mapped_taxid: u32,
+    genomes: Vec<(u32, u32, u32)>,
+}
+
+impl From<MappedGenomesU32> for MappedGenomes {
+    fn from(entry: MappedGenomesU32) -> Self {
+        MappedGenomes {
+            mapped_taxid: entry.mapped_taxid,
+            genomes: entry
+                .genomes
+                .into_iter()
+                .map(|(taxid, kmers_mapped, total_kmers)| GenomeKmers {
+                    taxid,
+                    kmers_mapped: kmers_mapped.into(),
+                    total_kmers: total_kmers.into(),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Parses a line of a text kmer distribution; `None` for the header or a
+/// malformed line. Malformed genome items are skipped.
+pub fn parse_line(line: &str) -> Option<MappedGenomes> {
+    let mut fields = line.trim().split('\t');
+    let mapped_taxid = fields.next()?.parse::<u32>().ok()?;
+    let genomes = fields
+        .next()
+        .unwrap_or_default()
+        .split_whitespace()
+        .filter_map(|item| {
+            let mut parts = item.split(':');
+            let genome = GenomeKmers {
+                taxid: parts.next()?.parse().ok()?,
+                kmers_mapped: parts.next()?.parse().ok()?,
+                total_kmers: parts.next()?.parse().ok()?,
+            };
+            parts.next().is_none().then_some(genome)
+        })
+        .collect();
+    Some(MappedGenomes {
+        mapped_taxid,
+        genomes,
+    })
+}
+
+/// Whether a kmer distribution file, possibly compressed, is in the binary
+/// format.
+pub fn is_binary(path: &Path) -> Result<bool, DistribError> {
+    let mut reader = database::open_maybe_compressed_quiet(path)?;
+    let magic = reader.fill_buf().map_err(DistribError::io(path))?;
+    Ok(magic.starts_with(BINARY_MAGIC) || magic.starts_with(BINARY_MAGIC_U32))
+}
+
+/// Calls `f` on every entry of a kmer distribution file, text or binary and
+/// possibly gzip- or zstd-compressed, showing `message` on the progress bar.
+/// Returns the number of malformed lines of a text file, which are skipped.
+pub fn for_each_entry<F>(
+    path: &Path,
+    message: &'static str,
+    mut f: F,
+) -> Result<usize, DistribError>
+where
+    F: FnMut(MappedGenomes),
+{
+    let mut reader = database::open_maybe_compressed(path, message)?;
+    let (binary, counts_u32) = {
+        let magic = reader.fill_buf().map_err(DistribError::io(path))?;
+        let counts_u32 = magic.starts_with(BINARY_MAGIC_U32);
+        (magic.starts_with(BINARY_MAGIC) || counts_u32, counts_u32)
+    };
+    let mut malformed = 0;
+    if binary {
+        let binary_error = |source| DistribError::Binary {
+            path: path.to_path_buf(),
+            source,
+        };
+        let mut magic = [0u8; 8];
+        reader
+            .read_exact(&mut magic)
+            .map_err(DistribError::io(path))?;
+        let n_entries: u64 = bincode::deserialize_from(&mut reader).map_err(binary_error)?;
+        for _ in 0..n_entries {
+            if counts_u32 {
+                let entry: MappedGenomesU32 =
+                    bincode::deserialize_from(&mut reader).map_err(binary_error)?;
+                f(entry.into());
+            } else {
+                f(bincode::deserialize_from(&mut reader).map_err(binary_error)?);
+            }
+        }
+    } else {
+        // `#` lines, then the column header
+        let mut header = false;
+        for line in reader.lines() {
+            let line = line.map_err(DistribError::io(path))?;
+            if !header {
+                header = !line.starts_with('#');
+                continue;
+            }
+            match parse_line(&line) {
+                Some(entry) => f(entry),
+                None if line.trim().is_empty() => {}
+                None => malformed += 1,
+            }
+        }
+    }
+    Ok(malformed)
+}
+
+/// Writes kmer distribution entries in the text format.
+pub fn write_text(entries: &[MappedGenomes], output: &Path) -> Result<(), DistribError> {
+    let tmp = database::tmp_path(output);
+    let mut file = BufWriter::new(File::create(&tmp).map_err(DistribError::io(&tmp))?);
+    let mut write = || -> io::Result<()> {
+        writeln!(file, "{}", TEXT_HEADER)?;
+        for entry in entries {
+            write!(file, "{}\t", entry.mapped_taxid)?;
+            for (ix, genome) in entry.genomes.iter().enumerate() {
+                let sep = if ix == 0 { "" } else { " " };
+                write!(
+                    file,
+                    "{}{}:{}:{}",
+                    sep, genome.taxid, genome.kmers_mapped, genome.total_kmers
+                )?;
+            }
+            writeln!(file)?;
+        }
+        file.flush()
+    };
+    write().map_err(DistribError::io(&tmp))?;
+    database::rename_tmp(output)
+}
+
+/// Writes kmer distribution entries in the binary format.
+pub fn write_binary(entries: &[MappedGenomes], output: &Path) -> Result<(), DistribError> {
+    let tmp = database::tmp_path(output);
+    let binary_error = |source| DistribError::Binary {
+        path: tmp.clone(),
+        source,
+    };
+    let mut file = BufWriter::new(File::create(&tmp).map_err(DistribError::io(&tmp))?);
+    file.write_all(BINARY_MAGIC)
+        .map_err(DistribError::io(&tmp))?;
+    bincode::serialize_into(&mut file, &(entries.len() as u64)).map_err(binary_error)?;
+    for entry in entries {
+        bincode::serialize_into(&mut file, entry).map_err(binary_error)?;
+    }
+    file.flush().map_err(DistribError::io(&tmp))?;
+    database::rename_tmp(output)
+}
+
+/// Number of lines parsed in parallel at a time.
+const CHUNK_LINES: usize = 100_000;
+
+/// Adds `count` to the kmer `counter` of `genome`, failing on overflow.
+fn add_kmers(counter: &mut u64, count: u64, genome: &str) -> Result<(), DistribError> {
+    *counter = counter
+        .checked_add(count)
+        .ok_or_else(|| DistribError::KmerOverflow(genome.to_string()))?;
+    Ok(())
+}
+
+/// Genome taxid, total kmers and kmers by mapped taxid of a line of read
+/// distributions; genome "0" for the lines to skip.
+type GenomeCounts = (String, u64, HashMap<String, u64>);
+
+fn parse_single_genome(curr_str: &str) -> Result<GenomeCounts, DistribError> {
+    let split_str: Vec<&str> = curr_str.trim().split('\t').collect();
+    if split_str.len() < 4 {
+        return Ok(("0".to_string(), 0, HashMap::new()));
+    }
+
+    let genome_taxid = split_str[1].to_string();
+    let mut mapped_id_kmers = HashMap::new();
+    let mut total_kmers = 0;
+
+    for kmers in split_str[3].split_whitespace() {
+        let pair: Vec<&str> = kmers.split(':').collect();
+        if pair.len() != 2 {
+            continue;
+        }
+        let (curr_m_id, curr_kmers) = (pair[0], pair[1].parse::<u64>().unwrap_or(0));
+        add_kmers(&mut total_kmers, curr_kmers, &genome_taxid)?;
+        let counter = mapped_id_kmers.entry(curr_m_id.to_string()).or_insert(0);
+        add_kmers(counter, curr_kmers, &genome_taxid)?;
+    }
+
+    if mapped_id_kmers.is_empty() {
+        return Ok(("0".to_string(), 0, HashMap::new()));
+    }
+
+    Ok((genome_taxid, total_kmers, mapped_id_kmers))
+}
+
+/// How [`generate_kmer_distribution`] selects the genomes and writes the
+/// kmer distribution.
+#[derive(Debug, Clone, Default)]
+pub struct DistribOptions {
+    pub compression: Compression,
+    /// Leave out the genomes with fewer kmers in total (e.g. plasmid-only or
+    /// heavily masked entries), whose noisy fractions distort the
+    /// redistribution.
+    pub min_genome_kmers: u64,
+    /// Genomes to leave out, e.g. contaminated or retracted assemblies.
+    pub exclude_taxids: HashSet<u32>,
+    /// Check the genome and mapped taxids against this taxonomy folder
+    /// (nodes.dmp, and merged.dmp and delnodes.dmp if it has them).
+    pub taxonomy_dir: Option<PathBuf>,
+    pub invalid_taxids: InvalidTaxids,
+    /// Write the taxids that fail the check, with their issue, to this file.
+    pub taxid_issues: Option<PathBuf>,
+}
+
+/// Handling of the genome and mapped taxids that are missing from the
+/// taxonomy, merged or deleted, whose reads are not redistributed.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum InvalidTaxids {
+    /// Log how many there are and the first ones.
+    #[default]
+    Warn,
+    /// Fail before writing the kmer distribution.
+    Fail,
+}
+
+const TAXID_ISSUES_HEADER: &str = "taxid\trole\tissue";
+
+/// Taxids of the kmer distribution that are missing from the taxonomy of
+/// `taxonomy_dir`, merged or deleted, sorted, with their role (genome,
+/// mapped or both) and issue.
+fn taxid_issues<'a>(
+    taxonomy_dir: &Path,
+    genomes: impl Iterator<Item = &'a String>,
+    mapped: impl Iterator<Item = &'a String>,
+) -> Result<Vec<(u32, &'static str, String)>, DistribError> {
+    let taxo = taxonomy::load_taxonomy(taxonomy_dir.to_path_buf())?;
+    let merged_file = taxonomy_dir.join("merged.dmp");
+    let merged = if merged_file.exists() {
+        MergedTaxids::load_ncbi_dmp(&merged_file)?
+    } else {
+        MergedTaxids::default()
+    };
+    let delnodes_file = taxonomy_dir.join("delnodes.dmp");
+    let deleted = if delnodes_file.exists() {
+        taxonomy::load_deleted_taxids(&delnodes_file)?
+    } else {
+        HashSet::new()
+    };
+
+    let mut roles: BTreeMap<u32, &'static str> = BTreeMap::new();
+    for genome in genomes.filter_map(|taxid| taxid.parse().ok()) {
+        roles.insert(genome, "genome");
+    }
+    // Taxid 0 holds the unclassified kmers
+    for m_taxid in mapped.filter_map(|taxid| taxid.parse::<u32>().ok()) {
+        if m_taxid != 0 {
+            roles
+                .entry(m_taxid)
+                .and_modify(|role| *role = "both")
+                .or_insert("mapped");
+        }
+    }
+    Ok(roles
+        .into_iter()
+        .filter_map(|(taxid, role)| {
+            let issue = if deleted.contains(&taxid) {
+                "deleted".to_string()
+            } else if merged.remap(taxid) != taxid {
+                format!("merged into {}", merged.remap(taxid))
+            } else if taxo.get_node(&taxid).is_none() {
+                "missing".to_string()
+            } else {
+                return None;
+            };
+            Some((taxid, role, issue))
+        })
+        .collect())
+}
+
+/// Checks the taxids of the distribution against the taxonomy of
+/// `options`, writing the issues to `options.taxid_issues`.
+fn check_taxids(
+    genome_dict: &HashMap<String, HashMap<String, u64>>,
+    mapped_taxids_dict: &HashMap<String, HashMap<String, u64>>,
+    options: &DistribOptions,
+) -> Result<(), DistribError> {
+    let Some(taxonomy_dir) = &options.taxonomy_dir else {
+        return Ok(());
+    };
+    let issues = taxid_issues(taxonomy_dir, genome_dict.keys(), mapped_taxids_dict.keys())?;
+    if let Some(path) = &options.taxid_issues {
+        let tmp = database::tmp_path(path);
+        let write = || -> io::Result<()> {
+            let mut file = BufWriter::new(File::create(&tmp)?);
+            writeln!(file, "{}", TAXID_ISSUES_HEADER)?;
+            for (taxid, role, issue) in &issues {
+                writeln!(file, "{}\t{}\t{}", taxid, role, issue)?;
+            }
+            file.flush()
+        };
+        write().map_err(DistribError::io(&tmp))?;
+        database::rename_tmp(path)?;
+    }
+    if issues.is_empty() {
+        info!("...all genome and mapped taxids are in the taxonomy");
+        return Ok(());
+    }
+    let first: Vec<String> = issues
+        .iter()
+        .take(5)
+        .map(|(taxid, role, issue)| format!("{} ({}, {})", taxid, role, issue))
+        .collect();
+    warn!(
+        "{} genome or mapped taxids are not in the taxonomy, e.g. {}",
+        issues.len(),
+        first.join(", ")
+    );
+    match options.invalid_taxids {
+        InvalidTaxids::Warn => Ok(()),
+        InvalidTaxids::Fail => Err(DistribError::InvalidTaxids(issues.len())),
+    }
+}
+
+/// Number of ambiguous taxids listed in a [`DistribSummary`].
+const TOP_AMBIGUOUS: usize = 10;
+
+/// Statistics of a generated kmer distribution, to sanity-check a database.
+#[derive(Serialize, Debug, Default)]
+pub struct DistribSummary {
+    pub genomes: u32,
+    pub mapped_taxids: usize,
+    /// Kmers of all the genomes.
+    pub total_kmers: u64,
+    /// Mapped taxids drawing kmers from the most genomes.
+    pub top_ambiguous: Vec<AmbiguousTaxid>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct AmbiguousTaxid {
+    pub taxid: u32,
+    pub genomes: usize,
+}
+
+impl DistribSummary {
+    fn log(&self) {
+        info!("...{} genomes", self.genomes);
+        info!("...{} mapped taxids", self.mapped_taxids);
+        info!("...{} total genome kmers", self.total_kmers);
+        for ambiguous in &self.top_ambiguous {
+            info!(
+                "...ambiguous taxid {}: kmers from {} genomes",
+                ambiguous.taxid, ambiguous.genomes
+            );
+        }
+    }
+}
+
+/// Lengths in the `#` header line of a read distribution that must agree
+/// between the inputs of a kmer distribution.
+const CHECKED_LENGTHS: [&str; 2] = ["kmer_len", "read_len"];
+
+/// Converts the read distributions of kmer2read-distr (one line per read of
+/// every genome, or their binary records) into the kmer distribution file
+/// read by est-abundance. Several inputs (e.g. the partial outputs of a
+/// distributed conversion, or of libraries added later) are aggregated; the
+/// kmer and read lengths in their headers must agree. Returns the
+/// statistics of the written distribution.
+pub fn generate_kmer_distribution(
+    inputs: &[PathBuf],
+    output: &Path,
+    options: &DistribOptions,
+) -> Result<DistribSummary, DistribError> {
+    // 64-bit counters: the kmers of all the sequences of a taxid add up
+    let mut genome_dict: HashMap<String, HashMap<String, u64>> = HashMap::new();
+    let mut genome_dict_totalkmers: HashMap<String, u64> = HashMap::new();
+    let mut num_genomes = 0u32;
+    let mut add = |(genome_taxid, total_kmers, mapped_taxids_kmers): GenomeCounts| -> Result<(), DistribError> {
+        if genome_taxid == "0" {
+            return Ok(());
+        }
+
+        let counter = genome_dict_totalkmers
+            .entry(genome_taxid.clone())
+            .or_insert(0);
+        add_kmers(counter, total_kmers, &genome_taxid)?;
+        if !genome_dict.contains_key(&genome_taxid) {
+            num_genomes += 1;
+        }
+
+        let sub_map = genome_dict.entry(genome_taxid.clone()).or_default();
+        for (m_taxid, count) in mapped_taxids_kmers {
+            add_kmers(sub_map.entry(m_taxid).or_insert(0), count, &genome_taxid)?;
+        }
+        Ok(())
+    };
+
+    // Lengths of the first header giving them, with its input
+    let mut lengths: HashMap<String, (String, &Path)> = HashMap::new();
+    let mut provenance = Provenance::default();
+    for input in inputs {
+        let mut reader = database::open_maybe_compressed(input, "Reading read distributions")?;
+        let (binary, header) = {
+            let head = reader.fill_buf().map_err(DistribError::io(input))?;
+            (
+                head.starts_with(kmer2read_distr::COUNTS_MAGIC),
+                head.starts_with(b"# "),
+            )
+        };
+        if header {
+            let mut header = String::new();
+            reader
+                .read_line(&mut header)
+                .map_err(DistribError::io(input))?;
+            for (key, value) in header[2..]
+                .split_whitespace()
+                .filter_map(|field| field.split_once('='))
+            {
+                // A chunk of the inputs is not that of the output
+                if key != "chunk" {
+                    provenance.insert(key, value);
+                }
+                if !CHECKED_LENGTHS.contains(&key) {
+                    continue;
+                }
+                match lengths.get(key) {
+                    Some((first, first_input)) if first != value => {
+                        return Err(DistribError::MergeCounts(
+                            input.clone(),
+                            format!(
+                                "{} {} differs from {} in {:?}",
+                                key, value, first, first_input
+                            ),
+                        ));
+                    }
+                    Some(_) => {}
+                    None => {
+                        lengths.insert(key.to_string(), (value.to_string(), input));
+                    }
+                }
+            }
+        }
+        if binary {
+            reader.consume(kmer2read_distr::COUNTS_MAGIC.len());
+            let mut added = Ok(());
+            kmer2read_distr::read_binary_counts(reader, input, |genome_taxid, counts| {
+                if counts.is_empty() || added.is_err() {
+                    return;
+                }
+                let total_kmers = counts.iter().map(|&(_, count)| count).sum();
+                let mapped_taxids_kmers = counts
+                    .iter()
+                    .map(|&(taxid, count)| (taxid.to_string(), count))
+                    .collect();
+                added = add((genome_taxid.to_string(), total_kmers, mapped_taxids_kmers));
+            })?;
+            added?;
+        } else {
+            // 分块读取, 块内的行并行解析
+            let mut lines = reader.lines();
+            loop {
+                let chunk = lines
+                    .by_ref()
+                    .take(CHUNK_LINES)
+                    .collect::<Result<Vec<String>, _>>()
+                    .map_err(DistribError::io(input))?;
+                if chunk.is_empty() {
+                    break;
+                }
+                let parsed: Vec<_> = chunk
+                    .par_iter()
+                    .map(|line| parse_single_genome(line))
+                    .collect::<Result<_, _>>()?;
+                for genome in parsed {
+                    add(genome)?;
+                }
+            }
+        }
+    }
+    info!(
+        "...{} total genomes read from {} kraken output file(s)",
+        num_genomes,
+        inputs.len()
+    );
+    if !options.exclude_taxids.is_empty() {
+        genome_dict.retain(|genome, _| {
+            genome
+                .parse()
+                .map_or(true, |taxid| !options.exclude_taxids.contains(&taxid))
+        });
+        let excluded = num_genomes - genome_dict.len() as u32;
+        num_genomes -= excluded;
+        info!("...{} excluded genomes left out", excluded);
+    }
+    if options.min_genome_kmers > 0 {
+        genome_dict.retain(|genome, _| {
+            genome_dict_totalkmers.get(genome).copied().unwrap_or(0) >= options.min_genome_kmers
+        });
+        let excluded = num_genomes - genome_dict.len() as u32;
+        num_genomes -= excluded;
+        info!(
+            "...{} genomes with fewer than {} kmers excluded",
+            excluded, options.min_genome_kmers
+        );
+    }
+
+    let mut mapped_taxids_dict: HashMap<String, HashMap<String, u64>> = HashMap::new();
+    for (genome, sub_map) in &genome_dict {
+        for (m_taxid, count) in sub_map {
+            mapped_taxids_dict
+                .entry(m_taxid.clone())
+                .or_default()
+                .insert(genome.clone(), *count);
+        }
+    }
+
+    check_taxids(&genome_dict, &mapped_taxids_dict, options)?;
+
+    // Written aside and renamed once complete
+    let tmp = database::tmp_path(output);
+    let mut output_file = BufWriter::new(
+        CompressedWriter::create(&tmp, options.compression).map_err(DistribError::io(&tmp))?,
+    );
+    provenance.insert("version", env!("CARGO_PKG_VERSION"));
+    provenance.insert(
+        "date",
+        &Local::now().format("%Y-%m-%dT%H:%M:%S").to_string(),
+    );
+    writeln!(output_file, "{}", provenance.line()).map_err(DistribError::io(&tmp))?;
+    writeln!(output_file, "{}", TEXT_HEADER).map_err(DistribError::io(&tmp))?;
+
+    for (m_taxid, sub_map) in &mapped_taxids_dict {
+        let mut line = format!("{}\t", m_taxid); // 以基因组 ID 开头
+
+        for (genome_taxid, count) in sub_map {
+            let total_kmers = genome_dict_totalkmers.get(genome_taxid).unwrap_or(&0);
+            line.push_str(&format!("{}:{}:{} ", genome_taxid, count, total_kmers));
+            // 构建同一行的多个条目
+        }
+
+        writeln!(output_file, "{}", line.trim_end()).map_err(DistribError::io(&tmp))?;
+        // 写入整行，去除末尾的空格
+    }
+    output_file
+        .into_inner()
+        .map_err(io::IntoInnerError::into_error)
+        .and_then(CompressedWriter::finish)
+        .map_err(DistribError::io(&tmp))?;
+    database::rename_tmp(output)?;
+
+    // Taxid 0 holds the unclassified kmers
+    let mut ambiguous: Vec<AmbiguousTaxid> = mapped_taxids_dict
+        .iter()
+        .filter(|(m_taxid, sub_map)| sub_map.len() > 1 && m_taxid.as_str() != "0")
+        .filter_map(|(m_taxid, sub_map)| {
+            Some(AmbiguousTaxid {
+                taxid: m_taxid.parse().ok()?,
+                genomes: sub_map.len(),
+            })
+        })
+        .collect();
+    ambiguous.sort_unstable_by(|a, b| b.genomes.cmp(&a.genomes).then(a.taxid.cmp(&b.taxid)));
+    ambiguous.truncate(TOP_AMBIGUOUS);
+    let summary = DistribSummary {
+        genomes: num_genomes,
+        mapped_taxids: mapped_taxids_dict.len(),
+        total_kmers: genome_dict
+            .keys()
+            .filter_map(|genome| genome_dict_totalkmers.get(genome))
+            .sum(),
+        top_ambiguous: ambiguous,
+    };
+    summary.log();
+    Ok(summary)
+}
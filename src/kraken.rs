@@ -1,11 +1,54 @@
+use crate::database::DistribError;
+use crate::kmer_distrib::{self, MappedGenomes};
+use crate::taxonomy::{MergedTaxids, NCBINames};
+use crate::{progress, sampling};
 use core::str;
-use std::collections::HashMap;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::collections::{HashMap, HashSet};
 use std::fs::File;
-use std::io::{BufRead, BufReader};
-use std::path::PathBuf;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 
 pub const MAIN_LVLS: &[char; 9] = &['R', 'K', 'D', 'P', 'C', 'O', 'F', 'G', 'S'];
 
+/// Errors reading Kraken reports, taxid lists and levels.
+#[derive(Debug, thiserror::Error)]
+pub enum KrakenError {
+    #[error("{path:?}: {source}")]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    #[error("{0:?} is the Kraken default output; Bracken requires the Kraken report file (--report option with Kraken)")]
+    KrakenOutput(PathBuf),
+    #[error("{0:?} is an mpa-style report; Bracken requires the default Kraken report format")]
+    MpaReport(PathBuf),
+    #[error("malformed report line: {0}")]
+    MalformedLine(&'static str),
+    #[error("{path:?} line {line}: invalid taxid {value:?}")]
+    InvalidTaxid {
+        path: PathBuf,
+        line: usize,
+        value: String,
+    },
+    #[error("invalid level {level:?}: {reason}")]
+    InvalidLevel { level: String, reason: &'static str },
+    #[error("reports disagree on taxid {taxid}: {message}")]
+    TaxonomyMismatch { taxid: u32, message: String },
+}
+
+impl KrakenError {
+    fn io(path: &Path) -> impl FnOnce(io::Error) -> Self + '_ {
+        move |source| Self::Io {
+            path: path.to_path_buf(),
+            source,
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct Node {
     pub name: String,
@@ -18,11 +61,13 @@ pub struct Node {
     pub parent: Option<usize>,
 }
 
-impl Node {
-    pub fn from_str(curr_str: &str) -> Result<Self, &'static str> {
+impl FromStr for Node {
+    type Err = KrakenError;
+
+    fn from_str(curr_str: &str) -> Result<Self, Self::Err> {
         let split_str: Vec<&str> = curr_str.trim().split('\t').collect();
         if split_str.len() < 5 {
-            return Err("Input string has too few elements.");
+            return Err(KrakenError::MalformedLine("too few fields"));
         }
 
         let mut level_id = split_str[split_str.len() - 3].to_string();
@@ -32,7 +77,7 @@ impl Node {
 
         let lvl_reads = split_str[2]
             .parse::<usize>()
-            .map_err(|_| "Invalid number for lvl_reads")?;
+            .map_err(|_| KrakenError::MalformedLine("Invalid number for lvl_reads"))?;
 
         let mut name = split_str[split_str.len() - 1].to_string();
         let spaces = name.chars().take_while(|&c| c == ' ').count();
@@ -41,11 +86,11 @@ impl Node {
         // 尝试将读数转换为整数
         let all_reads = split_str[1]
             .parse::<usize>()
-            .map_err(|_| "Invalid number for all_reads")?;
+            .map_err(|_| KrakenError::MalformedLine("Invalid number for all_reads"))?;
 
         let taxid = split_str[split_str.len() - 2]
             .parse::<u32>()
-            .map_err(|_| "Invalid number for taxid")?;
+            .map_err(|_| KrakenError::MalformedLine("Invalid number for taxid"))?;
 
         // 计算层级编号
         let level_num = spaces / 2;
@@ -61,84 +106,509 @@ impl Node {
             parent: None,
         })
     }
+}
 
+impl Node {
     // 添加子节点
     pub fn add_child(&mut self, indx: usize) {
         self.children.push(indx);
     }
 }
 
+fn check_and_parse(input: &str) -> Result<usize, &'static str> {
+    let mut chars = input.chars();
+
+    // 检查第一个字符是否是字母
+    if let Some(first_char) = chars.next() {
+        if !first_char.is_alphabetic() {
+            return Err("The first character is not a letter.");
+        }
+    } else {
+        return Err("Input is empty.");
+    }
+
+    let remaining: String = chars.collect();
+
+    if remaining.is_empty() {
+        return Ok(0);
+    }
+
+    // 尝试将剩余的字符串解析为数字
+    remaining
+        .parse::<usize>()
+        .map_err(|_| "Failed to parse the remaining characters as a number.")
+}
+
+/// Parses a level such as "S" or "S1" into its branch (the number after the
+/// rank) and the index of the rank among the main levels.
+pub fn level_branch(level: &str) -> Result<(usize, usize), KrakenError> {
+    let invalid = |reason| KrakenError::InvalidLevel {
+        level: level.to_string(),
+        reason,
+    };
+    let branch = check_and_parse(level).map_err(invalid)?;
+
+    // 查找给定级别的索引
+    let branch_lvl = main_lvl_index(level).ok_or_else(|| invalid("unknown rank"))?;
+    Ok((branch, branch_lvl))
+}
+
+/// Checks that a file is a Kraken report rather than the per-read Kraken
+/// output or an mpa-style report.
+pub fn check_report_file(input_file: &PathBuf) -> Result<(), KrakenError> {
+    log::info!(">> Checking report file: {:?}", input_file);
+    let r_file = File::open(input_file).map_err(KrakenError::io(input_file))?;
+    let mut reader = BufReader::new(r_file);
+    let mut first_line = String::new();
+
+    // 读取第一行
+    if reader
+        .read_line(&mut first_line)
+        .map_err(KrakenError::io(input_file))?
+        == 0
+    {
+        log::warn!("File is empty");
+        return Ok(());
+    }
+
+    if let Some(first_char) = first_line.chars().next() {
+        if first_char == 'C' || first_char == 'U' {
+            return Err(KrakenError::KrakenOutput(input_file.clone()));
+        }
+    }
+
+    // 检查是否是 mpa 风格报告
+    if first_line.split('\t').count() == 2 {
+        return Err(KrakenError::MpaReport(input_file.clone()));
+    }
+
+    Ok(())
+}
+
+/// 读取 kraken report 的所有节点, 返回 (行号, 节点)
+pub fn read_kraken_report(input_file: &PathBuf) -> Result<Vec<(usize, Node)>, KrakenError> {
+    let r_file = File::open(input_file).map_err(KrakenError::io(input_file))?;
+    let len = r_file.metadata().map(|m| m.len()).unwrap_or(0);
+    let bar = progress::bytes(len, "Reading report");
+    let reader = BufReader::new(bar.wrap_read(r_file));
+
+    let mut report = Vec::new();
+    for (indx, line) in reader.lines().enumerate() {
+        let line = line.map_err(KrakenError::io(input_file))?;
+        if line.is_empty() || line.starts_with("#") || line.starts_with("%") {
+            continue;
+        }
+        if let Ok(node) = line.parse::<Node>() {
+            report.push((indx, node));
+        }
+    }
+    bar.finish_and_clear();
+    Ok(report)
+}
+
+/// Replaces the report names by the scientific names of the taxonomy; taxa
+/// missing from it keep their report name. Returns the number of names changed.
+pub fn apply_names(report: &mut [(usize, Node)], names: &NCBINames) -> usize {
+    let mut renamed = 0;
+    for (_, node) in report.iter_mut() {
+        if let Some(name) = names.get(&node.taxid) {
+            if node.name != name {
+                node.name = name.to_string();
+                renamed += 1;
+            }
+        }
+    }
+    renamed
+}
+
+/// Replaces the taxids of the report that were merged into other taxids.
+/// Returns the number of taxids remapped.
+pub fn remap_merged_taxids(report: &mut [(usize, Node)], merged: &MergedTaxids) -> usize {
+    let mut remapped = 0;
+    for (_, node) in report.iter_mut() {
+        let taxid = merged.remap(node.taxid);
+        if taxid != node.taxid {
+            log::info!(
+                "\tRemapped merged taxid {} ({}) to {}",
+                node.taxid,
+                node.name,
+                taxid
+            );
+            node.taxid = taxid;
+            remapped += 1;
+        }
+    }
+    remapped
+}
+
+/// Removes the subtrees rooted at the given taxids from the report and
+/// subtracts their reads from the clade counts of all ancestors.
+/// Returns the number of reads removed.
+pub fn exclude_subtrees(report: &mut Vec<(usize, Node)>, taxids: &HashSet<u32>) -> usize {
+    if taxids.is_empty() {
+        return 0;
+    }
+
+    let mut excluded_reads = 0;
+    let mut skip_level: Option<usize> = None;
+    // 祖先节点在 kept 中的位置
+    let mut ancestors: Vec<usize> = Vec::new();
+    let mut kept: Vec<(usize, Node)> = Vec::with_capacity(report.len());
+
+    for (indx, node) in report.drain(..) {
+        if let Some(lvl) = skip_level {
+            if node.level_num > lvl {
+                continue;
+            }
+            skip_level = None;
+        }
+        while let Some(&pos) = ancestors.last() {
+            if kept[pos].1.level_num < node.level_num {
+                break;
+            }
+            ancestors.pop();
+        }
+
+        if taxids.contains(&node.taxid) {
+            excluded_reads += node.all_reads;
+            for &pos in ancestors.iter() {
+                let ancestor = &mut kept[pos].1;
+                ancestor.all_reads = ancestor.all_reads.saturating_sub(node.all_reads);
+            }
+            skip_level = Some(node.level_num);
+            continue;
+        }
+
+        ancestors.push(kept.len());
+        kept.push((indx, node));
+    }
+
+    *report = kept;
+    excluded_reads
+}
+
+/// Keeps only the subtrees whose root satisfies `is_root`. Ancestors of the
+/// kept subtrees stay in the report to preserve the hierarchy, but lose their
+/// own reads and their clade counts are reduced to the kept subtrees.
+/// Returns the number of reads removed.
+pub fn restrict_to_subtrees<F>(report: &mut Vec<(usize, Node)>, is_root: F) -> usize
+where
+    F: Fn(&Node) -> bool,
+{
+    let mut keep = vec![false; report.len()];
+    let mut path_reads: Vec<Option<usize>> = vec![None; report.len()];
+    let mut skip_level: Option<usize> = None;
+    let mut ancestors: Vec<usize> = Vec::new();
+
+    for (pos, (_, node)) in report.iter().enumerate() {
+        if let Some(lvl) = skip_level {
+            if node.level_num > lvl {
+                keep[pos] = true;
+                continue;
+            }
+            skip_level = None;
+        }
+        while let Some(&a) = ancestors.last() {
+            if report[a].1.level_num < node.level_num {
+                break;
+            }
+            ancestors.pop();
+        }
+
+        if node.level_id == "U" || node.name == "unclassified" {
+            keep[pos] = true;
+        } else if is_root(node) {
+            keep[pos] = true;
+            skip_level = Some(node.level_num);
+            for &a in ancestors.iter() {
+                *path_reads[a].get_or_insert(0) += node.all_reads;
+            }
+        } else {
+            ancestors.push(pos);
+        }
+    }
+
+    let mut removed_reads = 0;
+    let mut kept = Vec::with_capacity(report.len());
+    for (pos, (indx, mut node)) in report.drain(..).enumerate() {
+        if keep[pos] {
+            kept.push((indx, node));
+        } else if let Some(all_reads) = path_reads[pos] {
+            removed_reads += node.lvl_reads;
+            node.lvl_reads = 0;
+            node.all_reads = all_reads;
+            kept.push((indx, node));
+        } else {
+            removed_reads += node.lvl_reads;
+        }
+    }
+
+    *report = kept;
+    removed_reads
+}
+
+/// Recomputes the clade counts (all_reads) of every node from the reads
+/// assigned directly to the node and its descendants.
+pub fn recompute_clade_reads(report: &mut [(usize, Node)]) {
+    let mut ancestors: Vec<usize> = Vec::new();
+    for pos in 0..report.len() {
+        let level_num = report[pos].1.level_num;
+        while let Some(&a) = ancestors.last() {
+            if report[a].1.level_num < level_num {
+                break;
+            }
+            ancestors.pop();
+        }
+        let lvl_reads = report[pos].1.lvl_reads;
+        report[pos].1.all_reads = lvl_reads;
+        for &a in ancestors.iter() {
+            report[a].1.all_reads += lvl_reads;
+        }
+        ancestors.push(pos);
+    }
+}
+
+/// Draws a bootstrap replicate of the report: the reads assigned to each node
+/// are resampled (multinomial) at the same total depth.
+pub fn resample_report<R: Rng>(report: &[(usize, Node)], rng: &mut R) -> Vec<(usize, Node)> {
+    let counts: Vec<f64> = report
+        .iter()
+        .map(|(_, node)| node.lvl_reads as f64)
+        .collect();
+    let total = report.iter().map(|(_, node)| node.lvl_reads).sum();
+    let sampled = sampling::multinomial(&counts, total, rng);
+
+    let mut replicate = report.to_vec();
+    for ((_, node), lvl_reads) in replicate.iter_mut().zip(sampled) {
+        node.lvl_reads = lvl_reads;
+    }
+    recompute_clade_reads(&mut replicate);
+    replicate
+}
+
+/// Rarefies the report to `depth` reads: the reads assigned to each node are
+/// subsampled without replacement. Reports with at most `depth` reads are
+/// returned unchanged.
+pub fn subsample_report<R: Rng>(
+    report: &[(usize, Node)],
+    depth: usize,
+    rng: &mut R,
+) -> Vec<(usize, Node)> {
+    let counts: Vec<usize> = report.iter().map(|(_, node)| node.lvl_reads).collect();
+    if counts.iter().sum::<usize>() <= depth {
+        return report.to_vec();
+    }
+    let sampled = sampling::hypergeometric(&counts, depth, rng);
+
+    let mut subsample = report.to_vec();
+    for ((_, node), lvl_reads) in subsample.iter_mut().zip(sampled) {
+        node.lvl_reads = lvl_reads;
+    }
+    recompute_clade_reads(&mut subsample);
+    subsample
+}
+
+/// Writes report nodes in the Kraken report format, indenting the names by
+/// their level and computing the percentages from the reads of all nodes.
+pub fn write_kraken_report<W: Write>(report: &[(usize, Node)], out: &mut W) -> io::Result<()> {
+    let total: usize = report.iter().map(|(_, node)| node.lvl_reads).sum();
+    for (_, node) in report {
+        writeln!(
+            out,
+            "{:6.2}\t{}\t{}\t{}\t{}\t{}{}",
+            100.0 * node.all_reads as f64 / total.max(1) as f64,
+            node.all_reads,
+            node.lvl_reads,
+            node.level_id,
+            node.taxid,
+            "  ".repeat(node.level_num),
+            node.name
+        )?;
+    }
+    Ok(())
+}
+
+/// A node of merged reports: its report line and the taxids of its parent
+/// and children.
+struct MergedNode {
+    node: Node,
+    parent: Option<u32>,
+    children: Vec<u32>,
+}
+
+/// Sums several reports into one tree. Nodes are matched by taxid, which
+/// must have the same rank code and parent in every report; the children of
+/// every node are ordered by decreasing clade reads, as Kraken does.
+pub fn merge_reports(reports: &[Vec<(usize, Node)>]) -> Result<Vec<(usize, Node)>, KrakenError> {
+    let mut merged: HashMap<u32, MergedNode> = HashMap::new();
+    let mut top_level: Vec<u32> = Vec::new();
+
+    for report in reports {
+        // 祖先节点的 taxid
+        let mut ancestors: Vec<(usize, u32)> = Vec::new();
+        for (_, node) in report {
+            while ancestors
+                .last()
+                .is_some_and(|&(level_num, _)| level_num >= node.level_num)
+            {
+                ancestors.pop();
+            }
+            let parent = ancestors.last().map(|&(_, taxid)| taxid);
+            ancestors.push((node.level_num, node.taxid));
+
+            match merged.get_mut(&node.taxid) {
+                Some(existing) => {
+                    if existing.node.level_id != node.level_id {
+                        return Err(KrakenError::TaxonomyMismatch {
+                            taxid: node.taxid,
+                            message: format!(
+                                "rank {} and {}",
+                                existing.node.level_id, node.level_id
+                            ),
+                        });
+                    }
+                    if existing.parent != parent {
+                        return Err(KrakenError::TaxonomyMismatch {
+                            taxid: node.taxid,
+                            message: format!("parent {:?} and {:?}", existing.parent, parent),
+                        });
+                    }
+                    existing.node.lvl_reads += node.lvl_reads;
+                }
+                None => {
+                    merged.insert(
+                        node.taxid,
+                        MergedNode {
+                            node: node.clone(),
+                            parent,
+                            children: Vec::new(),
+                        },
+                    );
+                    match parent {
+                        Some(parent) => merged
+                            .get_mut(&parent)
+                            .expect("parent node is merged before its children")
+                            .children
+                            .push(node.taxid),
+                        None => top_level.push(node.taxid),
+                    }
+                }
+            }
+        }
+    }
+
+    // 按深度优先顺序输出, 子节点按分支读数降序排列
+    let mut clade_reads: HashMap<u32, usize> = HashMap::new();
+    fn clade(
+        taxid: u32,
+        merged: &HashMap<u32, MergedNode>,
+        reads: &mut HashMap<u32, usize>,
+    ) -> usize {
+        let node = &merged[&taxid];
+        let total = node.node.lvl_reads
+            + node
+                .children
+                .iter()
+                .map(|&child| clade(child, merged, reads))
+                .sum::<usize>();
+        reads.insert(taxid, total);
+        total
+    }
+    for &taxid in top_level.iter() {
+        clade(taxid, &merged, &mut clade_reads);
+    }
+
+    let order = |taxids: &[u32]| {
+        let mut taxids = taxids.to_vec();
+        taxids.sort_by(|a, b| clade_reads[b].cmp(&clade_reads[a]).then(a.cmp(b)));
+        taxids
+    };
+    let mut roots = order(&top_level);
+    // 未分类节点在最前
+    roots.sort_by_key(|taxid| merged[taxid].node.level_id != "U");
+    let mut stack: Vec<(u32, usize)> = roots.into_iter().rev().map(|taxid| (taxid, 0)).collect();
+    let mut report = Vec::with_capacity(merged.len());
+    while let Some((taxid, level_num)) = stack.pop() {
+        let entry = &merged[&taxid];
+        let mut node = entry.node.clone();
+        node.level_num = level_num;
+        node.all_reads = clade_reads[&taxid];
+        report.push((report.len(), node));
+        for child in order(&entry.children).into_iter().rev() {
+            stack.push((child, level_num + 1));
+        }
+    }
+    Ok(report)
+}
+
 pub fn parse_kraken_report(
-    input_file: &PathBuf,
+    report: Vec<(usize, Node)>,
     level: &str,
     threshold: usize,
     branch: usize,
     branch_lvl: usize,
     stats: &mut Stats,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let r_file = File::open(&input_file)?;
-    let reader = BufReader::new(r_file);
-
+) -> Result<(), KrakenError> {
     let mut prev_node_index: usize = 0; // 使用索引而非引用
                                         // let mut leaf_nodes = Vec::new();
                                         // let mut nodes: HashMap<usize, Node> = HashMap::new();
-    for (indx, line) in reader.lines().enumerate().into_iter() {
-        let line = line?;
-        if line.is_empty() || line.starts_with("#") || line.starts_with("%") {
+    for (indx, mut node) in report {
+        stats.total_reads += node.lvl_reads;
+        if node.level_id == "U" || node.name == "unclassified" {
+            stats.u_reads = node.lvl_reads;
             continue;
         }
-        if let Ok(mut node) = Node::from_str(&line) {
-            stats.total_reads += node.lvl_reads;
-            if node.level_id == "U" || node.name == "unclassified" {
-                stats.u_reads = node.lvl_reads;
-                continue;
-            }
 
-            stats.nodes.insert(indx, node.clone());
-            if node.taxid == 1 {
-                prev_node_index = indx; // 保存索引
-                continue;
-            }
+        stats.nodes.insert(indx, node.clone());
+        if node.taxid == 1 {
+            prev_node_index = indx; // 保存索引
+            continue;
+        }
 
-            stats.nodes.get_mut(&indx).unwrap().parent = Some(prev_node_index);
+        stats.nodes.get_mut(&indx).unwrap().parent = Some(prev_node_index);
 
-            let mut prev_node = stats.nodes.get(&prev_node_index).unwrap();
-            if node.level_num != prev_node.level_num + 1 {
-                stats.leaf_nodes.push(prev_node.clone());
-            }
-            while node.level_num != prev_node.level_num + 1 {
-                if let Some(pre) = stats.nodes.get(&prev_node_index) {
-                    prev_node = pre;
-                    prev_node_index = pre.parent.expect("node has no parent");
-                } else {
-                    break;
+        let mut prev_node = stats.nodes.get(&prev_node_index).unwrap();
+        if node.level_num != prev_node.level_num + 1 {
+            stats.leaf_nodes.push(prev_node.clone());
+        }
+        // Move to correct parent
+        while node.level_num != prev_node.level_num + 1 {
+            match prev_node
+                .parent
+                .and_then(|p| stats.nodes.get(&p).map(|n| (p, n)))
+            {
+                Some((parent_index, parent)) => {
+                    prev_node_index = parent_index;
+                    prev_node = parent;
                 }
+                None => break,
             }
-
-            let (level_id, test_branch) = correct_level_id(&node, &prev_node);
-            abundance_est(
-                &node,
-                level,
-                threshold,
-                test_branch,
-                branch,
-                branch_lvl,
-                stats,
-            );
-            node.level_id = level_id;
-            stats
-                .nodes
-                .get_mut(&prev_node_index)
-                .unwrap()
-                .add_child(indx);
-            stats.nodes.get_mut(&indx).unwrap().parent = Some(prev_node_index);
-            prev_node_index = indx;
         }
+
+        let (level_id, test_branch) = correct_level_id(&node, prev_node);
+        abundance_est(
+            &node,
+            level,
+            threshold,
+            test_branch,
+            branch,
+            branch_lvl,
+            stats,
+        );
+        node.level_id = level_id;
+        stats
+            .nodes
+            .get_mut(&prev_node_index)
+            .unwrap()
+            .add_child(indx);
+        stats.nodes.get_mut(&indx).unwrap().parent = Some(prev_node_index);
+        prev_node_index = indx;
     }
 
-    stats
-        .leaf_nodes
-        .push(stats.nodes.get(&prev_node_index).unwrap().clone());
+    // 报告可能只有 unclassified 一行
+    if let Some(node) = stats.nodes.get(&prev_node_index) {
+        stats.leaf_nodes.push(node.clone());
+    }
     Ok(())
 }
 
@@ -169,29 +639,77 @@ fn correct_level_id(node: &Node, prev_node: &Node) -> (String, usize) {
 
 #[derive(Debug)]
 /// name,all_reads,level_reads,add_reads
-pub struct LvlValue(pub String, pub usize, pub usize, pub usize);
+pub struct LvlValue(pub String, pub usize, pub usize, pub f64);
 
 impl LvlValue {
     pub fn from_node(node: &Node) -> Self {
-        Self(node.name.clone(), node.all_reads, node.lvl_reads, 0)
+        Self(node.name.clone(), node.all_reads, node.lvl_reads, 0.0)
+    }
+
+    /// Kraken assigned reads plus the reads added by the redistribution.
+    pub fn new_est_reads(&self) -> f64 {
+        self.1 as f64 + self.3
     }
 }
 
 #[derive(Debug)]
-/// taxid,level_reads,add_reads
-pub struct Map2LvlValue(pub u32, pub usize, pub usize);
+/// lvl_taxid,level_reads,add_reads
+pub struct Map2LvlValue(pub u32, pub usize, pub f64);
 
 impl Map2LvlValue {
-    pub fn from_node(node: &Node) -> Self {
-        Self(node.taxid, node.lvl_reads, 0)
+    pub fn from_node(lvl_taxid: u32, node: &Node) -> Self {
+        Self(lvl_taxid, node.lvl_reads, 0.0)
     }
 }
 
-fn main_lvl_index(level_id: &str) -> usize {
-    MAIN_LVLS
-        .iter()
-        .position(|&x| x == level_id.chars().next().unwrap())
-        .unwrap()
+/// Index in MAIN_LVLS of the main rank of `level_id`, None for levels
+/// outside the main ranks (e.g. `-` of raw report levels).
+fn main_lvl_index(level_id: &str) -> Option<usize> {
+    let rank = level_id.chars().next()?;
+    MAIN_LVLS.iter().position(|&x| x == rank)
+}
+
+/// Why the reads of a node could not be redistributed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NondistributedReason {
+    /// No genome of the kmer distribution produces this classification.
+    NoGenomes,
+    /// The genomes producing this classification all belong to level taxa
+    /// below the threshold (or removed by filters).
+    NoKeptGenomes,
+    /// The kept genomes have no estimated reads or no kmers at this node.
+    ZeroProbability,
+}
+
+impl NondistributedReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::NoGenomes => "no_genomes",
+            Self::NoKeptGenomes => "no_kept_genomes",
+            Self::ZeroProbability => "zero_probability",
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct NondistributedNode {
+    pub taxid: u32,
+    pub name: String,
+    pub level_id: String,
+    pub reads: usize,
+    pub reason: NondistributedReason,
+}
+
+impl NondistributedNode {
+    fn new(node: &Node, reason: NondistributedReason) -> Self {
+        Self {
+            taxid: node.taxid,
+            name: node.name.clone(),
+            level_id: node.level_id.clone(),
+            reads: node.lvl_reads,
+            reason,
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -210,6 +728,13 @@ pub struct Stats {
     pub nodes: HashMap<usize, Node>,
     pub nondistributed_reads: usize,
     pub distributed_reads: usize,
+    pub excluded_reads: usize,
+    pub n_lvl_filtered: usize,
+    pub filtered_reads: usize,
+    /// Level taxa dropped by the threshold, with their kraken reads.
+    pub discarded_taxids: HashMap<u32, LvlValue>,
+    /// Nodes whose reads could not be redistributed, with the reason.
+    pub nondistributed_nodes: Vec<NondistributedNode>,
 }
 
 impl Default for Stats {
@@ -229,6 +754,11 @@ impl Default for Stats {
             nodes: HashMap::new(),
             nondistributed_reads: 0,
             distributed_reads: 0,
+            excluded_reads: 0,
+            n_lvl_filtered: 0,
+            filtered_reads: 0,
+            discarded_taxids: HashMap::new(),
+            nondistributed_nodes: Vec::new(),
         }
     }
 }
@@ -251,13 +781,16 @@ pub fn abundance_est(
         if node.all_reads < threshold {
             stats.n_lvl_del += 1;
             stats.ignored_reads += node.all_reads;
+            stats
+                .discarded_taxids
+                .insert(node.taxid, LvlValue::from_node(node));
             stats.last_taxid = -1;
         } else {
             stats.n_lvl_est += 1;
             stats.kept_reads += node.all_reads;
             stats
                 .lvl_taxids
-                .insert(node.taxid.clone(), LvlValue::from_node(node));
+                .insert(node.taxid, LvlValue::from_node(node));
             stats.last_taxid = node.taxid as isize; // 假设 taxid 是 usize，这里需要确保类型一致
             should_insert_map2lvl = true;
         }
@@ -265,85 +798,384 @@ pub fn abundance_est(
         if stats.last_taxid != -1 {
             should_insert_map2lvl = true;
         }
-    } else if main_lvl_index(&node.level_id) >= branch_lvl {
-        if stats.last_taxid != -1 {
-            should_insert_map2lvl = true;
-        }
+    } else if main_lvl_index(&node.level_id).is_some_and(|lvl| lvl >= branch_lvl)
+        && stats.last_taxid != -1
+    {
+        should_insert_map2lvl = true;
     }
 
     // 根据标志进行单次插入
     if should_insert_map2lvl {
-        stats
-            .map2lvl_taxids
-            .insert(node.taxid, Map2LvlValue::from_node(node));
+        stats.map2lvl_taxids.insert(
+            node.taxid,
+            Map2LvlValue::from_node(stats.last_taxid as u32, node),
+        );
+    }
+}
+
+/// 读取 taxid 列表文件, 每行第一列为 taxid, 忽略空行和 # 注释
+pub fn read_taxid_list(filename: &PathBuf) -> Result<HashSet<u32>, KrakenError> {
+    let file = File::open(filename).map_err(KrakenError::io(filename))?;
+    let reader = BufReader::new(file);
+    let mut taxids = HashSet::new();
+
+    for (line_num, line) in reader.lines().enumerate() {
+        let line = line.map_err(KrakenError::io(filename))?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if let Some(field) = line.split_whitespace().next() {
+            let taxid = field
+                .parse::<u32>()
+                .map_err(|_| KrakenError::InvalidTaxid {
+                    path: filename.clone(),
+                    line: line_num + 1,
+                    value: field.to_string(),
+                })?;
+            taxids.insert(taxid);
+        }
+    }
+    Ok(taxids)
+}
+
+/// Restricts the level taxa to those in `include` (if given) and drops those
+/// in `exclude`, together with the genomes mapped to them.
+pub fn filter_lvl_taxids(
+    stats: &mut Stats,
+    include: Option<&HashSet<u32>>,
+    exclude: &HashSet<u32>,
+) {
+    let removed: Vec<u32> = stats
+        .lvl_taxids
+        .keys()
+        .filter(|taxid| {
+            include.is_some_and(|include| !include.contains(taxid)) || exclude.contains(taxid)
+        })
+        .copied()
+        .collect();
+
+    for taxid in removed {
+        if let Some(value) = stats.lvl_taxids.remove(&taxid) {
+            stats.n_lvl_est -= 1;
+            stats.kept_reads -= value.1;
+            stats.n_lvl_filtered += 1;
+            stats.filtered_reads += value.1;
+        }
     }
+
+    let lvl_taxids = &stats.lvl_taxids;
+    stats
+        .map2lvl_taxids
+        .retain(|taxid, value| lvl_taxids.contains_key(&value.0) && !exclude.contains(taxid));
 }
 
+/// Fractions of the kmers of the genomes kept in `stats` mapped to the
+/// taxid of an entry, with merged taxids remapped.
 fn process_kmer_distribution(
-    curr_str: &str,
+    entry: MappedGenomes,
     stats: &Stats,
-) -> Option<(u32, HashMap<u32, Vec<f32>>)> {
-    let split_str: Vec<&str> = curr_str.trim().split('\t').collect();
+    merged: &MergedTaxids,
+    remapped: &mut HashMap<u32, u32>,
+) -> (u32, HashMap<u32, Vec<f32>>) {
     let mut temp_dict: HashMap<u32, Vec<f32>> = HashMap::new();
+    let mut remap = |taxid: u32| {
+        let new_taxid = merged.remap(taxid);
+        if new_taxid != taxid {
+            remapped.insert(taxid, new_taxid);
+        }
+        new_taxid
+    };
 
-    if let Ok(mapped_taxid) = split_str[0].parse::<u32>() {
-        if split_str.len() > 1 {
-            for genome_str in split_str[1].split_whitespace() {
-                let parts: Vec<&str> = genome_str.split(':').collect();
-                if parts.len() == 3 {
-                    if let (Ok(g_taxid), Ok(mkmers), Ok(tkmers)) = (
-                        parts[0].parse::<u32>(),
-                        parts[1].parse::<f32>(),
-                        parts[2].parse::<f32>(),
-                    ) {
-                        let fraction = mkmers / tkmers;
-                        if stats.lvl_taxids.contains_key(&g_taxid)
-                            || stats.map2lvl_taxids.contains_key(&g_taxid)
-                        {
-                            temp_dict
-                                .entry(g_taxid)
-                                .or_insert_with(Vec::new)
-                                .push(fraction);
-                        }
-                    }
-                }
-            }
+    for genome in entry.genomes {
+        let g_taxid = remap(genome.taxid);
+        let fraction = genome.kmers_mapped as f32 / genome.total_kmers as f32;
+        if stats.lvl_taxids.contains_key(&g_taxid) || stats.map2lvl_taxids.contains_key(&g_taxid) {
+            temp_dict.entry(g_taxid).or_default().push(fraction);
         }
-        return Some((mapped_taxid, temp_dict));
     }
-    None
+    (remap(entry.mapped_taxid), temp_dict)
 }
 
+/// Fractions of each genome's reads expected at each mapped taxid:
+/// mapped taxid -> genome taxid -> fractions.
+pub type KmerDistr = HashMap<u32, HashMap<u32, Vec<f32>>>;
+
+/// Reads the kmer distribution of the genomes kept in `stats`, remapping
+/// the taxids that were merged into other taxids. Mapped taxids whose
+/// genomes were all dropped keep an empty entry, which tells them apart from
+/// the taxids missing from the distribution.
 pub fn read_kmer_distribution(
-    filename: &PathBuf,
+    filename: &Path,
     stats: &Stats,
-) -> HashMap<u32, HashMap<u32, Vec<f32>>> {
-    let file = File::open(filename).expect("Unable to open file");
-    let reader = BufReader::new(file);
+    merged: &MergedTaxids,
+) -> Result<KmerDistr, DistribError> {
     let mut kmer_distr: HashMap<u32, HashMap<u32, Vec<f32>>> = HashMap::new();
+    let mut remapped = HashMap::new();
 
-    for line in reader.lines().skip(1) {
-        if let Ok(line) = line {
-            if let Some((mapped_taxid, mapped_taxid_dict)) = process_kmer_distribution(&line, stats)
-            {
-                if !mapped_taxid_dict.is_empty() {
-                    kmer_distr.insert(mapped_taxid, mapped_taxid_dict);
+    kmer_distrib::for_each_entry(filename, "Reading kmer distribution", |entry| {
+        let (mapped_taxid, mapped_taxid_dict) =
+            process_kmer_distribution(entry, stats, merged, &mut remapped);
+        let entry = kmer_distr.entry(mapped_taxid).or_default();
+        for (g_taxid, fractions) in mapped_taxid_dict {
+            entry.entry(g_taxid).or_default().extend(fractions);
+        }
+    })?;
+
+    for (taxid, new_taxid) in remapped.iter() {
+        log::info!(
+            "\tRemapped merged taxid {} of the kmer distribution to {}",
+            taxid,
+            new_taxid
+        );
+    }
+    Ok(kmer_distr)
+}
+
+/// Taxids listed in a kmer distribution file.
+#[derive(Debug, Default)]
+pub struct KmerDistrTaxids {
+    /// Taxids that reads are classified to (first column).
+    pub mapped: HashSet<u32>,
+    /// Genomes whose reads map to those taxids.
+    pub genomes: HashSet<u32>,
+}
+
+/// Reads the mapped taxids and genome taxids of a kmer distribution file,
+/// without filtering them by a report, remapping merged taxids.
+pub fn read_kmer_distribution_taxids(
+    filename: &Path,
+    merged: &MergedTaxids,
+) -> Result<KmerDistrTaxids, DistribError> {
+    let mut taxids = KmerDistrTaxids::default();
+    kmer_distrib::for_each_entry(filename, "Reading kmer distribution taxids", |entry| {
+        taxids.mapped.insert(merged.remap(entry.mapped_taxid));
+        taxids.genomes.extend(
+            entry
+                .genomes
+                .iter()
+                .map(|genome| merged.remap(genome.taxid)),
+        );
+    })?;
+    Ok(taxids)
+}
+
+/// A taxid on which the report and the kmer distribution disagree.
+#[derive(Debug)]
+pub struct KmerDistrMismatch {
+    pub taxid: u32,
+    pub name: String,
+    pub issue: &'static str,
+    /// Reads affected by the mismatch.
+    pub reads: usize,
+}
+
+/// Lists the mismatches between a parsed report and a kmer distribution:
+/// report nodes above `level` whose reads cannot be distributed because the
+/// distribution has no entry for them, level taxa without any genome in the
+/// distribution (they receive no reads), and genomes absent from the report.
+pub fn kmer_distribution_mismatches(
+    stats: &Stats,
+    level: &str,
+    taxids: &KmerDistrTaxids,
+) -> Vec<KmerDistrMismatch> {
+    let mut mismatches = Vec::new();
+    let level_index = main_lvl_index(level);
+    // Raw report levels such as `-` are no main rank above the level
+    let above_level = |level_id: &str| match (main_lvl_index(level_id), level_index) {
+        (Some(node_index), Some(level_index)) => node_index < level_index,
+        _ => false,
+    };
+
+    let mut report_taxids = HashSet::new();
+    for node in stats.nodes.values() {
+        report_taxids.insert(node.taxid);
+        if node.lvl_reads > 0 && above_level(&node.level_id) && !taxids.mapped.contains(&node.taxid)
+        {
+            mismatches.push(KmerDistrMismatch {
+                taxid: node.taxid,
+                name: node.name.clone(),
+                issue: "report_taxid_not_in_kmer_distr",
+                reads: node.lvl_reads,
+            });
+        }
+    }
+
+    let covered: HashSet<u32> = stats
+        .map2lvl_taxids
+        .iter()
+        .filter(|(genome, _)| taxids.genomes.contains(genome))
+        .map(|(_, value)| value.0)
+        .chain(
+            stats
+                .lvl_taxids
+                .keys()
+                .filter(|taxid| taxids.genomes.contains(taxid))
+                .copied(),
+        )
+        .collect();
+    for (taxid, value) in stats.lvl_taxids.iter() {
+        if !covered.contains(taxid) {
+            mismatches.push(KmerDistrMismatch {
+                taxid: *taxid,
+                name: value.0.clone(),
+                issue: "level_taxid_without_genome",
+                reads: value.1,
+            });
+        }
+    }
+
+    for genome in taxids.genomes.difference(&report_taxids) {
+        mismatches.push(KmerDistrMismatch {
+            taxid: *genome,
+            name: String::new(),
+            issue: "genome_not_in_report",
+            reads: 0,
+        });
+    }
+
+    mismatches.sort_by(|a, b| {
+        a.issue
+            .cmp(b.issue)
+            .then(b.reads.cmp(&a.reads))
+            .then(a.taxid.cmp(&b.taxid))
+    });
+    mismatches
+}
+
+/// Level taxa of the database genomes that received no reads. Genomes found
+/// in the report are listed under their ancestor at `level`; genomes absent
+/// from the report are listed under their own taxid, as the report carries
+/// no lineage for them.
+pub fn zero_count_taxa(
+    stats: &Stats,
+    genomes: &HashSet<u32>,
+    level: &str,
+) -> HashMap<u32, LvlValue> {
+    let taxid_index: HashMap<u32, usize> = stats
+        .nodes
+        .iter()
+        .map(|(index, node)| (node.taxid, *index))
+        .collect();
+
+    let mut zero_taxa = HashMap::new();
+    for genome in genomes {
+        let Some(index) = taxid_index.get(genome) else {
+            if !stats.lvl_taxids.contains_key(genome) {
+                zero_taxa.insert(*genome, LvlValue("unknown".to_string(), 0, 0, 0.0));
+            }
+            continue;
+        };
+        let mut current = stats.nodes.get(index);
+        while let Some(node) = current {
+            if node.level_id == level {
+                if !stats.lvl_taxids.contains_key(&node.taxid) {
+                    zero_taxa.insert(node.taxid, LvlValue(node.name.clone(), 0, 0, 0.0));
                 }
+                break;
             }
+            current = node.parent.and_then(|p| stats.nodes.get(&p));
         }
     }
-    kmer_distr
+    zero_taxa
+}
+
+/// Formula used to split a node's reads between the genomes below it.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DistributionMode {
+    /// Same as the original Bracken: each genome receives
+    /// `add_fraction * lvl_reads` reads.
+    #[default]
+    BrackenCompat,
+    /// Formula of earlier bracken-rust releases (`add_fraction / lvl_reads`,
+    /// truncated genome read estimates), kept to reproduce old results.
+    Legacy,
 }
 
+/// How the reads of a node are rounded to whole reads per genome.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Rounding {
+    /// Round down and hand the remaining reads to the genomes with the
+    /// largest remainders, so every node's reads are conserved exactly.
+    #[default]
+    LargestRemainder,
+    /// Truncate every genome's share independently (legacy behavior).
+    Truncate,
+    /// Keep fractional reads and truncate only in the output, like the
+    /// original Bracken.
+    None,
+    /// Assign every read to a genome by sampling (seeded), so the integer
+    /// counts match the model in expectation.
+    Stochastic,
+}
+
+/// Options of the read redistribution step.
+#[derive(Clone, Copy, Debug)]
+pub struct DistributionOptions {
+    pub mode: DistributionMode,
+    pub rounding: Rounding,
+    /// Maximum number of EM rounds (1 = single Bayesian pass).
+    pub em_iterations: usize,
+    /// Largest relative change of a genome estimate at which EM stops.
+    pub em_tolerance: f64,
+    /// Seed of the random number generator used by stochastic rounding.
+    pub seed: u64,
+}
+
+impl Default for DistributionOptions {
+    fn default() -> Self {
+        Self {
+            mode: DistributionMode::default(),
+            rounding: Rounding::default(),
+            em_iterations: 1,
+            em_tolerance: 1e-4,
+            seed: 0,
+        }
+    }
+}
+
+/// Largest-remainder rounding of `quotas` to integers summing to their
+/// rounded total. Ties are broken by taxid to keep the result deterministic.
+fn largest_remainder(quotas: Vec<(u32, f64)>) -> Vec<(u32, usize)> {
+    let total = quotas.iter().map(|q| q.1).sum::<f64>().round() as usize;
+    let mut rounded: Vec<(u32, usize, f64)> = quotas
+        .into_iter()
+        .map(|(genome, quota)| (genome, quota.floor() as usize, quota - quota.floor()))
+        .collect();
+    let assigned: usize = rounded.iter().map(|r| r.1).sum();
+    let mut remaining = total.saturating_sub(assigned);
+
+    rounded.sort_by(|a, b| b.2.total_cmp(&a.2).then(a.0.cmp(&b.0)));
+    for r in rounded.iter_mut() {
+        if remaining == 0 {
+            break;
+        }
+        r.1 += 1;
+        remaining -= 1;
+    }
+    rounded
+        .into_iter()
+        .map(|(genome, reads, _)| (genome, reads))
+        .collect()
+}
+
+/// Distributes the reads of every node above `level` to the genomes below it.
+/// `priors` overrides the estimated number of reads per genome, which by
+/// default is derived from the reads Kraken assigned uniquely to the genome.
 pub fn dfs_iterative(
     root_index: usize,
     stats: &mut Stats,
     level: &str,
-    kmer_distr: HashMap<u32, HashMap<u32, Vec<f32>>>,
+    kmer_distr: &HashMap<u32, HashMap<u32, Vec<f32>>>,
+    priors: Option<&HashMap<u32, f64>>,
+    options: &DistributionOptions,
 ) {
     let mut stack = vec![root_index];
+    let mut rng = StdRng::seed_from_u64(options.seed);
+    let bar = progress::items(stats.nodes.len() as u64, "Redistributing reads");
 
     while let Some(node_index) = stack.pop() {
+        bar.inc(1);
         if let Some(node) = stats.nodes.get(&node_index) {
             if node.level_id == level {
                 continue;
@@ -357,21 +1189,26 @@ pub fn dfs_iterative(
                 continue;
             }
             // No genomes produce this classification
-            if !kmer_distr.contains_key(&node.taxid) {
+            let Some(curr_dict) = kmer_distr.get(&node.taxid) else {
                 stats.nondistributed_reads += node.lvl_reads;
+                stats.nondistributed_nodes.push(NondistributedNode::new(
+                    node,
+                    NondistributedReason::NoGenomes,
+                ));
                 continue;
-            }
+            };
 
-            stats.distributed_reads += node.lvl_reads;
-            let curr_dict = kmer_distr.get(&node.taxid).unwrap();
-            let mut all_genome_reads = 0;
-            let mut probability_dict_prelim = HashMap::<u32, (f32, usize)>::new();
+            let mut all_genome_reads = 0.0;
+            let mut probability_dict_prelim = HashMap::<u32, (f32, f64)>::new();
             for (genome, value) in curr_dict {
                 // Get the fraction of kmers of the genome expected to map to this node
                 let fraction: f32 = value[0];
                 // Determine the number of reads classified by Kraken uniquely for the genome
                 // and the fraction of the genome that is unique
-                let num_classified_reads = stats.map2lvl_taxids.get(genome).unwrap().1;
+                let num_classified_reads = match stats.map2lvl_taxids.get(genome) {
+                    Some(value) => value.1,
+                    None => continue,
+                };
 
                 let lvl_fraction = if kmer_distr.contains_key(genome)
                     && kmer_distr.get(genome).unwrap().contains_key(genome)
@@ -381,11 +1218,28 @@ pub fn dfs_iterative(
                     1.0
                 };
 
-                let est_genome_reads = (num_classified_reads as f64 / lvl_fraction as f64) as usize;
+                let est_genome_reads = match (priors, options.mode) {
+                    (Some(priors), _) => priors.get(genome).copied().unwrap_or(0.0),
+                    (None, DistributionMode::BrackenCompat) => {
+                        num_classified_reads as f64 / lvl_fraction as f64
+                    }
+                    (None, DistributionMode::Legacy) => {
+                        ((num_classified_reads as f64 / lvl_fraction as f64) as usize) as f64
+                    }
+                };
                 all_genome_reads += est_genome_reads;
                 probability_dict_prelim.insert(*genome, (fraction, est_genome_reads));
             }
-            if all_genome_reads == 0 {
+            if all_genome_reads <= 0.0 {
+                let reason = if probability_dict_prelim.is_empty() {
+                    NondistributedReason::NoKeptGenomes
+                } else {
+                    NondistributedReason::ZeroProbability
+                };
+                stats.nondistributed_reads += node.lvl_reads;
+                stats
+                    .nondistributed_nodes
+                    .push(NondistributedNode::new(node, reason));
                 continue;
             }
             // # Get final probabilities
@@ -395,20 +1249,147 @@ pub fn dfs_iterative(
             let mut total_probability = 0.0;
             let mut probability_dict_final = HashMap::new();
             for (genome, value) in probability_dict_prelim.iter() {
-                let p_a = value.1 as f64 / all_genome_reads as f64;
+                let p_a = value.1 / all_genome_reads;
                 let p_a_r = value.0 as f64 * p_a;
-                probability_dict_final.insert(genome, p_a_r);
+                probability_dict_final.insert(*genome, p_a_r);
                 total_probability += p_a_r;
             }
+            if total_probability <= 0.0 {
+                stats.nondistributed_reads += node.lvl_reads;
+                stats.nondistributed_nodes.push(NondistributedNode::new(
+                    node,
+                    NondistributedReason::ZeroProbability,
+                ));
+                continue;
+            }
+            stats.distributed_reads += node.lvl_reads;
 
             // Find the normalize probabilty and Distribute reads accordingly
-            for (genome, value) in probability_dict_final.iter() {
-                let add_fraction = value / total_probability;
-                let add_reads = (add_fraction / node.lvl_reads as f64) as usize;
+            let quotas: Vec<(u32, f64)> = probability_dict_final
+                .into_iter()
+                .map(|(genome, value)| {
+                    let add_fraction = value / total_probability;
+                    let add_reads = match options.mode {
+                        DistributionMode::BrackenCompat => add_fraction * node.lvl_reads as f64,
+                        DistributionMode::Legacy => add_fraction / node.lvl_reads as f64,
+                    };
+                    (genome, add_reads)
+                })
+                .collect();
+            let rounded: Vec<(u32, f64)> = match options.rounding {
+                Rounding::None => quotas,
+                Rounding::Truncate => quotas
+                    .into_iter()
+                    .map(|(genome, add_reads)| (genome, add_reads.trunc()))
+                    .collect(),
+                Rounding::LargestRemainder => largest_remainder(quotas)
+                    .into_iter()
+                    .map(|(genome, add_reads)| (genome, add_reads as f64))
+                    .collect(),
+                Rounding::Stochastic => {
+                    let mut quotas = quotas;
+                    // 按 taxid 排序, 保证相同种子得到相同结果
+                    quotas.sort_by_key(|q| q.0);
+                    let weights: Vec<f64> = quotas.iter().map(|q| q.1).collect();
+                    let n = weights.iter().sum::<f64>().round() as usize;
+                    let sampled = sampling::multinomial(&weights, n, &mut rng);
+                    quotas
+                        .into_iter()
+                        .zip(sampled)
+                        .map(|((genome, _), add_reads)| (genome, add_reads as f64))
+                        .collect()
+                }
+            };
+            for (genome, add_reads) in rounded {
                 stats.map2lvl_taxids.get_mut(&genome).unwrap().2 += add_reads;
             }
         } else {
-            println!("Node with index {} not found.", node_index);
+            log::warn!("Node with index {} not found.", node_index);
+        }
+    }
+    bar.finish_and_clear();
+}
+
+/// For all genomes, map the added reads up to their level taxon.
+pub fn map_reads_to_level(stats: &mut Stats) {
+    for value in stats.map2lvl_taxids.values() {
+        if let Some(lvl_value) = stats.lvl_taxids.get_mut(&value.0) {
+            lvl_value.3 += value.2;
+        }
+    }
+}
+
+/// Sums the estimated reads of the level taxa into their ancestors at `rank`
+/// (e.g. species into genera), keyed by the ancestor's taxid. Level taxa
+/// without an ancestor at that rank are left out.
+pub fn roll_up_to_rank(stats: &Stats, rank: &str) -> HashMap<u32, LvlValue> {
+    let taxid_index: HashMap<u32, usize> = stats
+        .nodes
+        .iter()
+        .map(|(index, node)| (node.taxid, *index))
+        .collect();
+
+    let mut rank_taxids: HashMap<u32, LvlValue> = HashMap::new();
+    for (taxid, value) in stats.lvl_taxids.iter() {
+        let mut current = taxid_index.get(taxid).and_then(|i| stats.nodes.get(i));
+        while let Some(node) = current {
+            if node.level_id == rank {
+                let entry = rank_taxids
+                    .entry(node.taxid)
+                    .or_insert_with(|| LvlValue(node.name.clone(), 0, 0, 0.0));
+                entry.1 += value.1;
+                entry.2 += value.2;
+                entry.3 += value.3;
+                break;
+            }
+            current = node.parent.and_then(|p| stats.nodes.get(&p));
+        }
+    }
+    rank_taxids
+}
+
+/// Iterative EM redistribution: each round re-estimates the genome priors from
+/// the abundances of the previous round and redistributes all reads again,
+/// until the largest relative change of a genome estimate drops below
+/// `em_tolerance` or `em_iterations` rounds have run. Returns the number of rounds.
+pub fn em_redistribute(
+    root_index: usize,
+    stats: &mut Stats,
+    level: &str,
+    kmer_distr: &HashMap<u32, HashMap<u32, Vec<f32>>>,
+    options: &DistributionOptions,
+) -> usize {
+    dfs_iterative(root_index, stats, level, kmer_distr, None, options);
+
+    let mut iterations = 1;
+    while iterations < options.em_iterations {
+        let priors: HashMap<u32, f64> = stats
+            .map2lvl_taxids
+            .iter()
+            .map(|(taxid, value)| (*taxid, value.1 as f64 + value.2))
+            .collect();
+        let total: f64 = priors.values().sum();
+        if total <= 0.0 {
+            break;
+        }
+
+        for value in stats.map2lvl_taxids.values_mut() {
+            value.2 = 0.0;
+        }
+        stats.distributed_reads = 0;
+        stats.nondistributed_reads = 0;
+        stats.nondistributed_nodes.clear();
+        dfs_iterative(root_index, stats, level, kmer_distr, Some(&priors), options);
+        iterations += 1;
+
+        let max_change = stats
+            .map2lvl_taxids
+            .iter()
+            .map(|(taxid, value)| (value.1 as f64 + value.2 - priors[taxid]).abs() / total)
+            .fold(0.0, f64::max);
+        if max_change < options.em_tolerance {
+            break;
         }
     }
+    iterations
 }
@@ -0,0 +1,220 @@
+//! database.kraken generated by classifying the library sequences of a
+//! Kraken database with kraken2, like bracken-build does.
+
+use crate::database::{self, DistribError};
+use crate::seqid2taxid;
+use log::{info, warn};
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, Command, Stdio};
+
+/// Options of the kraken2 runs.
+#[derive(Debug, Clone)]
+pub struct Kraken2Options {
+    /// kraken2 executable.
+    pub binary: PathBuf,
+    /// Threads of every kraken2 run.
+    pub threads: usize,
+    /// Bytes of library FASTA classified by a kraken2 run.
+    pub chunk_size: u64,
+}
+
+/// kmer and minimizer lengths of a Kraken2 database.
+#[derive(Debug, Clone, Copy)]
+pub struct IndexOptions {
+    pub kmer_len: usize,
+    pub minimizer_len: usize,
+}
+
+/// Index options of the Kraken2 database in `database_dir`, read from its
+/// opts.k2d, or `None` if it has none (a Kraken 1 database).
+pub fn index_options(database_dir: &Path) -> Result<Option<IndexOptions>, DistribError> {
+    let path = database_dir.join("opts.k2d");
+    let bytes = match fs::read(&path) {
+        Ok(bytes) => bytes,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(DistribError::io(&path)(e)),
+    };
+    // The IndexOptions struct of Kraken2, dumped as is, starts with k and l
+    // as 64-bit integers, little-endian on the platforms Kraken2 supports
+    let field = |i: usize| {
+        bytes
+            .get(8 * i..8 * (i + 1))
+            .map(|field| u64::from_le_bytes(field.try_into().unwrap()) as usize)
+    };
+    match (field(0), field(1)) {
+        (Some(kmer_len), Some(minimizer_len))
+            if (1..=256).contains(&kmer_len) && (1..=kmer_len).contains(&minimizer_len) =>
+        {
+            Ok(Some(IndexOptions {
+                kmer_len,
+                minimizer_len,
+            }))
+        }
+        _ => Err(DistribError::IndexOptions(path)),
+    }
+}
+
+/// Folder of the classified chunks of a database, removed once they are
+/// merged into database.kraken.
+pub fn parts_dir(database_dir: &Path) -> PathBuf {
+    database_dir.join("database.kraken.parts")
+}
+
+/// A kraken2 run classifying a chunk of the library.
+struct ChunkRun {
+    index: usize,
+    child: Child,
+    stdin: BufWriter<ChildStdin>,
+    tmp: PathBuf,
+    output: PathBuf,
+    log: PathBuf,
+}
+
+impl ChunkRun {
+    fn start(
+        database_dir: &Path,
+        options: &Kraken2Options,
+        index: usize,
+        output: PathBuf,
+    ) -> Result<Self, DistribError> {
+        let tmp = output.with_extension("kraken.tmp");
+        let log = output.with_extension("log");
+        let stdout = File::create(&tmp).map_err(DistribError::io(&tmp))?;
+        let stderr = File::create(&log).map_err(DistribError::io(&log))?;
+        info!(">> Classifying chunk {} with {:?}", index, options.binary);
+        // kraken2 requires input files, the chunk is piped to /dev/stdin
+        let mut child = Command::new(&options.binary)
+            .arg("--db")
+            .arg(database_dir)
+            .arg("--threads")
+            .arg(options.threads.to_string())
+            .arg("/dev/stdin")
+            .stdin(Stdio::piped())
+            .stdout(stdout)
+            .stderr(stderr)
+            .spawn()
+            .map_err(DistribError::io(&options.binary))?;
+        let stdin = BufWriter::new(child.stdin.take().expect("piped stdin"));
+        Ok(Self {
+            index,
+            child,
+            stdin,
+            tmp,
+            output,
+            log,
+        })
+    }
+
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        self.stdin.write_all(line.as_bytes())?;
+        self.stdin.write_all(b"\n")
+    }
+
+    /// Waits for kraken2 and keeps its output if it succeeded.
+    fn finish(self) -> Result<(), DistribError> {
+        let Self {
+            index,
+            mut child,
+            mut stdin,
+            tmp,
+            output,
+            log,
+        } = self;
+        let flushed = stdin.flush();
+        drop(stdin);
+        let status = child.wait().map_err(DistribError::io(&log))?;
+        if !status.success() {
+            return Err(DistribError::Kraken2 {
+                chunk: index,
+                status: status.to_string(),
+                log,
+            });
+        }
+        flushed.map_err(DistribError::io(&tmp))?;
+        fs::rename(&tmp, &output).map_err(DistribError::io(&output))
+    }
+}
+
+/// Classifies the sequences of the FASTA files of `<database_dir>/library`
+/// with kraken2, in chunks of `options.chunk_size` bytes so an interrupted
+/// build resumes after the last classified chunk. Returns database.kraken.
+pub fn classify_library(
+    database_dir: &Path,
+    options: &Kraken2Options,
+) -> Result<PathBuf, DistribError> {
+    let library = database_dir.join("library");
+    let output = database_dir.join("database.kraken");
+    if !library.exists() {
+        return Err(DistribError::MissingInput(library));
+    }
+    let files = seqid2taxid::fasta_files(std::slice::from_ref(&library))?;
+    if files.is_empty() {
+        return Err(DistribError::MissingInput(library.join("*.fna")));
+    }
+
+    // Chunks of another chunk size hold other sequences
+    let parts = parts_dir(database_dir);
+    let chunk_size_file = parts.join("chunk_size");
+    let chunk_size = options.chunk_size.to_string();
+    if fs::read_to_string(&chunk_size_file).is_ok_and(|size| size.trim() != chunk_size) {
+        warn!(
+            "Discarding the chunks of {:?} classified with another chunk size",
+            parts
+        );
+        fs::remove_dir_all(&parts).map_err(DistribError::io(&parts))?;
+    }
+    fs::create_dir_all(&parts).map_err(DistribError::io(&parts))?;
+    fs::write(&chunk_size_file, &chunk_size).map_err(DistribError::io(&chunk_size_file))?;
+    let chunk_output = |index: usize| parts.join(format!("chunk{:05}.kraken", index));
+
+    let (mut n_chunks, mut chunk_bytes) = (0, 0);
+    let mut run: Option<ChunkRun> = None;
+    for file in files.iter() {
+        let reader = database::open_maybe_compressed(file, "Reading library")?;
+        for line in reader.lines() {
+            let line = line.map_err(DistribError::io(file))?;
+            if line.starts_with('>') && (n_chunks == 0 || chunk_bytes >= options.chunk_size) {
+                if let Some(run) = run.take() {
+                    run.finish()?;
+                }
+                let output = chunk_output(n_chunks);
+                if output.exists() {
+                    info!(">> Reusing chunk {}", n_chunks);
+                } else {
+                    run = Some(ChunkRun::start(database_dir, options, n_chunks, output)?);
+                }
+                n_chunks += 1;
+                chunk_bytes = 0;
+            }
+            chunk_bytes += line.len() as u64 + 1;
+            if let Some(current) = run.as_mut() {
+                if let Err(e) = current.write_line(&line) {
+                    // kraken2 exited early; its status explains why
+                    run.take().map(ChunkRun::finish).transpose()?;
+                    return Err(DistribError::io(file)(e));
+                }
+            }
+        }
+    }
+    if let Some(run) = run.take() {
+        run.finish()?;
+    }
+
+    info!(">> Merging {} chunks into {:?}", n_chunks, output);
+    let tmp = output.with_extension("kraken.tmp");
+    let merge = || -> io::Result<()> {
+        let mut merged = BufWriter::new(File::create(&tmp)?);
+        for index in 0..n_chunks {
+            io::copy(&mut File::open(chunk_output(index))?, &mut merged)?;
+        }
+        merged.flush()?;
+        fs::rename(&tmp, &output)
+    };
+    merge().map_err(DistribError::io(&output))?;
+    if let Err(e) = fs::remove_dir_all(&parts) {
+        warn!("Could not remove {:?}: {}", parts, e);
+    }
+    Ok(output)
+}
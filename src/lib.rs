@@ -1,4 +1,16 @@
+pub mod bracken_output;
+pub mod config;
 pub mod ctime;
+pub mod database;
+pub mod decontam;
+pub mod est_abundance;
+pub mod exit_code;
 pub mod kmer2read_distr;
+pub mod kmer_distrib;
 pub mod kraken;
+pub mod kraken2;
+pub mod logging;
+pub mod progress;
+pub mod sampling;
+pub mod seqid2taxid;
 pub mod taxonomy;
@@ -0,0 +1,20 @@
+use log::LevelFilter;
+use std::io::Write;
+
+/// 初始化日志: 进度和摘要输出到 stderr, stdout 只保留数据表
+///
+/// `verbose` 为 -v 的次数; `quiet` 只保留警告和错误.
+pub fn init(verbose: u8, quiet: bool) {
+    let level = match (quiet, verbose) {
+        (true, _) => LevelFilter::Warn,
+        (false, 0) => LevelFilter::Info,
+        (false, 1) => LevelFilter::Debug,
+        (false, _) => LevelFilter::Trace,
+    };
+    env_logger::Builder::new()
+        .filter_level(level)
+        .parse_env("RUST_LOG")
+        .target(env_logger::Target::Stderr)
+        .format(|buf, record| writeln!(buf, "{}", record.args()))
+        .init();
+}
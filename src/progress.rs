@@ -0,0 +1,48 @@
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static ENABLED: AtomicBool = AtomicBool::new(true);
+
+/// 开启或关闭进度条 (--no-progress); 进度条只在 stderr 是终端时显示
+pub fn init(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+fn new_bar(len: u64, template: &str, message: &'static str) -> ProgressBar {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return ProgressBar::hidden();
+    }
+    let bar = ProgressBar::with_draw_target(Some(len), ProgressDrawTarget::stderr());
+    if let Ok(style) = ProgressStyle::with_template(template) {
+        bar.set_style(style);
+    }
+    bar.set_message(message);
+    bar
+}
+
+/// Progress of reading a file of `len` bytes, with throughput and ETA.
+pub fn bytes(len: u64, message: &'static str) -> ProgressBar {
+    new_bar(
+        len,
+        "{msg} [{elapsed_precise}] {wide_bar} {bytes}/{total_bytes} ({bytes_per_sec}, ETA {eta})",
+        message,
+    )
+}
+
+/// Progress of processing `len` items, with ETA.
+pub fn items(len: u64, message: &'static str) -> ProgressBar {
+    new_bar(
+        len,
+        "{msg} [{elapsed_precise}] {wide_bar} {human_pos}/{human_len} (ETA {eta})",
+        message,
+    )
+}
+
+/// Progress of processing about `len` items, with their rate and ETA.
+pub fn counter(len: u64, message: &'static str) -> ProgressBar {
+    new_bar(
+        len,
+        "{msg} [{elapsed_precise}] {wide_bar} {human_pos}/{human_len} ({per_sec}, ETA {eta})",
+        message,
+    )
+}
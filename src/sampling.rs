@@ -0,0 +1,64 @@
+use rand::Rng;
+use rand_distr::{Binomial, Distribution, Hypergeometric};
+
+/// 按 weights 的比例做多项式抽样, 共抽取 n 次
+pub fn multinomial<R: Rng>(weights: &[f64], n: usize, rng: &mut R) -> Vec<usize> {
+    let mut remaining_n = n as u64;
+    let mut remaining_total: f64 = weights.iter().sum();
+    let mut sampled = Vec::with_capacity(weights.len());
+    // 最后一个正权重的类别取走剩余的次数, 浮点误差不会丢失抽样
+    let last = weights.iter().rposition(|&weight| weight > 0.0);
+
+    for (i, &weight) in weights.iter().enumerate() {
+        if Some(i) == last {
+            sampled.push(remaining_n as usize);
+            remaining_n = 0;
+            continue;
+        }
+        if remaining_n == 0 || weight <= 0.0 || remaining_total <= 0.0 {
+            sampled.push(0);
+            remaining_total -= weight.max(0.0);
+            continue;
+        }
+        let p = (weight / remaining_total).min(1.0);
+        let k = Binomial::new(remaining_n, p)
+            .map(|binomial| binomial.sample(rng))
+            .unwrap_or(0);
+        sampled.push(k as usize);
+        remaining_n -= k;
+        remaining_total -= weight;
+    }
+    sampled
+}
+
+/// 不放回地从 counts 中抽取 n 个计数 (多元超几何抽样)
+pub fn hypergeometric<R: Rng>(counts: &[usize], n: usize, rng: &mut R) -> Vec<usize> {
+    let mut remaining_n = n as u64;
+    let mut remaining_total: u64 = counts.iter().map(|&c| c as u64).sum();
+    let mut sampled = Vec::with_capacity(counts.len());
+
+    for &count in counts {
+        let count = count as u64;
+        if remaining_n == 0 || count == 0 {
+            sampled.push(0);
+            remaining_total -= count;
+            continue;
+        }
+        let k = Hypergeometric::new(remaining_total, count, remaining_n.min(remaining_total))
+            .map(|hypergeometric| hypergeometric.sample(rng))
+            .unwrap_or(0);
+        sampled.push(k as usize);
+        remaining_n -= k;
+        remaining_total -= count;
+    }
+    sampled
+}
+
+/// 取已排序样本的分位数 (最近秩法)
+pub fn percentile(sorted: &[f64], q: f64) -> f64 {
+    if sorted.is_empty() {
+        return 0.0;
+    }
+    let rank = (q * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
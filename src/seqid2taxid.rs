@@ -0,0 +1,301 @@
+//! seqid2taxid.map generated from the headers of library FASTA files, or
+//! from the assemblies they belong to, for databases whose Kraken build
+//! artifacts are not available.
+
+use crate::database::{open_maybe_compressed, DistribError};
+use log::{info, warn};
+use memmap2::Mmap;
+use rayon::prelude::*;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// First bytes of an accession index, followed by the number of records
+/// (u64, little endian) and the records sorted by accession.
+pub const INDEX_MAGIC: &[u8; 8] = b"BRKACC01";
+
+/// Bytes of the accession of a record, zero-padded. Longer accessions are
+/// not indexed.
+const KEY_LEN: usize = 20;
+/// Bytes of a record: the accession and the taxid (u32, little endian).
+const RECORD_LEN: usize = KEY_LEN + 4;
+const HEADER_LEN: usize = INDEX_MAGIC.len() + 8;
+
+/// Extensions of the FASTA files searched in folders.
+const FASTA_EXTENSIONS: &[&str] = &["fna", "fa", "fasta", "ffn", "fas"];
+
+/// FASTA files of `paths`, searching folders recursively for files with a
+/// FASTA extension (optionally gzip-compressed), in a stable order.
+pub fn fasta_files(paths: &[PathBuf]) -> Result<Vec<PathBuf>, DistribError> {
+    fn is_fasta(path: &Path) -> bool {
+        let path = match path.extension() {
+            Some(ext) if ext == "gz" => path.with_extension(""),
+            _ => path.to_path_buf(),
+        };
+        path.extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| FASTA_EXTENSIONS.contains(&ext))
+    }
+    fn walk(dir: &Path, files: &mut Vec<PathBuf>) -> Result<(), DistribError> {
+        let mut entries = fs::read_dir(dir)
+            .map_err(DistribError::io(dir))?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<io::Result<Vec<_>>>()
+            .map_err(DistribError::io(dir))?;
+        entries.sort();
+        for path in entries {
+            if path.is_dir() {
+                walk(&path, files)?;
+            } else if is_fasta(&path) {
+                files.push(path);
+            }
+        }
+        Ok(())
+    }
+
+    let mut files = Vec::new();
+    for path in paths {
+        if path.is_dir() {
+            walk(path, &mut files)?;
+        } else if path.exists() {
+            files.push(path.clone());
+        } else {
+            return Err(DistribError::MissingInput(path.clone()));
+        }
+    }
+    Ok(files)
+}
+
+/// Calls `f` on the sequence id (first word of the header) of every record
+/// of a FASTA file.
+pub fn for_each_seqid<F>(path: &Path, mut f: F) -> Result<(), DistribError>
+where
+    F: FnMut(&str),
+{
+    let mut reader = open_maybe_compressed(path, "Reading FASTA")?;
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if reader
+            .read_line(&mut line)
+            .map_err(DistribError::io(path))?
+            == 0
+        {
+            return Ok(());
+        }
+        if let Some(header) = line.strip_prefix('>') {
+            if let Some(seqid) = header.split_whitespace().next() {
+                f(seqid);
+            }
+        }
+    }
+}
+
+/// Taxid of a sequence id in the Kraken form `kraken:taxid|<taxid>|...`.
+pub fn kraken_taxid(seqid: &str) -> Option<u32> {
+    seqid
+        .strip_prefix("kraken:taxid|")?
+        .split('|')
+        .next()?
+        .parse()
+        .ok()
+}
+
+/// Accession of a sequence id, without its version: `NC_000913.3` and
+/// `gi|556503834|ref|NC_000913.3|` both give `NC_000913`.
+pub fn accession(seqid: &str) -> &str {
+    let accession = if seqid.contains('|') {
+        let fields: Vec<&str> = seqid.split('|').collect();
+        fields
+            .iter()
+            .position(|field| matches!(*field, "ref" | "gb" | "emb" | "dbj"))
+            .and_then(|ix| fields.get(ix + 1))
+            .or_else(|| fields.iter().rev().find(|field| !field.is_empty()))
+            .copied()
+            .unwrap_or(seqid)
+    } else {
+        seqid
+    };
+    match accession.rsplit_once('.') {
+        Some((base, version)) if version.bytes().all(|b| b.is_ascii_digit()) => base,
+        _ => accession,
+    }
+}
+
+fn key(accession: &str) -> Option<[u8; KEY_LEN]> {
+    let bytes = accession.as_bytes();
+    if bytes.is_empty() || bytes.len() > KEY_LEN {
+        return None;
+    }
+    let mut key = [0u8; KEY_LEN];
+    key[..bytes.len()].copy_from_slice(bytes);
+    Some(key)
+}
+
+/// Builds a sorted binary accession index from NCBI `*.accession2taxid`
+/// files (plain or gzip-compressed). Returns the number of accessions.
+pub fn build_accession_index(sources: &[PathBuf], index: &Path) -> Result<usize, DistribError> {
+    let mut records: Vec<([u8; KEY_LEN], u32)> = Vec::new();
+    let mut skipped = 0;
+    for source in sources {
+        info!(">> Reading accessions of {:?}", source);
+        let reader = open_maybe_compressed(source, "Reading accessions")?;
+        for line in reader.lines() {
+            let line = line.map_err(DistribError::io(source))?;
+            // accession, accession.version, taxid, gi
+            let mut fields = line.split('\t');
+            let (Some(accession), Some(taxid)) = (fields.next(), fields.nth(1)) else {
+                continue;
+            };
+            match (key(accession), taxid.trim().parse::<u32>()) {
+                (Some(key), Ok(taxid)) => records.push((key, taxid)),
+                // the header line
+                _ if accession == "accession" => {}
+                _ => skipped += 1,
+            }
+        }
+    }
+    if skipped > 0 {
+        warn!(
+            "{} accessions not indexed (malformed or longer than {} bytes)",
+            skipped, KEY_LEN
+        );
+    }
+    records.par_sort_unstable_by(|a, b| a.0.cmp(&b.0));
+    records.dedup_by(|a, b| a.0 == b.0);
+
+    let tmp = index.with_extension("tmp");
+    let write = || -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(&tmp)?);
+        out.write_all(INDEX_MAGIC)?;
+        out.write_all(&(records.len() as u64).to_le_bytes())?;
+        for (key, taxid) in records.iter() {
+            out.write_all(key)?;
+            out.write_all(&taxid.to_le_bytes())?;
+        }
+        out.flush()?;
+        fs::rename(&tmp, index)
+    };
+    write().map_err(DistribError::io(index))?;
+    Ok(records.len())
+}
+
+/// Memory-mapped accession index written by [`build_accession_index`].
+pub struct AccessionIndex {
+    mmap: Mmap,
+    len: usize,
+}
+
+impl AccessionIndex {
+    pub fn open(path: &Path) -> Result<Self, DistribError> {
+        let file = File::open(path).map_err(DistribError::io(path))?;
+        let mmap = unsafe { Mmap::map(&file) }.map_err(DistribError::io(path))?;
+        let invalid = |reason| DistribError::AccessionIndex(path.to_path_buf(), reason);
+        if mmap.len() < HEADER_LEN || &mmap[..INDEX_MAGIC.len()] != INDEX_MAGIC {
+            return Err(invalid("bad header"));
+        }
+        let mut count = [0u8; 8];
+        count.copy_from_slice(&mmap[INDEX_MAGIC.len()..HEADER_LEN]);
+        let len = u64::from_le_bytes(count) as usize;
+        if mmap.len() != HEADER_LEN + len * RECORD_LEN {
+            return Err(invalid("truncated"));
+        }
+        Ok(Self { mmap, len })
+    }
+
+    /// Number of indexed accessions.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn record(&self, ix: usize) -> &[u8] {
+        let start = HEADER_LEN + ix * RECORD_LEN;
+        &self.mmap[start..start + RECORD_LEN]
+    }
+
+    /// Taxid of an accession (without version).
+    pub fn get(&self, accession: &str) -> Option<u32> {
+        let key = key(accession)?;
+        let (mut lo, mut hi) = (0, self.len);
+        while lo < hi {
+            let mid = lo + (hi - lo) / 2;
+            let record = self.record(mid);
+            match record[..KEY_LEN].cmp(&key) {
+                std::cmp::Ordering::Less => lo = mid + 1,
+                std::cmp::Ordering::Greater => hi = mid,
+                std::cmp::Ordering::Equal => {
+                    let mut taxid = [0u8; 4];
+                    taxid.copy_from_slice(&record[KEY_LEN..]);
+                    return Some(u32::from_le_bytes(taxid));
+                }
+            }
+        }
+        None
+    }
+}
+
+/// Whether `index` is missing or older than one of its `sources`.
+pub fn index_is_stale(index: &Path, sources: &[PathBuf]) -> bool {
+    let modified = |path: &Path| fs::metadata(path).and_then(|m| m.modified()).ok();
+    match modified(index) {
+        Some(index_time) => sources
+            .iter()
+            .any(|source| modified(source).is_none_or(|time| time > index_time)),
+        None => true,
+    }
+}
+
+/// Assembly accession (`GCF_000005845.2` or `GCA_...`, without version) in
+/// the name of a file or of one of its folders, nearest first.
+pub fn assembly_accession(path: &Path) -> Option<String> {
+    path.iter().rev().find_map(|component| {
+        let name = component.to_str()?;
+        let start = name.find("GCF_").or_else(|| name.find("GCA_"))?;
+        let digits = name.get(start + 4..start + 13)?;
+        digits
+            .bytes()
+            .all(|b| b.is_ascii_digit())
+            .then(|| name[start..start + 13].to_string())
+    })
+}
+
+/// Taxids of the assemblies of RefSeq/GenBank `assembly_summary.txt` files,
+/// keyed by assembly accession without version: the `species_taxid` column
+/// if `species` is set, else the (strain) `taxid` column.
+pub fn read_assembly_summary(
+    paths: &[PathBuf],
+    species: bool,
+) -> Result<HashMap<String, u32>, DistribError> {
+    let column = if species { 6 } else { 5 };
+    let mut taxids = HashMap::new();
+    for path in paths {
+        let reader = open_maybe_compressed(path, "Reading assembly summary")?;
+        let mut malformed = 0;
+        for line in reader.lines() {
+            let line = line.map_err(DistribError::io(path))?;
+            if line.starts_with('#') || line.trim().is_empty() {
+                continue;
+            }
+            let fields: Vec<&str> = line.split('\t').collect();
+            let accession = fields[0].split('.').next().unwrap_or_default();
+            match fields
+                .get(column)
+                .and_then(|taxid| taxid.parse::<u32>().ok())
+            {
+                Some(taxid) if accession.len() == 13 => {
+                    taxids.insert(accession.to_string(), taxid);
+                }
+                _ => malformed += 1,
+            }
+        }
+        if malformed > 0 {
+            warn!("{} malformed lines of {:?} skipped", malformed, path);
+        }
+    }
+    Ok(taxids)
+}
@@ -1,50 +1,55 @@
+use dashmap::DashMap;
 use memmap2::Mmap;
 use serde::{Deserialize, Serialize};
-use serde_json;
-use std::collections::HashMap;
-use std::error::Error;
-use std::fmt::{self, Debug};
+use std::collections::{HashMap, HashSet};
+use std::fmt::Debug;
 use std::fs::File;
 use std::hash::Hash;
-use std::io::{BufRead, BufReader, Write};
+use std::io::{self, BufRead, BufReader, Write};
 use std::path::{Path, PathBuf};
 
-#[derive(Debug, Clone)]
-pub struct TaxonomyError {
-    pub message: String,
+/// Errors loading or saving the taxonomy.
+#[derive(Debug, thiserror::Error)]
+pub enum TaxonomyError {
+    #[error("no taxonomy nodes file (nodes.json or nodes.dmp) found in {0:?}")]
+    MissingNodes(PathBuf),
+    #[error("unsupported taxonomy file format: {0:?}")]
+    UnsupportedFormat(PathBuf),
+    #[error("{path:?}: {source}")]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    #[error("{path:?} line {line}: {message}")]
+    Parse {
+        path: PathBuf,
+        line: usize,
+        message: String,
+    },
+    #[error("{path:?}: {source}")]
+    Json {
+        path: PathBuf,
+        #[source]
+        source: serde_json::Error,
+    },
+    #[error("taxid {0} should not be equal to its parent unless it's 1")]
+    SelfParent(u32),
 }
 
 impl TaxonomyError {
-    pub fn new(message: &str) -> Self {
-        TaxonomyError {
-            message: message.to_string(),
+    fn io(path: &Path) -> impl FnOnce(io::Error) -> Self + '_ {
+        move |source| Self::Io {
+            path: path.to_path_buf(),
+            source,
         }
     }
-}
-
-impl fmt::Display for TaxonomyError {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "TaxonomyError: {}", self.message)
-    }
-}
-
-impl Error for TaxonomyError {}
-
-impl From<std::io::Error> for TaxonomyError {
-    fn from(_: std::io::Error) -> Self {
-        TaxonomyError::new("io Error: Failed to handle nodes file")
-    }
-}
-
-impl From<std::num::ParseIntError> for TaxonomyError {
-    fn from(_: std::num::ParseIntError) -> Self {
-        TaxonomyError::new("Failed to parse nodes file line")
-    }
-}
 
-impl From<serde_json::Error> for TaxonomyError {
-    fn from(_: serde_json::Error) -> Self {
-        TaxonomyError::new("Failed to handle serde_json error")
+    fn json(path: &Path) -> impl FnOnce(serde_json::Error) -> Self + '_ {
+        move |source| Self::Json {
+            path: path.to_path_buf(),
+            source,
+        }
     }
 }
 
@@ -57,6 +62,15 @@ where
     backward: HashMap<T, T>,
 }
 
+impl<T> Default for BiMap<T>
+where
+    T: Debug + Copy + Eq + PartialEq + Hash,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl<T> BiMap<T>
 where
     T: Debug + Copy + Eq + PartialEq + Hash,
@@ -94,9 +108,7 @@ pub struct TaxonomyNode {
 impl TaxonomyNode {
     fn new(taxid: u32, parent: u32, rank: String, depth: u32) -> Result<Self, TaxonomyError> {
         if taxid != 1 && taxid == parent {
-            return Err(TaxonomyError::new(
-                "taxid should not be equal to parent unless it's 1",
-            ));
+            return Err(TaxonomyError::SelfParent(taxid));
         }
 
         Ok(Self {
@@ -119,26 +131,24 @@ impl Default for TaxonomyNode {
     }
 }
 
-#[derive(Serialize, Deserialize)]
+/// Pairs of taxa whose lowest common ancestor is kept once computed.
+const LCA_CACHE_SIZE: usize = 1 << 16;
+
+#[derive(Default, Serialize, Deserialize)]
 pub struct NCBITaxonomy {
     pub nodes: Vec<TaxonomyNode>,
     pub id_map: BiMap<u32>,
-}
-
-impl Default for NCBITaxonomy {
-    fn default() -> Self {
-        Self {
-            nodes: Vec::new(),
-            id_map: BiMap::new(),
-        }
-    }
+    /// Lowest common ancestors already computed, by ordered pair of taxa.
+    #[serde(skip)]
+    lca_cache: DashMap<(u32, u32), u32>,
 }
 
 impl NCBITaxonomy {
     pub fn save_to_file<P: AsRef<Path>>(&self, path: P) -> Result<(), TaxonomyError> {
-        let mut file = File::create(path)?;
-        let json = serde_json::to_string(self)?;
-        writeln!(file, "{}", json)?;
+        let path = path.as_ref();
+        let mut file = File::create(path).map_err(TaxonomyError::io(path))?;
+        let json = serde_json::to_string(self).map_err(TaxonomyError::json(path))?;
+        writeln!(file, "{}", json).map_err(TaxonomyError::io(path))?;
         Ok(())
     }
 
@@ -192,41 +202,132 @@ impl NCBITaxonomy {
             return if a != 0 { a } else { b };
         }
 
-        let na = self.get_node(&a).unwrap();
-        let nb = self.get_node(&b).unwrap();
-
-        let path_a = &na.path_to_root;
-        let path_b = &nb.path_to_root;
-
-        let mut i = 0;
-        while i < path_a.len() && i < path_b.len() && path_a[i] == path_b[i] {
-            i += 1;
+        let key = (a.min(b), a.max(b));
+        if let Some(lca) = self.lca_cache.get(&key) {
+            return *lca;
+        }
+        let lca = self.path_lca(a, b);
+        if self.lca_cache.len() < LCA_CACHE_SIZE {
+            self.lca_cache.insert(key, lca);
         }
+        lca
+    }
 
-        if i == 0 {
+    fn path_lca(&self, a: u32, b: u32) -> u32 {
+        // Taxa missing from the taxonomy (or pruned away) have none
+        let (Some(na), Some(nb)) = (self.get_node(&a), self.get_node(&b)) else {
             return 0;
+        };
+
+        // 路径包含节点本身, 祖先与其后代的共同祖先是祖先本身
+        fn path(node: &TaxonomyNode) -> impl Iterator<Item = u32> + '_ {
+            let own = (node.path_to_root.last() != Some(&node.taxid)).then_some(node.taxid);
+            node.path_to_root.iter().copied().chain(own)
         }
 
         // 返回最后一个共同的祖先
-        *path_a.get(i - 1).unwrap_or(&0)
+        path(na)
+            .zip(path(nb))
+            .take_while(|(a, b)| a == b)
+            .last()
+            .map_or(0, |(taxid, _)| taxid)
+    }
+
+    /// Ancestor of a taxon (or the taxon itself) whose rank is one of the
+    /// NCBI ranks of a report rank code (see [`ncbi_ranks`]).
+    pub fn ancestor_at_rank(&self, taxid: u32, rank: &str) -> Option<u32> {
+        let ranks = ncbi_ranks(rank)?;
+        let node = self.get_node(&taxid)?;
+        std::iter::once(&node.taxid)
+            .chain(node.path_to_root.iter().rev())
+            .find(|&&ancestor| {
+                self.get_node(&ancestor)
+                    .is_some_and(|node| ranks.contains(&node.rank.as_str()))
+            })
+            .copied()
+    }
+
+    /// Taxa from the root down to the taxon, included.
+    pub fn lineage(&self, taxid: u32) -> Option<Vec<u32>> {
+        let node = self.get_node(&taxid)?;
+        let mut lineage = node.path_to_root.clone();
+        if lineage.last() != Some(&taxid) {
+            lineage.push(taxid);
+        }
+        Some(lineage)
+    }
+
+    /// Direct children of a taxon.
+    pub fn children(&self, taxid: u32) -> Vec<u32> {
+        self.nodes
+            .iter()
+            .filter(|node| node.parent == taxid && node.taxid != taxid)
+            .map(|node| node.taxid)
+            .collect()
+    }
+
+    /// All descendants of a taxon, excluding the taxon.
+    pub fn descendants(&self, taxid: u32) -> Vec<u32> {
+        self.nodes
+            .iter()
+            .filter(|node| node.taxid != taxid && node.path_to_root.contains(&taxid))
+            .map(|node| node.taxid)
+            .collect()
+    }
+
+    /// The taxonomy of `taxids` and their ancestors only, with the same paths
+    /// to the root; taxids missing from the taxonomy are left out.
+    pub fn prune(self, taxids: impl IntoIterator<Item = u32>) -> NCBITaxonomy {
+        let mut keep = HashSet::new();
+        for taxid in taxids {
+            if let Some(node) = self.get_node(&taxid) {
+                if keep.insert(taxid) {
+                    keep.extend(node.path_to_root.iter().copied());
+                }
+            }
+        }
+        let total = self.nodes.len();
+        let mut pruned = NCBITaxonomy::default();
+        for node in self.nodes {
+            if keep.contains(&node.taxid) {
+                pruned.id_map.insert(node.taxid, pruned.nodes.len() as u32);
+                pruned.nodes.push(node);
+            }
+        }
+        log::info!(
+            "\t\t{:?} of {:?} nodes kept for the taxa of the sequences",
+            pruned.nodes.len(),
+            total
+        );
+        pruned
     }
 
     pub fn load_ncbi_dmp<P: AsRef<Path>>(node_file: P) -> Result<NCBITaxonomy, TaxonomyError> {
-        let nodes_file = std::fs::File::open(node_file)?;
+        let path = node_file.as_ref();
+        let nodes_file = std::fs::File::open(path).map_err(TaxonomyError::io(path))?;
 
         let mut ncbi_taxo = NCBITaxonomy::default();
 
         for (ix, line) in BufReader::new(nodes_file).lines().enumerate() {
-            let line = line?;
+            let line = line.map_err(TaxonomyError::io(path))?;
+            let parse_error = |message: &str| TaxonomyError::Parse {
+                path: path.to_path_buf(),
+                line: ix + 1,
+                message: message.to_string(),
+            };
             let fields: Vec<_> = line.split("\t|\t").collect();
             if fields.len() < 10 {
                 // should be at least 14
-                return Err(TaxonomyError::new(
-                    "Not enough fields in nodes.dmp; bad line?",
-                ));
+                return Err(parse_error("not enough fields in nodes.dmp"));
             }
-            let taxid = fields[0].trim().parse::<u32>()?;
-            let parent = fields[1].trim().parse::<u32>()?;
+            let taxid = fields[0]
+                .trim()
+                .parse::<u32>()
+                .map_err(|_| parse_error("invalid taxid"))?;
+            let parent = fields[1]
+                .trim()
+                .parse::<u32>()
+                .map_err(|_| parse_error("invalid parent taxid"))?;
             let rank = fields[2].trim().to_string();
 
             let depth = if taxid == 1 { 1 } else { 0 };
@@ -241,13 +342,13 @@ impl NCBITaxonomy {
     }
 
     pub fn load_from_json<P: AsRef<Path>>(path: P) -> Result<Self, TaxonomyError> {
-        let file = File::open(path).map_err(|e| TaxonomyError::new(&e.to_string()))?;
+        let path = path.as_ref();
+        let file = File::open(path).map_err(TaxonomyError::io(path))?;
         // let reader = BufReader::new(file);
 
-        let mmap = unsafe { Mmap::map(&file) }.map_err(|e| TaxonomyError::new(&e.to_string()))?;
+        let mmap = unsafe { Mmap::map(&file) }.map_err(TaxonomyError::io(path))?;
 
-        let taxo =
-            serde_json::from_slice(&mmap[..]).map_err(|e| TaxonomyError::new(&e.to_string()))?;
+        let taxo = serde_json::from_slice(&mmap[..]).map_err(TaxonomyError::json(path))?;
 
         // 直接从 Buffered Reader 反序列化 JSON 数据
         // let taxo =
@@ -259,16 +360,173 @@ impl NCBITaxonomy {
         match path.as_ref().extension().and_then(|s| s.to_str()) {
             Some("json") => Self::load_from_json(path),
             Some("dmp") => Self::load_ncbi_dmp(path),
-            _ => Err(TaxonomyError::new("Unsupported file format")),
+            _ => Err(TaxonomyError::UnsupportedFormat(
+                path.as_ref().to_path_buf(),
+            )),
+        }
+    }
+}
+
+/// NCBI ranks of a Kraken report rank code (D, P, C, O, F, G, S, ...).
+pub fn ncbi_ranks(rank: &str) -> Option<&'static [&'static str]> {
+    let ranks: &'static [&'static str] = match rank {
+        "K" => &["kingdom"],
+        // Viruses are an "acellular root" since the 2025 NCBI ranks
+        "D" => &["superkingdom", "domain", "acellular root"],
+        "P" => &["phylum"],
+        "C" => &["class"],
+        "O" => &["order"],
+        "F" => &["family"],
+        "G" => &["genus"],
+        "S" => &["species"],
+        _ => return None,
+    };
+    Some(ranks)
+}
+
+/// Scientific names of the taxa, read from an NCBI `names.dmp` file.
+#[derive(Default)]
+pub struct NCBINames {
+    names: HashMap<u32, String>,
+}
+
+impl NCBINames {
+    pub fn load_ncbi_dmp<P: AsRef<Path>>(names_file: P) -> Result<Self, TaxonomyError> {
+        let path = names_file.as_ref();
+        let file = File::open(path).map_err(TaxonomyError::io(path))?;
+
+        let mut names = HashMap::new();
+        for (ix, line) in BufReader::new(file).lines().enumerate() {
+            let line = line.map_err(TaxonomyError::io(path))?;
+            let parse_error = |message: &str| TaxonomyError::Parse {
+                path: path.to_path_buf(),
+                line: ix + 1,
+                message: message.to_string(),
+            };
+            // tax_id | name_txt | unique name | name class |
+            let fields: Vec<_> = line.trim_end_matches("\t|").split("\t|\t").collect();
+            if fields.len() < 4 {
+                return Err(parse_error("not enough fields in names.dmp"));
+            }
+            if fields[3].trim() != "scientific name" {
+                continue;
+            }
+            let taxid = fields[0]
+                .trim()
+                .parse::<u32>()
+                .map_err(|_| parse_error("invalid taxid"))?;
+            names.insert(taxid, fields[1].trim().to_string());
+        }
+        Ok(Self { names })
+    }
+
+    pub fn get(&self, taxid: &u32) -> Option<&str> {
+        self.names.get(taxid).map(String::as_str)
+    }
+
+    /// Taxids whose scientific name is `name`, ignoring case.
+    pub fn taxids_named(&self, name: &str) -> Vec<u32> {
+        self.names
+            .iter()
+            .filter(|(_, taxon_name)| taxon_name.eq_ignore_ascii_case(name))
+            .map(|(&taxid, _)| taxid)
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+}
+
+/// Taxids merged into other taxids, read from an NCBI `merged.dmp` file.
+#[derive(Default)]
+pub struct MergedTaxids {
+    merged: HashMap<u32, u32>,
+}
+
+impl MergedTaxids {
+    pub fn load_ncbi_dmp<P: AsRef<Path>>(merged_file: P) -> Result<Self, TaxonomyError> {
+        let path = merged_file.as_ref();
+        let file = File::open(path).map_err(TaxonomyError::io(path))?;
+
+        let mut merged = HashMap::new();
+        for (ix, line) in BufReader::new(file).lines().enumerate() {
+            let line = line.map_err(TaxonomyError::io(path))?;
+            let parse_error = |message: &str| TaxonomyError::Parse {
+                path: path.to_path_buf(),
+                line: ix + 1,
+                message: message.to_string(),
+            };
+            // old_tax_id | new_tax_id |
+            let fields: Vec<_> = line.trim_end_matches("\t|").split("\t|\t").collect();
+            if fields.len() < 2 {
+                return Err(parse_error("not enough fields in merged.dmp"));
+            }
+            let old_taxid = fields[0]
+                .trim()
+                .parse::<u32>()
+                .map_err(|_| parse_error("invalid old taxid"))?;
+            let new_taxid = fields[1]
+                .trim()
+                .parse::<u32>()
+                .map_err(|_| parse_error("invalid new taxid"))?;
+            merged.insert(old_taxid, new_taxid);
+        }
+        Ok(Self { merged })
+    }
+
+    /// Current taxid of a taxid, which is the taxid itself unless it was merged.
+    pub fn remap(&self, taxid: u32) -> u32 {
+        let mut current = taxid;
+        // merged.dmp is normally resolved, but follow chains of merges anyway
+        for _ in 0..self.merged.len() {
+            match self.merged.get(&current) {
+                Some(&new_taxid) if new_taxid != current => current = new_taxid,
+                _ => break,
+            }
         }
+        current
+    }
+
+    pub fn len(&self) -> usize {
+        self.merged.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.merged.is_empty()
     }
 }
 
+/// Deleted taxids, read from an NCBI `delnodes.dmp` file.
+pub fn load_deleted_taxids(delnodes_file: &Path) -> Result<HashSet<u32>, TaxonomyError> {
+    let file = File::open(delnodes_file).map_err(TaxonomyError::io(delnodes_file))?;
+    let mut deleted = HashSet::new();
+    for (ix, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.map_err(TaxonomyError::io(delnodes_file))?;
+        // tax_id |
+        let taxid = line.trim_end_matches("\t|").trim();
+        if taxid.is_empty() {
+            continue;
+        }
+        let taxid = taxid.parse::<u32>().map_err(|_| TaxonomyError::Parse {
+            path: delnodes_file.to_path_buf(),
+            line: ix + 1,
+            message: "invalid taxid".to_string(),
+        })?;
+        deleted.insert(taxid);
+    }
+    Ok(deleted)
+}
+
 pub fn load_taxonomy(taxonomy_dir: PathBuf) -> Result<NCBITaxonomy, TaxonomyError> {
     let json_file = taxonomy_dir.join("nodes.json");
     let dmp_file = taxonomy_dir.join("nodes.dmp");
 
-    print!("\t>>STEP 2: READING NODES.DMP FILE\n");
+    log::info!("\t>>STEP 2: READING NODES.DMP FILE");
     let taxo = if json_file.exists() {
         NCBITaxonomy::load(json_file)?
     } else if dmp_file.exists() {
@@ -276,9 +534,25 @@ pub fn load_taxonomy(taxonomy_dir: PathBuf) -> Result<NCBITaxonomy, TaxonomyErro
         taxo.save_to_file(&json_file)?;
         taxo
     } else {
-        return Err(TaxonomyError::new("No suitable taxonomy nodes file found."));
+        return Err(TaxonomyError::MissingNodes(taxonomy_dir));
     };
 
-    print!("\t\t{:?} total nodes read\n", taxo.nodes.len());
+    log::info!("\t\t{:?} total nodes read", taxo.nodes.len());
     Ok(taxo)
 }
+
+/// Scientific names from the names.dmp file of a taxonomy directory; empty
+/// when the directory has none.
+pub fn load_taxonomy_names(taxonomy_dir: &Path) -> Result<NCBINames, TaxonomyError> {
+    let names_file = taxonomy_dir.join("names.dmp");
+    if !names_file.exists() {
+        log::warn!(
+            "No names.dmp in {:?}; taxa are named by taxid",
+            taxonomy_dir
+        );
+        return Ok(NCBINames::default());
+    }
+    let names = NCBINames::load_ncbi_dmp(&names_file)?;
+    log::info!("\t\t{:?} scientific names read", names.len());
+    Ok(names)
+}
@@ -0,0 +1,74 @@
+//! Compares est-abundance against outputs of the original (Python) Bracken
+//! on the shared fixtures in `tests/data`.
+
+mod common;
+
+use common::{output_path, read_rows, run_est_abundance, KMER_DISTR, REPORT};
+use std::path::Path;
+use std::process::Command;
+
+fn assert_matches_reference(name: &str, level: &str, expected: &str) {
+    let output = run_est_abundance(name, &["-l", level, "--rounding", "none"]);
+    let (header, rows) = read_rows(&output);
+    let (expected_header, expected_rows) = read_rows(Path::new(expected));
+    std::fs::remove_file(output).ok();
+
+    assert_eq!(header, expected_header);
+    assert_eq!(rows, expected_rows);
+}
+
+#[test]
+fn species_matches_reference_bracken() {
+    assert_matches_reference("species", "S", "tests/data/expected_bracken_S.tsv");
+}
+
+#[test]
+fn genus_matches_reference_bracken() {
+    assert_matches_reference("genus", "G", "tests/data/expected_bracken_G.tsv");
+}
+
+#[test]
+fn binary_kmer_distribution_matches_text() {
+    let binary = output_path("distr").with_extension("kmer_distrib");
+    let output = output_path("binary-distr");
+    let bracken = |args: &[&std::ffi::OsStr]| {
+        let status = Command::new(env!("CARGO_BIN_EXE_bracken"))
+            .args(args)
+            .status()
+            .expect("failed to run bracken");
+        assert!(status.success());
+    };
+    bracken(&[
+        "convert-distr".as_ref(),
+        "-i".as_ref(),
+        KMER_DISTR.as_ref(),
+        "-o".as_ref(),
+        binary.as_os_str(),
+    ]);
+    bracken(&[
+        "est-abundance".as_ref(),
+        "-i".as_ref(),
+        REPORT.as_ref(),
+        "-k".as_ref(),
+        binary.as_os_str(),
+        "-o".as_ref(),
+        output.as_os_str(),
+        "--rounding".as_ref(),
+        "none".as_ref(),
+    ]);
+    let (_, rows) = read_rows(&output);
+    let (_, expected_rows) = read_rows(Path::new("tests/data/expected_bracken_S.tsv"));
+    std::fs::remove_file(binary).ok();
+    std::fs::remove_file(output).ok();
+
+    assert_eq!(rows, expected_rows);
+}
+
+#[test]
+fn self_test_passes() {
+    let status = Command::new(env!("CARGO_BIN_EXE_bracken"))
+        .args(["-q", "self-test"])
+        .status()
+        .expect("failed to run bracken");
+    assert!(status.success());
+}
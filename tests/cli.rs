@@ -0,0 +1,772 @@
+//! Subcommands working on Bracken outputs and reports.
+
+mod common;
+
+use common::{output_path, read_rows, run_est_abundance, REPORT};
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::process::Command;
+
+#[test]
+fn combine_zero_fills_missing_taxa() {
+    let sample_a = run_est_abundance("combine-a", &[]);
+    let sample_b = run_est_abundance("combine-b", &["-t", "150"]);
+    let output = output_path("combine");
+    let status = Command::new(env!("CARGO_BIN_EXE_bracken"))
+        .args(["combine", "--sample-names", "a,b", "-o"])
+        .arg(&output)
+        .arg("-i")
+        .args([&sample_a, &sample_b])
+        .status()
+        .expect("failed to run bracken");
+    assert!(status.success());
+
+    let counts = output.with_extension("counts.tsv");
+    let (header, rows) = read_rows(&counts);
+    for path in [
+        sample_a,
+        sample_b,
+        counts,
+        output.with_extension("fractions.tsv"),
+    ] {
+        std::fs::remove_file(path).ok();
+    }
+
+    assert!(header.ends_with("\ta\tb"));
+    // Escherichia phage T4 is below the threshold of sample b
+    assert_eq!(rows[&10665][4], "0");
+    assert_eq!(rows.len(), 5);
+}
+
+#[test]
+fn combine_flags_and_removes_contaminants_of_negative_controls() {
+    let control = run_est_abundance("decontam-control", &[]);
+    let sample = run_est_abundance("decontam-sample", &["-t", "150"]);
+    let combine = |name: &str, mode: &str| {
+        let output = output_path(name);
+        let status = Command::new(env!("CARGO_BIN_EXE_bracken"))
+            .args(["combine", "--sample-names", "ctrl,s"])
+            // A control named twice is one control
+            .args(["--negative-controls", "ctrl,ctrl"])
+            .arg(mode)
+            .arg("-o")
+            .arg(&output)
+            .arg("-i")
+            .args([&control, &sample])
+            .status()
+            .expect("failed to run bracken");
+        assert!(status.success());
+        let counts = output.with_extension("counts.tsv");
+        let rows = read_rows(&counts);
+        std::fs::remove_file(counts).ok();
+        std::fs::remove_file(output.with_extension("fractions.tsv")).ok();
+        rows
+    };
+    let (flagged_header, flagged) = combine("decontam-flag", "--flag-contaminants");
+    let (_, removed) = combine("decontam-remove", "--remove-contaminants");
+    std::fs::remove_file(control).ok();
+    std::fs::remove_file(sample).ok();
+
+    assert!(flagged_header.ends_with("\tctrl\ts\tcontaminant"));
+    // Escherichia phage T4 and Escherichia albertii are only found in the
+    // control, the sample drops them below its threshold
+    let contaminants = [10665, 208962];
+    for (taxid, row) in flagged.iter() {
+        let expected = if contaminants.contains(taxid) {
+            "yes"
+        } else {
+            "no"
+        };
+        assert_eq!(row[5], expected);
+    }
+    assert_eq!(removed.len(), flagged.len() - contaminants.len());
+    assert!(contaminants
+        .iter()
+        .all(|taxid| !removed.contains_key(taxid)));
+}
+
+#[test]
+fn combine_flags_contaminants_by_frequency_without_controls() {
+    // Samples of 1000, 10000 and 100000 reads: taxon 1 is a constant
+    // fraction of every sample, taxon 2 the same 50 reads in each
+    let samples: Vec<_> = [(100, 850), (1000, 8950), (10_000, 89_950)]
+        .iter()
+        .enumerate()
+        .map(|(ix, (genuine, filler))| {
+            let path = output_path(&format!("frequency-{}", ix));
+            let total = (genuine + 50 + filler) as f64;
+            let row = |name: &str, taxid: u32, reads: usize| {
+                let fraction = reads as f64 / total;
+                format!("{name}\t{taxid}\tS\t{reads}\t0\t{reads}\t{fraction:.5}\n")
+            };
+            let content = format!(
+                "name\ttaxonomy_id\ttaxonomy_lvl\tkraken_assigned_reads\tadded_reads\tnew_est_reads\tfraction_total_reads\n{}{}{}",
+                row("genuine", 1, *genuine),
+                row("contaminant", 2, 50),
+                row("filler", 3, *filler)
+            );
+            std::fs::write(&path, content).unwrap();
+            path
+        })
+        .collect();
+    let output = output_path("frequency");
+    let run = Command::new(env!("CARGO_BIN_EXE_bracken"))
+        .args(["combine", "--contaminant-method", "frequency"])
+        .args(["--flag-contaminants", "-o"])
+        .arg(&output)
+        .arg("-i")
+        .args(&samples)
+        .output()
+        .expect("failed to run bracken");
+    let prevalence = Command::new(env!("CARGO_BIN_EXE_bracken"))
+        .args(["combine", "--flag-contaminants", "-o"])
+        .arg(output_path("prevalence"))
+        .arg("-i")
+        .args(&samples)
+        .output()
+        .expect("failed to run bracken");
+    let counts = output.with_extension("counts.tsv");
+    let (_, rows) = read_rows(&counts);
+    for path in samples
+        .iter()
+        .chain([&counts, &output.with_extension("fractions.tsv")])
+    {
+        std::fs::remove_file(path).ok();
+    }
+
+    assert!(run.status.success());
+    assert_eq!(rows[&1][6], "no");
+    assert_eq!(rows[&2][6], "yes");
+    assert_eq!(rows[&3][6], "no");
+    // the prevalence method needs negative controls
+    assert!(!prevalence.status.success());
+    assert!(String::from_utf8_lossy(&prevalence.stderr).contains("needs --negative-controls"));
+}
+
+#[test]
+fn rarefaction_skips_empty_samples_and_rejects_bad_depths() {
+    let matrix = output_path("normalize-matrix");
+    let infinite = output_path("normalize-inf");
+    let header = "name\ttaxonomy_id\ttaxonomy_lvl\ta\tb\tempty\n";
+    std::fs::write(
+        &matrix,
+        format!("{}X\t1\tS\t10\t20\t0\nY\t2\tS\t5\t30\t0\n", header),
+    )
+    .unwrap();
+    std::fs::write(&infinite, format!("{}X\t1\tS\t10\tinf\t0\n", header)).unwrap();
+    let normalize = |input: &Path, extra_args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_bracken"))
+            .args(["normalize", "-m", "rarefy", "-o", "-", "-i"])
+            .arg(input)
+            .args(extra_args)
+            .output()
+            .expect("failed to run bracken")
+    };
+    let rarefied = normalize(&matrix, &[]);
+    let zero_depth = normalize(&matrix, &["--depth", "0"]);
+    let non_finite = normalize(&infinite, &[]);
+    for path in [matrix, infinite] {
+        std::fs::remove_file(path).ok();
+    }
+
+    let table = String::from_utf8_lossy(&rarefied.stdout);
+    assert!(rarefied.status.success());
+    assert!(table.contains("# depth: 15"));
+    assert!(table.contains("taxonomy_lvl\ta\tb\n"));
+    assert!(String::from_utf8_lossy(&rarefied.stderr).contains("Sample empty has 0 reads"));
+    assert_eq!(zero_depth.status.code(), Some(5));
+    assert_eq!(non_finite.status.code(), Some(3));
+}
+
+/// Output of a bracken run with the arguments.
+fn bracken(args: &[&str]) -> std::process::Output {
+    Command::new(env!("CARGO_BIN_EXE_bracken"))
+        .args(args)
+        .output()
+        .expect("failed to run bracken")
+}
+
+/// Lines of the standard output of a successful bracken run, split on tabs.
+fn stdout_rows(output: &std::process::Output) -> Vec<Vec<String>> {
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(|line| line.split('\t').map(String::from).collect())
+        .collect()
+}
+
+#[test]
+fn rarefaction_counts_the_taxa_observed_at_each_depth() {
+    let curve = |extra_args: &[&str]| {
+        let mut args = vec!["rarefaction", "-i", REPORT, "-o", "-", "-r", "2"];
+        args.extend(extra_args);
+        stdout_rows(&bracken(&args))
+    };
+    let rows = curve(&["-d", "1860,0,930,5000"]);
+    let thresholded = curve(&["-d", "1860", "-t", "100"]);
+
+    assert_eq!(rows[0], ["depth", "replicate", "level", "observed_taxa"]);
+    // the depths are sorted and the one above the 1860 reads is skipped
+    let depths: Vec<&str> = rows[1..].iter().map(|row| row[0].as_str()).collect();
+    assert_eq!(depths, ["0", "0", "930", "930", "1860", "1860"]);
+    assert_eq!(rows[1][3], "0");
+    // all 7 species of the report at its full depth
+    assert_eq!(rows[6][3], "7");
+    assert_eq!(rows[1..], curve(&["-d", "1860,0,930,5000"])[1..]);
+    assert_eq!(thresholded[1][3], "5");
+}
+
+#[test]
+fn diff_calls_gained_lost_and_changed_taxa() {
+    let all = run_est_abundance("diff-a", &[]);
+    // Escherichia phage T4 and Escherichia albertii are below 150 reads
+    let thresholded = run_est_abundance("diff-b", &["-t", "150"]);
+    let (a, b) = (all.to_str().unwrap(), thresholded.to_str().unwrap());
+    let changes = |args: &[&str]| -> BTreeMap<String, String> {
+        let mut diff_args = vec!["diff"];
+        diff_args.extend(args);
+        stdout_rows(&bracken(&diff_args))[1..]
+            .iter()
+            .map(|row| (row[1].clone(), row[8].clone()))
+            .collect()
+    };
+    let lost = changes(&[a, b]);
+    let gained = changes(&[b, a, "--min-log2-fold", "0.1"]);
+    std::fs::remove_file(&all).ok();
+    std::fs::remove_file(&thresholded).ok();
+
+    assert_eq!(lost["10665"], "lost");
+    assert_eq!(lost["208962"], "lost");
+    // the fractions of the other taxa change by less than twofold
+    assert_eq!(lost["562"], "unchanged");
+    assert_eq!(gained["10665"], "gained");
+    assert_eq!(gained["562"], "down");
+    assert_eq!(gained["9606"], "down");
+}
+
+#[test]
+fn top_lists_the_most_abundant_taxa_with_their_cumulative_percentage() {
+    let output = run_est_abundance("top", &[]);
+    let top = |extra_args: &[&str]| -> Vec<Vec<String>> {
+        let mut args = vec!["top", output.to_str().unwrap()];
+        args.extend(extra_args);
+        let run = bracken(&args);
+        assert!(run.status.success());
+        String::from_utf8_lossy(&run.stdout)
+            .lines()
+            .skip(1)
+            .map(|line| line.split_whitespace().map(String::from).collect())
+            .collect()
+    };
+    let species = top(&["-n", "3"]);
+    let domains = top(&["-r", "D", "--taxonomy", "tests/data/self_test"]);
+    std::fs::remove_file(&output).ok();
+
+    let taxids: Vec<&str> = species.iter().map(|row| row[4].as_str()).collect();
+    assert_eq!(taxids, ["562", "28901", "9606", "-"]);
+    assert_eq!(species[2][3], "82.23%");
+    // the remaining taxa on one line
+    assert_eq!(species[3][..3], ["295", "0.17771", "100.00%"]);
+    let domains: Vec<(&str, &str)> = domains
+        .iter()
+        .map(|row| (row[4].as_str(), row[1].as_str()))
+        .collect();
+    assert_eq!(domains, [("2", "1316"), ("2759", "176"), ("10239", "168")]);
+}
+
+#[test]
+fn collapse_sums_the_estimates_up_to_each_rank() {
+    let species = run_est_abundance("collapse-species", &[]);
+    let output = output_path("collapse");
+    let run = bracken(&[
+        "collapse",
+        "-i",
+        species.to_str().unwrap(),
+        "--taxonomy",
+        "tests/data/self_test",
+        "-r",
+        "G,D",
+        "-o",
+        output.to_str().unwrap(),
+    ]);
+    assert!(run.status.success());
+    let (_, genera) = read_rows(&output.with_extension("G.tsv"));
+    let (_, domains) = read_rows(&output.with_extension("D.tsv"));
+    for path in [
+        species,
+        output.with_extension("G.tsv"),
+        output.with_extension("D.tsv"),
+    ] {
+        std::fs::remove_file(path).ok();
+    }
+
+    // Escherichia sums E. coli and E. albertii
+    assert_eq!(genera[&561][..2], ["Escherichia", "561"]);
+    assert_eq!(genera[&561][3..6], ["620", "168", "788"]);
+    assert_eq!(genera.len(), 4);
+    assert_eq!(domains[&2][5], "1316");
+    for table in [&genera, &domains] {
+        assert!(table
+            .values()
+            .all(|row| row[2] == table.values().next().unwrap()[2]));
+        let reads: usize = table
+            .values()
+            .map(|row| row[5].parse::<usize>().unwrap())
+            .sum();
+        assert_eq!(reads, 1660);
+    }
+}
+
+#[test]
+fn filter_drops_taxa_and_renormalizes_the_fractions() {
+    let output = run_est_abundance("filter", &[]);
+    let exclude = output_path("filter-exclude");
+    std::fs::write(&exclude, "28901\n").unwrap();
+    let filter = |extra_args: &[&str]| -> BTreeMap<String, String> {
+        let mut args = vec!["filter", "-i", output.to_str().unwrap(), "-o", "-"];
+        args.extend(extra_args);
+        stdout_rows(&bracken(&args))[1..]
+            .iter()
+            .map(|row| (row[1].clone(), row[6].clone()))
+            .collect()
+    };
+    let by_reads = filter(&[
+        "--min-reads",
+        "170",
+        "--exclude-taxids",
+        exclude.to_str().unwrap(),
+    ]);
+    let by_fraction = filter(&["--min-fraction", "0.2"]);
+    let other_level = filter(&["-l", "G"]);
+    std::fs::remove_file(&output).ok();
+    std::fs::remove_file(&exclude).ok();
+
+    // Salmonella enterica is excluded, the other taxa have fewer reads
+    assert_eq!(
+        by_reads.into_iter().collect::<Vec<_>>(),
+        [
+            ("562".to_string(), "0.78973".to_string()),
+            ("9606".to_string(), "0.21027".to_string())
+        ]
+    );
+    assert_eq!(by_fraction.keys().collect::<Vec<_>>(), ["28901", "562"]);
+    assert_eq!(by_fraction["562"], "0.55593");
+    assert!(other_level.is_empty());
+}
+
+#[test]
+fn merge_kreports_sums_the_reads_of_matching_trees() {
+    let self_test = "tests/data/self_test/report.kreport";
+    let merged = bracken(&["merge-kreports", "-i", REPORT, self_test, "-o", "-"]);
+    let mismatched = output_path("merge-mismatch");
+    let content = std::fs::read_to_string(self_test).unwrap();
+    std::fs::write(&mismatched, content.replace("\tS\t562\t", "\tG\t562\t")).unwrap();
+    let conflict = bracken(&[
+        "merge-kreports",
+        "-i",
+        REPORT,
+        mismatched.to_str().unwrap(),
+        "-o",
+        "-",
+    ]);
+    std::fs::remove_file(&mismatched).ok();
+
+    let lines: Vec<Vec<String>> = stdout_rows(&merged)
+        .into_iter()
+        .map(|row| {
+            row.into_iter()
+                .map(|field| field.trim().to_string())
+                .collect()
+        })
+        .collect();
+    let line = |taxid: &str| lines.iter().find(|row| row[4] == taxid).unwrap();
+    assert_eq!(lines.len(), 24);
+    assert_eq!(line("0")[1..3], ["130", "130"]);
+    assert_eq!(line("1")[1], "1830");
+    assert_eq!(line("562")[1..3], ["560", "440"]);
+    // the taxa of one report only keep their reads and place in the tree
+    assert_eq!(line("9606")[1..3], ["170", "170"]);
+    let position = |taxid: &str| lines.iter().position(|row| row[4] == taxid).unwrap();
+    assert!(position("561") < position("562") && position("562") < position("83333"));
+
+    assert_eq!(conflict.status.code(), Some(3));
+    assert!(String::from_utf8_lossy(&conflict.stderr).contains("disagree on taxid 562"));
+}
+
+#[test]
+fn downsampled_reports_are_valid_reports_of_the_depth() {
+    let downsample = |name: &str, extra_args: &[&str]| {
+        let output = output_path(name).with_extension("kreport");
+        let mut args = vec!["downsample-report", "-i", REPORT, "-o"];
+        args.push(output.to_str().unwrap());
+        args.extend(extra_args);
+        assert!(bracken(&args).status.success());
+        let lint = bracken(&["lint", output.to_str().unwrap()]);
+        let content = std::fs::read_to_string(&output).unwrap();
+        std::fs::remove_file(&output).ok();
+        assert!(lint.status.success());
+        // only the header of the findings
+        assert_eq!(String::from_utf8_lossy(&lint.stdout).lines().count(), 1);
+        content
+    };
+    let clade_reads = |content: &str, taxid: &str| -> usize {
+        content
+            .lines()
+            .map(|line| line.split('\t').collect::<Vec<_>>())
+            .find(|fields| fields[4] == taxid)
+            .map_or(0, |fields| fields[1].parse().unwrap())
+    };
+    let sparse = downsample("downsample", &["-d", "100"]);
+    let with_zero = downsample("downsample-zero", &["-d", "100", "--keep-zero"]);
+    let unchanged = downsample("downsample-all", &["-d", "100000"]);
+
+    assert_eq!(clade_reads(&sparse, "0") + clade_reads(&sparse, "1"), 100);
+    assert_eq!(with_zero.lines().count(), 24);
+    assert!(sparse.lines().count() < 24);
+    // the same seed gives the same subsample
+    assert_eq!(
+        sparse,
+        downsample("downsample-again", &["-d", "100", "--seed", "0"])
+    );
+    assert_ne!(
+        sparse,
+        downsample("downsample-seed", &["-d", "100", "--seed", "1"])
+    );
+    assert_eq!(unchanged, std::fs::read_to_string(REPORT).unwrap());
+}
+
+#[test]
+fn kreport2mpa_writes_lineages_of_the_main_ranks() {
+    let convert = |extra_args: &[&str]| -> BTreeMap<String, String> {
+        let mut args = vec!["kreport2mpa", "-i", REPORT, "-o", "-"];
+        args.extend(extra_args);
+        stdout_rows(&bracken(&args))
+            .into_iter()
+            .map(|row| (row[0].clone(), row[1].clone()))
+            .collect()
+    };
+    let reads = convert(&[]);
+    let percentages = convert(&[
+        "--percentages",
+        "--intermediate-ranks",
+        "--display-header",
+        "--rank-prefix",
+        "D=k__",
+    ]);
+
+    let e_coli = "d__Bacteria|p__Proteobacteria|c__Gammaproteobacteria|o__Enterobacterales|f__Enterobacteriaceae|g__Escherichia|s__Escherichia_coli";
+    assert_eq!(reads[e_coli], "520");
+    assert_eq!(reads["d__Viruses|g__Tequatrovirus"], "150");
+    // neither the root, the unclassified reads nor the strains
+    assert_eq!(reads.len(), 17);
+    assert!(reads.keys().all(|lineage| !lineage.contains("x__")));
+
+    assert_eq!(percentages["#Classification"], "report");
+    let strain = format!(
+        "{}|x__Escherichia_coli_K-12",
+        e_coli.replacen("d__", "k__", 1)
+    );
+    assert_eq!(percentages[&strain], "4.83871");
+    assert_eq!(percentages["k__Viruses"], "8.87097");
+}
+
+#[test]
+fn extract_subtree_keeps_or_removes_subtrees_with_their_counts() {
+    let extract = |extra_args: &[&str]| -> Vec<Vec<String>> {
+        let mut args = vec!["extract-subtree", "-i", REPORT, "-o", "-"];
+        args.extend(extra_args);
+        stdout_rows(&bracken(&args))
+    };
+    let line = |lines: &[Vec<String>], taxid: &str| -> Option<Vec<String>> {
+        lines.iter().find(|row| row[4] == taxid).cloned()
+    };
+    let salmonella = extract(&["-t", "590"]);
+    let host_free = extract(&["-t", "9606", "--remove"]);
+    let rerooted = extract(&["-t", "590,10663", "--reroot"]);
+
+    // the ancestors of Salmonella only keep its reads
+    assert_eq!(line(&salmonella, "1").unwrap()[1..3], ["460", "0"]);
+    assert_eq!(line(&salmonella, "0").unwrap()[1], "120");
+    assert_eq!(line(&salmonella, "28901").unwrap()[1..3], ["410", "380"]);
+    assert!(line(&salmonella, "562").is_none());
+
+    assert!(line(&host_free, "9606").is_none());
+    assert_eq!(line(&host_free, "2759").unwrap()[1..3], ["5", "5"]);
+    assert_eq!(line(&host_free, "1").unwrap()[1], "1570");
+    assert_eq!(line(&host_free, "1").unwrap()[0].trim(), "92.90");
+
+    let roots: Vec<&str> = rerooted
+        .iter()
+        .filter(|row| !row[5].starts_with(' '))
+        .map(|row| row[4].as_str())
+        .collect();
+    assert_eq!(roots, ["590", "10663"]);
+    assert_eq!(rerooted.len(), 7);
+    assert_eq!(
+        line(&rerooted, "10665").unwrap()[5],
+        "  Escherichia phage T4"
+    );
+}
+
+#[test]
+fn simulated_reads_come_from_the_references_in_the_composition() {
+    let dir = output_path("simulate").with_extension("");
+    std::fs::create_dir_all(&dir).unwrap();
+    // a fixed pseudo-random sequence of the length
+    let sequence = |len: usize, seed: u64| -> String {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state = state
+                    .wrapping_mul(6364136223846793005)
+                    .wrapping_add(1442695040888963407);
+                b"ACGT"[(state >> 62) as usize] as char
+            })
+            .collect()
+    };
+    let references = [
+        ("a", sequence(2000, 1)),
+        ("b1", sequence(1000, 2)),
+        ("b2", sequence(3000, 3)),
+    ];
+    let [(_, a), (_, b1), (_, b2)] = &references;
+    std::fs::write(dir.join("a.fa"), format!(">a\n{}\n", a)).unwrap();
+    std::fs::write(dir.join("b.fa"), format!(">b1\n{}\n>b2\n{}\n", b1, b2)).unwrap();
+    let composition = dir.join("composition.tsv");
+    std::fs::write(&composition, "a.fa\t562\t3\tE. coli\nb.fa\t9606\t1\n").unwrap();
+    let simulate = |name: &str, extra_args: &[&str]| {
+        let fastq = dir.join(format!("{}.fastq", name));
+        let truth = dir.join(format!("{}.truth.tsv", name));
+        let mut args = vec!["simulate", "-n", "1000", "-l", "100", "-c"];
+        args.extend([composition.to_str().unwrap(), "-o", fastq.to_str().unwrap()]);
+        args.extend(["--truth", truth.to_str().unwrap()]);
+        args.extend(extra_args);
+        assert!(bracken(&args).status.success());
+        let truth: BTreeMap<String, (String, usize)> = std::fs::read_to_string(truth)
+            .unwrap()
+            .lines()
+            .skip(1)
+            .map(|line| {
+                let fields: Vec<&str> = line.split('\t').collect();
+                (
+                    fields[1].to_string(),
+                    (fields[0].to_string(), fields[2].parse().unwrap()),
+                )
+            })
+            .collect();
+        (std::fs::read_to_string(fastq).unwrap(), truth)
+    };
+    let (fastq, truth) = simulate("reads", &[]);
+    let (_, cells) = simulate("cells", &["--cell-abundance"]);
+    let (same_seed, _) = simulate("again", &[]);
+    std::fs::remove_dir_all(&dir).ok();
+
+    assert_eq!(truth["562"].0, "E. coli");
+    // without a name column, the name of the first sequence
+    assert_eq!(truth["9606"].0, "b");
+    assert_eq!(truth["562"].1 + truth["9606"].1, 1000);
+    assert!((700..800).contains(&truth["562"].1));
+    // 3 copies of 2000 bp against 1 copy of 4000 bp
+    assert!((550..650).contains(&cells["562"].1));
+    assert_eq!(fastq, same_seed);
+
+    let lines: Vec<&str> = fastq.lines().collect();
+    let mut reads_per_taxid: BTreeMap<&str, usize> = BTreeMap::new();
+    for record in lines.chunks(4) {
+        // @read1 taxid=562 a:499+
+        let header: Vec<&str> = record[0].split(' ').collect();
+        *reads_per_taxid
+            .entry(&header[1]["taxid=".len()..])
+            .or_default() += 1;
+        let (seqid, position) = header[2].split_once(':').unwrap();
+        let start: usize = position[..position.len() - 1].parse().unwrap();
+        let reference = &references.iter().find(|(id, _)| *id == seqid).unwrap().1;
+        let origin = &reference[start - 1..start - 1 + 100];
+        let read = if position.ends_with('-') {
+            record[1]
+                .chars()
+                .rev()
+                .map(|base| match base {
+                    'A' => 'T',
+                    'C' => 'G',
+                    'G' => 'C',
+                    _ => 'A',
+                })
+                .collect()
+        } else {
+            record[1].to_string()
+        };
+        assert_eq!(&read, origin);
+        assert_eq!(record[3].len(), 100);
+    }
+    assert_eq!(reads_per_taxid["562"], truth["562"].1);
+}
+
+#[test]
+fn evaluate_compares_the_estimates_with_the_truth() {
+    let output = run_est_abundance("evaluate", &[]);
+    // E. coli and S. enterica at other fractions, and a missed taxon
+    let truth = output_path("evaluate-truth");
+    std::fs::write(
+        &truth,
+        "name\ttaxonomy_id\treads\tfraction_total_reads\n\
+         E\t562\t50\t0.5\nS\t28901\t30\t0.3\nX\t1280\t20\t0.2\n",
+    )
+    .unwrap();
+    let per_taxon = output_path("evaluate-per-taxon");
+    let evaluate = |truth: &Path, extra_args: &[&str]| -> BTreeMap<String, String> {
+        let mut args = vec![
+            "evaluate",
+            output.to_str().unwrap(),
+            truth.to_str().unwrap(),
+        ];
+        args.extend(extra_args);
+        stdout_rows(&bracken(&args))[1..]
+            .iter()
+            .map(|row| (row[0].clone(), row[1].clone()))
+            .collect()
+    };
+    let itself = evaluate(&output, &[]);
+    let metrics = evaluate(&truth, &["--per-taxon", per_taxon.to_str().unwrap()]);
+    let detected = evaluate(&truth, &["--min-fraction", "0.2"]);
+    let genera = evaluate(&truth, &["-r", "G", "--taxonomy", "tests/data/self_test"]);
+    let (_, taxa) = read_rows(&per_taxon);
+    for path in [output.clone(), truth.clone(), per_taxon] {
+        std::fs::remove_file(path).ok();
+    }
+
+    assert_eq!(itself["l1_error"], "0.000000");
+    assert_eq!(itself["f1"], "1.000000");
+    assert_eq!(metrics["l1_error"], "0.603614");
+    assert_eq!(metrics["bray_curtis"], "0.301807");
+    assert_eq!(metrics["l2_error"], "0.279335");
+    assert_eq!(
+        [
+            &metrics["true_positives"],
+            &metrics["false_positives"],
+            &metrics["false_negatives"]
+        ],
+        ["2", "3", "1"]
+    );
+    assert_eq!(metrics["precision"], "0.400000");
+    assert_eq!(metrics["recall"], "0.666667");
+    assert_eq!(taxa[&1280][5], "FN");
+    assert_eq!(taxa[&562][4], "-0.101807");
+    assert_eq!(taxa[&9606][5], "FP");
+    // the taxa below 0.2 are not detected, the errors do not change
+    assert_eq!(detected["false_positives"], "0");
+    assert_eq!(detected["l1_error"], metrics["l1_error"]);
+    // Escherichia sums E. coli and E. albertii
+    assert_eq!(genera["l1_error"], "0.450602");
+    assert_eq!(genera["false_positives"], "2");
+}
+
+#[test]
+fn lint_reports_the_findings_and_repairs_the_report() {
+    let content = std::fs::read_to_string(REPORT).unwrap();
+    // E. coli with 20 clade reads missing and an odd indentation
+    let broken = output_path("lint-broken").with_extension("kreport");
+    std::fs::write(
+        &broken,
+        content.replace(
+            "\t520\t400\tS\t562\t                Escherichia",
+            "\t500\t400\tS\t562\t                 Escherichia",
+        ),
+    )
+    .unwrap();
+    let duplicate = output_path("lint-duplicate").with_extension("kreport");
+    std::fs::write(&duplicate, content.replace("\t208962\t", "\t562\t")).unwrap();
+    let fixed = output_path("lint-fixed").with_extension("kreport");
+    let lint = |report: &Path, extra_args: &[&str]| {
+        let mut args = vec!["lint", report.to_str().unwrap()];
+        args.extend(extra_args);
+        let run = bracken(&args);
+        let findings: Vec<Vec<String>> = String::from_utf8_lossy(&run.stdout)
+            .lines()
+            .skip(1)
+            .map(|line| line.split('\t').map(String::from).collect())
+            .collect();
+        (run.status.code(), findings)
+    };
+    let (broken_code, findings) = lint(&broken, &[]);
+    let (fixed_code, _) = lint(&broken, &["--fix", fixed.to_str().unwrap()]);
+    let repaired = std::fs::read_to_string(&fixed).unwrap();
+    std::fs::remove_file(&fixed).ok();
+    let (duplicate_code, duplicates) = lint(&duplicate, &["--fix", fixed.to_str().unwrap()]);
+    let unrepaired = fixed.exists();
+    let json = bracken(&["lint", duplicate.to_str().unwrap(), "--format", "json"]);
+    for path in [broken, duplicate] {
+        std::fs::remove_file(path).ok();
+    }
+
+    let codes: Vec<(&str, &str)> = findings
+        .iter()
+        .map(|row| (row[3].as_str(), row[2].as_str()))
+        .collect();
+    assert_eq!(
+        codes,
+        [
+            ("561", "clade_count_mismatch"),
+            ("562", "odd_indentation"),
+            ("562", "clade_count_mismatch"),
+            ("562", "percentage_mismatch")
+        ]
+    );
+    assert_eq!(broken_code, Some(3));
+    assert_eq!(fixed_code, Some(0));
+    assert_eq!(repaired, content);
+
+    assert_eq!(duplicates.len(), 1);
+    assert_eq!(
+        duplicates[0][..5],
+        ["14", "error", "duplicate_taxid", "562", "false"]
+    );
+    assert_eq!(duplicate_code, Some(3));
+    assert!(!unrepaired);
+    let findings: serde_json::Value = serde_json::from_slice(&json.stdout).unwrap();
+    assert_eq!(findings[0]["code"], "duplicate_taxid");
+    assert_eq!(findings[0]["fixable"], false);
+}
+
+#[test]
+fn report_stats_summarize_the_reads_per_rank_and_depth() {
+    let rows = stdout_rows(&bracken(&["report-stats", REPORT]));
+    let json = bracken(&["report-stats", REPORT, "--format", "json"]);
+    let stats: serde_json::Value = serde_json::from_slice(&json.stdout).unwrap();
+
+    let row = |section: &str, key: &str| -> &Vec<String> {
+        rows.iter()
+            .find(|row| row[0] == section && row[1] == key)
+            .unwrap()
+    };
+    let reads = |section: &str| -> usize {
+        rows.iter()
+            .filter(|row| row[0] == section)
+            .map(|row| row[4].parse::<usize>().unwrap())
+            .sum()
+    };
+    assert_eq!(row("reads", "total")[4], "1860");
+    assert_eq!(row("reads", "unclassified")[6], "0.06452");
+    assert_eq!(row("rank", "S")[2..6], ["7", "7", "1190", "1340"]);
+    assert_eq!(row("rank", "G")[2], "3");
+    // every read of the report at one rank and one depth
+    assert_eq!(reads("rank"), 1860);
+    assert_eq!(reads("depth"), 1860);
+    assert_eq!(row("depth", "10")[5], "30");
+
+    assert_eq!(stats["total_reads"], 1860);
+    assert_eq!(stats["unclassified_reads"], 120);
+    assert_eq!(stats["taxa"], 24);
+    assert_eq!(stats["max_depth"], 10);
+    let species = stats["ranks"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|rank| rank["key"] == "S")
+        .unwrap();
+    assert_eq!(species["clade_reads"], 1340);
+}
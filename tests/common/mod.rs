@@ -0,0 +1,57 @@
+//! Fixtures and helpers shared by the integration tests.
+#![allow(dead_code)]
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+pub const REPORT: &str = "tests/data/report.kreport";
+pub const KMER_DISTR: &str = "tests/data/database100mers.kmer_distrib";
+
+pub fn output_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("bracken-test-{}-{}.tsv", std::process::id(), name))
+}
+
+pub fn run_est_abundance(name: &str, extra_args: &[&str]) -> PathBuf {
+    let output = output_path(name);
+    let status = Command::new(env!("CARGO_BIN_EXE_bracken"))
+        .args(["est-abundance", "-i", REPORT, "-k", KMER_DISTR, "-o"])
+        .arg(&output)
+        .args(extra_args)
+        .status()
+        .expect("failed to run bracken");
+    assert!(status.success());
+    output
+}
+
+/// Copies the self-test database into the Kraken database folder `db`,
+/// without database.kraken unless `kraken` is set.
+pub fn self_test_db(db: &Path, kraken: bool) {
+    let self_test = Path::new("tests/data/self_test");
+    std::fs::create_dir_all(db.join("taxonomy")).unwrap();
+    let mut files = vec![
+        ("seqid2taxid.map", "seqid2taxid.map"),
+        ("nodes.dmp", "taxonomy/nodes.dmp"),
+        ("names.dmp", "taxonomy/names.dmp"),
+    ];
+    if kraken {
+        files.push(("database.kraken", "database.kraken"));
+    }
+    for (source, target) in files {
+        std::fs::copy(self_test.join(source), db.join(target)).unwrap();
+    }
+}
+
+/// Rows of a Bracken output keyed by taxid; the row order is not significant.
+pub fn read_rows(path: &Path) -> (String, BTreeMap<u32, Vec<String>>) {
+    let content = std::fs::read_to_string(path).unwrap();
+    let mut lines = content.lines();
+    let header = lines.next().unwrap().to_string();
+    let rows = lines
+        .map(|line| {
+            let fields: Vec<String> = line.split('\t').map(String::from).collect();
+            (fields[1].parse().unwrap(), fields)
+        })
+        .collect();
+    (header, rows)
+}
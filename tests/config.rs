@@ -0,0 +1,90 @@
+//! Default argument values from configuration files.
+
+mod common;
+
+use clap::{value_parser, Arg, Command as Cli};
+use common::{output_path, read_rows, KMER_DISTR, REPORT};
+use std::ffi::OsString;
+use std::path::PathBuf;
+use std::process::Command;
+
+#[test]
+fn config_tables_supply_defaults_the_command_line_overrides() {
+    let config = output_path("config").with_extension("toml");
+    std::fs::write(&config, "[est-abundance]\nthreshold = 150\n").unwrap();
+    let est_abundance = |name: &str, extra_args: &[&str]| {
+        let output = output_path(name);
+        let status = Command::new(env!("CARGO_BIN_EXE_bracken"))
+            .args(["est-abundance", "-i", REPORT, "-k", KMER_DISTR, "-o"])
+            .arg(&output)
+            .args(extra_args)
+            // Arguments are not read from the environment
+            .env("BRACKEN_THRESHOLD", "150")
+            .status()
+            .expect("failed to run bracken");
+        assert!(status.success());
+        let (_, rows) = read_rows(&output);
+        std::fs::remove_file(output).ok();
+        rows
+    };
+    let config_arg = config.to_str().unwrap();
+    let configured = est_abundance("config-default", &["--config", config_arg]);
+    let overridden = est_abundance("config-override", &["--config", config_arg, "-t", "10"]);
+    let unconfigured = est_abundance("config-none", &[]);
+    std::fs::remove_file(&config).ok();
+
+    // Escherichia phage T4 is below a threshold of 150
+    assert!(!configured.contains_key(&10665));
+    assert!(overridden.contains_key(&10665));
+    assert_eq!(overridden.len(), unconfigured.len());
+}
+
+#[test]
+fn command_line_arguments_drop_the_configured_values_they_conflict_with() {
+    let dir = output_path("config-conflict").with_extension("");
+    let empty_db = dir.join("db");
+    std::fs::create_dir_all(&empty_db).unwrap();
+    let config = dir.join("bracken.toml");
+    std::fs::write(&config, format!("kmer_distr = {:?}\n", KMER_DISTR)).unwrap();
+    let output = dir.join("report.bracken");
+    let run = Command::new(env!("CARGO_BIN_EXE_bracken"))
+        .args(["est-abundance", "-i", REPORT, "--config"])
+        .arg(&config)
+        .arg("--database-dir")
+        .arg(&empty_db)
+        .args(["--read-len", "100", "-o"])
+        .arg(&output)
+        .output()
+        .expect("failed to run bracken");
+    let written = output.exists();
+    std::fs::remove_dir_all(&dir).ok();
+
+    // --database-dir conflicts with --kmer-distr, so the configured
+    // distribution is not used in place of the empty database
+    assert_eq!(run.status.code(), Some(3));
+    assert!(String::from_utf8_lossy(&run.stderr).contains("kmer_distrib file found"));
+    assert!(!written);
+}
+
+#[test]
+fn config_path_is_not_taken_from_the_arguments_after_a_double_dash() {
+    std::env::remove_var("BRACKEN_CONFIG");
+    let cli = Cli::new("bracken")
+        .arg(
+            Arg::new("config")
+                .long("config")
+                .global(true)
+                .value_parser(value_parser!(PathBuf)),
+        )
+        .arg(Arg::new("rest").num_args(0..).last(true));
+    let config_path = |args: &[&str]| {
+        let args: Vec<OsString> = args.iter().map(OsString::from).collect();
+        bracken::config::config_path(&bracken::config::pre_parse(&cli, &args))
+    };
+
+    assert_eq!(
+        config_path(&["bracken", "--config", "a.toml"]),
+        Some(PathBuf::from("a.toml"))
+    );
+    assert_eq!(config_path(&["bracken", "--", "--config", "a.toml"]), None);
+}
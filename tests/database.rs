@@ -0,0 +1,421 @@
+//! Builds Bracken databases with build and pipeline.
+
+mod common;
+
+use common::{output_path, read_rows, self_test_db, REPORT};
+use std::path::Path;
+use std::process::Command;
+
+#[test]
+fn mean_read_length_reads_gzipped_fastq() {
+    use std::io::Write;
+    let reads = output_path("reads").with_extension("fastq.gz");
+    let mut fastq = String::new();
+    for (i, len) in [100, 150, 50].iter().enumerate() {
+        let seq = "A".repeat(*len);
+        fastq.push_str(&format!("@read{}\n{}\n+\n{}\n", i, seq, "I".repeat(*len)));
+    }
+    // Trailing blank lines are no reads
+    fastq.push_str("\n\n");
+    let mut gz = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    gz.write_all(fastq.as_bytes()).unwrap();
+    std::fs::write(&reads, gz.finish().unwrap()).unwrap();
+
+    let mean = bracken::database::mean_read_length(&reads, 10_000);
+    std::fs::remove_file(&reads).ok();
+    assert_eq!(mean.unwrap(), 100);
+}
+
+/// kraken2 stand-in classifying the sequences of /dev/stdin like the
+/// database.kraken of the self-test database, failing on $STUB_FAIL.
+#[cfg(unix)]
+const KRAKEN2_STUB: &str = r#"#!/bin/sh
+for input; do :; done
+[ "$input" = /dev/stdin ] || exit 2
+echo chunk >> "$(dirname "$0")/calls"
+awk -v fail="$STUB_FAIL" '
+    FNR == NR { lines[$2] = $0; next }
+    /^>/ { seqid = substr($1, 2); if (seqid == fail) exit 1; print lines[seqid] }
+' "$(dirname "$0")/database.kraken" "$input"
+"#;
+
+#[cfg(unix)]
+#[test]
+fn build_classifies_the_library_in_resumable_kraken2_chunks() {
+    use std::os::unix::fs::PermissionsExt;
+    let self_test = Path::new("tests/data/self_test");
+    let dir = output_path("kraken2").with_extension("");
+    let db = dir.join("db");
+    self_test_db(&db, false);
+    std::fs::create_dir_all(db.join("library")).unwrap();
+    std::fs::copy(
+        self_test.join("database.kraken"),
+        dir.join("database.kraken"),
+    )
+    .unwrap();
+    let stub = dir.join("kraken2");
+    std::fs::write(&stub, KRAKEN2_STUB).unwrap();
+    std::fs::set_permissions(&stub, std::fs::Permissions::from_mode(0o755)).unwrap();
+    // Sequences over the 1 MB chunk size, so each one is a chunk
+    let sequence = "ACGTTGCA".repeat(10).repeat(14_000);
+    let library: String = ["seqE", "seqA", "seqS"]
+        .iter()
+        .map(|seqid| format!(">{}\n{}\n", seqid, sequence))
+        .collect();
+    std::fs::write(db.join("library/library.fna"), library).unwrap();
+
+    let build = |fail: &str| {
+        let output = Command::new(env!("CARGO_BIN_EXE_bracken"))
+            .args(["build", "--chunk-size", "1", "--db"])
+            .arg(&db)
+            .arg("--kraken2")
+            .arg(&stub)
+            .env("STUB_FAIL", fail)
+            .output()
+            .expect("failed to run bracken");
+        let log = String::from_utf8_lossy(&output.stderr).to_string();
+        (output.status.success(), log)
+    };
+    let (interrupted_ok, _) = build("seqS");
+    let (resumed_ok, resumed_log) = build("");
+    let calls = std::fs::read_to_string(dir.join("calls")).unwrap();
+    let kraken = std::fs::read_to_string(db.join("database.kraken")).unwrap();
+    let parts_left = db.join("database.kraken.parts").exists();
+    std::fs::remove_dir_all(&dir).ok();
+
+    assert!(!interrupted_ok);
+    assert!(resumed_ok);
+    assert!(resumed_log.contains("Reusing chunk 0"));
+    assert!(resumed_log.contains("Reusing chunk 1"));
+    assert!(resumed_log.contains("Classifying chunk 2"));
+    // Chunks 0 and 1 and the failed chunk 2, then chunk 2 again
+    assert_eq!(calls.lines().count(), 4);
+    let expected = std::fs::read_to_string(self_test.join("database.kraken")).unwrap();
+    assert_eq!(kraken, expected);
+    assert!(!parts_left);
+}
+
+#[test]
+fn kraken2_databases_read_their_minimizer_length() {
+    let db = output_path("minimizers").with_extension("");
+    self_test_db(&db, true);
+    // opts.k2d of a database of 35-mers with 31-mer minimizers
+    let opts: Vec<u8> = [35u64, 31].iter().flat_map(|v| v.to_le_bytes()).collect();
+    std::fs::write(db.join("opts.k2d"), opts).unwrap();
+    let explicit = db.join("explicit.kraken_cnts");
+    let from_db = db.join("from_db.kraken_cnts");
+    let kmer2read_distr = |output: &Path, args: &[&str]| {
+        let status = Command::new(env!("CARGO_BIN_EXE_bracken"))
+            .args(["kmer2read-distr", "--output"])
+            .arg(output)
+            .args(args)
+            .status()
+            .expect("failed to run bracken");
+        assert!(status.success());
+    };
+    let kraken = db.join("database.kraken");
+    let seqid2taxid = db.join("seqid2taxid.map");
+    let taxonomy = db.join("taxonomy");
+    kmer2read_distr(
+        &explicit,
+        &[
+            "-k",
+            "35",
+            "--minimizer-len",
+            "31",
+            "--kraken",
+            kraken.to_str().unwrap(),
+            "--seqid2taxid",
+            seqid2taxid.to_str().unwrap(),
+            "--taxonomy",
+            taxonomy.to_str().unwrap(),
+        ],
+    );
+    kmer2read_distr(&from_db, &["--db", db.to_str().unwrap()]);
+    let status = Command::new(env!("CARGO_BIN_EXE_bracken"))
+        .args(["build", "--db"])
+        .arg(&db)
+        .status()
+        .expect("failed to run bracken");
+    assert!(status.success());
+    let body = |path: &Path| {
+        let content = std::fs::read_to_string(path).unwrap();
+        let lines: Vec<String> = content
+            .lines()
+            .filter(|line| !line.starts_with('#'))
+            .map(str::to_string)
+            .collect();
+        (content, lines)
+    };
+    let (_, explicit_rows) = body(&explicit);
+    let (from_db_content, from_db_rows) = body(&from_db);
+    let (_, built_rows) = body(&db.join("database100mers.kraken_cnts"));
+    std::fs::remove_dir_all(&db).ok();
+
+    assert!(from_db_content.contains("minimizer_len=31"));
+    assert_eq!(from_db_rows, explicit_rows);
+    assert_eq!(built_rows, explicit_rows);
+}
+
+#[test]
+fn compressed_builds_are_found_by_read_length() {
+    let dir = output_path("compressed-build").with_extension("");
+    let (plain_db, gzip_db) = (dir.join("plain"), dir.join("gzip"));
+    self_test_db(&plain_db, true);
+    self_test_db(&gzip_db, true);
+    let build = |db: &Path, extra_args: &[&str]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_bracken"))
+            .args(["build", "--db"])
+            .arg(db)
+            .args(extra_args)
+            .output()
+            .expect("failed to run bracken");
+        assert!(output.status.success());
+        String::from_utf8_lossy(&output.stderr).to_string()
+    };
+    let est_abundance = |db: &Path, name: &str| {
+        let output = dir.join(name);
+        let status = Command::new(env!("CARGO_BIN_EXE_bracken"))
+            .args(["est-abundance", "-i", "tests/data/self_test/report.kreport"])
+            .arg("--database-dir")
+            .arg(db)
+            .args(["--read-len", "100", "-o"])
+            .arg(&output)
+            .status()
+            .expect("failed to run bracken");
+        assert!(status.success());
+        read_rows(&output).1
+    };
+    build(&plain_db, &[]);
+    build(&gzip_db, &["--gzip-output"]);
+    let rebuild_log = build(&gzip_db, &["--gzip-output"]);
+    let plain_rows = est_abundance(&plain_db, "plain.bracken");
+    let gzip_rows = est_abundance(&gzip_db, "gzip.bracken");
+    let compressed = gzip_db.join("database100mers.kmer_distrib.gz").exists();
+    let uncompressed = gzip_db.join("database100mers.kmer_distrib").exists();
+    std::fs::remove_dir_all(&dir).ok();
+
+    assert!(compressed && !uncompressed);
+    assert!(rebuild_log.contains("Reusing \"") && rebuild_log.contains("kmer_distrib.gz"));
+    assert!(!rebuild_log.contains("Generating"));
+    assert!(!gzip_rows.is_empty());
+    assert_eq!(gzip_rows, plain_rows);
+}
+
+#[test]
+fn pipeline_skips_completed_stages() {
+    let outdir = output_path("pipeline").with_extension("");
+    let run_pipeline = || {
+        let output = Command::new(env!("CARGO_BIN_EXE_bracken"))
+            .args(["pipeline", "--db", "tests/data", "-r", REPORT, "--outdir"])
+            .arg(&outdir)
+            .args(["--", "-l", "G"])
+            .output()
+            .expect("failed to run bracken");
+        assert!(output.status.success());
+        String::from_utf8_lossy(&output.stderr).to_string()
+    };
+    let first = run_pipeline();
+    let second = run_pipeline();
+    let (_, rows) = read_rows(&outdir.join("report.bracken"));
+    let (_, expected_rows) = read_rows(Path::new("tests/data/expected_bracken_G.tsv"));
+    let manifest = std::fs::read_to_string(outdir.join("manifest.json")).unwrap();
+    std::fs::remove_dir_all(&outdir).ok();
+
+    assert!(!first.contains("already completed"));
+    assert!(second.contains("Stage est-abundance:report already completed"));
+    assert!(manifest.contains("report.bracken"));
+    assert_eq!(
+        rows.keys().collect::<Vec<_>>(),
+        expected_rows.keys().collect::<Vec<_>>()
+    );
+}
+
+#[test]
+fn build_writes_the_distributions_of_every_read_length() {
+    let db = output_path("build").with_extension("");
+    self_test_db(&db, true);
+    let build = |extra_args: &[&str]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_bracken"))
+            .args(["build", "-k", "35", "-l", "50,100", "-t", "2", "--db"])
+            .arg(&db)
+            .args(extra_args)
+            .output()
+            .expect("failed to run bracken");
+        assert!(output.status.success());
+        String::from_utf8_lossy(&output.stderr).to_string()
+    };
+    // the genomes of a mapped taxid are in no particular order
+    let sorted_lines = |content: &str| {
+        let mut lines: Vec<Vec<String>> = content
+            .lines()
+            .filter(|line| !line.starts_with('#'))
+            .map(|line| {
+                let mut items: Vec<String> = line.split(['\t', ' ']).map(String::from).collect();
+                items[1..].sort_unstable();
+                items
+            })
+            .collect();
+        lines.sort();
+        lines
+    };
+    build(&[]);
+    let rebuild_log = build(&[]);
+    let forced_log = build(&["--force"]);
+    let outputs: Vec<bool> = ["50", "100"]
+        .iter()
+        .flat_map(|len| ["kraken_cnts", "kmer_distrib"].map(|ext| (len, ext)))
+        .map(|(len, ext)| db.join(format!("database{}mers.{}", len, ext)).exists())
+        .collect();
+    let kmer_distr = std::fs::read_to_string(db.join("database50mers.kmer_distrib")).unwrap();
+    std::fs::remove_dir_all(&db).ok();
+
+    assert_eq!(outputs, [true; 4]);
+    let expected = std::fs::read_to_string("tests/data/self_test/expected.kmer_distrib").unwrap();
+    assert_eq!(sorted_lines(&kmer_distr), sorted_lines(&expected));
+    assert_eq!(rebuild_log.matches("Reusing").count(), 4);
+    assert!(!forced_log.contains("Reusing"));
+}
+
+#[test]
+fn bench_times_every_stage_on_synthetic_inputs() {
+    let workdir = output_path("bench").with_extension("");
+    let bench = |extra_args: &[&str]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_bracken"))
+            .args(["bench", "--synthetic-genomes", "5"])
+            .args(["--synthetic-seq-len", "2000", "--threads", "2", "--workdir"])
+            .arg(&workdir)
+            .args(extra_args)
+            .output()
+            .expect("failed to run bracken");
+        assert!(output.status.success());
+        String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .skip(1)
+            .map(|line| line.split('\t').map(String::from).collect::<Vec<_>>())
+            .collect::<Vec<_>>()
+    };
+    let timings = bench(&[]);
+    let removed = !workdir.exists();
+    bench(&["--keep"]);
+    let (_, rows) = read_rows(&workdir.join("report.bracken"));
+    std::fs::remove_dir_all(&workdir).ok();
+
+    let stages: Vec<&str> = timings.iter().map(|row| row[0].as_str()).collect();
+    assert_eq!(stages, ["kmer2read-distr", "kmer-distrib", "est-abundance"]);
+    for row in timings.iter() {
+        assert_eq!(row[1], "2");
+        assert!(row[4].parse::<f64>().unwrap() > 0.0);
+        assert!(row[5].parse::<f64>().unwrap() > 0.0);
+    }
+    // a line of read distributions and kmer distribution per genome
+    assert_eq!(timings[0][3], "5");
+    assert_eq!(timings[1][3], "5");
+    assert!(removed);
+    assert!(!rows.is_empty());
+}
+
+#[test]
+fn seqid2taxid_maps_library_accessions_through_an_index() {
+    use std::io::Write;
+    let dir = output_path("accessions").with_extension("");
+    std::fs::create_dir_all(dir.join("library/nested")).unwrap();
+    std::fs::write(
+        dir.join("library/a.fna"),
+        ">NC_000913.3 Escherichia coli\nACGT\n>NZ_UNKNOWN.1 unknown\nAC\n",
+    )
+    .unwrap();
+    let mut gz = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    gz.write_all(b">kraken:taxid|9606|NC_000001.11\nAC\n>CP000001.2 newer version\nA\n")
+        .unwrap();
+    std::fs::write(dir.join("library/nested/b.fna.gz"), gz.finish().unwrap()).unwrap();
+    let accession2taxid = dir.join("nucl.accession2taxid");
+    std::fs::write(
+        &accession2taxid,
+        "accession\taccession.version\ttaxid\tgi\n\
+         NC_000913\tNC_000913.3\t511145\t1\n\
+         CP000001\tCP000001.1\t28901\t2\n",
+    )
+    .unwrap();
+    let unmapped = dir.join("unmapped.txt");
+    let seqid2taxid = || {
+        let output = Command::new(env!("CARGO_BIN_EXE_bracken"))
+            .args(["seqid2taxid", "-o", "-", "-a"])
+            .arg(&accession2taxid)
+            .arg("--unmapped")
+            .arg(&unmapped)
+            .arg(dir.join("library"))
+            .output()
+            .expect("failed to run bracken");
+        assert!(output.status.success());
+        let map = String::from_utf8_lossy(&output.stdout).to_string();
+        (map, String::from_utf8_lossy(&output.stderr).to_string())
+    };
+    let (map, first_log) = seqid2taxid();
+    let (rerun_map, rerun_log) = seqid2taxid();
+    let index_built = dir.join("nucl.accession2taxid.idx").exists();
+    let unmapped = std::fs::read_to_string(&unmapped).unwrap();
+    std::fs::remove_dir_all(&dir).ok();
+
+    // the accession without its version matches other versions
+    assert_eq!(
+        map,
+        "NC_000913.3\t511145\nkraken:taxid|9606|NC_000001.11\t9606\nCP000001.2\t28901\n"
+    );
+    assert_eq!(unmapped, "NZ_UNKNOWN.1\n");
+    assert!(index_built);
+    assert!(first_log.contains("Building the accession index"));
+    assert!(!rerun_log.contains("Building the accession index"));
+    assert_eq!(rerun_map, map);
+}
+
+#[test]
+fn seqid2taxid_maps_assembly_files_through_the_assembly_summary() {
+    let dir = output_path("assemblies").with_extension("");
+    let library = dir.join("library");
+    std::fs::create_dir_all(library.join("GCF_000005845.2")).unwrap();
+    // an assembly folder, an assembly file and a file of no assembly
+    std::fs::write(
+        library.join("GCF_000005845.2/genomic.fna"),
+        ">NC_000913.3 chromosome\nACGT\n>pX plasmid\nAC\n",
+    )
+    .unwrap();
+    std::fs::write(
+        library.join("GCA_000001405.29_GRCh38_genomic.fna"),
+        ">chr1\nACGT\n",
+    )
+    .unwrap();
+    std::fs::write(library.join("other.fna"), ">other\nACGT\n").unwrap();
+    let summary = dir.join("assembly_summary.txt");
+    std::fs::write(
+        &summary,
+        "#   See ftp://ftp.ncbi.nlm.nih.gov/genomes/README_assembly_summary.txt\n\
+         #assembly_accession\tbioproject\tbiosample\twgs_master\trefseq_category\ttaxid\tspecies_taxid\torganism_name\n\
+         GCF_000005845.2\tPRJNA225\tSAMN02604091\t\treference genome\t511145\t562\tEscherichia coli\n\
+         GCA_000001405.29\tPRJNA31257\t\t\treference genome\t9606\t9606\tHomo sapiens\n",
+    )
+    .unwrap();
+    let seqid2taxid = |extra_args: &[&str]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_bracken"))
+            .args(["seqid2taxid", "-o", "-"])
+            .arg(&library)
+            .arg("--assembly-summary")
+            .arg(&summary)
+            .args(extra_args)
+            .output()
+            .expect("failed to run bracken");
+        assert!(output.status.success());
+        let mut lines: Vec<String> = String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .map(String::from)
+            .collect();
+        lines.sort();
+        lines
+    };
+    let strains = seqid2taxid(&[]);
+    let species = seqid2taxid(&["--species-taxid"]);
+    std::fs::remove_dir_all(&dir).ok();
+
+    assert_eq!(strains, ["NC_000913.3\t511145", "chr1\t9606", "pX\t511145"]);
+    assert_eq!(species, ["NC_000913.3\t562", "chr1\t9606", "pX\t562"]);
+}
@@ -0,0 +1,35 @@
+//! Contaminant scores of taxa across samples and negative controls.
+
+use bracken::decontam::{score, Method};
+use std::collections::BTreeSet;
+
+#[test]
+fn prevalence_scores_compare_controls_with_the_other_samples() {
+    let controls = BTreeSet::from([0]);
+    let totals = [1000, 1000, 1000];
+    let prevalence = |counts: &[usize]| score(Method::Prevalence, counts, &totals, &controls);
+
+    assert_eq!(prevalence(&[5, 0, 0]), 1.0);
+    assert_eq!(prevalence(&[5, 7, 0]), 1.0 / 1.5);
+    assert_eq!(prevalence(&[5, 7, 9]), 0.5);
+    assert_eq!(prevalence(&[0, 7, 9]), 0.0);
+    assert_eq!(prevalence(&[0, 0, 0]), 0.0);
+}
+
+#[test]
+fn frequency_scores_contaminants_whose_reads_do_not_grow_with_the_sample() {
+    let no_controls = BTreeSet::new();
+    let totals = [1000, 10_000, 100_000];
+    let frequency = |counts: &[usize], controls: &BTreeSet<usize>| {
+        score(Method::Frequency, counts, &totals, controls)
+    };
+
+    // the same reads in every sample, whatever its size
+    assert!(frequency(&[50, 50, 50], &no_controls) > 0.999);
+    // the same fraction of every sample
+    assert!(frequency(&[10, 100, 1000], &no_controls) < 0.001);
+    let between = frequency(&[20, 60, 400], &no_controls);
+    assert!(between > 0.0 && between < 1.0);
+    // a single sample left after the controls cannot be fitted
+    assert_eq!(frequency(&[50, 50, 50], &BTreeSet::from([0, 1])), 0.0);
+}
@@ -0,0 +1,59 @@
+//! Typed errors of the library, matched on their variants.
+
+mod common;
+
+use bracken::config::{self, ConfigError};
+use bracken::kraken::{self, KrakenError};
+use bracken::taxonomy::{NCBITaxonomy, TaxonomyError};
+use common::output_path;
+use std::error::Error;
+use std::path::PathBuf;
+
+#[test]
+fn errors_carry_their_kind_path_and_source() {
+    let invalid_level = kraken::level_branch("X").unwrap_err();
+    assert!(matches!(
+        invalid_level,
+        KrakenError::InvalidLevel { ref level, .. } if level == "X"
+    ));
+    assert!(kraken::level_branch("S1").is_ok());
+
+    let missing = PathBuf::from("tests/data/missing.kreport");
+    match kraken::read_kraken_report(&missing).unwrap_err() {
+        KrakenError::Io { path, source } => {
+            assert_eq!(path, missing);
+            assert_eq!(source.kind(), std::io::ErrorKind::NotFound);
+        }
+        other => panic!("unexpected error {:?}", other),
+    }
+
+    let kraken_output = output_path("kraken-output").with_extension("kraken");
+    std::fs::write(&kraken_output, "C\tread1\t562\t100\t562:66\n").unwrap();
+    let not_a_report = kraken::check_report_file(&kraken_output).unwrap_err();
+    let bad_config = output_path("bad-config").with_extension("toml");
+    std::fs::write(&bad_config, "threshold = \n").unwrap();
+    let invalid_config = config::load(&bad_config).unwrap_err();
+    let bad_nodes = output_path("bad-nodes").with_extension("dmp");
+    std::fs::write(&bad_nodes, "1\t|\t1\t|\tno rank\n").unwrap();
+    let invalid_nodes = match NCBITaxonomy::load_ncbi_dmp(&bad_nodes) {
+        Err(e) => e,
+        Ok(_) => panic!("invalid nodes.dmp loaded"),
+    };
+    for path in [kraken_output, bad_config, bad_nodes.clone()] {
+        std::fs::remove_file(path).ok();
+    }
+
+    assert!(matches!(not_a_report, KrakenError::KrakenOutput(_)));
+    assert!(not_a_report
+        .to_string()
+        .contains("--report option with Kraken"));
+    assert!(matches!(invalid_config, ConfigError::Toml { .. }));
+    assert!(invalid_config.source().is_some());
+    match invalid_nodes {
+        TaxonomyError::Parse { path, line, .. } => {
+            assert_eq!(path, bad_nodes);
+            assert_eq!(line, 1);
+        }
+        other => panic!("unexpected error {:?}", other),
+    }
+}
@@ -0,0 +1,673 @@
+//! Runs est-abundance on the shared fixtures and on small inline reports.
+
+mod common;
+
+use common::{output_path, read_rows, run_est_abundance, KMER_DISTR, REPORT};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Writes `content` to a file of the temporary directory unique to this run.
+fn write_fixture(name: &str, content: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!(
+        "bracken-est-abundance-{}-{}",
+        std::process::id(),
+        name
+    ));
+    std::fs::write(&path, content).unwrap();
+    path
+}
+
+/// Header and rows of an est-abundance run on the shared fixtures.
+fn estimate(name: &str, extra_args: &[&str]) -> (String, BTreeMap<u32, Vec<String>>) {
+    let output = run_est_abundance(name, extra_args);
+    let table = read_rows(&output);
+    std::fs::remove_file(output).ok();
+    table
+}
+
+/// Sum of a column of the rows.
+fn column_sum(rows: &BTreeMap<u32, Vec<String>>, col: usize) -> f64 {
+    rows.values()
+        .map(|row| row[col].parse::<f64>().unwrap())
+        .sum()
+}
+
+#[test]
+fn reads_below_the_level_are_added_to_their_level_taxon() {
+    let report = write_fixture(
+        "strain.kreport",
+        "100.00\t100\t0\tR\t1\troot\n\
+         100.00\t100\t40\tS\t562\t  Escherichia coli\n\
+         60.00\t60\t50\tS1\t83333\t    Escherichia coli K-12\n\
+         10.00\t10\t10\tS2\t511145\t      Escherichia coli str. K-12 substr. MG1655\n",
+    );
+    let kmer_distr = write_fixture(
+        "strain.kmer_distrib",
+        "mapped_taxid\tgenome_taxids:kmers_mapped:total_genome_kmers\n\
+         562\t562:100:100\n",
+    );
+    let output = std::env::temp_dir().join(format!(
+        "bracken-est-abundance-{}-strain.tsv",
+        std::process::id()
+    ));
+
+    let status = Command::new(env!("CARGO_BIN_EXE_bracken"))
+        .args(["est-abundance", "-l", "S", "-t", "0", "-i"])
+        .arg(&report)
+        .arg("-k")
+        .arg(&kmer_distr)
+        .arg("-o")
+        .arg(&output)
+        .status()
+        .expect("failed to run bracken");
+    let content = std::fs::read_to_string(&output).unwrap_or_default();
+    for path in [&report, &kmer_distr, &output] {
+        std::fs::remove_file(path).ok();
+    }
+
+    assert!(status.success());
+    let rows: Vec<Vec<&str>> = content
+        .lines()
+        .skip(1)
+        .map(|line| line.split('\t').collect())
+        .collect();
+    // the strain reads belong to E. coli instead of rows of their own
+    assert_eq!(rows.len(), 1);
+    assert_eq!(rows[0][1], "562");
+    assert_eq!(rows[0][5], "100");
+}
+
+#[test]
+fn largest_remainder_conserves_distributed_reads() {
+    let output = run_est_abundance("conserve", &[]);
+    let (_, rows) = read_rows(&output);
+    std::fs::remove_file(output).ok();
+
+    let column_sum = |col: usize| -> usize {
+        rows.values()
+            .map(|r| r[col].parse::<usize>().unwrap())
+            .sum()
+    };
+    // 330 reads above species level are distributed in the fixture
+    assert_eq!(column_sum(5), column_sum(3) + 330);
+}
+
+#[test]
+fn legacy_mode_keeps_previous_formula() {
+    let output = run_est_abundance(
+        "legacy",
+        &["--distribution-mode", "legacy", "--rounding", "truncate"],
+    );
+    let (_, rows) = read_rows(&output);
+    std::fs::remove_file(output).ok();
+
+    for row in rows.values() {
+        assert_eq!(row[4], "0");
+        assert_eq!(row[3], row[5]);
+    }
+}
+
+#[test]
+fn threshold_sweep_writes_one_output_per_threshold() {
+    let output = output_path("sweep");
+    let status = Command::new(env!("CARGO_BIN_EXE_bracken"))
+        .args(["est-abundance", "-i", REPORT, "-k", KMER_DISTR, "-o"])
+        .arg(&output)
+        .args(["-t", "0,50"])
+        .status()
+        .expect("failed to run bracken");
+    assert!(status.success());
+
+    let stem = output.file_stem().unwrap().to_string_lossy().to_string();
+    let per_threshold = |t: usize| output.with_file_name(format!("{}.t{}.tsv", stem, t));
+    let (_, rows_0) = read_rows(&per_threshold(0));
+    let (_, rows_50) = read_rows(&per_threshold(50));
+    let summary = std::fs::read_to_string(output.with_extension("thresholds.tsv")).unwrap();
+    for path in [
+        per_threshold(0),
+        per_threshold(50),
+        output.with_extension("thresholds.tsv"),
+    ] {
+        std::fs::remove_file(path).ok();
+    }
+
+    assert!(rows_50.len() < rows_0.len());
+    assert_eq!(summary.lines().count(), 3);
+}
+
+#[test]
+fn subsampling_rarefies_to_depth() {
+    let output = run_est_abundance("subsample", &["--subsample-depth", "500", "--seed", "3"]);
+    let (_, rows) = read_rows(&output);
+    std::fs::remove_file(output).ok();
+
+    let assigned: usize = rows.values().map(|r| r[3].parse::<usize>().unwrap()).sum();
+    assert!(assigned > 0 && assigned < 500);
+}
+
+#[test]
+fn bootstrap_intervals_bracket_the_estimates() {
+    // Salmonella bongori (8 reads) crosses the threshold in some replicates
+    let output = run_est_abundance("bootstrap", &["--bootstrap", "50", "-t", "9"]);
+    let (header, rows) = read_rows(&output);
+    std::fs::remove_file(output).ok();
+
+    assert!(header.ends_with("fraction_total_reads_ci95_high"));
+    for row in rows.values() {
+        let value = |col: usize| row[col].parse::<f64>().unwrap();
+        assert!(value(7) <= value(5) && value(5) <= value(8), "{:?}", row);
+        assert!(value(9) <= value(6) && value(6) <= value(10), "{:?}", row);
+    }
+}
+
+#[test]
+fn rank_tables_roll_up_the_level_to_the_ranks_above() {
+    // Homo sapiens has no genus in the report, so would be left out of G
+    let output = run_est_abundance("ranks", &["--all-ranks", "--exclude-subtree", "9606"]);
+    let genus = output.with_extension("G.tsv");
+    let new_est_reads = |path: &Path| -> usize {
+        let (_, rows) = read_rows(path);
+        rows.values().map(|r| r[5].parse::<usize>().unwrap()).sum()
+    };
+    let (species_total, genus_total) = (new_est_reads(&output), new_est_reads(&genus));
+    let species_table = output.with_extension("S.tsv").exists();
+    for rank in ["D", "P", "C", "O", "F", "G"] {
+        std::fs::remove_file(output.with_extension(format!("{}.tsv", rank))).ok();
+    }
+    std::fs::remove_file(&output).ok();
+
+    assert_eq!(genus_total, species_total);
+    assert!(!species_table);
+
+    // No tables below the level, also of levels above D
+    let output = run_est_abundance("ranks-r1", &["--all-ranks", "-l", "R1", "-t", "0"]);
+    let ranks = ["D", "P", "C", "O", "F", "G", "S"];
+    let tables = ranks.map(|rank| output.with_extension(format!("{}.tsv", rank)));
+    let written = tables.iter().any(|path| path.exists());
+    for path in tables {
+        std::fs::remove_file(path).ok();
+    }
+    std::fs::remove_file(&output).ok();
+    assert!(!written);
+}
+
+#[test]
+fn taxid_lists_filter_level_taxa_and_warn_about_unmatched_ones() {
+    let include = output_path("include").with_extension("txt");
+    let exclude = output_path("exclude").with_extension("txt");
+    // 561 is a genus, 99999999 no taxon at all
+    std::fs::write(&include, "562\n28901\n561\n").unwrap();
+    std::fs::write(&exclude, "562\n99999999\n").unwrap();
+    let output = output_path("taxid-lists");
+    let run = |list_option: &str, list: &Path| {
+        let result = Command::new(env!("CARGO_BIN_EXE_bracken"))
+            .args(["est-abundance", "-i", REPORT, "-k", KMER_DISTR, "-o"])
+            .arg(&output)
+            .arg(list_option)
+            .arg(list)
+            .output()
+            .expect("failed to run bracken");
+        assert!(result.status.success());
+        let (_, rows) = read_rows(&output);
+        (rows, String::from_utf8_lossy(&result.stderr).to_string())
+    };
+    let (included, include_log) = run("--include-taxids", &include);
+    let (excluded, exclude_log) = run("--exclude-taxids", &exclude);
+    for path in [include, exclude, output] {
+        std::fs::remove_file(path).ok();
+    }
+
+    assert_eq!(included.keys().copied().collect::<Vec<_>>(), [562, 28901]);
+    assert!(include_log.contains("1 of the 3 taxids of --include-taxids"));
+    assert!(!excluded.contains_key(&562) && excluded.contains_key(&28901));
+    assert!(exclude_log.contains("1 of the 2 taxids of --exclude-taxids"));
+}
+
+#[test]
+fn domains_are_resolved_through_the_taxonomy() {
+    let taxonomy = output_path("domain-taxonomy").with_extension("");
+    std::fs::create_dir_all(&taxonomy).unwrap();
+    // Viruses with the 2025 NCBI rank; the report taxa missing from the
+    // taxonomy are in no domain
+    let nodes = [
+        (1, 1, "no rank"),
+        (131567, 1, "no rank"),
+        (2759, 131567, "domain"),
+        (9606, 2759, "species"),
+        (10239, 1, "acellular root"),
+        (10663, 10239, "genus"),
+        (10665, 10663, "species"),
+    ];
+    let dmp = |fields: &[String]| format!("{}\t|\n", fields.join("\t|\t"));
+    // nodes.dmp has 13 columns
+    let nodes_dmp: String = nodes
+        .iter()
+        .map(|(taxid, parent, rank)| {
+            let mut fields = vec![taxid.to_string(), parent.to_string(), rank.to_string()];
+            fields.resize(13, String::new());
+            dmp(&fields)
+        })
+        .collect();
+    let names_dmp: String = [(2759, "Eukaryota"), (10239, "Viruses")]
+        .iter()
+        .map(|(taxid, name)| {
+            dmp(&[
+                taxid.to_string(),
+                name.to_string(),
+                String::new(),
+                "scientific name".to_string(),
+            ])
+        })
+        .collect();
+    std::fs::write(taxonomy.join("nodes.dmp"), nodes_dmp).unwrap();
+    std::fs::write(taxonomy.join("names.dmp"), names_dmp).unwrap();
+    let output = output_path("domain");
+    let run = |domains: &[&str]| {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_bracken"));
+        command
+            .args(["est-abundance", "-i", REPORT, "-k", KMER_DISTR, "-o"])
+            .arg(&output)
+            .arg("--taxonomy")
+            .arg(&taxonomy);
+        for domain in domains {
+            command.args(["--domain", domain]);
+        }
+        let result = command.output().expect("failed to run bracken");
+        let stderr = String::from_utf8_lossy(&result.stderr).to_string();
+        (result.status.success(), stderr)
+    };
+
+    let (viruses_ok, _) = run(&["viruses"]);
+    let (_, viruses) = read_rows(&output);
+    let (both_ok, _) = run(&["Viruses", "2759"]);
+    let (_, both) = read_rows(&output);
+    let (typo_ok, typo_log) = run(&["Virusez"]);
+    std::fs::remove_dir_all(&taxonomy).ok();
+    std::fs::remove_file(&output).ok();
+
+    assert!(viruses_ok && both_ok);
+    assert_eq!(viruses.keys().copied().collect::<Vec<_>>(), [10665]);
+    assert_eq!(both.keys().copied().collect::<Vec<_>>(), [9606, 10665]);
+    assert!(!typo_ok);
+    assert!(typo_log.contains("\"Virusez\" is no domain"));
+}
+
+#[test]
+fn sensitivity_reports_use_the_abundance_unit_and_a_fraction_window() {
+    let report = output_path("sensitivity").with_extension("report.tsv");
+    let sensitivity = |name: &str, extra_args: &[&str]| {
+        let mut args = vec!["--sensitivity-report", report.to_str().unwrap()];
+        args.extend(extra_args);
+        std::fs::remove_file(run_est_abundance(name, &args)).ok();
+        let content = std::fs::read_to_string(&report).unwrap();
+        std::fs::remove_file(&report).ok();
+        content
+    };
+    let default = sensitivity("sensitivity-default", &[]);
+    let percent = sensitivity("sensitivity-percent", &["--normalize", "percent"]);
+    let zero = sensitivity("sensitivity-zero", &["-t", "0"]);
+    // 0.5% of the reads, over the 8 reads of Salmonella bongori
+    let zero_window = sensitivity(
+        "sensitivity-zero-window",
+        &[
+            "-t",
+            "0",
+            "--normalize",
+            "percent",
+            "--sensitivity-fraction-window",
+            "0.5",
+        ],
+    );
+    let taxids = |content: &str| -> Vec<String> {
+        content
+            .lines()
+            .skip(1)
+            .map(|line| line.split('\t').nth(1).unwrap().to_string())
+            .collect()
+    };
+
+    // Salmonella bongori has 8 reads, within 5 and 15 around the threshold
+    assert!(default.starts_with("name\t") && default.contains("\tfraction_total_reads\n"));
+    assert_eq!(taxids(&default), ["54736"]);
+    assert!(default.contains("\t8\t10\t-2\tbelow_threshold\t0\t0.00000\n"));
+    assert!(percent.contains("\tpercent_total_reads\n"));
+    assert_eq!(taxids(&zero), Vec::<String>::new());
+    assert!(zero_window.contains("\tpercent_total_reads\n"));
+    assert_eq!(taxids(&zero_window), ["2614656", "54736"]);
+    let kept = zero_window.lines().last().unwrap();
+    assert!(kept.contains("\t8\t0\t8\tkept\t9\t0.539"));
+}
+
+#[test]
+fn summary_json_lists_every_parameter_changing_the_output() {
+    let summary = output_path("summary").with_extension("json");
+    let include = output_path("summary-include").with_extension("txt");
+    std::fs::write(&include, "562\n28901\n").unwrap();
+    let output = run_est_abundance(
+        "summary",
+        &[
+            "--summary-json",
+            summary.to_str().unwrap(),
+            "--normalize",
+            "percent",
+            "--spike-in",
+            "28901:1000",
+            "--include-taxids",
+            include.to_str().unwrap(),
+            "--names",
+            "tests/data/self_test/names.dmp",
+            "--sort",
+            "name",
+        ],
+    );
+    let content = std::fs::read_to_string(&summary).unwrap();
+    for path in [output, summary, include.clone()] {
+        std::fs::remove_file(path).ok();
+    }
+
+    let json: serde_json::Value = serde_json::from_str(&content).unwrap();
+    let parameters = &json["parameters"];
+    assert_eq!(parameters["normalize"], "percent");
+    assert_eq!(parameters["spike_in"]["taxid"], 28901);
+    assert_eq!(parameters["spike_in"]["cells"], 1000.0);
+    assert_eq!(parameters["include_taxids"], include.to_str().unwrap());
+    assert_eq!(parameters["exclude_taxids"], serde_json::Value::Null);
+    assert_eq!(parameters["names"], "tests/data/self_test/names.dmp");
+    assert_eq!(parameters["sort"], "name");
+    assert_eq!(parameters["name_style"], "raw");
+    assert_eq!(parameters["threshold"], 10);
+}
+
+#[test]
+fn diagnostics_skip_report_levels_outside_the_main_ranks() {
+    let report = write_fixture(
+        "no-rank.kreport",
+        "100.00\t100\t0\tR\t1\troot\n\
+         100.00\t100\t10\t-\t131567\t  cellular organisms\n\
+         90.00\t90\t90\tS\t562\t    Escherichia coli\n",
+    );
+    let kmer_distr = write_fixture(
+        "no-rank.kmer_distrib",
+        "mapped_taxid\tgenome_taxids:kmers_mapped:total_genome_kmers\n\
+         562\t562:100:100\n\
+         9606\t9606:100:100\n",
+    );
+    let output = output_path("no-rank");
+    let diagnostics = output_path("no-rank-diagnostics");
+
+    let status = Command::new(env!("CARGO_BIN_EXE_bracken"))
+        .args(["est-abundance", "-i"])
+        .arg(&report)
+        .arg("-k")
+        .arg(&kmer_distr)
+        .arg("-o")
+        .arg(&output)
+        .arg("--diagnostics")
+        .arg(&diagnostics)
+        .status()
+        .expect("failed to run bracken");
+    let content = std::fs::read_to_string(&diagnostics).unwrap_or_default();
+    for path in [&report, &kmer_distr, &output, &diagnostics] {
+        std::fs::remove_file(path).ok();
+    }
+
+    assert!(status.success());
+    assert!(!content.contains("\t131567\t"));
+    // the genome of Homo sapiens is still reported as absent from the report
+    assert!(content.contains("9606"));
+}
+
+#[test]
+fn excluded_subtrees_leave_the_estimation_and_the_total() {
+    let (_, all) = estimate("exclude-none", &[]);
+    // Salmonella, with S. enterica, S. bongori and their strains
+    let (_, without_salmonella) = estimate("exclude-salmonella", &["--exclude-subtree", "590"]);
+    let (_, without_hosts) = estimate(
+        "exclude-hosts",
+        &["--exclude-subtree", "9606", "--exclude-subtree", "10239"],
+    );
+
+    assert!(all.contains_key(&28901));
+    assert!(!without_salmonella.contains_key(&28901));
+    assert_eq!(without_salmonella.len(), all.len() - 1);
+    // the fractions are relative to the reads left
+    assert!((column_sum(&without_salmonella, 6) - 1.0).abs() < 1e-3);
+    assert_eq!(
+        without_hosts.keys().copied().collect::<Vec<_>>(),
+        [562, 28901, 208962]
+    );
+}
+
+#[test]
+fn normalized_abundances_scale_the_fractions() {
+    let (fraction_header, fractions) = estimate("normalize-fraction", &[]);
+    let (percent_header, percents) = estimate("normalize-percent", &["--normalize", "percent"]);
+    let (rpm_header, rpms) = estimate("normalize-rpm", &["--normalize", "rpm"]);
+
+    assert!(fraction_header.ends_with("\tfraction_total_reads"));
+    assert!(percent_header.ends_with("\tpercent_total_reads"));
+    assert!(rpm_header.ends_with("\trpm_total_reads"));
+    // E. coli has 661 of the 1660 estimated reads
+    assert_eq!(fractions[&562][6], "0.39819");
+    assert_eq!(percents[&562][6], "39.819");
+    assert_eq!(rpms[&562][6], "398192.77");
+    assert!((column_sum(&percents, 6) - 100.0).abs() < 1e-2);
+    assert!((column_sum(&rpms, 6) - 1e6).abs() < 1.0);
+}
+
+#[test]
+fn spike_ins_scale_every_estimate_to_absolute_abundances() {
+    // 1000 copies of Escherichia phage T4, estimated at 168 reads
+    let (header, rows) = estimate("spike-in", &["--spike-in", "10665:1000"]);
+    let (_, doubled) = estimate("spike-in-doubled", &["--spike-in", "10665:2000"]);
+    let output = output_path("spike-in-missing");
+    // Salmonella bongori is below the threshold
+    let missing = Command::new(env!("CARGO_BIN_EXE_bracken"))
+        .args(["est-abundance", "-i", REPORT, "-k", KMER_DISTR, "-o"])
+        .arg(&output)
+        .args(["--spike-in", "54736:1000"])
+        .output()
+        .expect("failed to run bracken");
+    std::fs::remove_file(&output).ok();
+
+    assert!(header.ends_with("\tfraction_total_reads\tabsolute_abundance"));
+    assert_eq!(rows[&10665][7], "1000.00");
+    for (taxid, row) in rows.iter() {
+        let reads: f64 = row[5].parse().unwrap();
+        let absolute: f64 = row[7].parse().unwrap();
+        assert!(
+            (absolute - reads / 168.0 * 1000.0).abs() < 0.01,
+            "{:?}",
+            row
+        );
+        let doubled: f64 = doubled[taxid][7].parse().unwrap();
+        assert!((doubled - 2.0 * absolute).abs() < 0.02);
+    }
+    assert!(!missing.status.success());
+    assert!(String::from_utf8_lossy(&missing.stderr).contains("no estimated reads"));
+}
+
+#[test]
+fn em_rounds_converge_to_a_fixed_point() {
+    let em = |name: &str, extra_args: &[&str]| {
+        let summary = output_path(name).with_extension("json");
+        let mut args = vec!["--summary-json", summary.to_str().unwrap()];
+        args.extend(extra_args);
+        let (_, rows) = estimate(name, &args);
+        let json: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&summary).unwrap()).unwrap();
+        std::fs::remove_file(&summary).ok();
+        (rows, json["stats"]["em_rounds"].as_u64().unwrap())
+    };
+    let (single, single_rounds) = em("em-single", &[]);
+    let (converged, rounds) = em("em-50", &["--em-iterations", "50"]);
+    let (longer, longer_rounds) = em("em-500", &["--em-iterations", "500"]);
+    let (exhaustive, exhaustive_rounds) = em(
+        "em-exhaustive",
+        &["--em-iterations", "100", "--em-tolerance", "0"],
+    );
+
+    assert_eq!(single_rounds, 1);
+    // the tolerance stops the rounds long before the limit
+    assert!(rounds > 1 && rounds < 50);
+    assert_eq!(longer_rounds, rounds);
+    assert_eq!(longer, converged);
+    assert_eq!(exhaustive_rounds, 100);
+    assert_eq!(exhaustive, converged);
+    // the priors move the reads between genomes without losing any
+    assert_ne!(converged[&562][5], single[&562][5]);
+    assert_eq!(column_sum(&converged, 5), column_sum(&single, 5));
+}
+
+#[test]
+fn largest_remainder_rounding_loses_no_reads_at_any_level() {
+    for level in ["S", "G"] {
+        let rounded = |rounding: &str, seed: &str| {
+            let name = format!("rounding-{}-{}-{}", level, rounding, seed);
+            let (_, rows) = estimate(
+                &name,
+                &["-l", level, "--rounding", rounding, "--seed", seed],
+            );
+            column_sum(&rows, 5)
+        };
+        let largest_remainder = rounded("largest-remainder", "0");
+        // stochastic rounding hands out every read as well, whatever the seed
+        assert_eq!(largest_remainder, rounded("stochastic", "1"));
+        assert_eq!(largest_remainder, rounded("stochastic", "2"));
+        assert!(rounded("truncate", "0") < largest_remainder);
+        assert!(rounded("none", "0") <= largest_remainder);
+    }
+}
+
+#[test]
+fn output_rows_follow_the_sort_order() {
+    let order = |sort: &str| -> Vec<u32> {
+        let output = run_est_abundance(&format!("sort-{}", sort), &["--sort", sort]);
+        let content = std::fs::read_to_string(&output).unwrap();
+        std::fs::remove_file(output).ok();
+        content
+            .lines()
+            .skip(1)
+            .map(|line| line.split('\t').nth(1).unwrap().parse().unwrap())
+            .collect()
+    };
+
+    assert_eq!(order("reads"), [562, 28901, 9606, 10665, 208962]);
+    // Escherichia albertii, E. coli, E. phage T4, Homo sapiens, S. enterica
+    assert_eq!(order("name"), [208962, 562, 10665, 9606, 28901]);
+    assert_eq!(order("taxid"), [562, 9606, 10665, 28901, 208962]);
+    // the order does not depend on the run
+    assert_eq!(order("reads"), order("reads"));
+}
+
+#[test]
+fn zero_count_rows_list_the_database_taxa_without_reads() {
+    let (_, rows) = estimate("zero-none", &[]);
+    let (_, with_zero) = estimate("zero-rows", &["--include-zero"]);
+
+    // Salmonella bongori is a genome of the distribution below the
+    // threshold; Salmonella sp. HNK130 is no genome of it
+    assert_eq!(with_zero.len(), rows.len() + 1);
+    assert_eq!(with_zero[&54736][3..], ["0", "0", "0", "0.00000"]);
+    assert!(!with_zero.contains_key(&2614656));
+    for (taxid, row) in rows.iter() {
+        assert_eq!(&with_zero[taxid], row);
+    }
+}
+
+#[test]
+fn discarded_taxa_are_written_with_their_reads_and_threshold() {
+    let discarded = output_path("discarded").with_extension("discarded.tsv");
+    let (_, rows) = estimate(
+        "discarded",
+        &["-t", "120", "--out-discarded", discarded.to_str().unwrap()],
+    );
+    let content = std::fs::read_to_string(&discarded).unwrap();
+    std::fs::remove_file(&discarded).ok();
+
+    let mut lines = content.lines();
+    assert_eq!(
+        lines.next(),
+        Some("name\ttaxonomy_id\ttaxonomy_lvl\tkraken_assigned_reads\tthreshold")
+    );
+    let taxids: Vec<u32> = lines
+        .map(|line| {
+            let fields: Vec<&str> = line.split('\t').collect();
+            assert_eq!(fields[4], "120");
+            assert!(fields[3].parse::<usize>().unwrap() < 120);
+            fields[1].parse().unwrap()
+        })
+        .collect();
+    assert_eq!(taxids, [208962, 54736, 2614656]);
+    assert!(taxids.iter().all(|taxid| !rows.contains_key(taxid)));
+}
+
+#[test]
+fn nondistributed_reads_are_listed_per_node_with_their_reason() {
+    let nondistributed = |threshold: &str| {
+        let path = output_path(&format!("nondistributed-{}", threshold));
+        let summary = path.with_extension("json");
+        let name = format!("nondistributed-output-{}", threshold);
+        estimate(
+            &name,
+            &[
+                "-t",
+                threshold,
+                "--out-nondistributed",
+                path.to_str().unwrap(),
+                "--summary-json",
+                summary.to_str().unwrap(),
+            ],
+        );
+        let content = std::fs::read_to_string(&path).unwrap();
+        let json: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&summary).unwrap()).unwrap();
+        std::fs::remove_file(path).ok();
+        std::fs::remove_file(summary).ok();
+        let nodes: BTreeMap<u32, (usize, String)> = content
+            .lines()
+            .skip(1)
+            .map(|line| {
+                let fields: Vec<&str> = line.split('\t').collect();
+                let reads = fields[3].parse().unwrap();
+                (fields[1].parse().unwrap(), (reads, fields[4].to_string()))
+            })
+            .collect();
+        let total = json["stats"]["nondistributed_reads"].as_u64().unwrap() as usize;
+        (nodes, total)
+    };
+    let (default, default_total) = nondistributed("10");
+    // Salmonella enterica, Homo sapiens and the phage T4 are dropped
+    let (high, high_total) = nondistributed("450");
+
+    let reason = |nodes: &BTreeMap<u32, (usize, String)>, taxid: u32| nodes[&taxid].1.clone();
+    // Proteobacteria and Gammaproteobacteria are no mapped taxids
+    assert_eq!(default.keys().copied().collect::<Vec<_>>(), [1224, 1236]);
+    assert_eq!(reason(&default, 1224), "no_genomes");
+    assert_eq!(default_total, 70);
+    for taxid in [590, 2759, 10239, 10663] {
+        assert_eq!(reason(&high, taxid), "no_kept_genomes");
+    }
+    assert_eq!(reason(&high, 1236), "no_genomes");
+    assert_eq!(
+        high.values().map(|(reads, _)| reads).sum::<usize>(),
+        high_total
+    );
+}
+
+#[test]
+fn merged_taxids_are_remapped_in_the_report_and_the_distribution() {
+    // Escherichia coli as if merged into a newer taxid
+    let merged = write_fixture("merged.dmp", "562\t|\t999562\t|\n");
+    let (_, rows) = estimate("merged-none", &[]);
+    let (_, remapped) = estimate("merged", &["--merged", merged.to_str().unwrap()]);
+    std::fs::remove_file(&merged).ok();
+
+    assert!(!remapped.contains_key(&562));
+    // the report and the distribution agree on the new taxid, so the
+    // estimate is unchanged
+    assert_eq!(remapped[&999562][1], "999562");
+    assert_eq!(remapped[&999562][2..], rows[&562][2..]);
+    assert_eq!(remapped.len(), rows.len());
+}
@@ -0,0 +1,923 @@
+//! Read distributions of the database genomes computed by kmer2read-distr.
+
+mod common;
+
+use common::output_path;
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+const SEQID2TAXID: &str = "tests/data/self_test/seqid2taxid.map";
+const KRAKEN: &str = "tests/data/self_test/database.kraken";
+
+/// Runs kmer2read-distr with the self-test taxonomy, 5-mers and 8 bp reads
+/// (4 kmers each); returns the run and the read distributions it wrote.
+fn kmer2read_distr(name: &str, args: &[&str]) -> (Output, String) {
+    let output = output_path(name);
+    let run = Command::new(env!("CARGO_BIN_EXE_bracken"))
+        .args(["kmer2read-distr", "--taxonomy", "tests/data/self_test"])
+        .args(["-k", "5", "-l", "8", "--output"])
+        .arg(&output)
+        .args(args)
+        .output()
+        .expect("failed to run bracken");
+    let content = std::fs::read_to_string(&output).unwrap_or_default();
+    std::fs::remove_file(&output).ok();
+    // Failed runs leave their partial output in a temporary file
+    std::fs::remove_file(output.with_extension("tsv.tmp")).ok();
+    (run, content)
+}
+
+/// Writes a seqid2taxid.map and a kraken file of `n` sequences of the
+/// self-test genomes with kmers of the genome and of its ancestors.
+fn synthetic_library(name: &str, n: usize) -> (PathBuf, PathBuf) {
+    let genomes = [
+        (562, 561),
+        (208962, 561),
+        (28901, 590),
+        (9606, 2759),
+        (10665, 10239),
+    ];
+    let mut map = String::new();
+    let mut kraken = String::new();
+    for i in 0..n {
+        let (taxid, ancestor) = genomes[i % genomes.len()];
+        map.push_str(&format!("seq{}\t{}\n", i, taxid));
+        kraken.push_str(&format!(
+            "C\tseq{}\t{}\t100\t{}:{} {}:{} 0:{} {}:{}\n",
+            i,
+            taxid,
+            taxid,
+            4 + i % 13,
+            ancestor,
+            1 + i % 7,
+            i % 3,
+            taxid,
+            2 + i % 5
+        ));
+    }
+    let map_path = output_path(name).with_extension("map");
+    let kraken_path = output_path(name).with_extension("kraken");
+    std::fs::write(&map_path, map).unwrap();
+    std::fs::write(&kraken_path, kraken).unwrap();
+    (map_path, kraken_path)
+}
+
+/// Writes a seqid2taxid.map and a kraken file of seqE, whose reads are
+/// classified to E. coli or Escherichia, and seqO, whose 13 reads go to
+/// E. coli (2), cellular organisms (1), Homo sapiens (7) and no taxon (3).
+fn mixed_library(name: &str) -> (PathBuf, PathBuf) {
+    let map = output_path(name).with_extension("map");
+    std::fs::write(&map, "seqE\t562\nseqO\t562\nseqH\t9606\n").unwrap();
+    let kraken = output_path(name).with_extension("kraken");
+    std::fs::write(
+        &kraken,
+        "C\tseqE\t562\t234\t562:150 561:30 562:20\n\
+         C\tseqO\t562\t20\t562:4 9606:6 0:6\n",
+    )
+    .unwrap();
+    (map, kraken)
+}
+
+#[test]
+fn multiple_read_lengths_match_single_length_runs() {
+    let kmer2read_distr = |read_lens: &str, output: &Path| {
+        let status = Command::new(env!("CARGO_BIN_EXE_bracken"))
+            .args(["-q", "kmer2read-distr"])
+            .args(["--seqid2taxid", "tests/data/self_test/seqid2taxid.map"])
+            .args(["--taxonomy", "tests/data/self_test"])
+            .args(["--kraken", "tests/data/self_test/database.kraken"])
+            .args(["-k", "35", "-l", read_lens, "--ordered", "--output"])
+            .arg(output)
+            .status()
+            .expect("failed to run bracken");
+        assert!(status.success());
+    };
+    let single = output_path("single-length");
+    let multiple = output_path("length-{L}");
+    kmer2read_distr("50", &single);
+    kmer2read_distr("40,50", &multiple);
+    let multiple =
+        |read_len: &str| PathBuf::from(multiple.to_string_lossy().replace("{L}", read_len));
+    let (single_cnts, multiple_cnts) = (
+        std::fs::read_to_string(&single).unwrap(),
+        std::fs::read_to_string(multiple("50")).unwrap(),
+    );
+    for path in [single, multiple("40"), multiple("50")] {
+        assert!(path.exists());
+        std::fs::remove_file(path).ok();
+    }
+
+    assert_eq!(multiple_cnts, single_cnts);
+}
+
+#[test]
+fn merged_chunks_match_a_single_run() {
+    let kmer2read_distr = |chunk: Option<&str>, output: &Path| {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_bracken"));
+        command
+            .args(["-q", "kmer2read-distr"])
+            .args(["--seqid2taxid", "tests/data/self_test/seqid2taxid.map"])
+            .args(["--taxonomy", "tests/data/self_test"])
+            .args(["--kraken", "tests/data/self_test/database.kraken"])
+            .args(["-k", "35", "-l", "50", "--ordered", "--output"])
+            .arg(output);
+        if let Some(chunk) = chunk {
+            command.args(["--chunk", chunk]);
+        }
+        let status = command.status().expect("failed to run bracken");
+        assert!(status.success());
+    };
+    let single = output_path("unchunked");
+    let chunks = [output_path("chunk-1"), output_path("chunk-2")];
+    let merged = output_path("merged-chunks");
+    kmer2read_distr(None, &single);
+    kmer2read_distr(Some("1/2"), &chunks[0]);
+    kmer2read_distr(Some("2/2"), &chunks[1]);
+    let status = Command::new(env!("CARGO_BIN_EXE_bracken"))
+        .args(["-q", "merge-cnts", "-i"])
+        .args(&chunks)
+        .arg("-o")
+        .arg(&merged)
+        .status()
+        .expect("failed to run bracken");
+    assert!(status.success());
+    let sorted_lines = |path: &Path| {
+        let mut lines: Vec<String> = std::fs::read_to_string(path)
+            .unwrap()
+            .lines()
+            // Chunks have a header line, unlike the single run without --db
+            .filter(|line| !line.starts_with('#'))
+            .map(str::to_string)
+            .collect();
+        lines.sort();
+        lines
+    };
+    let (single_lines, merged_lines) = (sorted_lines(&single), sorted_lines(&merged));
+    for path in chunks.iter().chain([&single, &merged]) {
+        std::fs::remove_file(path).ok();
+    }
+
+    assert_eq!(merged_lines, single_lines);
+}
+
+#[test]
+fn compressed_kraken_files_are_detected_by_their_magic() {
+    let lines = std::fs::read_to_string(KRAKEN).unwrap();
+    let (head, tail) = lines.split_at(lines.find("C\tseqS").unwrap());
+    let gzip = |text: &str| {
+        let mut gz = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        gz.write_all(text.as_bytes()).unwrap();
+        gz.finish().unwrap()
+    };
+    // two gzip members, as concatenated .gz files are, without a .gz suffix
+    let gzipped = output_path("kraken-gzip").with_extension("kraken");
+    std::fs::write(&gzipped, [gzip(head), gzip(tail)].concat()).unwrap();
+    let zstd = output_path("kraken-zstd").with_extension("kraken.zst");
+    std::fs::write(&zstd, zstd::encode_all(lines.as_bytes(), 0).unwrap()).unwrap();
+    let convert = |name: &str, kraken: &Path| {
+        let (run, content) = kmer2read_distr(
+            name,
+            &[
+                "--seqid2taxid",
+                SEQID2TAXID,
+                "--ordered",
+                "--kraken",
+                kraken.to_str().unwrap(),
+            ],
+        );
+        assert!(run.status.success());
+        content
+    };
+    let plain = convert("kraken-plain-cnts", Path::new(KRAKEN));
+    let from_gzip = convert("kraken-gzip-cnts", &gzipped);
+    let from_zstd = convert("kraken-zstd-cnts", &zstd);
+    std::fs::remove_file(&gzipped).ok();
+    std::fs::remove_file(&zstd).ok();
+
+    assert_eq!(plain.lines().count(), 3);
+    assert_eq!(from_gzip, plain);
+    assert_eq!(from_zstd, plain);
+}
+
+#[test]
+fn ordered_outputs_do_not_depend_on_the_threads() {
+    let (map, kraken) = synthetic_library("ordered", 5000);
+    let convert = |name: &str, extra_args: &[&str]| {
+        let mut args = vec![
+            "--seqid2taxid",
+            map.to_str().unwrap(),
+            "--kraken",
+            kraken.to_str().unwrap(),
+            // batches of 64 KiB, so the lines are spread over many batches
+            "--max-memory",
+            "1K",
+        ];
+        args.extend(extra_args);
+        let (run, content) = kmer2read_distr(name, &args);
+        assert!(run.status.success());
+        content
+    };
+    let single = convert("ordered-1", &["--ordered", "--threads", "1"]);
+    let parallel = convert("ordered-4", &["--ordered", "--threads", "4"]);
+    let unordered = convert("unordered-4", &["--threads", "4"]);
+    std::fs::remove_file(&map).ok();
+    std::fs::remove_file(&kraken).ok();
+
+    assert_eq!(parallel, single);
+    let seqids: Vec<&str> = single
+        .lines()
+        .map(|line| line.split('\t').next().unwrap())
+        .collect();
+    let expected: Vec<String> = (0..5000).map(|i| format!("seq{}", i)).collect();
+    assert_eq!(seqids, expected);
+    // the same lines, the taxids of a line sorted
+    let sorted_lines = |content: &str| {
+        let mut lines: Vec<Vec<String>> = content
+            .lines()
+            .map(|line| {
+                let mut items: Vec<String> = line.split(['\t', ' ']).map(String::from).collect();
+                items[3..].sort_unstable();
+                items
+            })
+            .collect();
+        lines.sort();
+        lines
+    };
+    assert_eq!(sorted_lines(&unordered), sorted_lines(&single));
+}
+
+#[test]
+fn paired_reads_are_classified_to_the_lca_of_their_mates() {
+    // 10 kmers of E. coli, then 10 of Homo sapiens: 17 reads of 4 kmers
+    let map = output_path("paired").with_extension("map");
+    // the taxonomy is pruned to the lineages of the genomes, so Homo
+    // sapiens needs a genome of its own
+    std::fs::write(&map, "seqP\t562\nseqH\t9606\n").unwrap();
+    let kraken = output_path("paired").with_extension("kraken");
+    std::fs::write(&kraken, "C\tseqP\t562\t27\t562:10 9606:10\n").unwrap();
+    let convert = |name: &str, extra_args: &[&str]| {
+        let mut args = vec![
+            "--seqid2taxid",
+            map.to_str().unwrap(),
+            "--kraken",
+            kraken.to_str().unwrap(),
+            "--ordered",
+        ];
+        args.extend(extra_args);
+        let (run, content) = kmer2read_distr(name, &args);
+        assert!(run.status.success());
+        content
+    };
+    let single = convert("single-end", &[]);
+    // mates 4 reads apart
+    let paired = convert("paired-end", &["--insert-size", "12"]);
+    let spread = convert("paired-sd", &["--insert-size", "12", "--insert-sd", "1"]);
+    std::fs::remove_file(&map).ok();
+    std::fs::remove_file(&kraken).ok();
+
+    // the 2 mixed reads before the tie and the tie at cellular organisms
+    assert_eq!(single, "seqP\t562\t\t562:8 9606:8 131567:1\n");
+    // a fragment with a mate of each genome goes to their ancestor
+    assert_eq!(paired, "seqP\t562\t\t562:4 9606:4 131567:5\n");
+    // mates 2 to 6 reads apart, weighted 1, 4, 6, 4 and 1
+    let fragments: usize = spread
+        .trim_end()
+        .split(['\t', ' '])
+        .skip(3)
+        .map(|item| item.split_once(':').unwrap().1.parse::<usize>().unwrap())
+        .sum();
+    assert_eq!(fragments, 15 + 4 * 14 + 6 * 13 + 4 * 12 + 11);
+}
+
+#[test]
+fn binary_read_distributions_hold_the_text_counts() {
+    let (map, kraken) = synthetic_library("binary", 500);
+    let text = output_path("binary-text");
+    let binary = output_path("binary-counts");
+    for (output, extra_args) in [(&text, &[][..]), (&binary, &["--binary"][..])] {
+        let status = Command::new(env!("CARGO_BIN_EXE_bracken"))
+            .args([
+                "-q",
+                "kmer2read-distr",
+                "--taxonomy",
+                "tests/data/self_test",
+            ])
+            .args(["-k", "5", "-l", "8", "--ordered", "--seqid2taxid"])
+            .arg(&map)
+            .arg("--kraken")
+            .arg(&kraken)
+            .arg("--output")
+            .arg(output)
+            .args(extra_args)
+            .status()
+            .expect("failed to run bracken");
+        assert!(status.success());
+    }
+    let kmer_distrib = |input: &Path| {
+        let output = output_path("binary-distr").with_extension("kmer_distrib");
+        let status = Command::new(env!("CARGO_BIN_EXE_bracken"))
+            .args(["-q", "kmer-distrib", "-i"])
+            .arg(input)
+            .arg("-o")
+            .arg(&output)
+            .status()
+            .expect("failed to run bracken");
+        assert!(status.success());
+        let mut lines: Vec<String> = std::fs::read_to_string(&output)
+            .unwrap()
+            .lines()
+            .map(|line| {
+                let mut items: Vec<&str> = line.split(['\t', ' ']).collect();
+                items[1..].sort_unstable();
+                items.join(" ")
+            })
+            .collect();
+        std::fs::remove_file(output).ok();
+        lines.sort();
+        lines
+    };
+    let text_distr = kmer_distrib(&text);
+    let binary_distr = kmer_distrib(&binary);
+    let text_content = std::fs::read_to_string(&text).unwrap();
+    let bytes = std::fs::read(&binary).unwrap();
+    let truncated = output_path("binary-truncated");
+    std::fs::write(&truncated, &bytes[..bytes.len() - 3]).unwrap();
+    let truncated_run = Command::new(env!("CARGO_BIN_EXE_bracken"))
+        .args(["-q", "kmer-distrib", "-i"])
+        .arg(&truncated)
+        .arg("-o")
+        .arg(output_path("truncated-distr"))
+        .output()
+        .expect("failed to run bracken");
+    for path in [map, kraken, text, binary.clone(), truncated] {
+        std::fs::remove_file(path).ok();
+    }
+
+    let (magic, records) = bytes.split_at(bracken::kmer2read_distr::COUNTS_MAGIC.len());
+    assert_eq!(magic, bracken::kmer2read_distr::COUNTS_MAGIC);
+    let mut lines = Vec::new();
+    let seqids = bracken::kmer2read_distr::read_binary_counts(records, &binary, |taxid, counts| {
+        let items: Vec<String> = counts
+            .iter()
+            .map(|(taxid, count)| format!("{}:{}", taxid, count))
+            .collect();
+        lines.push((taxid, items.join(" ")));
+    })
+    .unwrap();
+    let decoded: String = seqids
+        .iter()
+        .zip(lines)
+        .map(|(seqid, (taxid, items))| format!("{}\t{}\t\t{}\n", seqid, taxid, items))
+        .collect();
+    assert_eq!(decoded, text_content);
+    assert!(bytes.len() < text_content.len());
+    assert_eq!(binary_distr, text_distr);
+    assert!(!truncated_run.status.success());
+    assert!(String::from_utf8_lossy(&truncated_run.stderr).contains("truncated file"));
+}
+
+#[test]
+fn classification_statistics_are_written_per_sequence() {
+    let (map, kraken) = mixed_library("stats");
+    let stats = output_path("stats-table");
+    let (run, content) = kmer2read_distr(
+        "stats-cnts",
+        &[
+            "--seqid2taxid",
+            map.to_str().unwrap(),
+            "--kraken",
+            kraken.to_str().unwrap(),
+            "--ordered",
+            "--stats",
+            stats.to_str().unwrap(),
+        ],
+    );
+    let table = std::fs::read_to_string(&stats).unwrap_or_default();
+    for path in [map, kraken, stats] {
+        std::fs::remove_file(path).ok();
+    }
+
+    assert!(run.status.success());
+    assert_eq!(
+        content.lines().nth(1),
+        Some("seqO\t562\t\t0:3 562:2 9606:7 131567:1")
+    );
+    assert_eq!(
+        table,
+        "seqid\ttaxid\tread_len\treads\town_fraction\tancestor_fraction\tother_fraction\tunclassified_fraction\n\
+         seqE\t562\t8\t197\t0.862944\t0.137056\t0.000000\t0.000000\n\
+         seqO\t562\t8\t13\t0.153846\t0.076923\t0.538462\t0.230769\n"
+    );
+}
+
+#[test]
+fn several_and_globbed_kraken_files_are_read_as_one() {
+    let dir = output_path("globbed").with_extension("");
+    std::fs::create_dir_all(&dir).unwrap();
+    let lines = std::fs::read_to_string(KRAKEN).unwrap();
+    let parts: Vec<PathBuf> = lines
+        .lines()
+        .enumerate()
+        .map(|(i, line)| {
+            let part = dir.join(format!("part-{}.kraken", i + 1));
+            std::fs::write(&part, format!("{}\n", line)).unwrap();
+            part
+        })
+        .collect();
+    let convert = |name: &str, kraken: &[&str]| {
+        let mut args = vec!["--seqid2taxid", SEQID2TAXID, "--ordered", "--kraken"];
+        args.extend(kraken);
+        kmer2read_distr(name, &args)
+    };
+    let (_, single) = convert("single-file", &[KRAKEN]);
+    let part_args: Vec<&str> = parts.iter().map(|part| part.to_str().unwrap()).collect();
+    let (_, several) = convert("several-files", &part_args);
+    let pattern = dir.join("part-*.kraken");
+    let (_, globbed) = convert("globbed-files", &[pattern.to_str().unwrap()]);
+    let no_match = dir.join("none-*.kraken");
+    let (unmatched, _) = convert("unmatched-files", &[no_match.to_str().unwrap()]);
+    std::fs::remove_dir_all(&dir).ok();
+
+    assert_eq!(single.lines().count(), 3);
+    assert_eq!(several, single);
+    assert_eq!(globbed, single);
+    assert!(!unmatched.status.success());
+    assert!(String::from_utf8_lossy(&unmatched.stderr).contains("no kraken file matches"));
+}
+
+#[test]
+fn reads_of_mostly_unclassified_kmers_follow_the_zero_kmers_mode() {
+    let (map, kraken) = mixed_library("zero-kmers");
+    let convert = |mode: &str| {
+        let (run, content) = kmer2read_distr(
+            &format!("zero-kmers-{}", mode),
+            &[
+                "--seqid2taxid",
+                map.to_str().unwrap(),
+                "--kraken",
+                kraken.to_str().unwrap(),
+                "--ordered",
+                "--zero-kmers",
+                mode,
+            ],
+        );
+        assert!(run.status.success());
+        content.lines().nth(1).unwrap().to_string()
+    };
+    let penalized = convert("penalize");
+    let counted = convert("count");
+    let ignored = convert("ignore");
+    std::fs::remove_file(&map).ok();
+    std::fs::remove_file(&kraken).ok();
+
+    // the read of 1 kmer of Homo sapiens and 3 unclassified kmers goes to
+    // Homo sapiens, to no taxon, or nowhere; the 3 reads of unclassified
+    // kmers only are dropped when ignored
+    assert_eq!(penalized, "seqO\t562\t\t0:3 562:2 9606:7 131567:1");
+    assert_eq!(counted, "seqO\t562\t\t0:4 562:2 9606:6 131567:1");
+    assert_eq!(ignored, "seqO\t562\t\t562:2 9606:6 131567:1");
+}
+
+#[test]
+fn memory_bounds_size_the_batches() {
+    let (map, kraken) = synthetic_library("max-memory", 2000);
+    let convert = |name: &str, max_memory: Option<&str>| {
+        let mut args = vec![
+            "-v",
+            "--threads",
+            "2",
+            "--seqid2taxid",
+            map.to_str().unwrap(),
+            "--kraken",
+            kraken.to_str().unwrap(),
+            "--ordered",
+        ];
+        if let Some(max_memory) = max_memory {
+            args.extend(["--max-memory", max_memory]);
+        }
+        let (run, content) = kmer2read_distr(name, &args);
+        (
+            run.status.code(),
+            String::from_utf8_lossy(&run.stderr).to_string(),
+            content,
+        )
+    };
+    let (_, _, unbounded) = convert("max-memory-none", None);
+    let (_, bounded_log, bounded) = convert("max-memory-16M", Some("16M"));
+    let (_, tight_log, tight) = convert("max-memory-1K", Some("1K"));
+    let (invalid_code, invalid_log, _) = convert("max-memory-invalid", Some("12Q"));
+    std::fs::remove_file(&map).ok();
+    std::fs::remove_file(&kraken).ok();
+
+    // 6 batches in flight for 2 threads, each taken 3 times by the batch,
+    // its conversion and its output
+    assert!(bounded_log.contains("at most 6 batches of about 932067 bytes in flight"));
+    assert!(tight_log.contains("1024 bytes are too few for 2 threads"));
+    assert!(tight_log.contains("batches of about 65536 bytes"));
+    assert_eq!(bounded, unbounded);
+    assert_eq!(tight, unbounded);
+    assert_eq!(invalid_code, Some(2));
+    assert!(invalid_log.contains("expected e.g. 512M or 16G"));
+}
+
+#[test]
+fn tied_reads_follow_the_tie_break() {
+    let map = output_path("tie-break").with_extension("map");
+    std::fs::write(&map, "seqS\t28901\nseqV\t10665\nseqE\t562\nseqA\t208962\n").unwrap();
+    // reads of two kmers of each of two taxa
+    let kraken = output_path("tie-break").with_extension("kraken");
+    std::fs::write(
+        &kraken,
+        "C\tseqS\t28901\t8\t28901:2 10665:2\nC\tseqE\t562\t8\t562:2 208962:2\n",
+    )
+    .unwrap();
+    let convert = |tie_break: &str| -> Vec<String> {
+        let (run, content) = kmer2read_distr(
+            &format!("tie-break-{}", tie_break),
+            &[
+                "--seqid2taxid",
+                map.to_str().unwrap(),
+                "--kraken",
+                kraken.to_str().unwrap(),
+                "--ordered",
+                "--tie-break",
+                tie_break,
+            ],
+        );
+        assert!(run.status.success());
+        content
+            .lines()
+            .map(|line| line.rsplit('\t').next().unwrap().to_string())
+            .collect()
+    };
+    let lca = convert("lca");
+    let deepest = convert("deepest");
+    let lowest_taxid = convert("lowest-taxid");
+    std::fs::remove_file(&map).ok();
+    std::fs::remove_file(&kraken).ok();
+
+    // Salmonella enterica is deeper than the phage T4, E. coli and
+    // E. albertii are equally deep
+    assert_eq!(lca, ["1:1", "561:1"]);
+    assert_eq!(deepest, ["28901:1", "562:1"]);
+    assert_eq!(lowest_taxid, ["10665:1", "562:1"]);
+}
+
+#[test]
+fn sequences_shorter_than_a_read_follow_the_short_seq_mode() {
+    let map = output_path("short-seq").with_extension("map");
+    std::fs::write(&map, "seqS\t562\nseqH\t9606\n").unwrap();
+    // three kmers, one short of a read of 8 bp
+    let kraken = output_path("short-seq").with_extension("kraken");
+    std::fs::write(&kraken, "C\tseqS\t562\t7\t562:2 0:1\n").unwrap();
+    let convert = |mode: &str| {
+        let (run, content) = kmer2read_distr(
+            &format!("short-seq-{}", mode),
+            &[
+                "--seqid2taxid",
+                map.to_str().unwrap(),
+                "--kraken",
+                kraken.to_str().unwrap(),
+                "--confidence",
+                "0.6",
+                "--short-seq",
+                mode,
+            ],
+        );
+        assert!(run.status.success());
+        assert!(
+            String::from_utf8_lossy(&run.stderr).contains("1 sequences shorter than 8 bp reads")
+        );
+        content
+    };
+    let skip = convert("skip");
+    let single_window = convert("single-window");
+    let pad = convert("pad");
+    std::fs::remove_file(&map).ok();
+    std::fs::remove_file(&kraken).ok();
+
+    assert_eq!(skip, "seqS\t562\t\t\n");
+    // 2 of 3 kmers reach the confidence, 2 of 4 once padded do not
+    assert_eq!(single_window, "seqS\t562\t\t562:1\n");
+    assert_eq!(pad, "seqS\t562\t\t0:1\n");
+}
+
+#[test]
+fn reads_span_the_minimizer_positions_of_their_length() {
+    let map = output_path("minimizer-len").with_extension("map");
+    std::fs::write(&map, "seqS\t562\nseqH\t9606\n").unwrap();
+    let kraken = output_path("minimizer-len").with_extension("kraken");
+    std::fs::write(&kraken, "C\tseqS\t562\t14\t562:10\n").unwrap();
+    let convert = |name: &str, args: &[&str]| {
+        let mut all_args = vec![
+            "--seqid2taxid",
+            map.to_str().unwrap(),
+            "--kraken",
+            kraken.to_str().unwrap(),
+        ];
+        all_args.extend_from_slice(args);
+        kmer2read_distr(name, &all_args)
+    };
+    let (_, kmers) = convert("minimizer-len-none", &[]);
+    let (minimizers_run, minimizers) = convert("minimizer-len-3", &["--minimizer-len", "3"]);
+    let (too_long_run, too_long) = convert("minimizer-len-6", &["--minimizer-len", "6"]);
+    std::fs::remove_file(&map).ok();
+    std::fs::remove_file(&kraken).ok();
+
+    // A read of 8 bp spans 4 kmers of 5 bp, but 6 minimizers of 3 bp
+    assert_eq!(kmers, "seqS\t562\t\t562:7\n");
+    assert!(
+        String::from_utf8_lossy(&minimizers_run.stderr).contains("positions of 3-mer minimizers")
+    );
+    assert_eq!(minimizers, "seqS\t562\t\t562:5\n");
+    assert!(!too_long_run.status.success());
+    assert!(String::from_utf8_lossy(&too_long_run.stderr)
+        .contains("--minimizer-len 6 is longer than the kmer length 5"));
+    assert!(too_long.is_empty());
+}
+
+#[test]
+fn duplicate_seqids_follow_the_on_duplicate_mode() {
+    let map = output_path("on-duplicate").with_extension("map");
+    std::fs::write(&map, "seqS\t562\nseqS\t9606\nseqH\t9606\n").unwrap();
+    let kraken = output_path("on-duplicate").with_extension("kraken");
+    std::fs::write(
+        &kraken,
+        "C\tseqS\t562\t14\t562:10\nC\tseqS\t562\t10\t9606:6\n",
+    )
+    .unwrap();
+    let convert = |mode: &str| {
+        kmer2read_distr(
+            &format!("on-duplicate-{}", mode),
+            &[
+                "--seqid2taxid",
+                map.to_str().unwrap(),
+                "--kraken",
+                kraken.to_str().unwrap(),
+                "--on-duplicate",
+                mode,
+            ],
+        )
+    };
+    let (error_run, error) = convert("error");
+    let (first_run, first) = convert("first");
+    let (merge_run, merge) = convert("merge");
+    std::fs::remove_file(&map).ok();
+    std::fs::remove_file(&kraken).ok();
+
+    assert!(!error_run.status.success());
+    assert!(String::from_utf8_lossy(&error_run.stderr)
+        .contains("seqid seqS is listed more than once (see --on-duplicate)"));
+    assert!(error.is_empty());
+    assert!(String::from_utf8_lossy(&first_run.stderr)
+        .contains("1 duplicate seqids in the kraken files: only the first kept"));
+    assert_eq!(first, "seqS\t562\t\t562:7\n");
+    // The seqid goes to the LCA of E. coli and human, and the reads of its
+    // kraken lines add up
+    assert!(merge_run.status.success());
+    assert_eq!(merge, "seqS\t131567\t\t562:7\nseqS\t131567\t\t9606:3\n");
+}
+
+#[test]
+fn legacy_format_matches_the_cpp_tool_byte_for_byte() {
+    let output = output_path("legacy-format");
+    let run = Command::new(env!("CARGO_BIN_EXE_bracken"))
+        .args(["kmer2read-distr", "--taxonomy", "tests/data/self_test"])
+        .args(["--seqid2taxid", SEQID2TAXID, "--kraken", KRAKEN])
+        .args(["-k", "35", "-l", "50", "--legacy-format", "--output"])
+        .arg(&output)
+        .output()
+        .expect("failed to run bracken");
+    let legacy = std::fs::read(&output).unwrap_or_default();
+    std::fs::remove_file(&output).ok();
+    let expected = std::fs::read_to_string("tests/data/self_test/expected.kraken_cnts").unwrap();
+
+    assert!(run.status.success());
+    // Ascending taxids of the std::map of the C++ tool, each item followed
+    // by a space
+    let legacy_text = String::from_utf8(legacy).unwrap();
+    assert_eq!(
+        legacy_text,
+        "seqE\t562\t\t561:15 562:170 \n\
+         seqA\t208962\t\t561:35 208962:150 \n\
+         seqS\t28901\t\t543:15 28901:170 \n"
+    );
+    // The same reads as the expected default output
+    let reordered: String = expected
+        .lines()
+        .map(|line| {
+            let (fields, items) = line.rsplit_once('\t').unwrap();
+            let mut items: Vec<(u32, &str)> = items
+                .split(' ')
+                .map(|item| (item.split(':').next().unwrap().parse().unwrap(), item))
+                .collect();
+            items.sort_unstable();
+            let items: String = items.iter().map(|(_, item)| format!("{} ", item)).collect();
+            format!("{}\t{}\n", fields, items)
+        })
+        .collect();
+    assert_eq!(legacy_text, reordered);
+}
+
+#[test]
+fn skip_and_limit_convert_a_slice_of_the_sequences() {
+    let (map, kraken) = synthetic_library("skip-limit", 20);
+    let convert = |name: &str, extra_args: &[&str]| {
+        let mut args = vec![
+            "--seqid2taxid",
+            map.to_str().unwrap(),
+            "--kraken",
+            kraken.to_str().unwrap(),
+            "--ordered",
+        ];
+        args.extend(extra_args);
+        let (run, content) = kmer2read_distr(name, &args);
+        assert!(run.status.success());
+        content
+    };
+    let all = convert("skip-limit-all", &[]);
+    let slice = convert("skip-limit-slice", &["--skip", "5", "--limit", "7"]);
+    let tail = convert("skip-limit-tail", &["--skip", "15"]);
+    let past_the_end = convert("skip-limit-past", &["--skip", "25", "--limit", "3"]);
+    std::fs::remove_file(&map).ok();
+    std::fs::remove_file(&kraken).ok();
+
+    let lines: Vec<&str> = all.lines().collect();
+    assert_eq!(lines.len(), 20);
+    assert_eq!(slice.lines().collect::<Vec<_>>(), lines[5..12]);
+    assert_eq!(tail.lines().collect::<Vec<_>>(), lines[15..]);
+    assert!(past_the_end.is_empty());
+}
+
+#[test]
+fn split_outputs_hold_the_sequences_of_each_taxid_or_clade() {
+    let map = output_path("split").with_extension("map");
+    std::fs::write(
+        &map,
+        "seqE\t562\nseqA\t208962\nseqS\t28901\nseqH\t9606\nseqG\t561\n",
+    )
+    .unwrap();
+    let kraken = output_path("split").with_extension("kraken");
+    std::fs::write(
+        &kraken,
+        "C\tseqE\t562\t14\t562:10\n\
+         C\tseqA\t208962\t14\t208962:6 561:4\n\
+         C\tseqS\t28901\t9\t28901:5\n\
+         C\tseqH\t9606\t9\t9606:5\n\
+         C\tseqG\t561\t9\t561:5\n",
+    )
+    .unwrap();
+    // The files of the split directory by the taxid prefixing their names
+    let split = |name: &str, extra_args: &[&str]| {
+        let dir = output_path(name).with_extension("");
+        let mut args = vec![
+            "--seqid2taxid",
+            map.to_str().unwrap(),
+            "--kraken",
+            kraken.to_str().unwrap(),
+            "--ordered",
+            "--split-by-taxid",
+            dir.to_str().unwrap(),
+        ];
+        args.extend(extra_args);
+        let (run, content) = kmer2read_distr(name, &args);
+        assert!(run.status.success());
+        assert!(content.is_empty());
+        let files: BTreeMap<u32, String> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| {
+                let path = entry.unwrap().path();
+                let file_name = path.file_name().unwrap().to_str().unwrap();
+                let taxid = file_name.split('.').next().unwrap().parse().unwrap();
+                (taxid, std::fs::read_to_string(&path).unwrap())
+            })
+            .collect();
+        std::fs::remove_dir_all(&dir).ok();
+        files
+    };
+    let by_taxid = split("split-taxid", &[]);
+    let by_genus = split("split-genus", &["--split-rank", "G"]);
+    let by_species = split("split-species", &["--split-rank", "s"]);
+    let (unknown_rank, _) = kmer2read_distr(
+        "split-unknown",
+        &["--split-by-taxid", "unused", "--split-rank", "X"],
+    );
+    std::fs::remove_file(&map).ok();
+    std::fs::remove_file(&kraken).ok();
+
+    let files = |files: &[(u32, &str)]| -> BTreeMap<u32, String> {
+        files
+            .iter()
+            .map(|&(taxid, content)| (taxid, content.to_string()))
+            .collect()
+    };
+    assert_eq!(
+        by_taxid,
+        files(&[
+            (561, "seqG\t561\t\t561:2\n"),
+            (562, "seqE\t562\t\t562:7\n"),
+            (9606, "seqH\t9606\t\t9606:2\n"),
+            (28901, "seqS\t28901\t\t28901:2\n"),
+            (208962, "seqA\t208962\t\t561:1 208962:6\n"),
+        ])
+    );
+    // The genus file of Escherichia gets E. coli, E. albertii and the
+    // genus itself
+    assert_eq!(
+        by_genus,
+        files(&[
+            (
+                561,
+                "seqE\t562\t\t562:7\nseqA\t208962\t\t561:1 208962:6\nseqG\t561\t\t561:2\n"
+            ),
+            (590, "seqS\t28901\t\t28901:2\n"),
+            (9605, "seqH\t9606\t\t9606:2\n"),
+        ])
+    );
+    // The genus sequence is in no species
+    assert_eq!(by_species[&0], "seqG\t561\t\t561:2\n");
+    assert_eq!(by_species.len(), 5);
+    assert_eq!(unknown_rank.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&unknown_rank.stderr)
+        .contains("unknown rank \"X\", expected D, P, C, O, F, G or S"));
+}
+
+#[test]
+fn taxid_summaries_add_up_the_reads_of_the_genome_sequences() {
+    let (map, kraken) = mixed_library("taxid-summary");
+    let summary = output_path("taxid-summary-table");
+    let (run, _) = kmer2read_distr(
+        "taxid-summary",
+        &[
+            "--seqid2taxid",
+            map.to_str().unwrap(),
+            "--kraken",
+            kraken.to_str().unwrap(),
+            "--taxid-summary",
+            summary.to_str().unwrap(),
+        ],
+    );
+    let table = std::fs::read_to_string(&summary).unwrap_or_default();
+    for path in [map, kraken, summary] {
+        std::fs::remove_file(path).ok();
+    }
+
+    assert!(run.status.success());
+    // seqE and seqO, of 197 and 13 reads: 170 + 2 of them unique to E.
+    // coli, 27 + 1 escalated to its ancestors, 7 human and 3 unclassified
+    assert_eq!(
+        table,
+        "taxid\trank\tread_len\tsequences\treads\tunique_fraction\tancestor_fraction\tother_fraction\tunclassified_fraction\n\
+         562\tspecies\t8\t2\t210\t0.819048\t0.133333\t0.033333\t0.014286\n"
+    );
+}
+
+#[test]
+fn malformed_lines_fail_or_are_read_in_part() {
+    let map = output_path("malformed").with_extension("map");
+    std::fs::write(&map, "seqE\t562\nseqB\t562\nseqH\t9606\n").unwrap();
+    let kraken = output_path("malformed").with_extension("kraken");
+    std::fs::write(
+        &kraken,
+        "C\tseqE\t562\t14\t562:10\n\
+         C\tseqX\t562\n\
+         C\tseqB\t562\t14\t562:4 x:3 562:3\n",
+    )
+    .unwrap();
+    let bad_item = output_path("malformed-item").with_extension("kraken");
+    std::fs::write(&bad_item, "C\tseqB\t562\t14\t562:4 x:3 562:3\n").unwrap();
+    let convert = |name: &str, kraken: &Path, mode: &str| {
+        kmer2read_distr(
+            name,
+            &[
+                "--seqid2taxid",
+                map.to_str().unwrap(),
+                "--kraken",
+                kraken.to_str().unwrap(),
+                "--malformed",
+                mode,
+            ],
+        )
+    };
+    let (strict_run, strict) = convert("malformed-strict", &kraken, "strict");
+    let (item_run, _) = convert("malformed-strict-item", &bad_item, "strict");
+    let (lenient_run, lenient) = convert("malformed-lenient", &kraken, "lenient");
+    for path in [map, kraken, bad_item] {
+        std::fs::remove_file(path).ok();
+    }
+
+    assert!(!strict_run.status.success());
+    assert!(String::from_utf8_lossy(&strict_run.stderr)
+        .contains("line 2: malformed kraken line (fewer than 5 fields), see --malformed"));
+    assert!(strict.is_empty());
+    assert!(String::from_utf8_lossy(&item_run.stderr)
+        .contains("line 1: malformed kraken line (invalid taxid in a kmer item)"));
+    // The short line is skipped and the 3 kmers of the bad item are
+    // unclassified
+    assert!(lenient_run.status.success());
+    assert!(String::from_utf8_lossy(&lenient_run.stderr)
+        .contains("2 malformed lines in the kraken files skipped or read in part"));
+    assert_eq!(lenient, "seqE\t562\t\t562:7\nseqB\t562\t\t562:7\n");
+}
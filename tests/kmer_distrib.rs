@@ -0,0 +1,293 @@
+//! Kmer distributions computed by kmer-distrib from read distributions,
+//! and their summaries.
+
+mod common;
+
+use common::{output_path, KMER_DISTR};
+use std::collections::{BTreeMap, BTreeSet};
+use std::process::Command;
+
+/// Binary read distributions of a genome of `taxid` with `counts`, then a
+/// seqid table whose seqid has `seqid_len` bytes and is `seqid`.
+fn binary_counts(taxid: u64, counts: &[(u64, u64)], seqid_len: u64, seqid: &str) -> Vec<u8> {
+    fn varint(out: &mut Vec<u8>, mut value: u64) {
+        while value >= 0x80 {
+            out.push(value as u8 | 0x80);
+            value >>= 7;
+        }
+        out.push(value as u8);
+    }
+    let mut file = bracken::kmer2read_distr::COUNTS_MAGIC.to_vec();
+    varint(&mut file, counts.len() as u64 + 1);
+    varint(&mut file, taxid);
+    for &(taxid, count) in counts {
+        varint(&mut file, taxid);
+        varint(&mut file, count);
+    }
+    file.extend([0, 1]);
+    varint(&mut file, seqid_len);
+    file.extend(seqid.as_bytes());
+    file
+}
+
+#[test]
+fn binary_read_distributions_keep_64_bit_counts_and_reject_bad_records() {
+    let kmer_distrib = |name: &str, content: Vec<u8>| {
+        let input = output_path(name).with_extension("kraken_cnts");
+        let output = output_path(name).with_extension("kmer_distrib");
+        std::fs::write(&input, content).unwrap();
+        let run = Command::new(env!("CARGO_BIN_EXE_bracken"))
+            .args(["kmer-distrib", "-i"])
+            .arg(&input)
+            .arg("-o")
+            .arg(&output)
+            .output()
+            .expect("failed to run bracken");
+        let distribution = std::fs::read_to_string(&output).unwrap_or_default();
+        std::fs::remove_file(input).ok();
+        std::fs::remove_file(output).ok();
+        let log = String::from_utf8_lossy(&run.stderr).to_string();
+        (run.status.code(), distribution, log)
+    };
+    let (large_code, large, _) = kmer_distrib(
+        "binary-large",
+        binary_counts(562, &[(562, 5_000_000_000), (561, 1)], 4, "seqE"),
+    );
+    let (taxid_code, _, taxid_log) = kmer_distrib(
+        "binary-taxid",
+        binary_counts(562, &[(1 << 40, 1)], 4, "seqE"),
+    );
+    // A corrupt seqid length is no allocation of that size
+    let (seqid_code, _, seqid_log) = kmer_distrib(
+        "binary-seqid",
+        binary_counts(562, &[(562, 1)], u64::MAX >> 1, "seqE"),
+    );
+
+    assert_eq!(large_code, Some(0));
+    assert!(large.contains("5000000000"));
+    assert_eq!(taxid_code, Some(3));
+    assert!(taxid_log.contains("taxid out of range"));
+    assert_eq!(seqid_code, Some(3));
+    assert!(seqid_log.contains("truncated file"));
+}
+
+#[test]
+fn kmer_distrib_filters_genomes_and_checks_taxids() {
+    let dir = output_path("kmer-distrib").with_extension("");
+    std::fs::create_dir_all(&dir).unwrap();
+    // A 3-kmer genome of Homo sapiens and a genome missing from the taxonomy
+    let extra = dir.join("extra.kraken_cnts");
+    std::fs::write(
+        &extra,
+        "seqP\t9606\t\t9606:3\nseqX\t4242424\t\t4242424:50 9606:2\n",
+    )
+    .unwrap();
+    let excluded = dir.join("excluded.txt");
+    std::fs::write(&excluded, "208962\n").unwrap();
+    let kmer_distrib = |name: &str, extra_args: &[&str]| {
+        let output = dir.join(format!("{}.kmer_distrib", name));
+        let summary = dir.join(format!("{}.json", name));
+        let run = Command::new(env!("CARGO_BIN_EXE_bracken"))
+            .args([
+                "kmer-distrib",
+                "-i",
+                "tests/data/self_test/expected.kraken_cnts",
+            ])
+            .arg(&extra)
+            .arg("-o")
+            .arg(&output)
+            .arg("--summary-json")
+            .arg(&summary)
+            .args(extra_args)
+            .output()
+            .expect("failed to run bracken");
+        let genomes: BTreeSet<String> = std::fs::read_to_string(&output)
+            .unwrap_or_default()
+            .lines()
+            .filter(|line| !line.starts_with('#') && !line.starts_with("mapped_taxid"))
+            .flat_map(|line| line.split('\t').nth(1).unwrap().split(' '))
+            .map(|genome| genome.split(':').next().unwrap().to_string())
+            .collect();
+        let summary = std::fs::read_to_string(&summary).unwrap_or_default();
+        (run.status.code(), genomes, summary)
+    };
+    let (all_code, all, all_summary) = kmer_distrib("all", &[]);
+    let (filtered_code, filtered, filtered_summary) = kmer_distrib(
+        "filtered",
+        &[
+            "--min-genome-kmers",
+            "10",
+            "--exclude-taxids",
+            excluded.to_str().unwrap(),
+        ],
+    );
+    let issues = dir.join("issues.tsv");
+    let (checked_code, _, _) = kmer_distrib(
+        "checked",
+        &[
+            "--taxonomy",
+            "tests/data/self_test",
+            "--on-invalid-taxid",
+            "fail",
+            "--taxid-issues",
+            issues.to_str().unwrap(),
+        ],
+    );
+    let issues = std::fs::read_to_string(&issues).unwrap_or_default();
+    std::fs::remove_dir_all(&dir).ok();
+
+    let taxids = |taxids: &[&str]| {
+        taxids
+            .iter()
+            .map(|t| t.to_string())
+            .collect::<BTreeSet<_>>()
+    };
+    assert_eq!(all_code, Some(0));
+    assert_eq!(all, taxids(&["208962", "28901", "4242424", "562", "9606"]));
+    assert!(all_summary.contains("\"genomes\": 5"));
+    assert!(all_summary.contains("\"mapped_taxids\": 7"));
+    assert_eq!(filtered_code, Some(0));
+    assert_eq!(filtered, taxids(&["28901", "4242424", "562"]));
+    assert!(filtered_summary.contains("\"genomes\": 3"));
+    assert!(filtered_summary.contains("\"mapped_taxids\": 6"));
+    assert_eq!(checked_code, Some(3));
+    assert!(issues.lines().any(|line| line == "4242424\tboth\tmissing"));
+}
+
+#[test]
+fn db_inspect_summarizes_the_distribution() {
+    let output = Command::new(env!("CARGO_BIN_EXE_bracken"))
+        .args(["db-inspect", KMER_DISTR, "-n", "2"])
+        .args(["--taxonomy", "tests/data/self_test"])
+        .output()
+        .expect("failed to run bracken");
+    assert!(output.status.success());
+    let content = String::from_utf8_lossy(&output.stdout);
+    let (summary, top) = content.split_once("\n\n").unwrap();
+    let summary: BTreeMap<&str, &str> = summary
+        .lines()
+        .map(|line| line.split_once('\t').unwrap())
+        .collect();
+    let top: Vec<Vec<&str>> = top
+        .lines()
+        .skip(1)
+        .map(|line| line.split_whitespace().collect())
+        .collect();
+
+    assert_eq!(summary["read_length"], "100 (from the file name)");
+    assert_eq!(summary["mapped_taxids"], "20");
+    assert_eq!(summary["genomes"], "7");
+    assert_eq!(summary["ambiguous_taxids"], "13");
+    assert_eq!(summary["mapped_kmers"], "53190");
+    assert_eq!(summary["kmers_to_own_genome"], "41800 (74.51%)");
+    // E. coli holds the kmers of its strain genomes
+    assert_eq!(top.len(), 2);
+    assert_eq!(
+        top[0],
+        [
+            "562",
+            "2",
+            "6100",
+            "11.47%",
+            "species",
+            "Escherichia",
+            "coli"
+        ]
+    );
+    assert_eq!(
+        top[1],
+        ["561", "3", "1380", "2.59%", "genus", "Escherichia"]
+    );
+}
+
+#[test]
+fn distr_diff_reports_the_changed_mapped_taxids() {
+    let before = "tests/data/self_test/expected.kmer_distrib";
+    // E. albertii is no genome of Escherichia anymore, 2 kmers of Salmonella
+    // enterica moved to Enterobacteriaceae and Homo sapiens was added
+    let after = output_path("distr-diff").with_extension("kmer_distrib");
+    std::fs::write(
+        &after,
+        "mapped_taxid\tgenome_taxids:kmers_mapped:total_genome_kmers\n\
+         208962\t208962:150:185\n28901\t28901:168:185\n561\t562:15:185\n\
+         543\t28901:17:185\n562\t562:170:185\n9606\t9606:10:10\n",
+    )
+    .unwrap();
+    let distr_diff = |a: &str, b: &str, extra_args: &[&str]| -> BTreeMap<String, Vec<String>> {
+        let output = Command::new(env!("CARGO_BIN_EXE_bracken"))
+            .args(["distr-diff", a, b])
+            .args(extra_args)
+            .output()
+            .expect("failed to run bracken");
+        assert!(output.status.success());
+        String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .skip(1)
+            .map(|line| {
+                let fields: Vec<String> = line.split('\t').map(String::from).collect();
+                (fields[0].clone(), fields[1..].to_vec())
+            })
+            .collect()
+    };
+    let after_path = after.to_str().unwrap();
+    let changes = distr_diff(before, after_path, &[]);
+    let all = distr_diff(before, after_path, &["--all"]);
+    let reverse = distr_diff(after_path, before, &["--tolerance", "0.02"]);
+    std::fs::remove_file(&after).ok();
+
+    let change = |rows: &BTreeMap<String, Vec<String>>, taxid: &str| rows[taxid][6].clone();
+    assert_eq!(
+        changes.keys().collect::<Vec<_>>(),
+        ["28901", "543", "561", "9606"]
+    );
+    assert_eq!(change(&changes, "9606"), "added");
+    assert_eq!(
+        changes["561"][..6],
+        ["2", "1", "0", "1", "208962", "-0.18919"]
+    );
+    assert_eq!(change(&changes, "543"), "changed");
+    assert_eq!(all.len(), 6);
+    assert_eq!(change(&all, "562"), "unchanged");
+    // changes of 2 in 185 kmers are within the tolerance
+    assert_eq!(reverse.keys().collect::<Vec<_>>(), ["561", "9606"]);
+    assert_eq!(change(&reverse, "9606"), "removed");
+}
+
+#[test]
+fn prune_distr_keeps_the_genomes_of_the_clades() {
+    let prune = |name: &str, extra_args: &[&str]| -> BTreeMap<String, String> {
+        let output = output_path(name).with_extension("kmer_distrib");
+        let status = Command::new(env!("CARGO_BIN_EXE_bracken"))
+            .args(["prune-distr", "-i", KMER_DISTR, "-c", "561"])
+            .args(["--taxonomy", "tests/data/self_test", "-o"])
+            .arg(&output)
+            .args(extra_args)
+            .status()
+            .expect("failed to run bracken");
+        assert!(status.success());
+        let content = std::fs::read_to_string(&output).unwrap();
+        std::fs::remove_file(&output).ok();
+        content
+            .lines()
+            .skip(1)
+            .map(|line| {
+                let (taxid, genomes) = line.split_once('\t').unwrap();
+                (taxid.to_string(), genomes.to_string())
+            })
+            .collect()
+    };
+    let pruned = prune("pruned", &[]);
+    let clades_only = prune("pruned-clades", &["--drop-ancestors"]);
+
+    // the genomes of Escherichia in the self-test taxonomy, which has no
+    // E. coli O157:H7
+    assert_eq!(pruned["2"], "511145:50:5000 208962:40:4600");
+    assert_eq!(pruned["1"], "511145:20:5000");
+    assert_eq!(pruned.len(), 10);
+    assert!(!pruned.contains_key("590") && !pruned.contains_key("83334"));
+    assert_eq!(
+        clades_only.keys().collect::<Vec<_>>(),
+        ["208962", "511145", "561", "562", "83333"]
+    );
+    assert_eq!(clades_only["561"], pruned["561"]);
+}
@@ -0,0 +1,32 @@
+//! Random sampling of read counts.
+
+#[test]
+fn multinomial_sampling_conserves_the_draws() {
+    use rand::{Rng, SeedableRng};
+    let fixed: [&[f64]; 4] = [
+        &[0.1; 10],
+        &[0.3, 0.3, 0.4, 0.0],
+        &[1e-300, 1.0, 0.0],
+        &[0.0, 0.7, 1e-12, 0.0],
+    ];
+    for seed in 0..200 {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(seed);
+        let random: Vec<f64> = (0..rng.gen_range(1..50))
+            .map(|_| match rng.gen_range(0..4) {
+                0 => 0.0,
+                1 => rng.gen::<f64>() * 1e-9,
+                _ => rng.gen::<f64>(),
+            })
+            .chain([1.0])
+            .collect();
+        for weights in fixed.iter().copied().chain([random.as_slice()]) {
+            let n = rng.gen_range(0..100_000);
+            let sampled = bracken::sampling::multinomial(weights, n, &mut rng);
+            assert_eq!(sampled.len(), weights.len());
+            assert_eq!(sampled.iter().sum::<usize>(), n, "{:?}", weights);
+            for (count, weight) in sampled.iter().zip(weights) {
+                assert!(*weight > 0.0 || *count == 0);
+            }
+        }
+    }
+}
@@ -0,0 +1,48 @@
+//! Queries of the NCBI taxonomy.
+
+#[test]
+fn lca_of_an_ancestor_and_its_descendant_is_the_ancestor() {
+    let taxo = bracken::taxonomy::NCBITaxonomy::load("tests/data/self_test/nodes.dmp").unwrap();
+    // Escherichia, E. coli, E. coli K-12 and E. albertii; twice for the cache
+    for _ in 0..2 {
+        assert_eq!(taxo.lca(561, 562), 561);
+        assert_eq!(taxo.lca(83333, 562), 562);
+        assert_eq!(taxo.lca(1, 562), 1);
+        assert_eq!(taxo.lca(83333, 208962), 561);
+        assert_eq!(taxo.lca(562, 9606), 131567);
+    }
+}
+
+#[test]
+fn taxonomy_subcommands_print_taxid_rank_and_name_lines() {
+    let query = |args: &[&str]| {
+        let output = std::process::Command::new(env!("CARGO_BIN_EXE_bracken"))
+            .args(["taxonomy", "--taxonomy", "tests/data/self_test"])
+            .args(args)
+            .output()
+            .expect("failed to run bracken");
+        let taxids: Vec<String> = String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .map(|line| line.split('\t').next().unwrap().to_string())
+            .collect();
+        (output, taxids)
+    };
+    let (lca, _) = query(&["lca", "562", "208962", "28901"]);
+    let (_, lineage) = query(&["lineage", "83333"]);
+    let (_, children) = query(&["children", "543"]);
+    let (_, species) = query(&["children", "543", "--rank", "S"]);
+    let (unknown, _) = query(&["lca", "562", "999"]);
+
+    assert_eq!(
+        String::from_utf8_lossy(&lca.stdout),
+        "543\tfamily\tEnterobacteriaceae\n"
+    );
+    assert_eq!(
+        lineage,
+        ["1", "131567", "2", "1224", "1236", "91347", "543", "561", "562", "83333"]
+    );
+    assert_eq!(children, ["561", "590"]);
+    assert_eq!(species, ["562", "28901", "208962"]);
+    assert_eq!(unknown.status.code(), Some(3));
+    assert!(String::from_utf8_lossy(&unknown.stderr).contains("taxid 999 is not in the taxonomy"));
+}